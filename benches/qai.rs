@@ -0,0 +1,94 @@
+//! Benchmarks for the hot paths of a query
+//!
+//! Run with `cargo bench`. The cold path covers what a fresh process does
+//! before the API request: load the config, the system prompt, and the tool
+//! cache, then render the prompt.
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use qai::config::Config;
+use qai::history::HistoryStore;
+use qai::prompt::{PromptContext, load_system_prompt, render_prompt};
+use qai::tools::{DualCommandList, ToolCache};
+use std::collections::BTreeMap;
+use std::hint::black_box;
+
+const SYSTEM_PROMPT: &str = include_str!("../prompts/system.pmt");
+
+const RESPONSE: &str = "MODERN:\n\
+    fd -e rs --size +100k\n\
+    rg -l 'TODO' src\n\
+    eza -la --sort=size\n\
+    STANDARD:\n\
+    find . -name '*.rs' -size +100k\n\
+    grep -rl 'TODO' src\n\
+    ls -laS\n";
+
+fn bench_prompt(c: &mut Criterion) {
+    let context = PromptContext::default();
+    c.bench_function("render_prompt", |b| {
+        b.iter(|| render_prompt(black_box(SYSTEM_PROMPT), black_box(&context)))
+    });
+}
+
+fn bench_history(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let mut store = HistoryStore::with_data_dir(dir.path().to_path_buf()).unwrap();
+    for i in 0..500 {
+        let query = format!("find files larger than {} MB", i % 50);
+        store.record_selection(&query, &format!("fd --size +{}m", i)).unwrap();
+    }
+    let results: Vec<String> = vec!["find . -size +10M".into(), "fd --size +10m".into(), "du -ah".into()];
+
+    c.bench_function("history_load", |b| {
+        b.iter(|| HistoryStore::with_data_dir(black_box(dir.path().to_path_buf())).unwrap())
+    });
+    c.bench_function("personalize_results", |b| {
+        b.iter(|| store.personalize_results(black_box("find files larger than 10 MB"), results.clone()))
+    });
+    c.bench_function("past_commands", |b| {
+        b.iter(|| store.past_commands(black_box("find files larger than 10 MB")))
+    });
+}
+
+fn bench_tools(c: &mut Criterion) {
+    let substitutions = BTreeMap::from([("fd".to_string(), "find".to_string())]);
+    let commands: Vec<String> = DualCommandList::parse(RESPONSE).all_commands();
+    // Warm the cache so the benchmarks measure lookups, not PATH searches
+    let mut cache = ToolCache::new();
+    cache.filter_commands(&commands);
+
+    c.bench_function("parse_response", |b| {
+        b.iter(|| DualCommandList::parse(black_box(RESPONSE)))
+    });
+    c.bench_function("filter_commands", |b| {
+        b.iter(|| cache.filter_commands(black_box(&commands)))
+    });
+    c.bench_function("process_response", |b| {
+        let response = DualCommandList::parse(RESPONSE);
+        b.iter(|| cache.process_response(black_box(&response), &substitutions))
+    });
+}
+
+fn bench_cold_start(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let config_path = dir.path().join("qai.yml");
+    std::fs::write(&config_path, "model: gpt-4o-mini\n").unwrap();
+    // Keep the tool cache out of the user's cache directory
+    qai::paths::init(qai::config::PathsConfig {
+        cache: Some(dir.path().to_path_buf()),
+        ..Default::default()
+    });
+
+    c.bench_function("cold_start", |b| {
+        b.iter(|| {
+            let config = Config::load(Some(black_box(&config_path))).unwrap();
+            let template = load_system_prompt().unwrap();
+            let prompt = render_prompt(&template, &PromptContext::default());
+            let cache = ToolCache::load();
+            (config, prompt, cache)
+        })
+    });
+}
+
+criterion_group!(benches, bench_prompt, bench_history, bench_tools, bench_cold_start);
+criterion_main!(benches);
@@ -0,0 +1,4 @@
+# Running docker containers, if the daemon is up
+# Copy to ~/.config/qai/context.d/ to add a "docker-ps" section to every prompt
+command -v docker >/dev/null 2>&1 || exit 1
+docker ps --format '{{.Names}} ({{.Image}}): {{.Status}}' 2>/dev/null | head -n 20
@@ -0,0 +1,4 @@
+# Branch and changed files of the current git repository, if any
+# Copy to ~/.config/qai/context.d/ to add a "git-status" section to every prompt
+git rev-parse --is-inside-work-tree >/dev/null 2>&1 || exit 1
+git status --short --branch 2>/dev/null | head -n 20
@@ -1,8 +1,29 @@
+//! Client for OpenAI-compatible chat completions endpoints
+//!
+//! [`OpenAIClient`] sends a system prompt and a query and returns the raw answer;
+//! splitting it into commands is left to [`crate::tools::DualCommandList`]. With
+//! `provider: mock` answers are replayed from fixtures instead, and with `record`
+//! each answer is saved as one (see [`crate::fixtures`]). With `provider: local` a GGUF
+//! model runs in-process (see [`crate::local`]). Rate-limited requests are retried and
+//! queued (see [`crate::ratelimit`]).
+
 use eyre::{Context, Result, eyre};
 use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
-
-use crate::config::Config;
+use std::ops::ControlFlow;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::budget::{Budget, TokenUsage};
+use crate::config::{Config, Provider, RateLimitConfig};
+use crate::context::estimate_tokens;
+#[cfg(all(feature = "daemon", unix))]
+use crate::daemon::{self, DaemonRequest};
+use crate::fixtures::Fixtures;
+use crate::local::{self, LocalModelFiles, Sampling};
+use crate::ratelimit::{self, Pacer};
+#[cfg(all(feature = "daemon", unix))]
+use std::path::PathBuf;
 
 #[derive(Debug, Serialize)]
 struct ChatMessage {
@@ -15,7 +36,11 @@ struct ChatRequest {
     model: String,
     messages: Vec<ChatMessage>,
     temperature: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
     max_tokens: u32,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -28,9 +53,41 @@ struct ChatResponseMessage {
     content: String,
 }
 
+#[derive(Debug, Deserialize)]
+struct ChatUsage {
+    prompt_tokens: u64,
+    completion_tokens: u64,
+}
+
 #[derive(Debug, Deserialize)]
 struct ChatResponse {
     choices: Vec<ChatChoice>,
+    /// Not every OpenAI-compatible server reports usage
+    #[serde(default)]
+    usage: Option<ChatUsage>,
+}
+
+/// One server-sent event of a streamed answer
+#[derive(Debug, Deserialize)]
+struct StreamChunk {
+    #[serde(default)]
+    choices: Vec<StreamChoice>,
+    #[serde(default)]
+    usage: Option<ChatUsage>,
+    /// Some servers report failures mid-stream as an event
+    #[serde(default)]
+    error: Option<ErrorDetail>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamChoice {
+    delta: StreamDelta,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamDelta {
+    #[serde(default)]
+    content: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -77,24 +134,54 @@ impl std::fmt::Display for ApiValidationError {
 
 impl std::error::Error for ApiValidationError {}
 
+impl ApiValidationError {
+    /// Whether the API couldn't be reached at all, rather than refusing the key
+    pub fn is_unreachable(&self) -> bool {
+        matches!(self, ApiValidationError::NetworkError(_))
+    }
+}
+
+/// Chat completions client for one endpoint and model
 #[derive(Debug)]
 pub struct OpenAIClient {
     client: reqwest::Client,
     api_key: Option<String>,
     api_base: String,
     model: String,
+    temperature: f32,
+    /// Sampling seed, sent when set (servers without seed support ignore it)
+    seed: Option<u64>,
     max_tokens: u32,
+    /// Timeout the daemon applies to requests it sends for this client
+    #[cfg(all(feature = "daemon", unix))]
     http_timeout_secs: u64,
+    /// Daily budget to enforce and record usage against (only when a limit is configured)
+    budget: Option<Budget>,
+    /// Tokens used by this client's requests so far
+    tokens_used: AtomicU64,
+    /// Whether answers come from the API, fixtures, or the API with each answer saved
+    provider: Provider,
+    fixtures: Fixtures,
+    /// Model files of `provider: local`
+    local: Option<LocalModelFiles>,
+    /// How 429s are retried, and the queue requests wait in meanwhile
+    rate_limit: RateLimitConfig,
+    pacer: Arc<Pacer>,
+    /// Socket of a `qai daemon` to send requests through, when one is listening
+    #[cfg(all(feature = "daemon", unix))]
+    daemon: Option<PathBuf>,
 }
 
 impl OpenAIClient {
+    /// Client for the configured endpoint, model, key, and query options; fails when no
+    /// API key is configured and `allow-no-api-key` is not set (or the provider is mock)
     pub fn new(config: &Config) -> Result<Self> {
         let api_key = match config.get_api_key() {
             Some(key) => Some(key),
-            None if config.allow_no_api_key => None,
+            None if config.allow_no_api_key || matches!(config.provider, Provider::Mock | Provider::Local) => None,
             None => {
                 return Err(eyre!(
-                    "No API key found. Set QAI_API_KEY environment variable or add api-key to ~/.config/qai/qai.yml"
+                    "No API key found. Set QAI_API_KEY environment variable or add api-key (or api-key-cmd) to ~/.config/qai/qai.yml"
                 ));
             }
         };
@@ -109,8 +196,25 @@ impl OpenAIClient {
             api_key,
             api_base: config.api_base.clone(),
             model: config.model.clone(),
-            max_tokens: config.max_tokens,
+            temperature: config.query.temperature,
+            seed: config.query.seed,
+            max_tokens: config.query.max_tokens.unwrap_or(config.max_tokens),
+            #[cfg(all(feature = "daemon", unix))]
             http_timeout_secs: config.http_timeout_secs,
+            // A local model costs nothing
+            budget: (config.budget.is_enabled() && config.provider != Provider::Local)
+                .then(|| Budget::new(config.budget.clone(), &config.model, Budget::default_path())),
+            tokens_used: AtomicU64::new(0),
+            provider: config.provider,
+            fixtures: Fixtures::open(),
+            local: match config.provider {
+                Provider::Local => Some(LocalModelFiles::from_config(config)?),
+                _ => None,
+            },
+            rate_limit: config.rate_limit.clone(),
+            pacer: ratelimit::shared(),
+            #[cfg(all(feature = "daemon", unix))]
+            daemon: Some(daemon::socket_path()),
         })
     }
 
@@ -132,15 +236,64 @@ impl OpenAIClient {
             api_key: Some(api_key),
             api_base,
             model,
+            temperature: 0.0,
+            seed: None,
             max_tokens,
+            #[cfg(all(feature = "daemon", unix))]
             http_timeout_secs,
+            budget: None,
+            tokens_used: AtomicU64::new(0),
+            provider: Provider::Openai,
+            fixtures: Fixtures::new(Default::default()),
+            local: None,
+            // Tests that retry 429s opt in with `with_rate_limit`
+            rate_limit: RateLimitConfig {
+                retries: 0,
+                ..Default::default()
+            },
+            pacer: Arc::new(Pacer::new()),
+            #[cfg(all(feature = "daemon", unix))]
+            daemon: None,
         })
     }
 
+    #[cfg(all(test, feature = "daemon", unix))]
+    pub fn with_daemon(mut self, socket: PathBuf) -> Self {
+        self.daemon = Some(socket);
+        self
+    }
+
+    /// Tokens used by this client's requests so far, as reported (or estimated)
+    pub fn tokens_used(&self) -> u64 {
+        self.tokens_used.load(Ordering::Relaxed)
+    }
+
+    #[cfg(test)]
+    pub fn with_rate_limit(mut self, rate_limit: RateLimitConfig) -> Self {
+        self.rate_limit = rate_limit;
+        self
+    }
+
+    #[cfg(test)]
+    pub fn with_budget(mut self, budget: Budget) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    #[cfg(test)]
+    pub fn with_fixtures(mut self, provider: Provider, fixtures: Fixtures) -> Self {
+        self.provider = provider;
+        self.fixtures = fixtures;
+        self
+    }
+
     /// Validate API key by calling GET /v1/models
     /// This endpoint authenticates but does NOT consume tokens
     #[allow(dead_code)]
     pub async fn validate_api_key(&self) -> std::result::Result<(), ApiValidationError> {
+        if matches!(self.provider, Provider::Mock | Provider::Local) {
+            return Ok(());
+        }
         let api_key = match &self.api_key {
             Some(key) => key,
             None => return Err(ApiValidationError::NotConfigured),
@@ -171,89 +324,388 @@ impl OpenAIClient {
         }
     }
 
+    /// POST a chat request, through the daemon when one is listening
+    async fn post(&self, url: &str, request: &ChatRequest) -> Result<(StatusCode, String)> {
+        #[cfg(all(feature = "daemon", unix))]
+        if let Some(socket) = &self.daemon {
+            let forwarded = DaemonRequest {
+                url: url.to_string(),
+                api_key: self.api_key.clone(),
+                body: serde_json::to_value(request).context("Failed to serialize request")?,
+                timeout_secs: self.http_timeout_secs,
+                rate_limit: self.rate_limit.clone(),
+            };
+            let mut queued = false;
+            let forwarded = daemon::forward(socket, &forwarded, &mut |ahead| {
+                queued = true;
+                ratelimit::report_queued(ahead);
+            })
+            .await;
+            if queued {
+                ratelimit::clear_status();
+            }
+            if let Some(response) = forwarded {
+                let response = response?;
+                if let Some(error) = response.error {
+                    return Err(eyre!(error));
+                }
+                log::debug!("Sent through daemon at {}", socket.display());
+                let status = StatusCode::from_u16(response.status).context("Invalid status from daemon")?;
+                return Ok((status, response.body));
+            }
+        }
+
+        let response = self.send_paced(url, request).await?;
+        let status = response.status();
+        let body = response.text().await.context("Failed to read response body")?;
+        Ok((status, body))
+    }
+
+    /// POST a chat request directly, retrying and queueing it while rate limited
+    async fn send_paced(&self, url: &str, request: &ChatRequest) -> Result<reqwest::Response> {
+        let build = || {
+            let mut request_builder = self
+                .client
+                .post(url)
+                .header("Content-Type", "application/json")
+                .json(request);
+            if let Some(key) = &self.api_key {
+                request_builder = request_builder.header("Authorization", format!("Bearer {}", key));
+            }
+            async move { request_builder.send().await.context(SEND_FAILED) }
+        };
+        let mut queued = false;
+        let response = self
+            .pacer
+            .send(&self.rate_limit, build, &mut |ahead| {
+                queued = true;
+                ratelimit::report_queued(ahead);
+            })
+            .await;
+        if queued {
+            ratelimit::clear_status();
+        }
+        response
+    }
+
+    /// Ask for a single command
     pub async fn query(&self, system_prompt: &str, user_query: &str) -> Result<String> {
-        self.query_internal(system_prompt, user_query, false, 1).await
+        self.query_internal(system_prompt, user_query, &[], false, 1).await
     }
 
+    /// Ask for `count` commands (the system prompt should ask for them one per line)
     pub async fn query_multi(&self, system_prompt: &str, user_query: &str, count: usize) -> Result<String> {
-        self.query_internal(system_prompt, user_query, true, count).await
+        self.query_internal(system_prompt, user_query, &[], true, count).await
     }
 
-    async fn query_internal(
+    /// Ask for `count` commands with the answer streamed, calling `on_text` with each piece as it
+    /// arrives (it breaks to stop reading); returns the answer received. Streamed requests are
+    /// always sent directly, never through the daemon
+    pub async fn query_multi_stream(
         &self,
         system_prompt: &str,
         user_query: &str,
-        _multi: bool,
         _count: usize,
+        on_text: &mut dyn FnMut(&str) -> ControlFlow<()>,
     ) -> Result<String> {
+        if self.provider == Provider::Mock {
+            let content = self.fixtures.replay(user_query)?;
+            let _ = on_text(&content);
+            return Ok(content);
+        }
+        if let Some(files) = &self.local {
+            return self.query_local(files, system_prompt, user_query, &[], on_text);
+        }
         let url = format!("{}/chat/completions", self.api_base);
+        if let Some(budget) = &self.budget {
+            budget.check()?;
+        }
 
+        let messages = chat_messages(system_prompt, user_query, &[]);
+        let messages_tokens: usize = messages.iter().map(|message| estimate_tokens(&message.content)).sum();
         let request = ChatRequest {
             model: self.model.clone(),
-            messages: vec![
-                ChatMessage {
-                    role: "system".to_string(),
-                    content: system_prompt.to_string(),
-                },
-                ChatMessage {
-                    role: "user".to_string(),
-                    content: user_query.to_string(),
-                },
-            ],
-            temperature: 0.0,
+            messages,
+            temperature: self.temperature,
+            seed: self.seed,
             max_tokens: self.max_tokens,
+            stream: true,
         };
 
-        log::debug!("Sending request to: {}", url);
+        log::debug!("Streaming request to: {}", url);
         log::debug!("Model: {}", self.model);
         log::debug!("User query: {}", user_query);
 
-        let mut request_builder = self
-            .client
-            .post(&url)
-            .header("Content-Type", "application/json")
-            .json(&request);
+        let mut response = self.send_paced(&url, &request).await?;
 
-        if let Some(key) = &self.api_key {
-            request_builder = request_builder.header("Authorization", format!("Bearer {}", key));
+        let status = response.status();
+        log::debug!("Response status: {}", status);
+        if !status.is_success() {
+            let body = response.text().await.context("Failed to read response body")?;
+            return Err(api_error(status, &body));
         }
 
-        let response = request_builder
-            .send()
+        let mut content = String::new();
+        let mut usage = None;
+        // Bytes of a line not fully received yet, and lines that aren't events (a server
+        // ignoring `stream` answers with the usual JSON body)
+        let mut partial = Vec::new();
+        let mut unstreamed = String::new();
+        let mut events = 0;
+        'read: while let Some(chunk) = response.chunk().await.context("Failed to read response body")? {
+            partial.extend_from_slice(&chunk);
+            while let Some(end) = partial.iter().position(|&byte| byte == b'\n') {
+                let line: Vec<u8> = partial.drain(..=end).collect();
+                let line = String::from_utf8_lossy(&line);
+                let Some(data) = line.trim().strip_prefix("data:") else {
+                    unstreamed.push_str(&line);
+                    continue;
+                };
+                let data = data.trim();
+                if data == "[DONE]" {
+                    break 'read;
+                }
+                events += 1;
+                let chunk: StreamChunk = match serde_json::from_str(data) {
+                    Ok(chunk) => chunk,
+                    Err(e) => {
+                        log::debug!("Skipping unparseable stream event ({}): {}", e, data);
+                        continue;
+                    }
+                };
+                if let Some(error) = chunk.error {
+                    return Err(eyre!("OpenAI API error: {}", error.message));
+                }
+                usage = chunk.usage.or(usage);
+                for choice in chunk.choices {
+                    if let Some(text) = choice.delta.content {
+                        content.push_str(&text);
+                        if on_text(&text).is_break() {
+                            log::debug!("Stopped reading the stream");
+                            break 'read;
+                        }
+                    }
+                }
+            }
+        }
+        unstreamed.push_str(&String::from_utf8_lossy(&partial));
+
+        if events == 0 {
+            log::debug!("Response was not streamed: {}", unstreamed);
+            let response: ChatResponse =
+                serde_json::from_str(&unstreamed).context("Failed to parse OpenAI response")?;
+            content = first_choice(&response)?;
+            let _ = on_text(&content);
+            usage = response.usage;
+        }
+        log::debug!("Streamed response: {}", content);
+
+        let content = content.trim().to_string();
+        self.record_usage(usage, messages_tokens, &content);
+        self.record_fixture(user_query, &content);
+        Ok(content)
+    }
+
+    /// Ask again in the same conversation: the previous answer and a correction follow the query
+    pub async fn query_multi_retry(
+        &self,
+        system_prompt: &str,
+        user_query: &str,
+        count: usize,
+        previous: &str,
+        correction: &str,
+    ) -> Result<String> {
+        let followup = [("assistant", previous), ("user", correction)];
+        self.query_internal(system_prompt, user_query, &followup, true, count)
             .await
-            .context("Failed to send request to OpenAI API")?;
+    }
 
-        let status = response.status();
-        let body = response.text().await.context("Failed to read response body")?;
+    async fn query_internal(
+        &self,
+        system_prompt: &str,
+        user_query: &str,
+        followup: &[(&str, &str)],
+        _multi: bool,
+        _count: usize,
+    ) -> Result<String> {
+        // Fixtures answer the last user message
+        let fixture_query = followup
+            .iter()
+            .rev()
+            .find(|(role, _)| *role == "user")
+            .map_or(user_query, |(_, content)| *content);
+        if self.provider == Provider::Mock {
+            return self.fixtures.replay(fixture_query);
+        }
+        if let Some(files) = &self.local {
+            return self.query_local(files, system_prompt, user_query, followup, &mut |_| {
+                ControlFlow::Continue(())
+            });
+        }
+        let url = format!("{}/chat/completions", self.api_base);
+        if let Some(budget) = &self.budget {
+            budget.check()?;
+        }
+
+        let messages = chat_messages(system_prompt, user_query, followup);
+        let messages_tokens: usize = messages.iter().map(|message| estimate_tokens(&message.content)).sum();
+
+        let request = ChatRequest {
+            model: self.model.clone(),
+            messages,
+            temperature: self.temperature,
+            seed: self.seed,
+            max_tokens: self.max_tokens,
+            stream: false,
+        };
+
+        log::debug!("Sending request to: {}", url);
+        log::debug!("Model: {}", self.model);
+        log::debug!("User query: {}", user_query);
+
+        let (status, body) = self.post(&url, &request).await?;
 
         log::debug!("Response status: {}", status);
         log::debug!("Response body: {}", body);
 
         if !status.is_success() {
-            // Try to parse error response
-            if let Ok(error) = serde_json::from_str::<ErrorResponse>(&body) {
-                return Err(eyre!("OpenAI API error: {}", error.error.message));
-            }
-            return Err(eyre!("OpenAI API error ({}): {}", status, body));
+            return Err(api_error(status, &body));
         }
 
         let response: ChatResponse = serde_json::from_str(&body).context("Failed to parse OpenAI response")?;
 
-        let content = response
-            .choices
-            .first()
-            .ok_or_else(|| eyre!("No response from OpenAI"))?
-            .message
-            .content
-            .trim()
-            .to_string();
+        let content = first_choice(&response)?.trim().to_string();
+        self.record_usage(response.usage, messages_tokens, &content);
+        self.record_fixture(fixture_query, &content);
 
         Ok(content)
     }
+
+    /// Answer with the local model; it runs on this thread, as queries are one per process
+    fn query_local(
+        &self,
+        files: &LocalModelFiles,
+        system_prompt: &str,
+        user_query: &str,
+        followup: &[(&str, &str)],
+        on_text: &mut dyn FnMut(&str) -> ControlFlow<()>,
+    ) -> Result<String> {
+        let turns: Vec<(&str, &str)> = std::iter::once(("user", user_query))
+            .chain(followup.iter().copied())
+            .collect();
+        let sampling = Sampling {
+            max_tokens: self.max_tokens,
+            temperature: self.temperature,
+            seed: self.seed,
+        };
+        let (content, usage) = local::generate(files, system_prompt, &turns, sampling, on_text)?;
+        let content = content.trim().to_string();
+        log::debug!("Local model response: {}", content);
+        self.record_usage(
+            Some(ChatUsage {
+                prompt_tokens: usage.prompt_tokens,
+                completion_tokens: usage.completion_tokens,
+            }),
+            0,
+            &content,
+        );
+        Ok(content)
+    }
+
+    /// With `provider: record`, save an answer as the fixture for its user message
+    fn record_fixture(&self, query: &str, content: &str) {
+        if self.provider != Provider::Record {
+            return;
+        }
+        match self.fixtures.record(query, &self.model, content) {
+            Ok(path) => log::debug!("Recorded fixture {}", path.display()),
+            Err(e) => log::warn!("Failed to record fixture: {:#}", e),
+        }
+    }
+
+    /// Record a query's tokens against the budget, estimating when the server doesn't report usage
+    fn record_usage(&self, usage: Option<ChatUsage>, messages_tokens: usize, content: &str) {
+        let usage = match usage {
+            Some(usage) => TokenUsage {
+                prompt_tokens: usage.prompt_tokens,
+                completion_tokens: usage.completion_tokens,
+            },
+            None => TokenUsage {
+                prompt_tokens: messages_tokens as u64,
+                completion_tokens: estimate_tokens(content) as u64,
+            },
+        };
+        // Shown on the log lines of the query span, when there is one
+        let span = tracing::Span::current();
+        span.record("prompt_tokens", usage.prompt_tokens);
+        span.record("completion_tokens", usage.completion_tokens);
+        self.tokens_used.fetch_add(usage.total(), Ordering::Relaxed);
+
+        if let Some(budget) = &self.budget
+            && let Err(e) = budget.record(usage)
+        {
+            log::warn!("Failed to record token usage: {}", e);
+        }
+    }
+}
+
+/// The system prompt, the query, then any follow-up turns
+fn chat_messages(system_prompt: &str, user_query: &str, followup: &[(&str, &str)]) -> Vec<ChatMessage> {
+    let mut messages = vec![
+        ChatMessage {
+            role: "system".to_string(),
+            content: system_prompt.to_string(),
+        },
+        ChatMessage {
+            role: "user".to_string(),
+            content: user_query.to_string(),
+        },
+    ];
+    messages.extend(followup.iter().map(|(role, content)| ChatMessage {
+        role: role.to_string(),
+        content: content.to_string(),
+    }));
+    messages
+}
+
+/// Context of errors sending a request, the API never answering it
+pub(crate) const SEND_FAILED: &str = "Failed to send request to OpenAI API";
+
+/// Whether a query failed because the API couldn't be reached (no network, DNS failure, refused
+/// connection, timeout), sent directly or through the daemon
+pub fn is_unreachable(error: &eyre::Report) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<reqwest::Error>()
+            .is_some_and(|e| e.is_connect() || e.is_timeout())
+            || cause.to_string().starts_with(SEND_FAILED)
+    })
+}
+
+/// Error for a failed request, with the API's message when the body has one
+fn api_error(status: StatusCode, body: &str) -> eyre::Report {
+    match serde_json::from_str::<ErrorResponse>(body) {
+        Ok(error) => eyre!("OpenAI API error: {}", error.error.message),
+        Err(_) => eyre!("OpenAI API error ({}): {}", status, body),
+    }
+}
+
+/// Content of the first choice of a response
+fn first_choice(response: &ChatResponse) -> Result<String> {
+    Ok(response
+        .choices
+        .first()
+        .ok_or_else(|| eyre!("No response from OpenAI"))?
+        .message
+        .content
+        .clone())
 }
 
 /// Validate API key using config
 pub async fn validate_api_key_from_config(config: &Config) -> std::result::Result<(), ApiValidationError> {
+    if matches!(config.provider, Provider::Mock | Provider::Local) {
+        return Ok(());
+    }
     let api_key = match config.get_api_key() {
         Some(key) => key,
         None if config.allow_no_api_key => return Ok(()),
@@ -293,7 +745,7 @@ pub async fn validate_api_key_from_config(config: &Config) -> std::result::Resul
 mod tests {
     use super::*;
     use crate::config::Config;
-    use wiremock::matchers::{header, method, path};
+    use wiremock::matchers::{body_string_contains, header, method, path};
     use wiremock::{Mock, MockServer, ResponseTemplate};
 
     fn create_success_response(content: &str) -> String {
@@ -320,6 +772,190 @@ mod tests {
         )
     }
 
+    #[tokio::test]
+    async fn test_query_multi_retry_sends_conversation() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_string_contains(r#""role":"assistant","content":"ls""#))
+            .and(body_string_contains("3 were requested"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(r#"{"choices": [{"message": {"content": "ls\nls -la\nls -lh"}}]}"#),
+            )
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let client = OpenAIClient::new_with_base(
+            "test-api-key".to_string(),
+            mock_server.uri(),
+            "gpt-4o-mini".to_string(),
+            100,
+            30,
+        )
+        .unwrap();
+
+        let result = client
+            .query_multi_retry("system", "list files", 3, "ls", "1 command, but 3 were requested")
+            .await
+            .unwrap();
+        assert_eq!(result, "ls\nls -la\nls -lh");
+    }
+
+    #[tokio::test]
+    #[cfg(all(feature = "daemon", unix))]
+    async fn test_query_through_daemon() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(header("Authorization", "Bearer test-api-key"))
+            .and(body_string_contains("list files"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(create_success_response("ls -la")))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("qai.sock");
+        let client = || {
+            OpenAIClient::new_with_base(
+                "test-api-key".to_string(),
+                mock_server.uri(),
+                "gpt-4o-mini".to_string(),
+                100,
+                30,
+            )
+            .unwrap()
+            .with_daemon(socket.clone())
+        };
+
+        // No daemon listening: sent directly
+        assert_eq!(client().query("system", "list files").await.unwrap(), "ls -la");
+
+        // A daemon answers in its place
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+        let listener = tokio::net::UnixListener::bind(&socket).unwrap();
+        let reply = serde_json::to_string(&daemon::DaemonResponse {
+            status: 200,
+            body: create_success_response("ls -la --from-daemon"),
+            error: None,
+            queued: None,
+        })
+        .unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut line = String::new();
+            BufReader::new(reader).read_line(&mut line).await.unwrap();
+            let request: DaemonRequest = serde_json::from_str(&line).unwrap();
+            assert_eq!(request.api_key.as_deref(), Some("test-api-key"));
+            assert_eq!(request.timeout_secs, 30);
+            writer.write_all(format!("{}\n", reply).as_bytes()).await.unwrap();
+        });
+
+        let result = client().query("system", "list files").await.unwrap();
+        assert_eq!(result, "ls -la --from-daemon");
+    }
+
+    #[tokio::test]
+    async fn test_record_then_replay_fixtures() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(create_success_response("ls -la")))
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let fixtures = Fixtures::new(dir.path().to_path_buf());
+        let client = |provider| {
+            OpenAIClient::new_with_base(
+                "test-api-key".to_string(),
+                mock_server.uri(),
+                "gpt-4o-mini".to_string(),
+                100,
+                30,
+            )
+            .unwrap()
+            .with_fixtures(provider, fixtures.clone())
+        };
+
+        let recorder = client(Provider::Record);
+        assert_eq!(recorder.query("system", "list files").await.unwrap(), "ls -la");
+        let streamed = recorder
+            .query_multi_stream("system", "list all files", 3, &mut |_| ControlFlow::Continue(()))
+            .await
+            .unwrap();
+        assert_eq!(streamed, "ls -la");
+        assert_eq!(fixtures.load().unwrap().len(), 2);
+
+        // Replayed without a request (the server expects only the two recorded ones)
+        let mock = client(Provider::Mock);
+        assert_eq!(mock.query("other system prompt", "list files").await.unwrap(), "ls -la");
+        let mut pieces = Vec::new();
+        let replayed = mock
+            .query_multi_stream("system", "list all files", 3, &mut |piece| {
+                pieces.push(piece.to_string());
+                ControlFlow::Continue(())
+            })
+            .await
+            .unwrap();
+        assert_eq!((replayed.as_str(), pieces.len()), ("ls -la", 1));
+        assert!(mock.query("system", "unrecorded").await.is_err());
+        assert!(mock.validate_api_key().await.is_ok());
+    }
+
+    #[test]
+    fn test_mock_provider_needs_no_api_key() {
+        let config = Config {
+            api_key: None,
+            api_key_cmd: None,
+            provider: Provider::Mock,
+            ..Default::default()
+        };
+        // QAI_API_KEY may be set where the tests run, so only the mock case is checked
+        assert!(OpenAIClient::new(&config).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_query_records_budget_usage() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"choices": [{"message": {"content": "ls"}}], "usage": {"prompt_tokens": 80, "completion_tokens": 20}}"#,
+            ))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let config = crate::config::BudgetConfig {
+            daily_tokens: Some(100),
+            ..Default::default()
+        };
+        let budget = || Budget::new(config.clone(), "gpt-4o-mini", dir.path().join("usage.json"));
+        let client = OpenAIClient::new_with_base(
+            "test-api-key".to_string(),
+            mock_server.uri(),
+            "gpt-4o-mini".to_string(),
+            100,
+            30,
+        )
+        .unwrap()
+        .with_budget(budget());
+
+        assert_eq!(client.query("system", "list files").await.unwrap(), "ls");
+        assert_eq!(budget().today().tokens, 100);
+        assert_eq!(client.tokens_used(), 100);
+
+        // The budget is used up: refused without another request (the mock expects exactly one)
+        let err = client.query("system", "list files").await.unwrap_err();
+        assert!(err.to_string().contains("Daily budget exceeded"));
+    }
+
     #[tokio::test]
     async fn test_query_success() {
         let mock_server = MockServer::start().await;
@@ -520,6 +1156,34 @@ mod tests {
         assert!(result.unwrap_err().to_string().contains("Rate limit"));
     }
 
+    #[tokio::test]
+    async fn test_query_retries_rate_limit() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(
+                ResponseTemplate::new(429)
+                    .insert_header("Retry-After", "0")
+                    .set_body_string(create_error_response("Rate limit exceeded")),
+            )
+            .up_to_n_times(2)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(create_success_response("ls -la")))
+            .mount(&mock_server)
+            .await;
+
+        let client =
+            OpenAIClient::new_with_base("key".to_string(), mock_server.uri(), "gpt-4o-mini".to_string(), 500, 30)
+                .unwrap()
+                .with_rate_limit(RateLimitConfig::default());
+
+        assert_eq!(client.query("system", "query").await.unwrap(), "ls -la");
+        assert_eq!(mock_server.received_requests().await.unwrap().len(), 3);
+    }
+
     #[test]
     fn test_new_with_base_works() {
         let result = OpenAIClient::new_with_base(
@@ -547,9 +1211,49 @@ mod tests {
         assert_eq!(client.api_base, "https://custom.api.com/v1");
         assert_eq!(client.model, "gpt-4o");
         assert_eq!(client.max_tokens, 500);
+        #[cfg(all(feature = "daemon", unix))]
         assert_eq!(client.http_timeout_secs, 30);
     }
 
+    #[test]
+    fn test_client_uses_query_config() {
+        let mut config = Config {
+            allow_no_api_key: true,
+            max_tokens: 800,
+            ..Config::default()
+        };
+        let client = OpenAIClient::new(&config).unwrap();
+        assert_eq!(client.temperature, 0.0);
+        assert_eq!(client.max_tokens, 800);
+        assert_eq!(client.seed, None);
+
+        config.query.temperature = 0.7;
+        config.query.seed = Some(7);
+        config.query.max_tokens = Some(200);
+        let client = OpenAIClient::new(&config).unwrap();
+        assert_eq!(client.temperature, 0.7);
+        assert_eq!(client.seed, Some(7));
+        assert_eq!(client.max_tokens, 200);
+    }
+
+    #[test]
+    fn test_chat_request_seed() {
+        let request = ChatRequest {
+            model: "gpt-4o-mini".to_string(),
+            messages: Vec::new(),
+            temperature: 0.0,
+            seed: None,
+            max_tokens: 100,
+            stream: false,
+        };
+        assert!(!serde_json::to_string(&request).unwrap().contains("seed"));
+        let request = ChatRequest {
+            seed: Some(42),
+            ..request
+        };
+        assert!(serde_json::to_string(&request).unwrap().contains(r#""seed":42"#));
+    }
+
     // API validation tests
 
     #[tokio::test]
@@ -640,6 +1344,22 @@ mod tests {
         assert!(err.to_string().contains("Unexpected"));
     }
 
+    #[tokio::test]
+    async fn test_validate_unreachable_api() {
+        let client = OpenAIClient::new_with_base(
+            "key".to_string(),
+            "http://127.0.0.1:1".to_string(),
+            "gpt-4o-mini".to_string(),
+            500,
+            5,
+        )
+        .unwrap();
+
+        let err = client.validate_api_key().await.unwrap_err();
+        assert!(err.is_unreachable());
+        assert!(!ApiValidationError::InvalidKey("bad key".to_string()).is_unreachable());
+    }
+
     #[tokio::test]
     async fn test_query_multi_works() {
         let mock_server = MockServer::start().await;
@@ -663,6 +1383,97 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    fn stream_client(uri: String) -> OpenAIClient {
+        OpenAIClient::new_with_base("key".to_string(), uri, "gpt-4o-mini".to_string(), 500, 30).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_query_multi_stream() {
+        let mock_server = MockServer::start().await;
+        let events = [
+            r#"data: {"choices":[{"delta":{"role":"assistant"}}]}"#,
+            r#"data: {"choices":[{"delta":{"content":"ls -la\n"}}]}"#,
+            r#"data: {"choices":[{"delta":{"content":"ls"}}]}"#,
+            r#"data: {"choices":[{"delta":{"content":" -lh\n"}}]}"#,
+            "data: [DONE]",
+        ];
+        let body = events.map(|event| format!("{}\n\n", event)).concat();
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_string_contains(r#""stream":true"#))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"))
+            .mount(&mock_server)
+            .await;
+
+        let mut pieces = Vec::new();
+        let result = stream_client(mock_server.uri())
+            .query_multi_stream("system", "list files", 2, &mut |piece| {
+                pieces.push(piece.to_string());
+                ControlFlow::Continue(())
+            })
+            .await
+            .unwrap();
+        assert_eq!(pieces, vec!["ls -la\n", "ls", " -lh\n"]);
+        assert_eq!(result, "ls -la\nls -lh");
+
+        // Reading stops when the caller breaks
+        let result = stream_client(mock_server.uri())
+            .query_multi_stream("system", "list files", 2, &mut |_| ControlFlow::Break(()))
+            .await
+            .unwrap();
+        assert_eq!(result, "ls -la");
+    }
+
+    #[tokio::test]
+    async fn test_query_multi_stream_unstreamed_answer() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(create_success_response("ls -la\\nls")))
+            .mount(&mock_server)
+            .await;
+
+        let mut pieces = Vec::new();
+        let result = stream_client(mock_server.uri())
+            .query_multi_stream("system", "list files", 2, &mut |piece| {
+                pieces.push(piece.to_string());
+                ControlFlow::Continue(())
+            })
+            .await
+            .unwrap();
+        assert_eq!(pieces, vec!["ls -la\nls"]);
+        assert_eq!(result, "ls -la\nls");
+    }
+
+    #[tokio::test]
+    async fn test_query_multi_stream_errors() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(429).set_body_string(create_error_response("Rate limit exceeded")))
+            .mount(&mock_server)
+            .await;
+        let result = stream_client(mock_server.uri())
+            .query_multi_stream("system", "list files", 2, &mut |_| ControlFlow::Continue(()))
+            .await;
+        assert!(result.unwrap_err().to_string().contains("Rate limit exceeded"));
+
+        let mock_server = MockServer::start().await;
+        let body = concat!(
+            "data: {\"choices\":[{\"delta\":{\"content\":\"ls\"}}]}\n\n",
+            "data: {\"error\":{\"message\":\"overloaded\"}}\n\n"
+        );
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"))
+            .mount(&mock_server)
+            .await;
+        let result = stream_client(mock_server.uri())
+            .query_multi_stream("system", "list files", 2, &mut |_| ControlFlow::Continue(()))
+            .await;
+        assert!(result.unwrap_err().to_string().contains("overloaded"));
+    }
+
     #[tokio::test]
     async fn test_validate_api_key_unexpected_status() {
         let mock_server = MockServer::start().await;
@@ -0,0 +1,232 @@
+//! Audit log of executed AI-suggested commands
+//!
+//! When `audit.enabled` is set, every AI-generated command that is actually run
+//! (as reported by the shell integration) is appended to a JSON Lines file,
+//! separate from history: it is never rewritten or cleared by qai, and can be
+//! exported as JSON Lines or CSV with `qai audit export`.
+
+use chrono::{DateTime, Utc};
+use eyre::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// One executed AI-suggested command
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub timestamp: DateTime<Utc>,
+    pub user: String,
+    pub hostname: String,
+    /// Directory the command ran in
+    pub cwd: Option<PathBuf>,
+    /// The query that produced the suggestion
+    pub query: String,
+    /// The command as qai suggested it
+    pub suggested: String,
+    /// The command line that actually ran (differs from `suggested` if edited)
+    pub command: String,
+    pub exit_code: i32,
+    pub model: String,
+}
+
+/// CSV columns, in export order
+const CSV_HEADER: &str = "timestamp,user,hostname,cwd,query,suggested,command,exit_code,model";
+
+impl AuditEntry {
+    /// Render as one CSV row (RFC 4180 quoting)
+    pub fn to_csv(&self) -> String {
+        let cwd = self
+            .cwd
+            .as_ref()
+            .map(|cwd| cwd.display().to_string())
+            .unwrap_or_default();
+        [
+            self.timestamp.to_rfc3339(),
+            self.user.clone(),
+            self.hostname.clone(),
+            cwd,
+            self.query.clone(),
+            self.suggested.clone(),
+            self.command.clone(),
+            self.exit_code.to_string(),
+            self.model.clone(),
+        ]
+        .iter()
+        .map(|field| csv_field(field))
+        .collect::<Vec<_>>()
+        .join(",")
+    }
+}
+
+/// Quote a CSV field if it contains a separator, quote, or line break
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Export format of `qai audit export`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+    Jsonl,
+    Csv,
+}
+
+/// Append-only audit log file
+#[derive(Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+}
+
+impl AuditLog {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Get the default audit log path
+    pub fn default_path() -> PathBuf {
+        crate::paths::data_dir().join("audit.jsonl")
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append an entry; the file is created readable by the owner only
+    pub fn append(&self, entry: &AuditEntry) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).context("Failed to create audit log directory")?;
+        }
+        let mut options = OpenOptions::new();
+        options.create(true).append(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&self.path).context("Failed to open audit log")?;
+
+        let json = serde_json::to_string(entry).context("Failed to serialize audit entry")?;
+        writeln!(file, "{}", json).context("Failed to write audit log")?;
+        Ok(())
+    }
+
+    /// All entries, oldest first, optionally only those at or after `since`
+    pub fn entries(&self, since: Option<DateTime<Utc>>) -> Result<Vec<AuditEntry>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let file = File::open(&self.path).context("Failed to open audit log")?;
+        Ok(BufReader::new(file)
+            .lines()
+            .map_while(|line| line.ok())
+            .filter_map(|line| serde_json::from_str::<AuditEntry>(&line).ok())
+            .filter(|entry| since.is_none_or(|since| entry.timestamp >= since))
+            .collect())
+    }
+
+    /// Write the (filtered) log to `out` in the given format
+    pub fn export(&self, format: ExportFormat, since: Option<DateTime<Utc>>, out: &mut impl Write) -> Result<()> {
+        let entries = self.entries(since)?;
+        if format == ExportFormat::Csv {
+            writeln!(out, "{}", CSV_HEADER)?;
+        }
+        for entry in entries {
+            match format {
+                ExportFormat::Jsonl => writeln!(out, "{}", serde_json::to_string(&entry)?)?,
+                ExportFormat::Csv => writeln!(out, "{}", entry.to_csv())?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn entry(command: &str, exit_code: i32) -> AuditEntry {
+        AuditEntry {
+            timestamp: Utc::now(),
+            user: "alice".to_string(),
+            hostname: "box".to_string(),
+            cwd: Some(PathBuf::from("/srv/app")),
+            query: "list files".to_string(),
+            suggested: "ls -la".to_string(),
+            command: command.to_string(),
+            exit_code,
+            model: "gpt-4o-mini".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_audit_log_append_and_read() {
+        let dir = TempDir::new().unwrap();
+        let log = AuditLog::new(dir.path().join("nested").join("audit.jsonl"));
+        assert!(log.entries(None).unwrap().is_empty());
+
+        log.append(&entry("ls -la", 0)).unwrap();
+        log.append(&entry("ls -la /tmp", 2)).unwrap();
+
+        let entries = log.entries(None).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].command, "ls -la /tmp");
+        assert_eq!(entries[1].exit_code, 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_audit_log_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new().unwrap();
+        let log = AuditLog::new(dir.path().join("audit.jsonl"));
+        log.append(&entry("ls", 0)).unwrap();
+        let mode = fs::metadata(log.path()).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn test_audit_log_since() {
+        let dir = TempDir::new().unwrap();
+        let log = AuditLog::new(dir.path().join("audit.jsonl"));
+        let mut old = entry("old", 0);
+        old.timestamp = "2024-01-01T00:00:00Z".parse().unwrap();
+        log.append(&old).unwrap();
+        log.append(&entry("new", 0)).unwrap();
+
+        let since = "2025-01-01T00:00:00Z".parse().unwrap();
+        let entries = log.entries(Some(since)).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].command, "new");
+    }
+
+    #[test]
+    fn test_audit_export_csv() {
+        let dir = TempDir::new().unwrap();
+        let log = AuditLog::new(dir.path().join("audit.jsonl"));
+        log.append(&entry(r#"echo "a, b""#, 0)).unwrap();
+
+        let mut out = Vec::new();
+        log.export(ExportFormat::Csv, None, &mut out).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], CSV_HEADER);
+        assert!(lines[1].contains(",alice,box,/srv/app,list files,ls -la,"));
+        assert!(lines[1].ends_with(r#","echo ""a, b""",0,gpt-4o-mini"#));
+    }
+
+    #[test]
+    fn test_audit_export_jsonl() {
+        let dir = TempDir::new().unwrap();
+        let log = AuditLog::new(dir.path().join("audit.jsonl"));
+        log.append(&entry("ls", 0)).unwrap();
+
+        let mut out = Vec::new();
+        log.export(ExportFormat::Jsonl, None, &mut out).unwrap();
+        let line = String::from_utf8(out).unwrap();
+        let parsed: AuditEntry = serde_json::from_str(line.trim()).unwrap();
+        assert_eq!(parsed.user, "alice");
+    }
+}
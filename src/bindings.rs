@@ -102,7 +102,7 @@ static KEY_MAP: LazyLock<HashMap<&'static str, &'static str>> = LazyLock::new(||
 ///
 /// # Examples
 /// ```
-/// use qai::keys::key_name_to_sequence;
+/// use qai::bindings::key_name_to_sequence;
 ///
 /// assert_eq!(key_name_to_sequence("tab").unwrap(), "^I");
 /// assert_eq!(key_name_to_sequence("ctrl-space").unwrap(), "^@");
@@ -0,0 +1,377 @@
+//! Daily token and cost budget
+//!
+//! Tracks how many tokens (and, with known prices, how many dollars) the API
+//! calls of the current day consumed, persisted in the data directory, so
+//! `budget.daily-tokens` / `budget.daily-usd` can refuse or warn once a day's
+//! budget is used up. Before that, crossing one of the `budget.warn-at`
+//! percentages prints a one-line warning.
+
+use chrono::{Local, NaiveDate};
+use eyre::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::{BudgetConfig, OnExceed};
+use crate::lock;
+
+/// Built-in prices in USD per million (input, output) tokens, matched by model-name prefix
+/// Longer prefixes come first so "gpt-4o-mini" isn't priced as "gpt-4o"
+const PRICES: &[(&str, f64, f64)] = &[
+    ("gpt-4.1-nano", 0.10, 0.40),
+    ("gpt-4.1-mini", 0.40, 1.60),
+    ("gpt-4.1", 2.00, 8.00),
+    ("gpt-4o-mini", 0.15, 0.60),
+    ("gpt-4o", 2.50, 10.00),
+    ("gpt-3.5-turbo", 0.50, 1.50),
+];
+
+/// Tokens reported for one API call
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl TokenUsage {
+    pub fn total(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+/// Consumption of one day
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DailyUsage {
+    pub date: NaiveDate,
+    pub tokens: u64,
+    pub usd: f64,
+    pub requests: u64,
+}
+
+impl DailyUsage {
+    fn empty(date: NaiveDate) -> Self {
+        Self {
+            date,
+            tokens: 0,
+            usd: 0.0,
+            requests: 0,
+        }
+    }
+}
+
+/// Persisted usage counter enforcing a [`BudgetConfig`]
+#[derive(Debug)]
+pub struct Budget {
+    config: BudgetConfig,
+    model: String,
+    path: PathBuf,
+}
+
+impl Budget {
+    pub fn new(config: BudgetConfig, model: &str, path: PathBuf) -> Self {
+        Self {
+            config,
+            model: model.to_string(),
+            path,
+        }
+    }
+
+    /// Get the default usage file path
+    pub fn default_path() -> PathBuf {
+        crate::paths::data_dir().join("usage.json")
+    }
+
+    /// Usage so far today (a new day starts from zero)
+    pub fn today(&self) -> DailyUsage {
+        let today = Local::now().date_naive();
+        fs::read_to_string(&self.path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<DailyUsage>(&content).ok())
+            .filter(|usage| usage.date == today)
+            .unwrap_or_else(|| DailyUsage::empty(today))
+    }
+
+    /// Price in USD per million (input, output) tokens: configured, else built in for the model
+    pub fn prices(&self) -> Option<(f64, f64)> {
+        match (self.config.input_usd_per_mtok, self.config.output_usd_per_mtok) {
+            (Some(input), Some(output)) => Some((input, output)),
+            _ => PRICES
+                .iter()
+                .find(|(prefix, _, _)| self.model.starts_with(prefix))
+                .map(|&(_, input, output)| (input, output)),
+        }
+    }
+
+    /// Check today's usage before a request: Err when over budget with `on-exceed: refuse`,
+    /// a warning on stderr with `warn`
+    pub fn check(&self) -> Result<()> {
+        let usage = self.today();
+        let mut exceeded = Vec::new();
+        if let Some(limit) = self.config.daily_tokens
+            && usage.tokens >= limit
+        {
+            exceeded.push(format!("{} of {} tokens", usage.tokens, limit));
+        }
+        if let Some(limit) = self.config.daily_usd
+            && usage.usd >= limit
+        {
+            exceeded.push(format!("${:.4} of ${:.2}", usage.usd, limit));
+        }
+        if exceeded.is_empty() {
+            return Ok(());
+        }
+
+        let message = format!("Daily budget exceeded: used {} today", exceeded.join(" and "));
+        match self.config.on_exceed {
+            OnExceed::Refuse => Err(eyre::eyre!("{} (resets at midnight; see budget in qai.yml)", message)),
+            OnExceed::Warn => {
+                eprintln!("# ⚠ {}", message);
+                Ok(())
+            }
+        }
+    }
+
+    /// Share of the daily budget used: the larger of the token and dollar shares, for the
+    /// limits that are set
+    pub fn used_fraction(&self, usage: &DailyUsage) -> Option<f64> {
+        let tokens = self
+            .config
+            .daily_tokens
+            .map(|limit| usage.tokens as f64 / limit.max(1) as f64);
+        let usd = self
+            .config
+            .daily_usd
+            .map(|limit| if limit > 0.0 { usage.usd / limit } else { 1.0 });
+        tokens.into_iter().chain(usd).reduce(f64::max)
+    }
+
+    /// Usage against each limit that is set, e.g. "24000 of 200000 tokens and $0.0100 of $0.50"
+    pub fn describe(&self, usage: &DailyUsage) -> String {
+        let mut parts = Vec::new();
+        if let Some(limit) = self.config.daily_tokens {
+            parts.push(format!("{} of {} tokens", usage.tokens, limit));
+        }
+        if let Some(limit) = self.config.daily_usd {
+            parts.push(format!("${:.4} of ${:.2}", usage.usd, limit));
+        }
+        parts.join(" and ")
+    }
+
+    /// The warning for the highest `warn-at` percentage that usage went past from `before` to
+    /// `after`, if any; past 100%, [`Budget::check`] takes over
+    pub fn alert(&self, before: &DailyUsage, after: &DailyUsage) -> Option<String> {
+        let (before_used, after_used) = (self.used_fraction(before)?, self.used_fraction(after)?);
+        let crossed = self
+            .config
+            .warn_at
+            .iter()
+            .filter(|&&percent| percent < 100)
+            .filter(|&&percent| {
+                let threshold = f64::from(percent) / 100.0;
+                before_used < threshold && after_used >= threshold
+            })
+            .max()?;
+        Some(format!("{}% of daily budget used ({})", crossed, self.describe(after)))
+    }
+
+    /// Add a request's tokens (and their cost, if the price is known) to today's usage, with a
+    /// warning on stderr when that crosses a `warn-at` percentage
+    pub fn record(&self, tokens: TokenUsage) -> Result<DailyUsage> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).context("Failed to create usage directory")?;
+        }
+        // Other shells record their requests too: read and write today's usage under the lock
+        let _lock = lock::acquire(&self.path.with_extension("lock"), "usage file")?;
+        let before = self.today();
+        let mut usage = before.clone();
+        usage.tokens += tokens.total();
+        usage.requests += 1;
+        match self.prices() {
+            Some((input, output)) => {
+                usage.usd += (tokens.prompt_tokens as f64 * input + tokens.completion_tokens as f64 * output) / 1e6
+            }
+            None if self.config.daily_usd.is_some() => {
+                log::warn!(
+                    "No price known for model {}; set budget.input-usd-per-mtok and output-usd-per-mtok",
+                    self.model
+                );
+            }
+            None => {}
+        }
+        save(&self.path, &usage)?;
+        if let Some(alert) = self.alert(&before, &usage) {
+            eprintln!("# ⚠ {}", alert);
+        }
+        Ok(usage)
+    }
+}
+
+fn save(path: &Path, usage: &DailyUsage) -> Result<()> {
+    let content = serde_json::to_string_pretty(usage).context("Failed to serialize usage")?;
+    // Write then rename, so a reader never sees a partly written file and starts the day over
+    let tmp = path.with_extension(format!("json.{}.tmp", std::process::id()));
+    fs::write(&tmp, content).context("Failed to write usage file")?;
+    fs::rename(&tmp, path).context("Failed to replace usage file")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn budget(dir: &TempDir, config: BudgetConfig, model: &str) -> Budget {
+        Budget::new(config, model, dir.path().join("usage.json"))
+    }
+
+    fn tokens(prompt_tokens: u64, completion_tokens: u64) -> TokenUsage {
+        TokenUsage {
+            prompt_tokens,
+            completion_tokens,
+        }
+    }
+
+    #[test]
+    fn test_budget_record_accumulates() {
+        let dir = TempDir::new().unwrap();
+        let budget = budget(&dir, BudgetConfig::default(), "gpt-4o-mini");
+        assert_eq!(budget.today().tokens, 0);
+
+        budget.record(tokens(1000, 200)).unwrap();
+        let usage = budget.record(tokens(500, 100)).unwrap();
+        assert_eq!(usage.tokens, 1800);
+        assert_eq!(usage.requests, 2);
+        // 1500 input at $0.15/M + 300 output at $0.60/M
+        assert!((usage.usd - 0.000405).abs() < 1e-9);
+        assert_eq!(budget.today(), usage);
+    }
+
+    #[test]
+    fn test_budget_concurrent_records_all_count() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("usage.json");
+        let shells: Vec<_> = (0..8)
+            .map(|_| {
+                let budget = Budget::new(BudgetConfig::default(), "gpt-4o-mini", path.clone());
+                std::thread::spawn(move || {
+                    for _ in 0..10 {
+                        budget.record(tokens(100, 10)).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for shell in shells {
+            shell.join().unwrap();
+        }
+
+        let usage = budget(&dir, BudgetConfig::default(), "gpt-4o-mini").today();
+        assert_eq!(usage.requests, 80);
+        assert_eq!(usage.tokens, 8800);
+    }
+
+    #[test]
+    fn test_budget_resets_on_a_new_day() {
+        let dir = TempDir::new().unwrap();
+        let budget = budget(&dir, BudgetConfig::default(), "gpt-4o-mini");
+        let yesterday = DailyUsage {
+            date: Local::now().date_naive().pred_opt().unwrap(),
+            tokens: 99_999,
+            usd: 5.0,
+            requests: 10,
+        };
+        save(&budget.path, &yesterday).unwrap();
+        assert_eq!(budget.today().tokens, 0);
+    }
+
+    #[test]
+    fn test_budget_check_tokens() {
+        let dir = TempDir::new().unwrap();
+        let config = BudgetConfig {
+            daily_tokens: Some(1000),
+            ..Default::default()
+        };
+        let budget = budget(&dir, config, "gpt-4o-mini");
+        assert!(budget.check().is_ok());
+
+        budget.record(tokens(900, 100)).unwrap();
+        let err = budget.check().unwrap_err();
+        assert!(err.to_string().contains("1000 of 1000 tokens"));
+    }
+
+    #[test]
+    fn test_budget_check_usd_and_warn() {
+        let dir = TempDir::new().unwrap();
+        let config = BudgetConfig {
+            daily_usd: Some(0.01),
+            on_exceed: OnExceed::Warn,
+            input_usd_per_mtok: Some(10_000.0),
+            output_usd_per_mtok: Some(10_000.0),
+            ..Default::default()
+        };
+        let budget = budget(&dir, config, "local-model");
+        budget.record(tokens(1, 0)).unwrap();
+        // Over budget, but only warned about
+        assert!(budget.check().is_ok());
+    }
+
+    #[test]
+    fn test_budget_alert_when_crossing_a_threshold() {
+        let dir = TempDir::new().unwrap();
+        let config = BudgetConfig {
+            daily_tokens: Some(1000),
+            warn_at: vec![50, 80],
+            ..Default::default()
+        };
+        let budget = budget(&dir, config, "gpt-4o-mini");
+        let usage = |tokens| DailyUsage {
+            tokens,
+            ..DailyUsage::empty(Local::now().date_naive())
+        };
+        assert_eq!(budget.alert(&usage(0), &usage(400)), None);
+        assert_eq!(
+            budget.alert(&usage(400), &usage(800)),
+            Some("80% of daily budget used (800 of 1000 tokens)".to_string())
+        );
+        // Only when crossing: past 80% already, no new warning
+        assert_eq!(budget.alert(&usage(800), &usage(900)), None);
+        assert_eq!(budget.used_fraction(&usage(900)), Some(0.9));
+    }
+
+    #[test]
+    fn test_budget_alert_uses_the_larger_share() {
+        let dir = TempDir::new().unwrap();
+        let config = BudgetConfig {
+            daily_tokens: Some(1_000_000),
+            daily_usd: Some(1.0),
+            ..Default::default()
+        };
+        let budget = budget(&dir, config.clone(), "gpt-4o-mini");
+        let before = DailyUsage::empty(Local::now().date_naive());
+        let after = DailyUsage {
+            tokens: 1000,
+            usd: 0.85,
+            ..before.clone()
+        };
+        let alert = budget.alert(&before, &after).unwrap();
+        assert!(alert.starts_with("80% of daily budget used"));
+        assert!(alert.contains("$0.8500 of $1.00"));
+
+        // No limits, no alerts
+        let unlimited = Budget::new(BudgetConfig::default(), "gpt-4o-mini", dir.path().join("usage.json"));
+        assert_eq!(unlimited.used_fraction(&after), None);
+        assert_eq!(unlimited.alert(&before, &after), None);
+    }
+
+    #[test]
+    fn test_budget_prices() {
+        let dir = TempDir::new().unwrap();
+        assert_eq!(
+            budget(&dir, BudgetConfig::default(), "gpt-4o-mini-2024-07-18").prices(),
+            Some((0.15, 0.60))
+        );
+        assert_eq!(
+            budget(&dir, BudgetConfig::default(), "gpt-4o").prices(),
+            Some((2.50, 10.00))
+        );
+        assert_eq!(budget(&dir, BudgetConfig::default(), "llama3").prices(), None);
+    }
+}
@@ -1,6 +1,8 @@
 use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
 use std::path::PathBuf;
 
+use crate::audit::ExportFormat;
+use crate::config::config_files_in;
 use crate::{build_status_footer, get_log_file};
 
 /// Build the after_help dynamically with status info
@@ -31,8 +33,20 @@ pub struct Cli {
     #[arg(short, long, help = "Enable verbose output")]
     pub verbose: bool,
 
+    /// Print how long each startup and query phase took to stderr (and the log)
+    #[arg(long, global = true, help = "Print per-phase timings to stderr")]
+    pub profile_startup: bool,
+
+    /// Plain output without colors (also with NO_COLOR set, or when stdout isn't a terminal)
+    #[arg(long, global = true, help = "Disable colored output")]
+    pub no_color: bool,
+
     #[command(subcommand)]
     pub command: Option<Commands>,
+
+    /// Name of the subcommand given, for telemetry
+    #[arg(skip)]
+    pub command_name: Option<String>,
 }
 
 impl Cli {
@@ -42,7 +56,9 @@ impl Cli {
         let after_help = get_after_help();
         let matches = Self::command().after_help(after_help).get_matches();
 
-        Self::from_arg_matches(&matches).expect("Failed to parse CLI arguments")
+        let mut cli = Self::from_arg_matches(&matches).expect("Failed to parse CLI arguments");
+        cli.command_name = matches.subcommand_name().map(str::to_string);
+        cli
     }
 }
 
@@ -55,15 +71,137 @@ pub enum Commands {
         #[arg(short, long, help = "Return multiple command options")]
         multi: bool,
 
-        /// Number of results to return when using --multi
-        #[arg(short = 'n', long, default_value = "5", help = "Number of results (with --multi)")]
-        count: usize,
+        /// Return a single command even with `query.multi: true` in the config
+        #[arg(short, long, conflicts_with = "multi", help = "Return a single command")]
+        single: bool,
+
+        /// Number of results to return when using --multi (default: query.count, or 5)
+        #[arg(short = 'n', long, help = "Number of results (with --multi)")]
+        count: Option<usize>,
+
+        /// Config profile from `profiles:` and/or prompt profile from
+        /// ~/.config/qai/prompts/<profile>.pmt (or QAI_PROFILE)
+        #[arg(short, long, help = "Config or prompt profile to use (e.g. work, k8s)")]
+        profile: Option<String>,
+
+        /// NUL-terminate each command instead of newline-separating them (for fzf --read0, xargs -0)
+        #[arg(
+            short = '0',
+            long,
+            visible_alias = "print0",
+            help = "Terminate each command with NUL (multi-line safe)"
+        )]
+        null: bool,
+
+        /// Separate commands with newlines but leave out the one after the last command
+        #[arg(
+            long,
+            conflicts_with_all = ["null", "annotate", "json"],
+            help = "Don't print a newline after the last command"
+        )]
+        no_trailing_newline: bool,
+
+        /// Prefix each command with a colored risk marker and a tab, NUL-terminated (for fzf --ansi)
+        #[arg(long, help = "Prefix each command with its risk marker (for pickers)")]
+        annotate: bool,
+
+        /// Print a JSON array of {command, risk} objects
+        #[arg(long, conflicts_with_all = ["null", "annotate"], help = "Print suggestions as JSON")]
+        json: bool,
+
+        /// Print each of the multiple results as soon as it is complete (default: query.stream)
+        #[arg(long, conflicts_with = "json", help = "Print results as they arrive (with --multi)")]
+        stream: bool,
+
+        /// Attach input to the query, e.g. `ls -la | qai query --context - "delete the .tmp ones"`
+        #[arg(long, value_name = "FILE", help = "Attach a file, or stdin with -, to the query")]
+        context: Option<String>,
+
+        /// Also put the first suggestion on the clipboard (OSC 52 over SSH or without a display)
+        #[arg(long, help = "Copy the first suggestion to the clipboard")]
+        copy: bool,
+
+        /// Sampling seed, for reproducible answers where the API supports it (default: query.seed)
+        #[arg(long, help = "Sampling seed (where the API supports it)")]
+        seed: Option<u64>,
+
+        /// Temperature 0 and a fixed seed (query.seed, or 42), for demos and tests
+        #[arg(long, help = "Reproducible suggestions: temperature 0 and a fixed seed")]
+        deterministic: bool,
+
+        /// Follow each command with a tab and a one-line `# comment` explaining it (default: query.teach)
+        #[arg(long, help = "Explain each command in a trailing comment")]
+        teach: bool,
+
+        /// Ask for extra results and keep the most distinct; simple queries history has settled
+        /// get a single command (default: query.adaptive)
+        #[arg(long, help = "Ask for extra results and keep the most distinct")]
+        adaptive: bool,
 
         /// The natural language query
         #[arg(required = true, num_args = 1.., trailing_var_arg = true)]
         query: Vec<String>,
     },
 
+    /// Convert a command to another shell or platform (default: this shell and OS), e.g. when
+    /// following Linux instructions on a Mac
+    #[command(name = "translate")]
+    Translate {
+        /// Shell or platform to convert to, e.g. fish, macOS, BSD, PowerShell (default: this machine)
+        #[arg(long, help = "Target shell or platform (default: this shell and OS)")]
+        to: Option<String>,
+
+        /// What the command was written for, e.g. bash or GNU/Linux (default: left to the model)
+        #[arg(long, help = "Source shell or platform")]
+        from: Option<String>,
+
+        /// The command to translate, or - to read it from stdin
+        #[arg(required = true, num_args = 1.., trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+
+    /// Generate a regex from a description, e.g. `qai regex "ISO dates like 2024-01-31"`
+    #[command(name = "regex")]
+    Regex {
+        /// Regex syntax to write, e.g. pcre, python, "grep -E" (default: portable syntax)
+        #[arg(long, help = "Regex flavor (default: portable syntax)")]
+        flavor: Option<String>,
+
+        /// Try the regex on sample lines from stdin and show the ones it matches
+        #[arg(long, help = "Test against sample input from stdin")]
+        test: bool,
+
+        /// What the regex should match
+        #[arg(required = true, num_args = 1.., trailing_var_arg = true)]
+        description: Vec<String>,
+    },
+
+    /// Generate a jq filter from a description, e.g. `qai jq "names of the failed jobs"`
+    #[command(name = "jq")]
+    Jq {
+        /// Run the filter on sample JSON from stdin (needs jq) and show its output
+        #[arg(long, help = "Test against sample input from stdin")]
+        test: bool,
+
+        /// What the filter should extract or transform
+        #[arg(required = true, num_args = 1.., trailing_var_arg = true)]
+        description: Vec<String>,
+    },
+
+    /// Write a crontab line, e.g. `qai cron "weekdays at 2:30am" "back up ~/notes to /mnt/backup"`
+    #[command(name = "cron")]
+    Cron {
+        /// Append the line to your crontab (via `crontab -`) after asking
+        #[arg(long, help = "Add the line to your crontab after confirmation")]
+        install: bool,
+
+        /// When to run, in words
+        schedule: String,
+
+        /// What to run, in words or as a command
+        task: String,
+    },
+
     /// Print shell initialization script
     #[command(name = "shell-init")]
     ShellInit {
@@ -76,6 +214,15 @@ pub enum Commands {
     #[command(name = "validate-api")]
     ValidateApi,
 
+    /// Validate the API key, preload prompts and the tool cache, and check for a daemon, all
+    /// at once (the zsh widget runs this in the background at shell start)
+    #[command(name = "warmup")]
+    Warmup,
+
+    /// Walk through a first query, AI mode, the picker, history, and feedback, checking each works here
+    #[command(name = "tutorial")]
+    Tutorial,
+
     /// Show query history and patterns
     #[command(name = "history")]
     History {
@@ -94,6 +241,28 @@ pub enum Commands {
         /// Clear all history
         #[arg(long)]
         clear: bool,
+
+        #[command(subcommand)]
+        action: Option<HistoryAction>,
+    },
+
+    /// Summarize your recent use of qai: queries, tokens, topics, acceptance, and new tools
+    #[command(name = "report")]
+    Report {
+        /// Cover the past week (the default)
+        #[arg(long, conflicts_with = "days")]
+        weekly: bool,
+
+        /// Cover the past N days instead
+        #[arg(long)]
+        days: Option<u32>,
+    },
+
+    /// Opt in to (or out of) anonymous usage counts, and show what is collected
+    #[command(name = "telemetry")]
+    Telemetry {
+        #[command(subcommand)]
+        action: TelemetryAction,
     },
 
     /// Manage tool cache for command suggestions
@@ -106,7 +275,290 @@ pub enum Commands {
         /// Clear the tool cache
         #[arg(long)]
         clear: bool,
+
+        /// Print the full cache as JSON
+        #[arg(long)]
+        json: bool,
+
+        #[command(subcommand)]
+        action: Option<ToolsAction>,
     },
+
+    /// Record that an AI-suggested command was run (called by the shell integration)
+    #[command(name = "record-exec", hide = true)]
+    RecordExec {
+        /// The query that produced the suggestion
+        #[arg(long, allow_hyphen_values = true)]
+        query: String,
+
+        /// The command qai inserted into the buffer
+        #[arg(long, allow_hyphen_values = true)]
+        suggested: String,
+
+        /// The command line that actually ran (may have been edited)
+        #[arg(long, allow_hyphen_values = true)]
+        command: String,
+
+        /// Exit status of the command
+        #[arg(long, allow_hyphen_values = true)]
+        exit_code: i32,
+
+        /// Directory the command ran in
+        #[arg(long)]
+        cwd: Option<PathBuf>,
+    },
+
+    /// Run the post-select hook for a picked suggestion (called by the shell integration)
+    #[command(name = "record-select", hide = true)]
+    RecordSelect {
+        /// The query that produced the suggestion
+        #[arg(long, allow_hyphen_values = true)]
+        query: String,
+
+        /// The command that was picked
+        #[arg(long, allow_hyphen_values = true)]
+        command: String,
+    },
+
+    /// Print why a command is dangerous, or nothing if it isn't (called by the shell integration)
+    #[command(name = "check-command", hide = true)]
+    CheckCommand {
+        /// Print why the command may run long instead, and how to bound it (the picker's preview)
+        #[arg(long)]
+        slow: bool,
+        /// The command line to check
+        #[arg(required = true, num_args = 1.., trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+
+    /// Manage prompt customizations
+    #[command(name = "prompt")]
+    Prompt {
+        #[command(subcommand)]
+        action: PromptAction,
+    },
+
+    /// Always answer a query with a command: first among the suggestions, or alone with --only
+    #[command(name = "pin")]
+    Pin {
+        /// The query, matched ignoring case
+        #[arg(required_unless_present = "list")]
+        query: Option<String>,
+
+        /// The command to answer it with
+        #[arg(required_unless_present_any = ["list", "remove"])]
+        command: Option<String>,
+
+        /// Answer the query with pinned commands only, without asking the model
+        #[arg(long)]
+        only: bool,
+
+        /// List pinned commands
+        #[arg(short, long, conflicts_with_all = ["query", "command", "only", "remove"])]
+        list: bool,
+
+        /// Remove the pins of the query
+        #[arg(short, long, conflicts_with_all = ["command", "only"])]
+        remove: bool,
+    },
+
+    /// Save queries with {placeholders} and run them with arguments
+    #[command(name = "snippet")]
+    Snippet {
+        #[command(subcommand)]
+        action: SnippetAction,
+    },
+
+    /// Inspect the extra context added to prompts
+    #[command(name = "context")]
+    Context {
+        #[command(subcommand)]
+        action: ContextAction,
+    },
+
+    /// Export the audit log of executed AI-suggested commands (needs audit.enabled: true)
+    #[command(name = "audit")]
+    Audit {
+        #[command(subcommand)]
+        action: AuditAction,
+    },
+
+    /// Review the AI-mode transcript of a shell session
+    #[command(name = "session")]
+    Session {
+        #[command(subcommand)]
+        action: SessionAction,
+    },
+
+    /// Check the configuration file or print its schema
+    #[command(name = "config")]
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+
+    /// Check the installation: fzf, API key, and configuration
+    #[command(name = "doctor")]
+    Doctor,
+
+    /// Keep a warm HTTP client running and serve queries over a unix socket
+    #[cfg(all(feature = "daemon", unix))]
+    #[command(name = "daemon")]
+    Daemon {
+        /// Socket to listen on (default: $XDG_RUNTIME_DIR/qai.sock, or QAI_DAEMON_SOCKET)
+        #[arg(long, help = "Socket path to listen on")]
+        socket: Option<PathBuf>,
+    },
+
+    /// Serve queries, explanations, and history as a JSON API on localhost
+    #[cfg(feature = "serve")]
+    #[command(name = "serve")]
+    Serve {
+        /// Port to listen on (127.0.0.1 only)
+        #[arg(short, long, default_value = "8711", help = "Port to listen on")]
+        port: u16,
+    },
+
+    /// Serve the clipboard text read from stdin until something else is copied (used by --copy)
+    #[cfg(all(feature = "clipboard", target_os = "linux"))]
+    #[command(name = "__copy-hold", hide = true)]
+    CopyHold,
+}
+
+#[derive(Subcommand, Clone)]
+pub enum ConfigAction {
+    /// Report unknown keys, invalid key names, malformed URLs, and syntax errors, with their lines
+    #[command(name = "validate")]
+    Validate,
+    /// Print a JSON Schema of qai.yml, for editor completion and validation
+    #[cfg(feature = "schema")]
+    #[command(name = "schema")]
+    Schema,
+}
+
+#[derive(Subcommand, Clone)]
+pub enum AuditAction {
+    /// Print the audit log to stdout
+    #[command(name = "export")]
+    Export {
+        /// Output format
+        #[arg(short, long, value_enum, default_value = "jsonl")]
+        format: ExportFormat,
+
+        /// Only entries at or after this date (YYYY-MM-DD or RFC 3339)
+        #[arg(long)]
+        since: Option<String>,
+    },
+
+    /// Print the audit log location
+    #[command(name = "path")]
+    Path,
+}
+
+#[derive(Subcommand, Clone)]
+pub enum SessionAction {
+    /// Show queries, candidates, and executed commands (needs session.transcript: true)
+    #[command(name = "show")]
+    Show {
+        /// Session id (default: the current shell's QAI_SESSION)
+        #[arg(long)]
+        id: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Clone)]
+pub enum PromptAction {
+    /// Add a query -> command example to ~/.config/qai/prompts/examples.pmt
+    #[command(name = "add-example")]
+    AddExample {
+        /// The natural language query
+        query: String,
+
+        /// The command it should produce
+        command: String,
+    },
+
+    /// Check prompt templates for unknown placeholders, missing includes, and size
+    #[command(name = "lint")]
+    Lint,
+}
+
+#[derive(Subcommand, Clone)]
+pub enum HistoryAction {
+    /// Show the commands you used most, across all queries, with counts and last-used dates
+    #[command(name = "top")]
+    Top {
+        /// Number of commands to show
+        #[arg(short = 'n', long, default_value = "10")]
+        limit: usize,
+    },
+}
+
+#[derive(Subcommand, Clone)]
+pub enum SnippetAction {
+    /// Save a query template, e.g. `qai snippet add backup "compress {dir} into {name}.tar.gz"`
+    #[command(name = "add")]
+    Add {
+        /// Name to run it by
+        name: String,
+
+        /// The query, with {placeholders} for the arguments
+        #[arg(required = true, num_args = 1.., trailing_var_arg = true)]
+        template: Vec<String>,
+    },
+
+    /// List saved snippets and their placeholders
+    #[command(name = "list")]
+    List,
+
+    /// Fill a snippet's placeholders and send it as a query, e.g. `qai snippet run backup src out`
+    #[command(name = "run")]
+    Run {
+        /// The snippet's name
+        name: String,
+
+        /// Placeholder values, in order or as name=value
+        #[arg(num_args = 0.., trailing_var_arg = true)]
+        args: Vec<String>,
+
+        /// Return multiple command options
+        #[arg(short, long)]
+        multi: bool,
+
+        /// Return a single command even with `query.multi: true` in the config
+        #[arg(short, long, conflicts_with = "multi")]
+        single: bool,
+    },
+}
+
+#[derive(Subcommand, Clone)]
+pub enum ContextAction {
+    /// Run the ~/.config/qai/context.d scripts and show each context section a query would get
+    #[command(name = "show")]
+    Show,
+}
+
+#[derive(Subcommand, Clone)]
+pub enum TelemetryAction {
+    /// Start counting which commands run and how long they take (no query content)
+    #[command(name = "enable")]
+    Enable,
+    /// Stop, and delete the anonymous id and anything not yet sent
+    #[command(name = "disable")]
+    Disable,
+    /// Show whether telemetry is on, and the data that would be sent next
+    #[command(name = "status")]
+    Status,
+    /// Describe every field that is sent
+    #[command(name = "schema")]
+    Schema,
+}
+
+#[derive(Subcommand, Clone)]
+pub enum ToolsAction {
+    /// Import shell aliases and function names from stdin (run by shell-init)
+    #[command(name = "import-aliases")]
+    ImportAliases,
 }
 
 /// Check if fzf is available and get its version
@@ -135,14 +587,25 @@ pub fn check_api_key_configured() -> bool {
         return true;
     }
 
-    // Check config file
+    // Check config file (qai.yml or qai.toml)
     if let Some(config_dir) = dirs::config_dir() {
-        let config_path = config_dir.join("qai").join("qai.yml");
-        if config_path.exists()
-            && let Ok(content) = std::fs::read_to_string(&config_path)
-        {
+        for config_path in config_files_in(&config_dir.join("qai")) {
+            let Ok(content) = std::fs::read_to_string(&config_path) else {
+                continue;
+            };
             // Simple check for api_key in config
-            let has_api_key = (content.contains("api-key:") || content.contains("api_key:"))
+            let has_api_key = [
+                "api-key:",
+                "api_key:",
+                "api-key =",
+                "api_key =",
+                "api-key-cmd:",
+                "api_key_cmd:",
+                "api-key-cmd =",
+                "api_key_cmd =",
+            ]
+            .iter()
+            .any(|key| content.contains(key))
                 && !content.contains("api-key: null")
                 && !content.contains("api_key: null");
             if has_api_key {
@@ -150,7 +613,13 @@ pub fn check_api_key_configured() -> bool {
             }
 
             // Allow explicit opt-out
-            if content.contains("allow-no-api-key: true") || content.contains("allow_no_api_key: true") {
+            let opt_outs = [
+                "allow-no-api-key: true",
+                "allow_no_api_key: true",
+                "allow-no-api-key = true",
+                "allow_no_api_key = true",
+            ];
+            if opt_outs.iter().any(|opt_out| content.contains(opt_out)) {
                 return true;
             }
         }
@@ -178,15 +647,194 @@ mod tests {
     fn test_cli_query_single_word() {
         let cli = Cli::try_parse_from(["qai", "query", "test"]).unwrap();
         match cli.command {
-            Some(Commands::Query { query, multi, count }) => {
+            Some(Commands::Query {
+                query, multi, count, ..
+            }) => {
                 assert_eq!(query, vec!["test"]);
                 assert!(!multi);
-                assert_eq!(count, 5);
+                assert_eq!(count, None);
             }
             _ => panic!("Expected Query command"),
         }
     }
 
+    #[test]
+    fn test_cli_query_profile() {
+        let cli = Cli::try_parse_from(["qai", "query", "--profile", "k8s", "restart", "the", "pod"]).unwrap();
+        match cli.command {
+            Some(Commands::Query { query, profile, .. }) => {
+                assert_eq!(query, vec!["restart", "the", "pod"]);
+                assert_eq!(profile.as_deref(), Some("k8s"));
+            }
+            _ => panic!("Expected Query command"),
+        }
+
+        let cli = Cli::try_parse_from(["qai", "query", "test"]).unwrap();
+        match cli.command {
+            Some(Commands::Query { profile, .. }) => assert!(profile.is_none()),
+            _ => panic!("Expected Query command"),
+        }
+    }
+
+    #[test]
+    fn test_cli_query_null() {
+        let cli = Cli::try_parse_from(["qai", "query", "--multi", "--null", "write", "a", "heredoc"]).unwrap();
+        match cli.command {
+            Some(Commands::Query { query, null, .. }) => {
+                assert!(null);
+                assert_eq!(query, vec!["write", "a", "heredoc"]);
+            }
+            _ => panic!("Expected Query command"),
+        }
+
+        let cli = Cli::try_parse_from(["qai", "query", "test"]).unwrap();
+        assert!(matches!(cli.command, Some(Commands::Query { null: false, .. })));
+    }
+
+    #[test]
+    fn test_cli_query_context() {
+        let cli = Cli::try_parse_from(["qai", "query", "--context", "-", "delete", "the", ".tmp", "ones"]).unwrap();
+        match cli.command {
+            Some(Commands::Query { query, context, .. }) => {
+                assert_eq!(context.as_deref(), Some("-"));
+                assert_eq!(query, vec!["delete", "the", ".tmp", "ones"]);
+            }
+            _ => panic!("Expected Query command"),
+        }
+        assert!(matches!(
+            Cli::try_parse_from(["qai", "query", "test"]).unwrap().command,
+            Some(Commands::Query { context: None, .. })
+        ));
+    }
+
+    #[test]
+    fn test_cli_query_print0_and_no_trailing_newline() {
+        let cli = Cli::try_parse_from(["qai", "query", "--multi", "--print0", "list"]).unwrap();
+        assert!(matches!(cli.command, Some(Commands::Query { null: true, .. })));
+        let cli = Cli::try_parse_from(["qai", "query", "--no-trailing-newline", "list"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Query {
+                no_trailing_newline: true,
+                ..
+            })
+        ));
+        assert!(Cli::try_parse_from(["qai", "query", "--print0", "--no-trailing-newline", "list"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_translate() {
+        let cli = Cli::try_parse_from(["qai", "translate", "--to", "fish", "export", "A=1"]).unwrap();
+        match cli.command {
+            Some(Commands::Translate { to, from, command }) => {
+                assert_eq!(to.as_deref(), Some("fish"));
+                assert_eq!(from, None);
+                assert_eq!(command, vec!["export", "A=1"]);
+            }
+            _ => panic!("Expected Translate command"),
+        }
+
+        // Flags after the command belong to it
+        let cli = Cli::try_parse_from(["qai", "translate", "sed", "-i", "s/a/b/", "f"]).unwrap();
+        assert!(matches!(cli.command, Some(Commands::Translate { command, .. }) if command[1] == "-i"));
+        assert!(Cli::try_parse_from(["qai", "translate"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_regex_and_jq() {
+        let cli = Cli::try_parse_from(["qai", "regex", "--flavor", "pcre", "--test", "ISO", "dates"]).unwrap();
+        match cli.command {
+            Some(Commands::Regex {
+                flavor,
+                test,
+                description,
+            }) => {
+                assert_eq!(flavor.as_deref(), Some("pcre"));
+                assert!(test);
+                assert_eq!(description, vec!["ISO", "dates"]);
+            }
+            _ => panic!("Expected Regex command"),
+        }
+
+        let cli = Cli::try_parse_from(["qai", "jq", "names", "of", "failed", "jobs"]).unwrap();
+        assert!(matches!(cli.command, Some(Commands::Jq { test: false, .. })));
+        assert!(Cli::try_parse_from(["qai", "jq"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_cron() {
+        let cli = Cli::try_parse_from(["qai", "cron", "--install", "every monday at 9", "rotate the logs"]).unwrap();
+        match cli.command {
+            Some(Commands::Cron {
+                install,
+                schedule,
+                task,
+            }) => {
+                assert!(install);
+                assert_eq!(schedule, "every monday at 9");
+                assert_eq!(task, "rotate the logs");
+            }
+            _ => panic!("Expected Cron command"),
+        }
+        assert!(Cli::try_parse_from(["qai", "cron", "hourly"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_query_copy() {
+        let cli = Cli::try_parse_from(["qai", "query", "--copy", "list", "files"]).unwrap();
+        assert!(matches!(cli.command, Some(Commands::Query { copy: true, .. })));
+        let cli = Cli::try_parse_from(["qai", "query", "list", "files"]).unwrap();
+        assert!(matches!(cli.command, Some(Commands::Query { copy: false, .. })));
+    }
+
+    #[test]
+    fn test_cli_query_deterministic() {
+        let cli = Cli::try_parse_from(["qai", "query", "--deterministic", "--seed", "7", "list", "files"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Query {
+                deterministic: true,
+                seed: Some(7),
+                ..
+            })
+        ));
+        assert!(Cli::try_parse_from(["qai", "query", "--seed", "x", "list"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_query_teach() {
+        let cli = Cli::try_parse_from(["qai", "query", "--teach", "--multi", "list", "files"]).unwrap();
+        assert!(matches!(cli.command, Some(Commands::Query { teach: true, .. })));
+        let cli = Cli::try_parse_from(["qai", "query", "list", "files"]).unwrap();
+        assert!(matches!(cli.command, Some(Commands::Query { teach: false, .. })));
+    }
+
+    #[test]
+    fn test_cli_query_adaptive() {
+        let cli = Cli::try_parse_from(["qai", "query", "--adaptive", "--multi", "list", "files"]).unwrap();
+        assert!(matches!(cli.command, Some(Commands::Query { adaptive: true, .. })));
+        let cli = Cli::try_parse_from(["qai", "query", "list", "files"]).unwrap();
+        assert!(matches!(cli.command, Some(Commands::Query { adaptive: false, .. })));
+    }
+
+    #[test]
+    fn test_cli_query_annotate_and_json() {
+        let cli = Cli::try_parse_from(["qai", "query", "--multi", "--annotate", "list", "files"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Query {
+                annotate: true,
+                json: false,
+                ..
+            })
+        ));
+
+        let cli = Cli::try_parse_from(["qai", "query", "--json", "list", "files"]).unwrap();
+        assert!(matches!(cli.command, Some(Commands::Query { json: true, .. })));
+
+        assert!(Cli::try_parse_from(["qai", "query", "--json", "--null", "list"]).is_err());
+    }
+
     #[test]
     fn test_cli_query_multiple_words() {
         let cli = Cli::try_parse_from(["qai", "query", "list", "all", "files"]).unwrap();
@@ -202,10 +850,12 @@ mod tests {
     fn test_cli_query_with_multi_flag() {
         let cli = Cli::try_parse_from(["qai", "query", "--multi", "find", "files"]).unwrap();
         match cli.command {
-            Some(Commands::Query { query, multi, count }) => {
+            Some(Commands::Query {
+                query, multi, count, ..
+            }) => {
                 assert_eq!(query, vec!["find", "files"]);
                 assert!(multi);
-                assert_eq!(count, 5);
+                assert_eq!(count, None);
             }
             _ => panic!("Expected Query command"),
         }
@@ -215,10 +865,12 @@ mod tests {
     fn test_cli_query_with_multi_and_count() {
         let cli = Cli::try_parse_from(["qai", "query", "--multi", "-n", "10", "find", "files"]).unwrap();
         match cli.command {
-            Some(Commands::Query { query, multi, count }) => {
+            Some(Commands::Query {
+                query, multi, count, ..
+            }) => {
                 assert_eq!(query, vec!["find", "files"]);
                 assert!(multi);
-                assert_eq!(count, 10);
+                assert_eq!(count, Some(10));
             }
             _ => panic!("Expected Query command"),
         }
@@ -252,6 +904,18 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_cli_warmup() {
+        let cli = Cli::try_parse_from(["qai", "warmup"]).unwrap();
+        assert!(matches!(cli.command, Some(Commands::Warmup)));
+    }
+
+    #[test]
+    fn test_cli_tutorial() {
+        let cli = Cli::try_parse_from(["qai", "tutorial"]).unwrap();
+        assert!(matches!(cli.command, Some(Commands::Tutorial)));
+    }
+
     #[test]
     fn test_cli_validate_api() {
         let cli = Cli::try_parse_from(["qai", "validate-api"]).unwrap();
@@ -356,7 +1020,7 @@ mod tests {
         let cli = Cli::try_parse_from(["qai", "query", "-n", "3", "test"]).unwrap();
         match cli.command {
             Some(Commands::Query { count, multi, .. }) => {
-                assert_eq!(count, 3);
+                assert_eq!(count, Some(3));
                 assert!(!multi);
             }
             _ => panic!("Expected Query command"),
@@ -372,11 +1036,13 @@ mod tests {
                 patterns,
                 stats,
                 clear,
+                action,
             }) => {
                 assert_eq!(limit, 10);
                 assert!(!patterns);
                 assert!(!stats);
                 assert!(!clear);
+                assert!(action.is_none());
             }
             _ => panic!("Expected History command"),
         }
@@ -460,11 +1126,63 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_cli_history_top() {
+        let cli = Cli::try_parse_from(["qai", "history", "top", "-n", "3"]).unwrap();
+        match cli.command {
+            Some(Commands::History {
+                action: Some(HistoryAction::Top { limit }),
+                ..
+            }) => {
+                assert_eq!(limit, 3);
+            }
+            _ => panic!("Expected History top command"),
+        }
+    }
+
+    #[test]
+    fn test_cli_report() {
+        let cli = Cli::try_parse_from(["qai", "report", "--weekly"]).unwrap();
+        match cli.command {
+            Some(Commands::Report { weekly, days }) => {
+                assert!(weekly);
+                assert_eq!(days, None);
+            }
+            _ => panic!("Expected Report command"),
+        }
+        assert!(Cli::try_parse_from(["qai", "report", "--weekly", "--days", "3"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_telemetry() {
+        let cli = Cli::try_parse_from(["qai", "telemetry", "enable"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Telemetry {
+                action: TelemetryAction::Enable
+            })
+        ));
+        // Off unless asked for: there is no default action
+        assert!(Cli::try_parse_from(["qai", "telemetry"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_record_select() {
+        let cli = Cli::try_parse_from(["qai", "record-select", "--query=list files", "--command=-ls"]).unwrap();
+        match cli.command {
+            Some(Commands::RecordSelect { query, command }) => {
+                assert_eq!(query, "list files");
+                assert_eq!(command, "-ls");
+            }
+            _ => panic!("Expected RecordSelect command"),
+        }
+    }
+
     #[test]
     fn test_cli_tools_default() {
         let cli = Cli::try_parse_from(["qai", "tools"]).unwrap();
         match cli.command {
-            Some(Commands::Tools { refresh, clear }) => {
+            Some(Commands::Tools { refresh, clear, .. }) => {
                 assert!(!refresh);
                 assert!(!clear);
             }
@@ -476,7 +1194,7 @@ mod tests {
     fn test_cli_tools_refresh() {
         let cli = Cli::try_parse_from(["qai", "tools", "--refresh"]).unwrap();
         match cli.command {
-            Some(Commands::Tools { refresh, clear }) => {
+            Some(Commands::Tools { refresh, clear, .. }) => {
                 assert!(refresh);
                 assert!(!clear);
             }
@@ -499,11 +1217,315 @@ mod tests {
     fn test_cli_tools_clear() {
         let cli = Cli::try_parse_from(["qai", "tools", "--clear"]).unwrap();
         match cli.command {
-            Some(Commands::Tools { refresh, clear }) => {
+            Some(Commands::Tools { refresh, clear, .. }) => {
                 assert!(!refresh);
                 assert!(clear);
             }
             _ => panic!("Expected Tools command"),
         }
     }
+
+    #[test]
+    fn test_cli_tools_json() {
+        let cli = Cli::try_parse_from(["qai", "tools", "--json"]).unwrap();
+        match cli.command {
+            Some(Commands::Tools { json, refresh, .. }) => {
+                assert!(json);
+                assert!(!refresh);
+            }
+            _ => panic!("Expected Tools command"),
+        }
+    }
+
+    #[test]
+    fn test_cli_prompt_add_example() {
+        let cli = Cli::try_parse_from(["qai", "prompt", "add-example", "list big files", "dust -d 1"]).unwrap();
+        match cli.command {
+            Some(Commands::Prompt {
+                action: PromptAction::AddExample { query, command },
+            }) => {
+                assert_eq!(query, "list big files");
+                assert_eq!(command, "dust -d 1");
+            }
+            _ => panic!("Expected Prompt add-example command"),
+        }
+    }
+
+    #[test]
+    fn test_cli_record_exec() {
+        let cli = Cli::try_parse_from([
+            "qai",
+            "record-exec",
+            "--query",
+            "list files",
+            "--suggested=ls -la",
+            "--command=-ls -lah",
+            "--exit-code",
+            "-1",
+        ])
+        .unwrap();
+        match cli.command {
+            Some(Commands::RecordExec {
+                query,
+                suggested,
+                command,
+                exit_code,
+                cwd,
+            }) => {
+                assert_eq!(query, "list files");
+                assert_eq!(suggested, "ls -la");
+                assert_eq!(command, "-ls -lah");
+                assert_eq!(exit_code, -1);
+                assert_eq!(cwd, None);
+            }
+            _ => panic!("Expected RecordExec command"),
+        }
+    }
+
+    #[test]
+    fn test_cli_check_command() {
+        let cli = Cli::try_parse_from(["qai", "check-command", "--", "rm -rf /"]).unwrap();
+        match cli.command {
+            Some(Commands::CheckCommand { command, slow }) => {
+                assert_eq!(command, vec!["rm -rf /"]);
+                assert!(!slow);
+            }
+            _ => panic!("Expected CheckCommand command"),
+        }
+        let cli = Cli::try_parse_from(["qai", "check-command", "--slow", "--", "find / -name x"]).unwrap();
+        assert!(matches!(cli.command, Some(Commands::CheckCommand { slow: true, .. })));
+
+        let help = Cli::command().render_help().to_string();
+        assert!(!help.contains("check-command"));
+    }
+
+    #[test]
+    fn test_cli_record_exec_is_hidden() {
+        use clap::CommandFactory;
+        let help = Cli::command().render_help().to_string();
+        assert!(!help.contains("record-exec"));
+    }
+
+    #[test]
+    fn test_cli_prompt_lint() {
+        let cli = Cli::try_parse_from(["qai", "prompt", "lint"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Prompt {
+                action: PromptAction::Lint
+            })
+        ));
+    }
+
+    #[test]
+    fn test_cli_pin() {
+        let cli = Cli::try_parse_from(["qai", "pin", "list files", "eza -la", "--only"]).unwrap();
+        match cli.command {
+            Some(Commands::Pin {
+                query, command, only, ..
+            }) => {
+                assert_eq!(query.as_deref(), Some("list files"));
+                assert_eq!(command.as_deref(), Some("eza -la"));
+                assert!(only);
+            }
+            _ => panic!("Expected Pin command"),
+        }
+        assert!(Cli::try_parse_from(["qai", "pin", "--remove", "list files"]).is_ok());
+        assert!(Cli::try_parse_from(["qai", "pin", "--list"]).is_ok());
+        assert!(Cli::try_parse_from(["qai", "pin", "list files"]).is_err());
+        assert!(Cli::try_parse_from(["qai", "pin", "--list", "list files"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_snippet_add_and_run() {
+        let cli = Cli::try_parse_from([
+            "qai",
+            "snippet",
+            "add",
+            "backup",
+            "compress",
+            "{dir}",
+            "into",
+            "{name}.tgz",
+        ])
+        .unwrap();
+        match cli.command {
+            Some(Commands::Snippet {
+                action: SnippetAction::Add { name, template },
+            }) => {
+                assert_eq!(name, "backup");
+                assert_eq!(template.join(" "), "compress {dir} into {name}.tgz");
+            }
+            _ => panic!("Expected Snippet add command"),
+        }
+
+        let cli = Cli::try_parse_from(["qai", "snippet", "run", "-m", "backup", "src", "name=out"]).unwrap();
+        match cli.command {
+            Some(Commands::Snippet {
+                action: SnippetAction::Run { name, args, multi, .. },
+            }) => {
+                assert_eq!(name, "backup");
+                assert_eq!(args, vec!["src", "name=out"]);
+                assert!(multi);
+            }
+            _ => panic!("Expected Snippet run command"),
+        }
+    }
+
+    #[test]
+    fn test_cli_context_show() {
+        let cli = Cli::try_parse_from(["qai", "context", "show"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Context {
+                action: ContextAction::Show
+            })
+        ));
+    }
+
+    #[test]
+    fn test_cli_audit_export() {
+        let cli = Cli::try_parse_from(["qai", "audit", "export"]).unwrap();
+        match cli.command {
+            Some(Commands::Audit {
+                action: AuditAction::Export { format, since },
+            }) => {
+                assert_eq!(format, ExportFormat::Jsonl);
+                assert_eq!(since, None);
+            }
+            _ => panic!("Expected Audit command"),
+        }
+
+        let cli = Cli::try_parse_from(["qai", "audit", "export", "-f", "csv", "--since", "2025-01-01"]).unwrap();
+        match cli.command {
+            Some(Commands::Audit {
+                action: AuditAction::Export { format, since },
+            }) => {
+                assert_eq!(format, ExportFormat::Csv);
+                assert_eq!(since.as_deref(), Some("2025-01-01"));
+            }
+            _ => panic!("Expected Audit command"),
+        }
+
+        assert!(Cli::try_parse_from(["qai", "audit", "export", "-f", "xml"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_session_show() {
+        let cli = Cli::try_parse_from(["qai", "session", "show"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Session {
+                action: SessionAction::Show { id: None }
+            })
+        ));
+
+        let cli = Cli::try_parse_from(["qai", "session", "show", "--id", "42-1700000000"]).unwrap();
+        match cli.command {
+            Some(Commands::Session {
+                action: SessionAction::Show { id },
+            }) => assert_eq!(id.as_deref(), Some("42-1700000000")),
+            _ => panic!("Expected Session command"),
+        }
+    }
+
+    #[test]
+    fn test_cli_config_validate_and_doctor() {
+        let cli = Cli::try_parse_from(["qai", "config", "validate"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Config {
+                action: ConfigAction::Validate
+            })
+        ));
+        assert!(Cli::try_parse_from(["qai", "config"]).is_err());
+
+        let cli = Cli::try_parse_from(["qai", "doctor"]).unwrap();
+        assert!(matches!(cli.command, Some(Commands::Doctor)));
+    }
+
+    #[test]
+    fn test_cli_profile_startup() {
+        let cli = Cli::try_parse_from(["qai", "query", "--profile-startup", "list", "files"]).unwrap();
+        assert!(cli.profile_startup);
+        let cli = Cli::try_parse_from(["qai", "query", "list", "files"]).unwrap();
+        assert!(!cli.profile_startup);
+    }
+
+    #[test]
+    #[cfg(feature = "schema")]
+    fn test_cli_config_schema() {
+        let cli = Cli::try_parse_from(["qai", "config", "schema"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Config {
+                action: ConfigAction::Schema
+            })
+        ));
+    }
+
+    #[test]
+    #[cfg(all(feature = "daemon", unix))]
+    fn test_cli_daemon() {
+        let cli = Cli::try_parse_from(["qai", "daemon"]).unwrap();
+        assert!(matches!(cli.command, Some(Commands::Daemon { socket: None })));
+
+        let cli = Cli::try_parse_from(["qai", "daemon", "--socket", "/tmp/qai.sock"]).unwrap();
+        match cli.command {
+            Some(Commands::Daemon { socket }) => assert_eq!(socket, Some(PathBuf::from("/tmp/qai.sock"))),
+            _ => panic!("Expected Daemon command"),
+        }
+    }
+
+    #[test]
+    fn test_cli_no_color() {
+        assert!(!Cli::try_parse_from(["qai", "history"]).unwrap().no_color);
+        // Global, so it also goes after the subcommand
+        assert!(Cli::try_parse_from(["qai", "history", "--no-color"]).unwrap().no_color);
+        assert!(Cli::try_parse_from(["qai", "--no-color", "tools"]).unwrap().no_color);
+    }
+
+    #[cfg(feature = "serve")]
+    #[test]
+    fn test_cli_serve() {
+        let cli = Cli::try_parse_from(["qai", "serve"]).unwrap();
+        assert!(matches!(cli.command, Some(Commands::Serve { port: 8711 })));
+
+        let cli = Cli::try_parse_from(["qai", "serve", "--port", "9000"]).unwrap();
+        assert!(matches!(cli.command, Some(Commands::Serve { port: 9000 })));
+        assert!(Cli::try_parse_from(["qai", "serve", "--port", "http"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_prompt_requires_action() {
+        assert!(Cli::try_parse_from(["qai", "prompt"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_tools_import_aliases() {
+        let cli = Cli::try_parse_from(["qai", "tools", "import-aliases"]).unwrap();
+        match cli.command {
+            Some(Commands::Tools {
+                refresh,
+                clear,
+                action: Some(ToolsAction::ImportAliases),
+                ..
+            }) => {
+                assert!(!refresh);
+                assert!(!clear);
+            }
+            _ => panic!("Expected Tools import-aliases command"),
+        }
+    }
+
+    #[test]
+    fn test_cli_tools_no_action_by_default() {
+        let cli = Cli::try_parse_from(["qai", "tools"]).unwrap();
+        match cli.command {
+            Some(Commands::Tools { action, .. }) => {
+                assert!(action.is_none());
+            }
+            _ => panic!("Expected Tools command"),
+        }
+    }
 }
@@ -0,0 +1,120 @@
+//! Putting a command on the clipboard for `qai query --copy`
+//!
+//! Locally it goes to the system clipboard (with the `clipboard` feature). Over
+//! SSH, or when there is no system clipboard (e.g. no display), it is sent to the
+//! terminal as an OSC 52 escape instead, which most terminal emulators put on the
+//! local clipboard (inside tmux, this needs `set-clipboard on`).
+//!
+//! On Linux the X11 clipboard is served by the process that set it, so a
+//! detached `qai __copy-hold` keeps serving it until something else is copied.
+
+use base64::Engine;
+use eyre::{Context, Result};
+use std::io::Write;
+
+/// Hidden subcommand that holds the Linux clipboard
+pub const HOLD_COMMAND: &str = "__copy-hold";
+
+/// Where the text was copied to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Copied {
+    System,
+    /// Sent as OSC 52 for the terminal to copy
+    Terminal,
+}
+
+/// Copy `text` to the system clipboard, else through the terminal
+pub fn copy(text: &str) -> Result<Copied> {
+    #[cfg(feature = "clipboard")]
+    if !is_remote() {
+        match copy_system(text) {
+            Ok(()) => return Ok(Copied::System),
+            Err(e) => log::info!("System clipboard unavailable, using OSC 52: {:#}", e),
+        }
+    }
+    copy_terminal(text)?;
+    Ok(Copied::Terminal)
+}
+
+/// Whether qai runs in an SSH session, where the system clipboard is the remote host's
+pub fn is_remote() -> bool {
+    ["SSH_TTY", "SSH_CONNECTION"]
+        .iter()
+        .any(|var| std::env::var_os(var).is_some_and(|value| !value.is_empty()))
+}
+
+/// The OSC 52 escape that sets the clipboard to `text`
+pub fn osc52_sequence(text: &str) -> String {
+    format!(
+        "\x1b]52;c;{}\x07",
+        base64::engine::general_purpose::STANDARD.encode(text)
+    )
+}
+
+/// Write the escape to the controlling terminal, since stdout is usually captured
+fn copy_terminal(text: &str) -> Result<()> {
+    let terminal = if cfg!(windows) { "CONOUT$" } else { "/dev/tty" };
+    let mut tty = std::fs::OpenOptions::new()
+        .write(true)
+        .open(terminal)
+        .context("No terminal to copy through")?;
+    tty.write_all(osc52_sequence(text).as_bytes())
+        .context("Failed to write to the terminal")?;
+    Ok(())
+}
+
+#[cfg(all(feature = "clipboard", not(target_os = "linux")))]
+fn copy_system(text: &str) -> Result<()> {
+    arboard::Clipboard::new()
+        .and_then(|mut clipboard| clipboard.set_text(text))
+        .context("Failed to set the clipboard")
+}
+
+#[cfg(all(feature = "clipboard", target_os = "linux"))]
+fn copy_system(text: &str) -> Result<()> {
+    use std::process::{Command, Stdio};
+
+    // Fail here, rather than in the detached process, when there is no display
+    arboard::Clipboard::new().context("Failed to open the clipboard")?;
+    let mut child = Command::new(std::env::current_exe().context("Failed to find the qai binary")?)
+        .arg(HOLD_COMMAND)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .context("Failed to start the clipboard process")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(text.as_bytes())
+            .context("Failed to pass the text to the clipboard process")?;
+    }
+    Ok(())
+}
+
+/// Set the clipboard to `text` and serve it until something else is copied (`qai __copy-hold`)
+#[cfg(all(feature = "clipboard", target_os = "linux"))]
+pub fn hold(text: &str) -> Result<()> {
+    use arboard::SetExtLinux;
+
+    arboard::Clipboard::new()
+        .and_then(|mut clipboard| clipboard.set().wait().text(text))
+        .context("Failed to set the clipboard")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_osc52_sequence() {
+        assert_eq!(osc52_sequence("ls -la"), "\x1b]52;c;bHMgLWxh\x07");
+    }
+
+    #[test]
+    fn test_osc52_sequence_keeps_multi_line_commands() {
+        let sequence = osc52_sequence("cat <<EOF\nhi\nEOF");
+        let encoded = sequence.trim_start_matches("\x1b]52;c;").trim_end_matches('\x07');
+        let decoded = base64::engine::general_purpose::STANDARD.decode(encoded).unwrap();
+        assert_eq!(decoded, b"cat <<EOF\nhi\nEOF");
+    }
+}
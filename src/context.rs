@@ -0,0 +1,827 @@
+//! Context providers for qai
+//!
+//! Each provider contributes a block of text describing the user's
+//! environment or workflow, which is appended to the system prompt.
+//! Sections are trimmed lowest priority first to keep the prompt within
+//! a token budget. Scripts dropped into `~/.config/qai/context.d` add a
+//! section each, named after the script, when they exit 0 within a deadline.
+
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Placeholder for redacted secrets
+pub const REDACTED: &str = "***";
+
+/// Name fragments that mark a variable or flag as holding a secret
+const SENSITIVE_NAMES: &[&str] = &[
+    "password",
+    "passwd",
+    "secret",
+    "token",
+    "apikey",
+    "api_key",
+    "api-key",
+    "auth",
+    "credential",
+    "private_key",
+    "private-key",
+];
+
+/// Prefixes of well-known API token formats
+const TOKEN_PREFIXES: &[&str] = &["sk-", "ghp_", "gho_", "github_pat_", "glpat-", "xoxb-", "xoxp-", "AKIA"];
+
+/// A source of extra prompt context
+pub trait ContextProvider {
+    /// Context to add to the prompt, if any
+    fn get_context(&self) -> Option<String>;
+
+    /// Higher = more important, included first
+    fn priority(&self) -> u8;
+}
+
+/// A block of extra prompt context
+#[derive(Debug, Clone, PartialEq)]
+pub struct PromptSection {
+    /// Higher = more important, trimmed last
+    pub priority: u8,
+    pub text: String,
+}
+
+impl PromptSection {
+    pub fn new(priority: u8, text: impl Into<String>) -> Self {
+        Self {
+            priority,
+            text: text.into(),
+        }
+    }
+}
+
+/// Turn each provider's context into a section with the provider's priority
+pub fn provider_sections(providers: &[Box<dyn ContextProvider>]) -> Vec<PromptSection> {
+    providers
+        .iter()
+        .filter_map(|p| p.get_context().map(|text| PromptSection::new(p.priority(), text)))
+        .collect()
+}
+
+/// Rough token count (~4 characters per token)
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// The user message for a query with attached input (`qai query --context`): the query, then
+/// the input in a fenced block, cut to its first `max_chars` characters (0 = no limit)
+pub fn attach_input(query: &str, input: &str, max_chars: usize) -> String {
+    let input = input.trim_end();
+    if input.trim().is_empty() {
+        return query.to_string();
+    }
+    let (input, note) = match input.char_indices().nth(max_chars).filter(|_| max_chars > 0) {
+        Some((end, _)) => (
+            &input[..end],
+            format!("\n({} more characters not shown)", input[end..].chars().count()),
+        ),
+        None => (input, String::new()),
+    };
+    format!("{}\n\nInput I'm referring to:\n```\n{}\n```{}", query, input, note)
+}
+
+/// Append sections to the base prompt, keeping the total under `max_tokens`
+/// Sections are admitted highest priority first; the first one that doesn't fit is
+/// cut to whole lines, and the rest are dropped. Kept sections stay in their original order.
+/// A `max_tokens` of 0 disables the limit.
+pub fn fit_to_budget(base: &str, sections: Vec<PromptSection>, max_tokens: usize) -> String {
+    let sections: Vec<PromptSection> = sections.into_iter().filter(|s| !s.text.trim().is_empty()).collect();
+
+    let mut order: Vec<usize> = (0..sections.len()).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse(sections[i].priority));
+
+    let mut kept: Vec<Option<String>> = vec![None; sections.len()];
+    let mut remaining = if max_tokens == 0 {
+        usize::MAX
+    } else {
+        max_tokens.saturating_sub(estimate_tokens(base))
+    };
+
+    for i in order {
+        let text = &sections[i].text;
+        // Account for the blank line separating sections
+        let cost = estimate_tokens(text) + 1;
+        if cost <= remaining {
+            remaining -= cost;
+            kept[i] = Some(text.clone());
+            continue;
+        }
+
+        let truncated = truncate_lines(text, remaining.saturating_sub(1));
+        if truncated.is_empty() {
+            log::info!(
+                "Dropped prompt section (priority {}) to fit token budget",
+                sections[i].priority
+            );
+        } else {
+            log::info!(
+                "Truncated prompt section (priority {}) to fit token budget",
+                sections[i].priority
+            );
+            kept[i] = Some(truncated);
+        }
+        remaining = 0;
+    }
+
+    let mut prompt = base.to_string();
+    for text in kept.into_iter().flatten() {
+        prompt.push_str("\n\n");
+        prompt.push_str(&text);
+    }
+    prompt
+}
+
+/// Keep the leading whole lines of `text` that fit in `max_tokens`
+fn truncate_lines(text: &str, max_tokens: usize) -> String {
+    let mut truncated = String::new();
+    for line in text.lines() {
+        let candidate_len = truncated.chars().count() + line.chars().count() + 1;
+        if candidate_len.div_ceil(4) > max_tokens {
+            break;
+        }
+        truncated.push_str(line);
+        truncated.push('\n');
+    }
+    truncated
+}
+
+/// Recent shell history entries, with secrets redacted
+pub struct ShellHistoryProvider {
+    /// Raw history, oldest first (one command per line)
+    history: Option<String>,
+    /// Number of entries to include
+    entries: usize,
+}
+
+impl ShellHistoryProvider {
+    /// Read recent history passed by the shell integration (QAI_RECENT_HISTORY),
+    /// falling back to the history file
+    pub fn from_env(entries: usize) -> Self {
+        let history = std::env::var("QAI_RECENT_HISTORY")
+            .ok()
+            .filter(|h| !h.trim().is_empty())
+            .map(String::into_bytes)
+            .or_else(|| history_file().and_then(|path| fs::read(path).ok()))
+            .map(|bytes| String::from_utf8_lossy(&bytes).into_owned());
+        Self { history, entries }
+    }
+
+    /// Use the given history text
+    #[allow(dead_code)]
+    pub fn new(history: &str, entries: usize) -> Self {
+        Self {
+            history: Some(history.to_string()),
+            entries,
+        }
+    }
+}
+
+impl ContextProvider for ShellHistoryProvider {
+    fn get_context(&self) -> Option<String> {
+        let entries = recent_entries(self.history.as_deref()?, self.entries);
+        if entries.is_empty() {
+            return None;
+        }
+
+        let lines: Vec<String> = entries.iter().map(|entry| format!("- {}", entry)).collect();
+        Some(format!("Recent shell history (oldest first):\n{}\n", lines.join("\n")))
+    }
+
+    fn priority(&self) -> u8 {
+        2
+    }
+}
+
+/// Names of the top-level entries of a directory (directories get a trailing `/`)
+pub struct DirectoryListingProvider {
+    dir: PathBuf,
+    /// Maximum number of names to include
+    max_entries: usize,
+}
+
+impl DirectoryListingProvider {
+    pub fn new(dir: &Path, max_entries: usize) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            max_entries,
+        }
+    }
+}
+
+impl ContextProvider for DirectoryListingProvider {
+    fn get_context(&self) -> Option<String> {
+        let mut names: Vec<String> = fs::read_dir(&self.dir)
+            .ok()?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
+                    format!("{}/", name)
+                } else {
+                    name
+                }
+            })
+            .collect();
+        if names.is_empty() {
+            return None;
+        }
+        names.sort();
+
+        let total = names.len();
+        names.truncate(self.max_entries);
+        let mut listing = names.join(", ");
+        if total > names.len() {
+            listing.push_str(&format!(", ... ({} more)", total - names.len()));
+        }
+        Some(format!("Files in the working directory: {}\n", listing))
+    }
+
+    fn priority(&self) -> u8 {
+        3
+    }
+}
+
+/// Values of explicitly whitelisted environment variables
+pub struct EnvVarsProvider {
+    /// (name, value) pairs, in whitelist order
+    vars: Vec<(String, String)>,
+}
+
+impl EnvVarsProvider {
+    /// Read the whitelisted variables from the environment; unset or empty ones are skipped
+    pub fn from_env(names: &[String]) -> Self {
+        let vars = names
+            .iter()
+            .filter_map(|name| {
+                let value = std::env::var(name).ok().filter(|v| !v.is_empty())?;
+                Some((name.clone(), value))
+            })
+            .collect();
+        Self { vars }
+    }
+
+    /// Use the given (name, value) pairs
+    #[allow(dead_code)]
+    pub fn new(vars: &[(&str, &str)]) -> Self {
+        Self {
+            vars: vars.iter().map(|(n, v)| (n.to_string(), v.to_string())).collect(),
+        }
+    }
+}
+
+impl ContextProvider for EnvVarsProvider {
+    fn get_context(&self) -> Option<String> {
+        if self.vars.is_empty() {
+            return None;
+        }
+
+        let lines: Vec<String> = self
+            .vars
+            .iter()
+            .map(|(name, value)| format!("- {}={}", name, value))
+            .collect();
+        Some(format!("Environment variables:\n{}\n", lines.join("\n")))
+    }
+
+    fn priority(&self) -> u8 {
+        4
+    }
+}
+
+/// Priority of sections from context scripts
+const SCRIPT_PRIORITY: u8 = 3;
+
+/// The drop-in directory of context scripts, `~/.config/qai/context.d`
+pub fn scripts_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("qai").join("context.d"))
+}
+
+/// The `*.sh` files in `dir`, sorted by name
+pub fn context_scripts(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut scripts: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "sh"))
+        .collect();
+    scripts.sort();
+    scripts
+}
+
+/// How a context script ended
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptStatus {
+    /// Exited 0, with its trimmed stdout
+    Ok(String),
+    /// Exited unsuccessfully
+    Failed(String),
+    /// Still running at the deadline, so it was killed
+    TimedOut,
+    /// Could not be started
+    Error(String),
+}
+
+/// The result of running one context script
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptRun {
+    /// File name without `.sh`, which names its prompt section
+    pub name: String,
+    pub path: PathBuf,
+    pub status: ScriptStatus,
+    pub elapsed: Duration,
+}
+
+impl ScriptRun {
+    /// The prompt section for a script that exited 0 with some output
+    pub fn section(&self) -> Option<PromptSection> {
+        match &self.status {
+            ScriptStatus::Ok(output) if !output.is_empty() => Some(PromptSection::new(
+                SCRIPT_PRIORITY,
+                format!("{} (from a context script):\n{}\n", self.name, output),
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// A started script waiting to finish
+struct Running {
+    child: Child,
+    /// Receives stdout once the script closes it
+    output: Receiver<Vec<u8>>,
+}
+
+/// Run every context script in `dir` at once with `sh`, in the current directory; scripts
+/// still running after `timeout` are killed. Results are in script order
+pub fn run_scripts(dir: &Path, timeout: Duration) -> Vec<ScriptRun> {
+    let start = Instant::now();
+    let deadline = start + timeout;
+    let scripts = context_scripts(dir);
+
+    let mut statuses: Vec<Option<(ScriptStatus, Duration)>> = vec![None; scripts.len()];
+    let mut running: Vec<(usize, Running)> = Vec::new();
+    for (i, path) in scripts.iter().enumerate() {
+        match start_script(path) {
+            Ok(script) => running.push((i, script)),
+            Err(e) => statuses[i] = Some((ScriptStatus::Error(e.to_string()), start.elapsed())),
+        }
+    }
+
+    while !running.is_empty() {
+        let mut still_running = Vec::new();
+        for (i, mut script) in running {
+            match script.child.try_wait() {
+                Ok(Some(status)) => {
+                    // Output can stay open after the script exits, when it left something running
+                    let wait = deadline.saturating_duration_since(Instant::now());
+                    statuses[i] = Some(match script.output.recv_timeout(wait) {
+                        Ok(output) if status.success() => (
+                            ScriptStatus::Ok(String::from_utf8_lossy(&output).trim().to_string()),
+                            start.elapsed(),
+                        ),
+                        Ok(_) => (ScriptStatus::Failed(status.to_string()), start.elapsed()),
+                        Err(_) => (ScriptStatus::TimedOut, timeout),
+                    });
+                }
+                Ok(None) if Instant::now() >= deadline => {
+                    let _ = script.child.kill();
+                    let _ = script.child.wait();
+                    statuses[i] = Some((ScriptStatus::TimedOut, timeout));
+                }
+                Ok(None) => still_running.push((i, script)),
+                Err(e) => statuses[i] = Some((ScriptStatus::Error(e.to_string()), start.elapsed())),
+            }
+        }
+        running = still_running;
+        if !running.is_empty() {
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    scripts
+        .into_iter()
+        .zip(statuses)
+        .map(|(path, status)| {
+            let (status, elapsed) = status.unwrap_or((ScriptStatus::TimedOut, timeout));
+            let name = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+            ScriptRun {
+                name,
+                path,
+                status,
+                elapsed,
+            }
+        })
+        .collect()
+}
+
+/// Start a script, reading its stdout on a separate thread so a chatty script never blocks
+fn start_script(path: &Path) -> std::io::Result<Running> {
+    let mut child = Command::new("sh")
+        .arg(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let (sender, output) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = stdout.read_to_end(&mut buf);
+        let _ = sender.send(buf);
+    });
+    Ok(Running { child, output })
+}
+
+/// Project marker files and the project type they indicate
+const PROJECT_MARKERS: &[(&str, &str)] = &[
+    ("Cargo.toml", "rust"),
+    ("package.json", "node"),
+    ("pyproject.toml", "python"),
+    ("go.mod", "go"),
+];
+
+/// Node lockfiles and the package manager that wrote them
+const NODE_LOCKFILES: &[(&str, &str)] = &[
+    ("pnpm-lock.yaml", "pnpm"),
+    ("yarn.lock", "yarn"),
+    ("bun.lockb", "bun"),
+    ("package-lock.json", "npm"),
+];
+
+/// Detect the project type from marker files in `dir` or its nearest ancestor that has any
+/// e.g. "rust", "node (pnpm)", or "rust, python" for mixed projects
+pub fn detect_project_type(dir: &Path) -> Option<String> {
+    dir.ancestors().find_map(|dir| {
+        let types: Vec<String> = PROJECT_MARKERS
+            .iter()
+            .filter(|(marker, _)| dir.join(marker).is_file())
+            .map(|(_, project_type)| match *project_type {
+                "node" => match NODE_LOCKFILES.iter().find(|(lockfile, _)| dir.join(lockfile).is_file()) {
+                    Some((_, manager)) => format!("node ({})", manager),
+                    None => "node".to_string(),
+                },
+                other => other.to_string(),
+            })
+            .collect();
+        (!types.is_empty()).then(|| types.join(", "))
+    })
+}
+
+/// The user's shell history file: $HISTFILE, ~/.zsh_history, or ~/.bash_history
+fn history_file() -> Option<PathBuf> {
+    if let Ok(path) = std::env::var("HISTFILE") {
+        return Some(PathBuf::from(path));
+    }
+    let home = dirs::home_dir()?;
+    [".zsh_history", ".bash_history"]
+        .into_iter()
+        .map(|name| home.join(name))
+        .find(|path| path.is_file())
+}
+
+/// Parse history text into the last `count` distinct commands, redacted
+/// Understands zsh extended history (`: <time>:<duration>;<command>`) and `\` continuations
+pub fn recent_entries(history: &str, count: usize) -> Vec<String> {
+    let mut commands: Vec<String> = Vec::new();
+    let mut continued = false;
+
+    for line in history.lines() {
+        let line = line.trim_end();
+        if continued && let Some(last) = commands.last_mut() {
+            last.push('\n');
+            last.push_str(line.trim_end_matches('\\'));
+        } else {
+            let command = match line.strip_prefix(": ").and_then(|rest| rest.split_once(';')) {
+                Some((_, command)) => command,
+                None => line,
+            };
+            commands.push(command.trim_end_matches('\\').trim().to_string());
+        }
+        continued = line.ends_with('\\');
+    }
+
+    commands.retain(|cmd| !cmd.is_empty());
+    commands.dedup();
+
+    let start = commands.len().saturating_sub(count);
+    commands[start..].iter().map(|cmd| redact(cmd)).collect()
+}
+
+/// Mask values that look like secrets: sensitive assignments and flags, bearer and API tokens
+pub fn redact(command: &str) -> String {
+    let mut redact_next = false;
+
+    command
+        .split(' ')
+        .map(|word| {
+            if std::mem::take(&mut redact_next) && !word.is_empty() {
+                // Keep a closing quote so the command stays balanced
+                let closing = &word[word.trim_end_matches(['\'', '"']).len()..];
+                return format!("{}{}", REDACTED, closing);
+            }
+            if let Some((name, _)) = word.split_once('=')
+                && is_sensitive_name(name)
+            {
+                return format!("{}={}", name, REDACTED);
+            }
+            let bare = word.trim_matches(['\'', '"']);
+            if bare.eq_ignore_ascii_case("bearer") || (word.starts_with('-') && is_sensitive_name(word)) {
+                redact_next = true;
+            }
+            if TOKEN_PREFIXES.iter().any(|prefix| bare.starts_with(prefix)) && bare.len() >= 16 {
+                return REDACTED.to_string();
+            }
+            word.to_string()
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Check if a variable or flag name suggests it holds a secret
+fn is_sensitive_name(name: &str) -> bool {
+    let name = name.to_lowercase();
+    SENSITIVE_NAMES.iter().any(|s| name.contains(s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fixed(&'static str, u8);
+
+    impl ContextProvider for Fixed {
+        fn get_context(&self) -> Option<String> {
+            Some(self.0.to_string())
+        }
+
+        fn priority(&self) -> u8 {
+            self.1
+        }
+    }
+
+    #[test]
+    fn test_provider_sections_keep_priority() {
+        let providers: Vec<Box<dyn ContextProvider>> = vec![Box::new(Fixed("low", 1)), Box::new(Fixed("high", 9))];
+        assert_eq!(
+            provider_sections(&providers),
+            vec![PromptSection::new(1, "low"), PromptSection::new(9, "high")]
+        );
+    }
+
+    #[test]
+    fn test_attach_input() {
+        let listing = "-rw-r--r-- a.tmp\n-rw-r--r-- b.rs\n";
+        assert_eq!(
+            attach_input("delete the .tmp ones", listing, 0),
+            "delete the .tmp ones\n\nInput I'm referring to:\n```\n-rw-r--r-- a.tmp\n-rw-r--r-- b.rs\n```"
+        );
+        assert_eq!(attach_input("list files", " \n\n", 100), "list files");
+    }
+
+    #[test]
+    fn test_attach_input_truncates() {
+        let message = attach_input("sum these", "ééééé", 3);
+        assert!(message.ends_with("```\nééé\n```\n(2 more characters not shown)"));
+        assert!(!attach_input("sum these", "ééééé", 5).contains("not shown"));
+    }
+
+    #[test]
+    fn test_estimate_tokens() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("abcd"), 1);
+        assert_eq!(estimate_tokens("abcde"), 2);
+    }
+
+    #[test]
+    fn test_fit_to_budget_keeps_everything_when_it_fits() {
+        let sections = vec![
+            PromptSection::new(1, "aaaa"),
+            PromptSection::new(5, ""),
+            PromptSection::new(9, "bbbb"),
+        ];
+        assert_eq!(fit_to_budget("base", sections, 100), "base\n\naaaa\n\nbbbb");
+    }
+
+    #[test]
+    fn test_fit_to_budget_drops_lowest_priority() {
+        let sections = vec![
+            PromptSection::new(1, "x".repeat(40)),
+            PromptSection::new(9, "y".repeat(40)),
+        ];
+        // base = 1 token, high = 10 + 1, leaving too little for any of the low section
+        let prompt = fit_to_budget("base", sections, 13);
+        assert_eq!(prompt, format!("base\n\n{}", "y".repeat(40)));
+    }
+
+    #[test]
+    fn test_fit_to_budget_truncates_to_whole_lines() {
+        let sections = vec![PromptSection::new(1, "line one\nline two\nline three\n")];
+        // base = 1 token, 1 for the separator, 3 for "line one\n"
+        let prompt = fit_to_budget("base", sections, 5);
+        assert_eq!(prompt, "base\n\nline one\n");
+    }
+
+    #[test]
+    fn test_fit_to_budget_unlimited() {
+        let sections = vec![PromptSection::new(1, "z".repeat(10_000))];
+        assert_eq!(fit_to_budget("", sections, 0).len(), 10_002);
+    }
+
+    #[test]
+    fn test_recent_entries_plain_history() {
+        let history = "ls\ncd src\ncargo build\ncargo build\ncargo test\n";
+        assert_eq!(recent_entries(history, 3), vec!["cd src", "cargo build", "cargo test"]);
+    }
+
+    #[test]
+    fn test_recent_entries_zsh_extended_history() {
+        let history = ": 1700000000:0;git status\n: 1700000005:2;make \\\n  test\n";
+        assert_eq!(recent_entries(history, 10), vec!["git status", "make\n  test"]);
+    }
+
+    #[test]
+    fn test_redact_sensitive_assignments() {
+        assert_eq!(redact("GITHUB_TOKEN=abc123 gh pr list"), "GITHUB_TOKEN=*** gh pr list");
+        assert_eq!(redact("export DB_PASSWORD=hunter2"), "export DB_PASSWORD=***");
+        assert_eq!(redact("FOO=bar make"), "FOO=bar make");
+    }
+
+    #[test]
+    fn test_redact_sensitive_flags_and_tokens() {
+        assert_eq!(
+            redact("login --password hunter2 --user me"),
+            "login --password *** --user me"
+        );
+        assert_eq!(redact("tool --api-key=xyz"), "tool --api-key=***");
+        assert_eq!(
+            redact(r#"curl -H "Authorization: Bearer abcdef" https://x"#),
+            r#"curl -H "Authorization: Bearer ***" https://x"#
+        );
+        assert_eq!(redact("echo sk-abcdefghijklmnopqrstuvwxyz"), "echo ***");
+    }
+
+    #[test]
+    fn test_shell_history_provider_context() {
+        let provider = ShellHistoryProvider::new("ls\nMY_SECRET=x ./deploy.sh\n", 5);
+        assert_eq!(
+            provider.get_context().as_deref(),
+            Some("Recent shell history (oldest first):\n- ls\n- MY_SECRET=*** ./deploy.sh\n")
+        );
+    }
+
+    #[test]
+    fn test_directory_listing_provider() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("build.sh"), "").unwrap();
+        fs::write(temp_dir.path().join("release.tar.gz"), "").unwrap();
+        fs::create_dir(temp_dir.path().join("src")).unwrap();
+
+        let provider = DirectoryListingProvider::new(temp_dir.path(), 10);
+        assert_eq!(
+            provider.get_context().as_deref(),
+            Some("Files in the working directory: build.sh, release.tar.gz, src/\n")
+        );
+    }
+
+    #[test]
+    fn test_directory_listing_provider_capped() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        for name in ["a", "b", "c", "d"] {
+            fs::write(temp_dir.path().join(name), "").unwrap();
+        }
+
+        let provider = DirectoryListingProvider::new(temp_dir.path(), 2);
+        assert_eq!(
+            provider.get_context().as_deref(),
+            Some("Files in the working directory: a, b, ... (2 more)\n")
+        );
+    }
+
+    #[test]
+    fn test_directory_listing_provider_empty_dir() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        assert_eq!(DirectoryListingProvider::new(temp_dir.path(), 10).get_context(), None);
+    }
+
+    #[test]
+    fn test_detect_project_type_in_ancestor() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("Cargo.toml"), "").unwrap();
+        let nested = temp_dir.path().join("src").join("bin");
+        fs::create_dir_all(&nested).unwrap();
+
+        assert_eq!(detect_project_type(&nested).as_deref(), Some("rust"));
+    }
+
+    #[test]
+    fn test_detect_project_type_node_package_manager() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("package.json"), "{}").unwrap();
+        assert_eq!(detect_project_type(temp_dir.path()).as_deref(), Some("node"));
+
+        fs::write(temp_dir.path().join("pnpm-lock.yaml"), "").unwrap();
+        assert_eq!(detect_project_type(temp_dir.path()).as_deref(), Some("node (pnpm)"));
+    }
+
+    #[test]
+    fn test_detect_project_type_mixed_nearest_wins() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("go.mod"), "").unwrap();
+        let sub = temp_dir.path().join("tools");
+        fs::create_dir(&sub).unwrap();
+        fs::write(sub.join("Cargo.toml"), "").unwrap();
+        fs::write(sub.join("pyproject.toml"), "").unwrap();
+
+        assert_eq!(detect_project_type(&sub).as_deref(), Some("rust, python"));
+        assert_eq!(detect_project_type(temp_dir.path()).as_deref(), Some("go"));
+    }
+
+    #[test]
+    fn test_env_vars_provider_context() {
+        let provider = EnvVarsProvider::new(&[("AWS_PROFILE", "staging"), ("VIRTUAL_ENV", "/work/.venv")]);
+        assert_eq!(
+            provider.get_context().as_deref(),
+            Some("Environment variables:\n- AWS_PROFILE=staging\n- VIRTUAL_ENV=/work/.venv\n")
+        );
+        assert_eq!(EnvVarsProvider::new(&[]).get_context(), None);
+    }
+
+    #[test]
+    fn test_env_vars_provider_only_whitelisted() {
+        let provider = EnvVarsProvider::from_env(&["QAI_TEST_SURELY_UNSET_VAR".to_string()]);
+        assert_eq!(provider.get_context(), None);
+
+        let provider = EnvVarsProvider::from_env(&["PATH".to_string()]);
+        let context = provider.get_context().unwrap();
+        assert!(context.contains("- PATH="));
+        assert!(!context.contains("HOME="));
+    }
+
+    #[test]
+    fn test_shell_history_provider_empty() {
+        assert_eq!(ShellHistoryProvider::new("\n\n", 5).get_context(), None);
+    }
+
+    #[cfg(unix)]
+    fn script(dir: &Path, name: &str, body: &str) {
+        fs::write(dir.join(name), body).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_scripts_sections_from_successful_scripts() {
+        let dir = tempfile::TempDir::new().unwrap();
+        script(dir.path(), "git-status.sh", "echo '## main'\necho ' M src/lib.rs'\n");
+        script(dir.path(), "broken.sh", "echo partial; exit 3\n");
+        script(dir.path(), "quiet.sh", "true\n");
+        script(dir.path(), "notes.txt", "echo not a script\n");
+
+        let runs = run_scripts(dir.path(), Duration::from_secs(5));
+        let names: Vec<&str> = runs.iter().map(|run| run.name.as_str()).collect();
+        assert_eq!(names, vec!["broken", "git-status", "quiet"]);
+        assert!(matches!(runs[0].status, ScriptStatus::Failed(_)));
+        assert_eq!(runs[1].status, ScriptStatus::Ok("## main\n M src/lib.rs".to_string()));
+
+        let sections: Vec<PromptSection> = runs.iter().filter_map(ScriptRun::section).collect();
+        assert_eq!(
+            sections,
+            vec![PromptSection::new(
+                SCRIPT_PRIORITY,
+                "git-status (from a context script):\n## main\n M src/lib.rs\n"
+            )]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_scripts_kills_slow_scripts() {
+        let dir = tempfile::TempDir::new().unwrap();
+        script(dir.path(), "slow.sh", "sleep 5; echo late\n");
+        script(dir.path(), "fast.sh", "echo fast\n");
+
+        let start = Instant::now();
+        let runs = run_scripts(dir.path(), Duration::from_millis(300));
+        assert!(start.elapsed() < Duration::from_secs(3));
+        assert_eq!(runs[0].status, ScriptStatus::Ok("fast".to_string()));
+        assert_eq!(runs[1].status, ScriptStatus::TimedOut);
+        assert_eq!(runs[1].section(), None);
+    }
+
+    #[test]
+    fn test_run_scripts_missing_dir() {
+        assert!(run_scripts(Path::new("/nonexistent/qai/context.d"), Duration::from_millis(10)).is_empty());
+    }
+}
@@ -0,0 +1,393 @@
+//! `qai cron`: crontab lines checked and described before they are used
+//!
+//! The model writes the line; the schedule is then validated field by field
+//! (ranges, steps, lists, month and weekday names, `@daily`-style macros) and
+//! described here rather than by the model, so the explanation always matches
+//! what cron will do.
+
+use eyre::{Result, eyre};
+
+/// Name, lowest value, highest value, and value names of the five schedule fields
+const FIELDS: [(&str, u32, u32, &[&str]); 5] = [
+    ("minute", 0, 59, &[]),
+    ("hour", 0, 23, &[]),
+    ("day of month", 1, 31, &[]),
+    ("month", 1, 12, &MONTHS),
+    ("day of week", 0, 7, &WEEKDAYS),
+];
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+const MONTH_NAMES: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+const WEEKDAY_NAMES: [&str; 8] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+    "Sunday",
+];
+
+/// Schedule macros and what they mean
+const MACROS: &[(&str, &str)] = &[
+    ("@reboot", "at startup"),
+    ("@yearly", "at 00:00 on January 1"),
+    ("@annually", "at 00:00 on January 1"),
+    ("@monthly", "at 00:00 on day 1 of every month"),
+    ("@weekly", "at 00:00 every Sunday"),
+    ("@daily", "at 00:00 every day"),
+    ("@midnight", "at 00:00 every day"),
+    ("@hourly", "at minute 0 of every hour"),
+];
+
+/// A validated crontab line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronLine {
+    /// Five fields, or a single `@` macro
+    pub schedule: Vec<String>,
+    pub command: String,
+}
+
+impl CronLine {
+    /// Parse and validate `line` (`m h dom mon dow command` or `@daily command`)
+    pub fn parse(line: &str) -> Result<Self> {
+        let line = line.trim();
+        if line.starts_with('@') {
+            let (schedule, command) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            if !MACROS.iter().any(|(name, _)| *name == schedule) {
+                return Err(eyre!("unknown schedule {}", schedule));
+            }
+            return Self::new(vec![schedule.to_string()], command);
+        }
+
+        let mut rest = line;
+        let mut schedule = Vec::new();
+        for (name, min, max, names) in FIELDS {
+            let (field, tail) = rest
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| eyre!("expected five schedule fields and a command"))?;
+            check_field(field, min, max, names).map_err(|e| eyre!("{} field '{}': {}", name, field, e))?;
+            schedule.push(field.to_string());
+            rest = tail.trim_start();
+        }
+        Self::new(schedule, rest)
+    }
+
+    fn new(schedule: Vec<String>, command: &str) -> Result<Self> {
+        let command = command.trim();
+        if command.is_empty() {
+            return Err(eyre!("the line has no command"));
+        }
+        Ok(Self {
+            schedule,
+            command: escape_percent(command),
+        })
+    }
+
+    /// The schedule in words, e.g. "at 02:30 on Monday through Friday"
+    pub fn describe(&self) -> String {
+        if let [name] = self.schedule.as_slice() {
+            let meaning = MACROS.iter().find(|(macro_name, _)| macro_name == name);
+            return meaning.map(|(_, meaning)| meaning.to_string()).unwrap_or_default();
+        }
+        let [minute, hour, dom, month, dow] = [0, 1, 2, 3, 4].map(|i| self.schedule[i].as_str());
+
+        let time = match (number(minute), number(hour)) {
+            (Some(m), Some(h)) => format!("at {:02}:{:02}", h, m),
+            (Some(m), None) if hour == "*" => format!("at minute {} of every hour", m),
+            (Some(m), None) => format!("at minute {} of {}", m, field_words(hour, Unit::Hour)),
+            (None, _) if minute == "*" && hour == "*" => "every minute".to_string(),
+            (None, _) if hour == "*" => field_words(minute, Unit::Minute),
+            (None, _) => format!(
+                "{} of {}",
+                field_words(minute, Unit::Minute),
+                field_words(hour, Unit::Hour)
+            ),
+        };
+
+        // cron runs when either day field matches if both are restricted
+        let days = match (dom, dow) {
+            ("*", "*") => "every day".to_string(),
+            ("*", _) => format!("on {}", field_words(dow, Unit::Weekday)),
+            (_, "*") => format!("on {} of the month", field_words(dom, Unit::Day)),
+            _ => format!(
+                "on {} of the month and on {}",
+                field_words(dom, Unit::Day),
+                field_words(dow, Unit::Weekday)
+            ),
+        };
+        let months = match month {
+            "*" => String::new(),
+            _ => format!(" in {}", field_words(month, Unit::Month)),
+        };
+        format!("{} {}{}", time, days, months)
+    }
+}
+
+impl std::fmt::Display for CronLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.schedule.join(" "), self.command)
+    }
+}
+
+/// cron turns a bare `%` in the command into a newline, so escape the ones that aren't yet
+pub fn escape_percent(command: &str) -> String {
+    let mut escaped = String::with_capacity(command.len());
+    let mut previous = None;
+    for c in command.chars() {
+        if c == '%' && previous != Some('\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+        previous = Some(c);
+    }
+    escaped
+}
+
+/// `crontab` (the output of `crontab -l`) with `line` appended, or None when it is already there
+pub fn append_line(crontab: &str, line: &CronLine) -> Option<String> {
+    let line = line.to_string();
+    if crontab.lines().any(|existing| existing.trim() == line) {
+        return None;
+    }
+    let mut updated = crontab.to_string();
+    if !updated.is_empty() && !updated.ends_with('\n') {
+        updated.push('\n');
+    }
+    updated.push_str(&line);
+    updated.push('\n');
+    Some(updated)
+}
+
+fn number(field: &str) -> Option<u32> {
+    field.parse().ok()
+}
+
+/// Check one field: `*`, values, `a-b` ranges, `/n` steps, and comma lists, with names allowed
+/// (case-insensitively) for months and weekdays
+fn check_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<()> {
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, Some(step)),
+            None => (part, None),
+        };
+        if let Some(step) = step {
+            match step.parse::<u32>() {
+                Ok(step) if step > 0 && step <= max => {}
+                _ => return Err(eyre!("invalid step '{}'", step)),
+            }
+        }
+        if range == "*" {
+            continue;
+        }
+        let (start, end) = range.split_once('-').unwrap_or((range, range));
+        let start = value(start, min, max, names)?;
+        let end = value(end, min, max, names)?;
+        if start > end {
+            return Err(eyre!("range {} is backwards", range));
+        }
+    }
+    Ok(())
+}
+
+fn value(text: &str, min: u32, max: u32, names: &[&str]) -> Result<u32> {
+    // Months count from 1, weekdays from 0 (Sunday)
+    let offset = if names.len() == MONTHS.len() { 1 } else { 0 };
+    let parsed = text.parse::<u32>().ok().or_else(|| {
+        names
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(text))
+            .map(|i| i as u32 + offset)
+    });
+    match parsed {
+        Some(v) if (min..=max).contains(&v) => Ok(v),
+        Some(v) => Err(eyre!("{} is outside {}-{}", v, min, max)),
+        None => Err(eyre!("'{}' is not a number", text)),
+    }
+}
+
+/// What a schedule field counts, for describing it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Unit {
+    Minute,
+    Hour,
+    Day,
+    Month,
+    Weekday,
+}
+
+impl Unit {
+    fn plural(self) -> &'static str {
+        match self {
+            Unit::Minute => "minutes",
+            Unit::Hour => "hours",
+            Unit::Day | Unit::Weekday => "days",
+            Unit::Month => "months",
+        }
+    }
+
+    /// A value in words: months and weekdays by name, the others as numbers
+    fn value(self, text: &str) -> String {
+        let (abbreviations, names, offset): (&[&str], &[&str], usize) = match self {
+            Unit::Month => (&MONTHS, &MONTH_NAMES, 1),
+            Unit::Weekday => (&WEEKDAYS, &WEEKDAY_NAMES, 0),
+            _ => return text.to_string(),
+        };
+        let index = match text.parse::<usize>() {
+            Ok(number) => number.checked_sub(offset),
+            Err(_) => abbreviations.iter().position(|name| name.eq_ignore_ascii_case(text)),
+        };
+        index
+            .and_then(|i| names.get(i))
+            .map_or_else(|| text.to_string(), |name| name.to_string())
+    }
+}
+
+/// A field in words: "every 15 minutes", "hours 9 through 17", "Monday through Friday"
+fn field_words(field: &str, unit: Unit) -> String {
+    let named = matches!(unit, Unit::Month | Unit::Weekday);
+    let parts: Vec<&str> = field.split(',').collect();
+    // A list of plain values: "minutes 0 and 30", "January and July"
+    if parts.iter().all(|part| !part.contains(['-', '/', '*'])) {
+        let values: Vec<String> = parts.iter().map(|part| unit.value(part)).collect();
+        return match (named, values.len()) {
+            (true, _) => join_words(&values),
+            (false, 1) => format!("{} {}", unit.plural().trim_end_matches('s'), values[0]),
+            (false, _) => format!("{} {}", unit.plural(), join_words(&values)),
+        };
+    }
+
+    let words: Vec<String> = parts
+        .iter()
+        .map(|part| {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => (range, Some(step)),
+                None => (*part, None),
+            };
+            let range = match range.split_once('-') {
+                Some((start, end)) => format!("{} through {}", unit.value(start), unit.value(end)),
+                None if range == "*" => String::new(),
+                None => unit.value(range),
+            };
+            match (step, range.is_empty()) {
+                (Some(step), true) => format!("every {} {}", step, unit.plural()),
+                (Some(step), false) => format!("every {} {} from {}", step, unit.plural(), range),
+                (None, _) if named => range,
+                (None, _) => format!("{} {}", unit.plural(), range),
+            }
+        })
+        .collect();
+    join_words(&words)
+}
+
+/// "a", "a and b", "a, b and c"
+fn join_words(words: &[String]) -> String {
+    match words {
+        [] => String::new(),
+        [word] => word.clone(),
+        [init @ .., last] => format!("{} and {}", init.join(", "), last),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cron_line() {
+        let line = CronLine::parse("30 2 * * 1-5 /usr/local/bin/backup.sh --full").unwrap();
+        assert_eq!(line.schedule, vec!["30", "2", "*", "*", "1-5"]);
+        assert_eq!(line.command, "/usr/local/bin/backup.sh --full");
+        assert_eq!(line.to_string(), "30 2 * * 1-5 /usr/local/bin/backup.sh --full");
+
+        let line = CronLine::parse("@daily  find /tmp -mtime +7 -delete").unwrap();
+        assert_eq!(line.schedule, vec!["@daily"]);
+        assert_eq!(line.command, "find /tmp -mtime +7 -delete");
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid_schedules() {
+        for line in [
+            "60 * * * * job",
+            "* 24 * * * job",
+            "* * 0 * * job",
+            "* * * 13 * job",
+            "* * * * 8 job",
+            "*/0 * * * * job",
+            "5-1 * * * * job",
+            "* * * foo * job",
+            "* * * * *",
+            "* * * job",
+            "@sometimes job",
+            "@daily",
+        ] {
+            assert!(CronLine::parse(line).is_err(), "{}", line);
+        }
+        assert!(CronLine::parse("0 9 * jan-mar MON-FRI job").is_ok());
+        assert!(CronLine::parse("0,30 */2 1,15 * 0 job").is_ok());
+    }
+
+    #[test]
+    fn test_escape_percent() {
+        assert_eq!(
+            escape_percent("tar czf /b/$(date +%F).tgz ~"),
+            "tar czf /b/$(date +\\%F).tgz ~"
+        );
+        assert_eq!(escape_percent("date +\\%F"), "date +\\%F");
+        let line = CronLine::parse("0 0 * * * date +%s >> /tmp/t").unwrap();
+        assert_eq!(line.command, "date +\\%s >> /tmp/t");
+    }
+
+    #[test]
+    fn test_append_line() {
+        let line = CronLine::parse("0 3 * * * /usr/bin/backup").unwrap();
+        assert_eq!(append_line("", &line).unwrap(), "0 3 * * * /usr/bin/backup\n");
+        assert_eq!(
+            append_line("MAILTO=me\n@reboot /bin/true", &line).unwrap(),
+            "MAILTO=me\n@reboot /bin/true\n0 3 * * * /usr/bin/backup\n"
+        );
+        assert_eq!(append_line("0 3 * * * /usr/bin/backup\n", &line), None);
+    }
+
+    #[test]
+    fn test_describe() {
+        let describe = |line: &str| CronLine::parse(line).unwrap().describe();
+        assert_eq!(describe("30 2 * * * job"), "at 02:30 every day");
+        assert_eq!(describe("0 9 * * 1-5 job"), "at 09:00 on Monday through Friday");
+        assert_eq!(describe("*/15 * * * * job"), "every 15 minutes every day");
+        assert_eq!(describe("5 * * * * job"), "at minute 5 of every hour every day");
+        assert_eq!(describe("0 0 1 * * job"), "at 00:00 on day 1 of the month");
+        assert_eq!(
+            describe("0 6 * jan,jul sun job"),
+            "at 06:00 on Sunday in January and July"
+        );
+        assert_eq!(
+            describe("0 0 13 * 5 job"),
+            "at 00:00 on day 13 of the month and on Friday"
+        );
+        assert_eq!(
+            describe("0,30 9-17 * * * job"),
+            "minutes 0 and 30 of hours 9 through 17 every day"
+        );
+        assert_eq!(describe("0 */6 * * * job"), "at minute 0 of every 6 hours every day");
+        assert_eq!(describe("@weekly job"), "at 00:00 every Sunday");
+    }
+}
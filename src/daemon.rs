@@ -0,0 +1,384 @@
+//! Daemon mode: a warm HTTP client shared over a unix socket
+//!
+//! `qai daemon` keeps one connection pool open, so queries skip the TCP and TLS
+//! handshakes that dominate a fresh process's latency. `OpenAIClient` checks for
+//! the socket and sends its chat request through the daemon when one is
+//! listening, falling back to a direct request otherwise. Only the HTTP request
+//! goes through the daemon: the prompt is built by the calling process, from its
+//! own config, tool cache, history, working directory, and environment. Each connection
+//! carries one JSON line each way: a `DaemonRequest`, then a `DaemonResponse`.
+//! While the API is rate limiting, requests from all connections wait in one
+//! queue (see [`crate::ratelimit`]), and the daemon sends a `DaemonResponse`
+//! with just `queued` set whenever a waiting request's place changes.
+
+use eyre::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::unix::OwnedWriteHalf;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::mpsc;
+
+use crate::api::SEND_FAILED;
+use crate::config::RateLimitConfig;
+use crate::ratelimit::Pacer;
+
+/// A chat completions request to forward
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DaemonRequest {
+    pub url: String,
+    pub api_key: Option<String>,
+    pub body: serde_json::Value,
+    pub timeout_secs: u64,
+    /// How the client wants 429s retried
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+}
+
+/// The upstream status and body, or why the request couldn't be sent
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DaemonResponse {
+    pub status: u16,
+    pub body: String,
+    pub error: Option<String>,
+    /// Set, and nothing else, while the request waits out a rate limit: requests ahead of it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub queued: Option<usize>,
+}
+
+/// Socket path: QAI_DAEMON_SOCKET, else `qai.sock` in the runtime directory (or the cache
+/// directory where there is none)
+pub fn socket_path() -> PathBuf {
+    if let Some(path) = std::env::var_os("QAI_DAEMON_SOCKET").filter(|path| !path.is_empty()) {
+        return PathBuf::from(path);
+    }
+    match dirs::runtime_dir() {
+        Some(dir) => dir.join("qai.sock"),
+        None => crate::paths::cache_dir().join("qai.sock"),
+    }
+}
+
+/// Serve requests on `path` until the process is stopped
+pub async fn serve(path: &Path) -> Result<()> {
+    if ping(path).await {
+        return Err(eyre::eyre!("A daemon is already listening on {}", path.display()));
+    }
+    // Left over from a daemon that didn't shut down cleanly
+    if path.exists() {
+        std::fs::remove_file(path).context("Failed to remove stale socket")?;
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).context("Failed to create socket directory")?;
+    }
+
+    let listener = bind_private(path).context(format!("Failed to listen on {}", path.display()))?;
+    log::info!("Daemon listening on {}", path.display());
+
+    let client = reqwest::Client::builder()
+        .pool_idle_timeout(Duration::from_secs(300))
+        .build()
+        .context("Failed to create HTTP client")?;
+    let pacer = Arc::new(Pacer::new());
+    loop {
+        let (stream, _) = listener.accept().await.context("Failed to accept connection")?;
+        let client = client.clone();
+        let pacer = pacer.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, &client, &pacer).await {
+                log::warn!("Daemon connection failed: {:#}", e);
+            }
+        });
+    }
+}
+
+/// Listen on `path` where only the owner can ever connect (requests carry API keys): the
+/// socket is bound in a new 0700 directory next to it, restricted to 0600, then moved into place
+fn bind_private(path: &Path) -> Result<UnixListener> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let private = dir.join(format!(".qai-daemon.{}", std::process::id()));
+    // Left over from an earlier daemon with the same pid
+    let _ = std::fs::remove_dir_all(&private);
+    std::fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&private)
+        .context("Failed to create private socket directory")?;
+    let staged = private.join("qai.sock");
+    let bound = UnixListener::bind(&staged)
+        .context("Failed to bind socket")
+        .and_then(|listener| {
+            std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o600))
+                .context("Failed to restrict socket permissions")?;
+            std::fs::rename(&staged, path).context("Failed to move socket into place")?;
+            Ok(listener)
+        });
+    let _ = std::fs::remove_dir_all(&private);
+    bound
+}
+
+async fn handle_connection(stream: UnixStream, client: &reqwest::Client, pacer: &Pacer) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut line = String::new();
+    BufReader::new(reader)
+        .read_line(&mut line)
+        .await
+        .context("Failed to read request")?;
+    let request: DaemonRequest = serde_json::from_str(&line).context("Failed to parse request")?;
+
+    // Queue positions are written as they come, until the request has been answered
+    let (positions, mut queued) = mpsc::unbounded_channel();
+    let sending = send(client, pacer, &request, positions);
+    tokio::pin!(sending);
+    let result = loop {
+        tokio::select! {
+            result = &mut sending => break result,
+            Some(ahead) = queued.recv() => {
+                let position = DaemonResponse {
+                    status: 0,
+                    body: String::new(),
+                    error: None,
+                    queued: Some(ahead),
+                };
+                write_line(&mut writer, &position).await?;
+            }
+        }
+    };
+
+    let response = match result {
+        Ok((status, body)) => DaemonResponse {
+            status,
+            body,
+            error: None,
+            queued: None,
+        },
+        Err(e) => DaemonResponse {
+            status: 0,
+            body: String::new(),
+            error: Some(format!("{:#}", e)),
+            queued: None,
+        },
+    };
+    write_line(&mut writer, &response).await
+}
+
+async fn write_line(writer: &mut OwnedWriteHalf, response: &DaemonResponse) -> Result<()> {
+    let mut out = serde_json::to_string(response).context("Failed to serialize response")?;
+    out.push('\n');
+    writer
+        .write_all(out.as_bytes())
+        .await
+        .context("Failed to write response")
+}
+
+async fn send(
+    client: &reqwest::Client,
+    pacer: &Pacer,
+    request: &DaemonRequest,
+    positions: mpsc::UnboundedSender<usize>,
+) -> Result<(u16, String)> {
+    let build = || {
+        let mut builder = client
+            .post(&request.url)
+            .timeout(Duration::from_secs(request.timeout_secs))
+            .json(&request.body);
+        if let Some(key) = &request.api_key {
+            builder = builder.header("Authorization", format!("Bearer {}", key));
+        }
+        async move { builder.send().await.context(SEND_FAILED) }
+    };
+    let response = pacer
+        .send(&request.rate_limit, build, &mut |ahead| {
+            let _ = positions.send(ahead);
+        })
+        .await?;
+    let status = response.status().as_u16();
+    let body = response.text().await.context("Failed to read response body")?;
+    Ok((status, body))
+}
+
+/// Whether a daemon is listening on `path`
+pub async fn ping(path: &Path) -> bool {
+    UnixStream::connect(path).await.is_ok()
+}
+
+/// Send `request` through the daemon on `path`, calling `on_queued` with the requests ahead
+/// while it waits out a rate limit; None when no daemon is listening
+pub async fn forward(
+    path: &Path,
+    request: &DaemonRequest,
+    on_queued: &mut (dyn FnMut(usize) + Send),
+) -> Option<Result<DaemonResponse>> {
+    let stream = UnixStream::connect(path).await.ok()?;
+    Some(exchange(stream, request, on_queued).await)
+}
+
+async fn exchange(
+    stream: UnixStream,
+    request: &DaemonRequest,
+    on_queued: &mut (dyn FnMut(usize) + Send),
+) -> Result<DaemonResponse> {
+    let (reader, mut writer) = stream.into_split();
+    let mut line = serde_json::to_string(request).context("Failed to serialize request")?;
+    line.push('\n');
+    writer
+        .write_all(line.as_bytes())
+        .await
+        .context("Failed to send request to daemon")?;
+
+    let mut reader = BufReader::new(reader);
+    loop {
+        let mut reply = String::new();
+        reader
+            .read_line(&mut reply)
+            .await
+            .context("Failed to read daemon response")?;
+        let response: DaemonResponse = serde_json::from_str(&reply).context("Failed to parse daemon response")?;
+        match response.queued {
+            Some(ahead) => on_queued(ahead),
+            None => return Ok(response),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    async fn start_daemon(socket: &Path) {
+        let path = socket.to_path_buf();
+        tokio::spawn(async move { serve(&path).await });
+        while UnixStream::connect(socket).await.is_err() {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    fn request(url: String) -> DaemonRequest {
+        DaemonRequest {
+            url,
+            api_key: Some("sk-test".to_string()),
+            body: serde_json::json!({"model": "gpt-4o-mini"}),
+            timeout_secs: 5,
+            rate_limit: RateLimitConfig::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_forward_through_daemon() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(header("Authorization", "Bearer sk-test"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("ok"))
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("qai.sock");
+        start_daemon(&socket).await;
+        assert!(ping(&socket).await);
+
+        let url = format!("{}/chat/completions", mock_server.uri());
+        for _ in 0..2 {
+            let response = forward(&socket, &request(url.clone()), &mut |_| {})
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(response.status, 200);
+            assert_eq!(response.body, "ok");
+            assert_eq!(response.error, None);
+        }
+
+        // A second daemon on the same socket is refused
+        assert!(serve(&socket).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_socket_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("qai.sock");
+        start_daemon(&socket).await;
+        let mode = std::fs::metadata(&socket).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        // The staging directory is gone
+        let entries: Vec<_> = std::fs::read_dir(dir.path()).unwrap().collect();
+        assert_eq!(entries.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_forward_reports_send_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("qai.sock");
+        start_daemon(&socket).await;
+
+        let response = forward(
+            &socket,
+            &request("http://127.0.0.1:1/chat/completions".to_string()),
+            &mut |_| {},
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(response.status, 0);
+        assert!(response.error.unwrap().contains("Failed to send request"));
+    }
+
+    #[tokio::test]
+    async fn test_forward_reports_queue_position() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(429))
+            .up_to_n_times(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("ok"))
+            .mount(&mock_server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("qai.sock");
+        start_daemon(&socket).await;
+
+        let mut request = request(mock_server.uri());
+        request.rate_limit.backoff_ms = 20;
+        let mut queued = Vec::new();
+        let response = forward(&socket, &request, &mut |ahead| queued.push(ahead))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, "ok");
+        // Waited out the 429 at the head of the queue
+        assert_eq!(queued, vec![0]);
+    }
+
+    #[tokio::test]
+    async fn test_forward_without_daemon() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("qai.sock");
+        assert!(
+            forward(&socket, &request("http://localhost/".to_string()), &mut |_| {})
+                .await
+                .is_none()
+        );
+
+        assert!(!ping(&socket).await);
+
+        // A stale socket file from a dead daemon
+        std::fs::write(&socket, "").unwrap();
+        assert!(!ping(&socket).await);
+        assert!(
+            forward(&socket, &request("http://localhost/".to_string()), &mut |_| {})
+                .await
+                .is_none()
+        );
+    }
+}
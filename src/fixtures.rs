@@ -0,0 +1,181 @@
+//! Canned answers for `provider: mock` and `provider: record`
+//!
+//! A fixture is a JSON file in the fixtures directory (`paths.fixtures`, or
+//! `QAI_FIXTURES_DIR`) holding the user message it answers and the answer:
+//! `{"query": "list files", "response": "ls -la"}`. In mock mode each request
+//! is answered with the fixture whose query is its last user message, or else
+//! with a fixture without a query (a catch-all). Record mode sends requests to
+//! the API as usual and saves every answer as a fixture, so fixtures can be
+//! recorded once and replayed without an API key, e.g. in CI.
+
+use eyre::{Context, Result, eyre};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+/// Characters of the query kept in a recorded fixture's file name
+const NAME_CHARS: usize = 40;
+
+/// One canned answer
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Fixture {
+    /// The user message answered; a fixture without one answers anything not matched otherwise
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub query: Option<String>,
+    /// The model's answer
+    pub response: String,
+    /// The model that gave the answer, when recorded (not used for matching)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+}
+
+/// The fixtures directory
+#[derive(Debug, Clone)]
+pub struct Fixtures {
+    dir: PathBuf,
+}
+
+impl Fixtures {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// The configured fixtures directory
+    pub fn open() -> Self {
+        Self::new(crate::paths::fixtures_dir())
+    }
+
+    /// Every fixture in the directory, by file name; unreadable files are skipped with a warning
+    pub fn load(&self) -> Result<Vec<Fixture>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).context(format!("Failed to read {}", self.dir.display())),
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect();
+        paths.sort();
+
+        let mut fixtures = Vec::new();
+        for path in paths {
+            let parsed = fs::read_to_string(&path)
+                .map_err(eyre::Report::from)
+                .and_then(|content| serde_json::from_str(&content).map_err(eyre::Report::from));
+            match parsed {
+                Ok(fixture) => fixtures.push(fixture),
+                Err(e) => log::warn!("Skipping fixture {}: {}", path.display(), e),
+            }
+        }
+        Ok(fixtures)
+    }
+
+    /// The canned answer to a user message
+    pub fn replay(&self, query: &str) -> Result<String> {
+        let fixtures = self.load()?;
+        fixtures
+            .iter()
+            .find(|fixture| fixture.query.as_deref().is_some_and(|q| q.trim() == query.trim()))
+            .or_else(|| fixtures.iter().find(|fixture| fixture.query.is_none()))
+            .map(|fixture| fixture.response.clone())
+            .ok_or_else(|| {
+                eyre!(
+                    "No fixture in {} answers: {} (record one with `provider: record`)",
+                    self.dir.display(),
+                    query
+                )
+            })
+    }
+
+    /// Save an answer as a fixture, replacing one recorded for the same message
+    pub fn record(&self, query: &str, model: &str, response: &str) -> Result<PathBuf> {
+        fs::create_dir_all(&self.dir).context(format!("Failed to create {}", self.dir.display()))?;
+        let fixture = Fixture {
+            query: Some(query.to_string()),
+            response: response.to_string(),
+            model: Some(model.to_string()),
+        };
+        let path = self.dir.join(fixture_name(query));
+        let content = serde_json::to_string_pretty(&fixture).context("Failed to serialize fixture")?;
+        fs::write(&path, format!("{}\n", content)).context(format!("Failed to write {}", path.display()))?;
+        Ok(path)
+    }
+}
+
+/// File name of a recorded fixture: the start of the query, and a hash of all of it so
+/// different queries with the same start get different files
+fn fixture_name(query: &str) -> String {
+    let mut slug = String::new();
+    for c in query.trim().chars().take(NAME_CHARS) {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_matches('-');
+    let slug = if slug.is_empty() { "query" } else { slug };
+    format!("{}-{:08x}.json", slug, fnv1a(query.trim()) as u32)
+}
+
+/// FNV-1a, which unlike the std hasher is the same across Rust releases
+fn fnv1a(text: &str) -> u64 {
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_record_and_replay() {
+        let temp_dir = TempDir::new().unwrap();
+        let fixtures = Fixtures::new(temp_dir.path().join("fixtures"));
+        assert!(fixtures.replay("list files").is_err());
+
+        let path = fixtures.record("list files", "gpt-4o-mini", "ls -la").unwrap();
+        assert!(path.file_name().unwrap().to_string_lossy().starts_with("list-files-"));
+        assert_eq!(fixtures.replay("list files").unwrap(), "ls -la");
+        assert!(fixtures.replay("show disk usage").is_err());
+
+        // Recording the same message again replaces the answer
+        fixtures.record("list files", "gpt-4o-mini", "ls -lah").unwrap();
+        assert_eq!(fixtures.load().unwrap().len(), 1);
+        assert_eq!(fixtures.replay("list files").unwrap(), "ls -lah");
+    }
+
+    #[test]
+    fn test_replay_catch_all() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("any.json"), r#"{"response": "echo ok"}"#).unwrap();
+        fs::write(temp_dir.path().join("broken.json"), "{").unwrap();
+        fs::write(temp_dir.path().join("notes.txt"), "not a fixture").unwrap();
+        fs::write(
+            temp_dir.path().join("pwd.json"),
+            r#"{"query": "where am I", "response": "pwd"}"#,
+        )
+        .unwrap();
+
+        let fixtures = Fixtures::new(temp_dir.path().to_path_buf());
+        assert_eq!(fixtures.load().unwrap().len(), 2);
+        assert_eq!(fixtures.replay("where am I").unwrap(), "pwd");
+        assert_eq!(fixtures.replay("anything else").unwrap(), "echo ok");
+    }
+
+    #[test]
+    fn test_fixture_name() {
+        assert_eq!(
+            fixture_name("List files!"),
+            format!("list-files-{:08x}.json", fnv1a("List files!") as u32)
+        );
+        assert!(fixture_name("???").starts_with("query-"));
+        assert_ne!(
+            fixture_name(&format!("{}a", "x".repeat(50))),
+            fixture_name(&format!("{}b", "x".repeat(50)))
+        );
+    }
+}
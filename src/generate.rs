@@ -0,0 +1,237 @@
+//! `qai regex` and `qai jq`: generate an expression from a description
+//!
+//! With sample input (`--test`, read from stdin), the expression is tried on it:
+//! regexes with the `regex` crate, jq filters with the installed `jq`. An
+//! expression that fails to compile or run is sent back to the model once,
+//! with the error, for a fix.
+
+use eyre::Result;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use crate::prompt::{load_jq_prompt, load_regex_prompt};
+
+/// Regex syntax asked for without `--flavor`: what grep -E, rg, Python, and JavaScript all accept
+pub const PORTABLE_FLAVOR: &str = "portable syntax (no lookaround or backreferences)";
+
+/// Sample lines shown when reporting a test
+const MAX_REPORTED_LINES: usize = 10;
+
+/// What to generate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Generator {
+    Regex,
+    Jq,
+}
+
+impl Generator {
+    pub fn name(self) -> &'static str {
+        match self {
+            Generator::Regex => "regex",
+            Generator::Jq => "jq filter",
+        }
+    }
+
+    /// The system prompt template (`~/.config/qai/prompts/regex.pmt` or `jq.pmt` when present)
+    pub fn load_prompt(self) -> Result<String> {
+        match self {
+            Generator::Regex => load_regex_prompt(),
+            Generator::Jq => load_jq_prompt(),
+        }
+    }
+
+    /// The user message for a description; `flavor` is the regex syntax to use
+    pub fn request(self, description: &str, flavor: Option<&str>) -> String {
+        match self {
+            Generator::Regex => format!(
+                "Write a regex in {} that matches: {}",
+                flavor.unwrap_or(PORTABLE_FLAVOR),
+                description
+            ),
+            Generator::Jq => format!("Write a jq filter that: {}", description),
+        }
+    }
+
+    /// Try the expression on the sample input
+    pub fn test(self, expression: &str, sample: &str) -> Tested {
+        match self {
+            Generator::Regex => test_regex(expression, sample),
+            Generator::Jq => test_jq(expression, sample),
+        }
+    }
+}
+
+/// Outcome of trying an expression on sample input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Tested {
+    /// It ran; the lines to report (matching lines, or the filter's output)
+    Ran(Vec<String>),
+    /// It doesn't compile or run: the error
+    Failed(String),
+    /// It couldn't be tried (e.g. jq isn't installed)
+    Skipped(String),
+}
+
+/// The expression in a response: the first line that isn't a code fence, without backticks,
+/// `/.../` delimiters, or shell quotes around it
+pub fn clean_expression(response: &str) -> String {
+    let line = response
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with("```"))
+        .unwrap_or_default();
+    let line = strip_pair(line, '`');
+    let line = strip_pair(line, '\'');
+    match line.strip_prefix('/').and_then(|rest| rest.strip_suffix('/')) {
+        Some(inner) if !inner.is_empty() => inner.to_string(),
+        _ => line.to_string(),
+    }
+}
+
+fn strip_pair(text: &str, delimiter: char) -> &str {
+    text.strip_prefix(delimiter)
+        .and_then(|rest| rest.strip_suffix(delimiter))
+        .filter(|inner| !inner.is_empty())
+        .unwrap_or(text)
+}
+
+/// The sample lines the regex matches
+pub fn test_regex(pattern: &str, sample: &str) -> Tested {
+    match regex::Regex::new(pattern) {
+        Ok(regex) => Tested::Ran(
+            sample
+                .lines()
+                .filter(|line| regex.is_match(line))
+                .map(str::to_string)
+                .collect(),
+        ),
+        Err(e) => Tested::Failed(e.to_string()),
+    }
+}
+
+/// The output of `jq <filter>` on the sample
+pub fn test_jq(filter: &str, sample: &str) -> Tested {
+    if which::which("jq").is_err() {
+        return Tested::Skipped("jq is not installed".to_string());
+    }
+    let child = Command::new("jq")
+        .arg(filter)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => return Tested::Skipped(format!("jq failed to start: {}", e)),
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        // jq may exit on a compile error before reading everything
+        let _ = stdin.write_all(sample.as_bytes());
+    }
+    match child.wait_with_output() {
+        Ok(output) if output.status.success() => Tested::Ran(
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .map(str::to_string)
+                .collect(),
+        ),
+        Ok(output) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            Tested::Failed(stderr.lines().next().unwrap_or("jq failed").trim().to_string())
+        }
+        Err(e) => Tested::Skipped(format!("jq failed: {}", e)),
+    }
+}
+
+/// Describe a test for stderr, in `# ...` lines
+pub fn report(generator: Generator, tested: &Tested, sample: &str) -> String {
+    let lines: &[String] = match tested {
+        Tested::Failed(error) => return format!("# ✗ {} failed on the sample: {}\n", generator.name(), error),
+        Tested::Skipped(reason) => return format!("# not tested: {}\n", reason),
+        Tested::Ran(lines) => lines,
+    };
+    let mut out = match generator {
+        Generator::Regex => format!("# {} of {} sample lines match\n", lines.len(), sample.lines().count()),
+        Generator::Jq => format!("# {} lines of output\n", lines.len()),
+    };
+    for line in lines.iter().take(MAX_REPORTED_LINES) {
+        out.push_str(&format!("#   {}\n", line));
+    }
+    if lines.len() > MAX_REPORTED_LINES {
+        out.push_str(&format!("#   ... {} more\n", lines.len() - MAX_REPORTED_LINES));
+    }
+    out
+}
+
+/// The message asking the model to fix an expression that failed on the sample
+pub fn fix_request(request: &str, expression: &str, error: &str) -> String {
+    format!(
+        "{}\n\nYour answer `{}` failed on the sample input: {}\nReply with a corrected one.",
+        request, expression, error
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clean_expression() {
+        assert_eq!(clean_expression("^\\d{3}-\\d{4}$"), "^\\d{3}-\\d{4}$");
+        assert_eq!(clean_expression("```\n.items[] | .name\n```"), ".items[] | .name");
+        assert_eq!(clean_expression("`[a-z]+`"), "[a-z]+");
+        assert_eq!(clean_expression("'.[] | select(.ok)'"), ".[] | select(.ok)");
+        assert_eq!(clean_expression("/^foo$/"), "^foo$");
+        assert_eq!(clean_expression("."), ".");
+        assert_eq!(clean_expression(""), "");
+    }
+
+    #[test]
+    fn test_regex_against_sample() {
+        let sample = "2024-01-05 ok\nnot a date\n1999-12-31 end\n";
+        assert_eq!(
+            test_regex(r"^\d{4}-\d{2}-\d{2}", sample),
+            Tested::Ran(vec!["2024-01-05 ok".to_string(), "1999-12-31 end".to_string()])
+        );
+        assert!(matches!(test_regex(r"(?<=a)b", sample), Tested::Failed(_)));
+    }
+
+    #[test]
+    fn test_jq_against_sample() {
+        let sample = r#"{"items": [{"name": "a"}, {"name": "b"}]}"#;
+        match test_jq(".items[].name", sample) {
+            Tested::Ran(lines) => assert_eq!(lines, vec!["\"a\"", "\"b\""]),
+            Tested::Skipped(_) => {} // jq not installed
+            Tested::Failed(error) => panic!("jq failed: {}", error),
+        }
+        assert!(!matches!(test_jq(".items[", sample), Tested::Ran(_)));
+    }
+
+    #[test]
+    fn test_report() {
+        let tested = Tested::Ran(vec!["a1".to_string()]);
+        assert_eq!(
+            report(Generator::Regex, &tested, "a1\nb\n"),
+            "# 1 of 2 sample lines match\n#   a1\n"
+        );
+        let many = Tested::Ran((0..12).map(|i| i.to_string()).collect());
+        assert!(report(Generator::Jq, &many, "").ends_with("#   ... 2 more\n"));
+        assert_eq!(
+            report(Generator::Jq, &Tested::Failed("syntax error".to_string()), ""),
+            "# ✗ jq filter failed on the sample: syntax error\n"
+        );
+    }
+
+    #[test]
+    fn test_request() {
+        assert_eq!(
+            Generator::Regex.request("US zip codes", Some("PCRE")),
+            "Write a regex in PCRE that matches: US zip codes"
+        );
+        assert!(Generator::Regex.request("US zip codes", None).contains(PORTABLE_FLAVOR));
+        assert_eq!(
+            Generator::Jq.request("lists the names", None),
+            "Write a jq filter that: lists the names"
+        );
+    }
+}
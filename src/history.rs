@@ -1,7 +1,8 @@
 //! History & Learning System for qai
 //!
 //! Tracks queries, results, selections, and corrections to personalize
-//! command suggestions over time.
+//! command suggestions over time. Exit codes of executed suggestions give each
+//! command pattern a success rate, and patterns that keep failing rank lower.
 
 #![allow(dead_code)] // APIs used in tests and will be used by shell integration
 
@@ -14,6 +15,18 @@ use std::io::{BufRead, BufReader, Write};
 use std::path::PathBuf;
 use uuid::Uuid;
 
+use crate::lock;
+use crate::parse;
+
+/// Failed runs of a command pattern before its suggestions are ranked lower
+const MIN_FAILURES: u32 = 2;
+
+/// Score taken off a suggestion whose pattern always failed (scaled by its failure rate)
+const FAILURE_PENALTY: f32 = 5.0;
+
+/// Share of a query's picks its preferred command needs for the query to count as settled
+const CONFIDENT_SHARE: f32 = 0.8;
+
 /// A single query interaction record
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QueryRecord {
@@ -38,11 +51,19 @@ pub struct QueryRecord {
     /// Whether the command was actually executed
     pub executed: bool,
 
+    /// Exit status of the executed command, reported by the shell integration
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+
     /// Working directory when query was made
     pub cwd: Option<PathBuf>,
 
     /// Model used for this query
     pub model: String,
+
+    /// Tokens the query's API requests used, for queries recorded when they were made
+    #[serde(default)]
+    pub tokens: Option<u64>,
 }
 
 impl QueryRecord {
@@ -56,8 +77,10 @@ impl QueryRecord {
             selected_index: None,
             edited_command: None,
             executed: false,
+            exit_code: None,
             cwd: std::env::current_dir().ok(),
             model,
+            tokens: None,
         }
     }
 
@@ -88,6 +111,14 @@ impl QueryRecord {
     }
 }
 
+/// How often a final command was used, across all queries
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandUsage {
+    pub command: String,
+    pub count: u32,
+    pub last_used: DateTime<Utc>,
+}
+
 /// Command selection statistics
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CommandSelection {
@@ -158,7 +189,64 @@ pub fn normalize_query(query: &str) -> String {
     query.trim().to_lowercase()
 }
 
+/// How often the commands of a pattern succeeded when run
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CommandOutcome {
+    pub successes: u32,
+    pub failures: u32,
+    pub last_run: Option<DateTime<Utc>>,
+}
+
+impl CommandOutcome {
+    /// Share of runs that succeeded (1.0 before any run)
+    pub fn success_rate(&self) -> f32 {
+        match self.successes + self.failures {
+            0 => 1.0,
+            runs => self.successes as f32 / runs as f32,
+        }
+    }
+
+    /// Failed often enough, and more often than not, to rank lower
+    pub fn keeps_failing(&self) -> bool {
+        self.failures >= MIN_FAILURES && self.success_rate() < 0.5
+    }
+}
+
+/// The pattern of a command that success rates are kept for: the binary and flags of each
+/// simple command, without operands, e.g. `tar -xzf a.tgz | less` -> `tar -xzf | less`
+pub fn command_pattern(command: &str) -> String {
+    parse::split_segments(command)
+        .into_iter()
+        .filter_map(|segment| {
+            let binary = parse::extract_binary(segment)?;
+            let mut words = vec![binary];
+            for word in parse::split_words(segment) {
+                if word.starts_with('-') && word.len() > 1 {
+                    let flag = word.split('=').next().unwrap_or_default().to_string();
+                    words.push(flag);
+                }
+            }
+            Some(words.join(" "))
+        })
+        .collect::<Vec<_>>()
+        .join(" | ")
+}
+
+/// Whether an exit code says the command failed. Codes above 128 mean it was killed by a
+/// signal, usually the user's Ctrl+C, which says nothing about the command
+fn is_failure(exit_code: i32) -> Option<bool> {
+    match exit_code {
+        0 => Some(false),
+        1..=128 => Some(true),
+        _ => None,
+    }
+}
+
 /// History store using flat files (JSON Lines format)
+///
+/// Many shells may write at once (e.g. one per tmux pane), so writes hold an
+/// advisory lock on `history.lock`, re-read patterns.json before updating it,
+/// and replace it atomically.
 #[derive(Debug)]
 pub struct HistoryStore {
     /// Directory where history files are stored
@@ -167,6 +255,9 @@ pub struct HistoryStore {
     /// In-memory cache of patterns
     patterns: HashMap<String, QueryPattern>,
 
+    /// Run outcomes by command pattern
+    outcomes: HashMap<String, CommandOutcome>,
+
     /// Whether patterns cache is dirty
     patterns_dirty: bool,
 }
@@ -185,21 +276,20 @@ impl HistoryStore {
         let mut store = Self {
             data_dir,
             patterns: HashMap::new(),
+            outcomes: HashMap::new(),
             patterns_dirty: false,
         };
 
-        // Load patterns from disk
+        // Load patterns and outcomes from disk
         store.load_patterns()?;
+        store.load_outcomes()?;
 
         Ok(store)
     }
 
     /// Get the default data directory
     pub fn default_data_dir() -> PathBuf {
-        dirs::data_local_dir()
-            .unwrap_or_else(|| PathBuf::from("."))
-            .join("qai")
-            .join("history")
+        crate::paths::history_dir()
     }
 
     /// Path to history.jsonl file
@@ -212,18 +302,67 @@ impl HistoryStore {
         self.data_dir.join("patterns.json")
     }
 
+    /// Path to outcomes.json, the success rates of command patterns
+    fn outcomes_path(&self) -> PathBuf {
+        self.data_dir.join("outcomes.json")
+    }
+
+    /// Path to the lock file guarding writes
+    fn lock_path(&self) -> PathBuf {
+        self.data_dir.join("history.lock")
+    }
+
+    /// Take the write lock, released when the returned file is dropped
+    fn lock(&self) -> Result<File> {
+        lock::acquire(&self.lock_path(), "history")
+    }
+
     /// Record a query and its results
     pub fn record_query(&mut self, record: &QueryRecord) -> Result<()> {
-        // Append to history.jsonl
+        let mut line = serde_json::to_string(record).context("Failed to serialize query record")?;
+        line.push('\n');
+
+        let _lock = self.lock()?;
+        // Append to history.jsonl in one write, so concurrent appends don't interleave
         let mut file = OpenOptions::new()
             .create(true)
             .append(true)
             .open(self.history_path())
             .context("Failed to open history file")?;
+        file.write_all(line.as_bytes())
+            .context("Failed to write to history file")?;
 
-        let json = serde_json::to_string(record).context("Failed to serialize query record")?;
-        writeln!(file, "{}", json).context("Failed to write to history file")?;
+        Ok(())
+    }
 
+    /// Record that a suggested command was run, as reported by the shell integration.
+    /// Only successful runs teach the pattern; edits before running are kept as the final command.
+    /// The exit code counts towards the success rate of the run command's pattern
+    pub fn record_execution(
+        &mut self,
+        query: &str,
+        suggested: &str,
+        executed: &str,
+        exit_code: i32,
+        model: &str,
+    ) -> Result<()> {
+        let mut record = QueryRecord::new(query.to_string(), vec![suggested.to_string()], model.to_string());
+        record.select(0);
+        if executed.trim() != suggested.trim() {
+            record.edit(executed.to_string());
+        }
+        record.execute();
+        record.exit_code = Some(exit_code);
+        self.record_query(&record)?;
+        if let Some(failed) = is_failure(exit_code) {
+            self.record_outcome(executed, failed)?;
+        }
+
+        if exit_code == 0
+            && let Some(command) = record.final_command()
+        {
+            self.record_selection(query, command)?;
+        }
         Ok(())
     }
 
@@ -231,6 +370,10 @@ impl HistoryStore {
     pub fn record_selection(&mut self, query: &str, command: &str) -> Result<()> {
         let normalized = normalize_query(query);
 
+        // Pick up selections other shells saved since this store was loaded
+        let _lock = self.lock()?;
+        self.load_patterns()?;
+
         // Update or create pattern
         let pattern = self
             .patterns
@@ -246,36 +389,110 @@ impl HistoryStore {
         Ok(())
     }
 
+    /// Count a run of a command towards its pattern's success rate
+    pub fn record_outcome(&mut self, command: &str, failed: bool) -> Result<()> {
+        let pattern = command_pattern(command);
+        if pattern.is_empty() {
+            return Ok(());
+        }
+
+        // Pick up runs other shells saved since this store was loaded
+        let _lock = self.lock()?;
+        self.load_outcomes()?;
+        let outcome = self.outcomes.entry(pattern).or_default();
+        if failed {
+            outcome.failures += 1;
+        } else {
+            outcome.successes += 1;
+        }
+        outcome.last_run = Some(Utc::now());
+
+        let path = self.outcomes_path();
+        let content = serde_json::to_string_pretty(&self.outcomes).context("Failed to serialize outcomes")?;
+        let tmp = path.with_extension(format!("json.{}.tmp", std::process::id()));
+        fs::write(&tmp, content).context("Failed to write outcomes file")?;
+        fs::rename(&tmp, &path).context("Failed to replace outcomes file")?;
+        Ok(())
+    }
+
+    /// How runs of a command's pattern went, if it was run before
+    pub fn outcome(&self, command: &str) -> Option<&CommandOutcome> {
+        self.outcomes.get(&command_pattern(command))
+    }
+
     /// Get pattern for a query if it exists
     pub fn get_pattern(&self, query: &str) -> Option<&QueryPattern> {
         let normalized = normalize_query(query);
         self.patterns.get(&normalized)
     }
 
-    /// Re-rank AI results based on user history
+    /// Re-rank AI results based on user history: commands picked for this query before come
+    /// first, and commands whose pattern keeps failing here go last
     pub fn personalize_results(&self, query: &str, ai_results: Vec<String>) -> Vec<String> {
-        let normalized = normalize_query(query);
+        if self.get_pattern(query).is_none() && self.outcomes.is_empty() {
+            // No history, return as-is
+            return ai_results;
+        }
 
-        if let Some(pattern) = self.patterns.get(&normalized) {
-            // Score each result based on history
-            let mut scored: Vec<(String, f32)> = ai_results
-                .into_iter()
-                .map(|cmd| {
-                    let score = self.score_command(&cmd, pattern);
-                    (cmd, score)
-                })
-                .collect();
+        // Score each result based on history
+        let mut scored: Vec<(String, f32)> = ai_results
+            .into_iter()
+            .map(|cmd| {
+                let score = self.preference(query, &cmd);
+                (cmd, score)
+            })
+            .collect();
 
-            // Sort by score descending
-            scored.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        // Sort by score descending; the sort is stable, so ties keep the model's order
+        scored.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
 
-            scored.into_iter().map(|(cmd, _)| cmd).collect()
-        } else {
-            // No history, return as-is
-            ai_results
+        scored.into_iter().map(|(cmd, _)| cmd).collect()
+    }
+
+    /// How much history favors a command for a query: a boost when it was picked for the query
+    /// before, less a penalty when its pattern keeps failing (0.0 without history)
+    pub fn preference(&self, query: &str, cmd: &str) -> f32 {
+        let score = self
+            .get_pattern(query)
+            .map_or(0.0, |pattern| self.score_command(cmd, pattern));
+        score - self.failure_penalty(cmd)
+    }
+
+    /// Score taken off a command whose pattern keeps failing, more the more often it failed
+    fn failure_penalty(&self, cmd: &str) -> f32 {
+        match self.outcome(cmd) {
+            Some(outcome) if outcome.keeps_failing() => FAILURE_PENALTY * (1.0 - outcome.success_rate()),
+            _ => 0.0,
         }
     }
 
+    /// Commands picked for this query before, most often picked first
+    pub fn past_commands(&self, query: &str) -> Vec<String> {
+        let Some(pattern) = self.patterns.get(&normalize_query(query)) else {
+            return Vec::new();
+        };
+        let mut selections: Vec<&CommandSelection> = pattern.command_history.iter().collect();
+        selections.sort_by_key(|s| std::cmp::Reverse(s.selection_count));
+        selections.into_iter().map(|s| s.command.clone()).collect()
+    }
+
+    /// The command a query is settled on: its preferred command, picked at least `min_picks`
+    /// times and for most of its picks, with a pattern that doesn't keep failing
+    pub fn confident_command(&self, query: &str, min_picks: u32) -> Option<&str> {
+        let pattern = self.get_pattern(query)?;
+        let preferred = pattern.preferred_command.as_deref()?;
+        let picks = |s: &CommandSelection| s.selection_count;
+        let total: u32 = pattern.command_history.iter().map(picks).sum();
+        let chosen = pattern
+            .command_history
+            .iter()
+            .find(|s| s.command == preferred)
+            .map_or(0, picks);
+        let failing = self.outcome(preferred).is_some_and(CommandOutcome::keeps_failing);
+        let settled = chosen >= min_picks.max(1) && chosen as f32 >= total as f32 * CONFIDENT_SHARE;
+        (settled && !failing).then_some(preferred)
+    }
+
     /// Score a command based on pattern history
     fn score_command(&self, cmd: &str, pattern: &QueryPattern) -> f32 {
         let mut score = 0.0;
@@ -300,6 +517,8 @@ impl HistoryStore {
     fn load_patterns(&mut self) -> Result<()> {
         let path = self.patterns_path();
         if !path.exists() {
+            // Never written, or cleared by another process
+            self.patterns.clear();
             return Ok(());
         }
 
@@ -310,6 +529,19 @@ impl HistoryStore {
         Ok(())
     }
 
+    /// Load command outcomes from disk
+    fn load_outcomes(&mut self) -> Result<()> {
+        let path = self.outcomes_path();
+        if !path.exists() {
+            self.outcomes.clear();
+            return Ok(());
+        }
+
+        let content = fs::read_to_string(&path).context("Failed to read outcomes file")?;
+        self.outcomes = serde_json::from_str(&content).unwrap_or_default();
+        Ok(())
+    }
+
     /// Save patterns to disk
     fn save_patterns(&mut self) -> Result<()> {
         if !self.patterns_dirty {
@@ -319,7 +551,10 @@ impl HistoryStore {
         let path = self.patterns_path();
         let content = serde_json::to_string_pretty(&self.patterns).context("Failed to serialize patterns")?;
 
-        fs::write(&path, content).context("Failed to write patterns file")?;
+        // Write then rename, so readers never see a partly written file
+        let tmp = path.with_extension(format!("json.{}.tmp", std::process::id()));
+        fs::write(&tmp, content).context("Failed to write patterns file")?;
+        fs::rename(&tmp, &path).context("Failed to replace patterns file")?;
 
         self.patterns_dirty = false;
         Ok(())
@@ -327,6 +562,36 @@ impl HistoryStore {
 
     /// Get recent queries (for history command)
     pub fn get_recent_queries(&self, limit: usize) -> Result<Vec<QueryRecord>> {
+        let records = self.read_records()?;
+        // Return last N records
+        let start = records.len().saturating_sub(limit);
+        Ok(records.into_iter().skip(start).collect())
+    }
+
+    /// The final commands of all queries, most often used first (most recently used first on
+    /// ties), with how often and when they were last used
+    pub fn top_commands(&self, limit: usize) -> Result<Vec<CommandUsage>> {
+        let mut usage: HashMap<String, CommandUsage> = HashMap::new();
+        for record in self.read_records()? {
+            let Some(command) = record.final_command() else {
+                continue;
+            };
+            let entry = usage.entry(command.to_string()).or_insert_with(|| CommandUsage {
+                command: command.to_string(),
+                count: 0,
+                last_used: record.timestamp,
+            });
+            entry.count += 1;
+            entry.last_used = entry.last_used.max(record.timestamp);
+        }
+        let mut top: Vec<CommandUsage> = usage.into_values().collect();
+        top.sort_by(|a, b| b.count.cmp(&a.count).then(b.last_used.cmp(&a.last_used)));
+        top.truncate(limit);
+        Ok(top)
+    }
+
+    /// Read all query records, oldest first, skipping lines that don't parse
+    pub fn read_records(&self) -> Result<Vec<QueryRecord>> {
         let path = self.history_path();
         if !path.exists() {
             return Ok(Vec::new());
@@ -335,9 +600,7 @@ impl HistoryStore {
         let file = File::open(&path).context("Failed to open history file")?;
         let reader = BufReader::new(file);
 
-        // Read all lines, keep last `limit` entries
         let mut records: Vec<QueryRecord> = Vec::new();
-
         for line in reader.lines() {
             let line = line.context("Failed to read history line")?;
             if line.trim().is_empty() {
@@ -348,27 +611,27 @@ impl HistoryStore {
                 records.push(record);
             }
         }
-
-        // Return last N records
-        let start = records.len().saturating_sub(limit);
-        Ok(records.into_iter().skip(start).collect())
+        Ok(records)
     }
 
     /// Get all patterns sorted by usage
     pub fn get_patterns_by_usage(&self) -> Vec<&QueryPattern> {
         let mut patterns: Vec<&QueryPattern> = self.patterns.values().collect();
-        patterns.sort_by(|a, b| b.query_count.cmp(&a.query_count));
+        patterns.sort_by_key(|p| std::cmp::Reverse(p.query_count));
         patterns
     }
 
     /// Clear all history
     pub fn clear(&mut self) -> Result<()> {
+        let _lock = self.lock()?;
         // Remove files
         let _ = fs::remove_file(self.history_path());
         let _ = fs::remove_file(self.patterns_path());
+        let _ = fs::remove_file(self.outcomes_path());
 
         // Clear in-memory cache
         self.patterns.clear();
+        self.outcomes.clear();
         self.patterns_dirty = false;
 
         Ok(())
@@ -388,6 +651,7 @@ impl HistoryStore {
             total_queries: query_count,
             unique_patterns: self.patterns.len(),
             patterns_with_preference: self.patterns.values().filter(|p| p.preferred_command.is_some()).count(),
+            failing_commands: self.outcomes.values().filter(|o| o.keeps_failing()).count(),
         })
     }
 }
@@ -397,6 +661,7 @@ impl Default for HistoryStore {
         Self::new().unwrap_or_else(|_| Self {
             data_dir: PathBuf::from("."),
             patterns: HashMap::new(),
+            outcomes: HashMap::new(),
             patterns_dirty: false,
         })
     }
@@ -408,6 +673,8 @@ pub struct HistoryStats {
     pub total_queries: usize,
     pub unique_patterns: usize,
     pub patterns_with_preference: usize,
+    /// Command patterns ranked lower because they keep failing
+    pub failing_commands: usize,
 }
 
 #[cfg(test)]
@@ -569,6 +836,43 @@ mod tests {
         assert_eq!(personalized, results);
     }
 
+    #[test]
+    fn test_history_store_past_commands() {
+        let (mut store, _temp_dir) = create_test_store();
+        assert!(store.past_commands("list files").is_empty());
+
+        store.record_selection("list files", "ls").unwrap();
+        store.record_selection("List Files", "ls -la").unwrap();
+        store.record_selection("list files", "ls -la").unwrap();
+
+        assert_eq!(store.past_commands("list files"), vec!["ls -la", "ls"]);
+    }
+
+    #[test]
+    fn test_history_store_confident_command() {
+        let (mut store, _temp_dir) = create_test_store();
+        assert_eq!(store.confident_command("list files", 3), None);
+
+        for _ in 0..4 {
+            store.record_selection("list files", "ls -la").unwrap();
+        }
+        assert_eq!(store.confident_command("List Files", 3), Some("ls -la"));
+        assert_eq!(store.confident_command("list files", 5), None);
+
+        // Picks spread over commands aren't settled
+        store.record_selection("list files", "eza").unwrap();
+        store.record_selection("list files", "ls").unwrap();
+        assert_eq!(store.confident_command("list files", 3), None);
+
+        // Nor is a command that keeps failing
+        for _ in 0..4 {
+            store.record_selection("show disk usage", "du -sh").unwrap();
+        }
+        store.record_outcome("du -sh", true).unwrap();
+        store.record_outcome("du -sh", true).unwrap();
+        assert_eq!(store.confident_command("show disk usage", 3), None);
+    }
+
     #[test]
     fn test_history_store_get_recent_queries() {
         let (mut store, _temp_dir) = create_test_store();
@@ -592,6 +896,35 @@ mod tests {
         assert!(recent.is_empty());
     }
 
+    #[test]
+    fn test_history_store_top_commands() {
+        let (mut store, _temp_dir) = create_test_store();
+        assert!(store.top_commands(10).unwrap().is_empty());
+
+        store
+            .record_execution("list files", "ls -la", "ls -la", 0, "model")
+            .unwrap();
+        store
+            .record_execution("show files", "ls -la", "ls -la", 1, "model")
+            .unwrap();
+        store
+            .record_execution("disk usage", "du -sh", "du -sh .", 0, "model")
+            .unwrap();
+        // Never picked: no final command
+        store
+            .record_query(&QueryRecord::new(
+                "free space".to_string(),
+                vec!["df -h".to_string()],
+                "model".to_string(),
+            ))
+            .unwrap();
+
+        let top = store.top_commands(10).unwrap();
+        let commands: Vec<(&str, u32)> = top.iter().map(|usage| (usage.command.as_str(), usage.count)).collect();
+        assert_eq!(commands, vec![("ls -la", 2), ("du -sh .", 1)]);
+        assert_eq!(store.top_commands(1).unwrap().len(), 1);
+    }
+
     #[test]
     fn test_history_store_get_patterns_by_usage() {
         let (mut store, _temp_dir) = create_test_store();
@@ -704,6 +1037,129 @@ mod tests {
         assert_eq!(deserialized.selection_count, selection.selection_count);
     }
 
+    #[test]
+    fn test_record_execution_success_teaches_pattern() {
+        let (mut store, _temp_dir) = create_test_store();
+
+        store
+            .record_execution("list files", "ls -la", "ls -la", 0, "model")
+            .unwrap();
+
+        let records = store.get_recent_queries(10).unwrap();
+        assert_eq!(records.len(), 1);
+        assert!(records[0].executed);
+        assert_eq!(records[0].exit_code, Some(0));
+        assert_eq!(records[0].edited_command, None);
+        assert_eq!(
+            store.get_pattern("list files").unwrap().preferred_command.as_deref(),
+            Some("ls -la")
+        );
+    }
+
+    #[test]
+    fn test_record_execution_edited_command() {
+        let (mut store, _temp_dir) = create_test_store();
+
+        store
+            .record_execution("list files", "ls -la", "ls -lah", 0, "model")
+            .unwrap();
+
+        let records = store.get_recent_queries(10).unwrap();
+        assert_eq!(records[0].final_command(), Some("ls -lah"));
+        assert_eq!(
+            store.get_pattern("list files").unwrap().preferred_command.as_deref(),
+            Some("ls -lah")
+        );
+    }
+
+    #[test]
+    fn test_record_execution_failure_does_not_teach() {
+        let (mut store, _temp_dir) = create_test_store();
+
+        store
+            .record_execution("list files", "ls --bogus", "ls --bogus", 2, "model")
+            .unwrap();
+
+        let records = store.get_recent_queries(10).unwrap();
+        assert_eq!(records[0].exit_code, Some(2));
+        assert!(store.get_pattern("list files").is_none());
+    }
+
+    #[test]
+    fn test_command_pattern() {
+        assert_eq!(command_pattern("ls --bogus src"), "ls --bogus");
+        assert_eq!(command_pattern("tar -xzf a.tgz | less"), "tar -xzf | less");
+        assert_eq!(command_pattern("sudo sort --key=2 -n data.txt"), "sort --key -n");
+        assert_eq!(command_pattern(""), "");
+    }
+
+    #[test]
+    fn test_record_execution_tracks_success_rate() {
+        let (mut store, temp_dir) = create_test_store();
+
+        store
+            .record_execution("list files", "ls --bogus", "ls --bogus a", 2, "model")
+            .unwrap();
+        store
+            .record_execution("list files", "ls --bogus", "ls --bogus b", 2, "model")
+            .unwrap();
+        store
+            .record_execution("list files", "ls --bogus", "ls --bogus c", 0, "model")
+            .unwrap();
+        // Interrupted with Ctrl+C: not counted
+        store
+            .record_execution("list files", "ls --bogus", "ls --bogus", 130, "model")
+            .unwrap();
+
+        let outcome = store.outcome("ls --bogus d").unwrap();
+        assert_eq!((outcome.successes, outcome.failures), (1, 2));
+        assert!((outcome.success_rate() - 1.0 / 3.0).abs() < f32::EPSILON);
+        assert!(outcome.keeps_failing());
+        assert!(store.outcome("ls -la").is_none());
+
+        // Seen by another store
+        let other = HistoryStore::with_data_dir(temp_dir.path().to_path_buf()).unwrap();
+        assert_eq!(other.outcome("ls --bogus").unwrap().failures, 2);
+    }
+
+    #[test]
+    fn test_personalize_results_down_ranks_failing_commands() {
+        let (mut store, _temp_dir) = create_test_store();
+        store.record_outcome("ls --bogus x", true).unwrap();
+        let results = vec!["ls --bogus".to_string(), "ls -la".to_string()];
+        // One failure isn't enough
+        assert_eq!(store.personalize_results("list files", results.clone()), results);
+
+        store.record_outcome("ls --bogus y", true).unwrap();
+        assert_eq!(
+            store.personalize_results("any query", results.clone()),
+            vec!["ls -la", "ls --bogus"]
+        );
+        assert_eq!(store.stats().unwrap().failing_commands, 1);
+
+        // A command picked many times before still outranks
+        for _ in 0..3 {
+            store.record_selection("list files", "ls --bogus").unwrap();
+        }
+        assert_eq!(
+            store.personalize_results("list files", results.clone())[0],
+            "ls --bogus"
+        );
+
+        store.clear().unwrap();
+        assert_eq!(store.personalize_results("any query", results.clone()), results);
+    }
+
+    #[test]
+    fn test_query_record_without_exit_code_deserializes() {
+        let record = QueryRecord::new("q".to_string(), vec!["ls".to_string()], "model".to_string());
+        let mut value = serde_json::to_value(&record).unwrap();
+        value.as_object_mut().unwrap().remove("exit_code");
+
+        let deserialized: QueryRecord = serde_json::from_value(value).unwrap();
+        assert_eq!(deserialized.exit_code, None);
+    }
+
     #[test]
     fn test_query_record_serialization() {
         let record = QueryRecord::new("test query".to_string(), vec!["cmd1".to_string()], "model".to_string());
@@ -736,6 +1192,7 @@ mod tests {
         assert_eq!(stats.total_queries, 0);
         assert_eq!(stats.unique_patterns, 0);
         assert_eq!(stats.patterns_with_preference, 0);
+        assert_eq!(stats.failing_commands, 0);
     }
 
     #[test]
@@ -774,4 +1231,46 @@ mod tests {
         assert_eq!(frequent.selection_count, 10);
         assert_eq!(rare.selection_count, 1);
     }
+
+    #[test]
+    fn test_concurrent_stores_keep_every_write() {
+        let temp_dir = TempDir::new().unwrap();
+        let shells: Vec<_> = (0..8)
+            .map(|shell| {
+                let data_dir = temp_dir.path().to_path_buf();
+                std::thread::spawn(move || {
+                    // Each shell loads its own store up front, as separate processes would
+                    let mut store = HistoryStore::with_data_dir(data_dir).unwrap();
+                    for i in 0..10 {
+                        let mut record = QueryRecord::new("query".into(), vec![], "model".into());
+                        record.edit(format!("cmd-{}-{}", shell, i));
+                        store.record_query(&record).unwrap();
+                        store.record_selection("query", "shared_cmd").unwrap();
+                    }
+                })
+            })
+            .collect();
+        for shell in shells {
+            shell.join().unwrap();
+        }
+
+        let store = HistoryStore::with_data_dir(temp_dir.path().to_path_buf()).unwrap();
+        let pattern = store.get_pattern("query").unwrap();
+        assert_eq!(pattern.command_history[0].selection_count, 80);
+        assert_eq!(store.get_recent_queries(1000).unwrap().len(), 80);
+        assert_eq!(store.stats().unwrap().total_queries, 80);
+    }
+
+    #[test]
+    fn test_clear_seen_by_other_store() {
+        let (mut store, temp_dir) = create_test_store();
+        store.record_selection("query", "old_cmd").unwrap();
+
+        let mut other = HistoryStore::with_data_dir(temp_dir.path().to_path_buf()).unwrap();
+        other.clear().unwrap();
+
+        store.record_selection("other query", "new_cmd").unwrap();
+        assert!(store.get_pattern("query").is_none());
+        assert!(store.get_pattern("other query").is_some());
+    }
 }
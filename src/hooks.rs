@@ -0,0 +1,86 @@
+//! User hooks: shell commands run before a query is sent and after a suggestion is picked
+//!
+//! Hooks run with `sh -c` (`cmd /C` on Windows), with the query (and picked command) in
+//! `QAI_QUERY` and `QAI_COMMAND`. Their stdout goes to stderr, since stdout of `qai query` is
+//! the suggestions. A failing pre-query hook refuses the query, so hooks can validate
+//! queries; post-select hooks only notify, so their failures are just logged.
+
+use eyre::{Context, Result, eyre};
+use std::process::{Command, Stdio};
+
+use crate::config::HooksConfig;
+
+/// Variable holding the query
+pub const QUERY_ENV: &str = "QAI_QUERY";
+/// Variable holding the picked command (post-select only)
+pub const COMMAND_ENV: &str = "QAI_COMMAND";
+
+/// Run a hook with extra environment variables; Err when it can't start or exits unsuccessfully
+pub fn run(hook: &str, env: &[(&str, &str)]) -> Result<()> {
+    let (shell, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+    let status = Command::new(shell)
+        .args([flag, hook])
+        .envs(env.iter().copied())
+        .stdin(Stdio::null())
+        .stdout(Stdio::from(std::io::stderr()))
+        .status()
+        .context(format!("Failed to run hook '{}'", hook))?;
+    if !status.success() {
+        return Err(eyre!("hook '{}' failed with {}", hook, status));
+    }
+    Ok(())
+}
+
+/// Run the pre-query hook, if any; Err when it refuses the query
+pub fn pre_query(hooks: &HooksConfig, query: &str) -> Result<()> {
+    match &hooks.pre_query {
+        Some(hook) => run(hook, &[(QUERY_ENV, query)]).context("The pre-query hook refused the query"),
+        None => Ok(()),
+    }
+}
+
+/// Run the post-select hook, if any, for a picked command
+pub fn post_select(hooks: &HooksConfig, query: &str, command: &str) {
+    if let Some(hook) = &hooks.post_select
+        && let Err(e) = run(hook, &[(QUERY_ENV, query), (COMMAND_ENV, command)])
+    {
+        log::warn!("post-select {:#}", e);
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_pre_query_without_hook() {
+        assert!(pre_query(&HooksConfig::default(), "list files").is_ok());
+    }
+
+    #[test]
+    fn test_pre_query_hook_can_refuse() {
+        let hooks = HooksConfig {
+            pre_query: Some(r#"case "$QAI_QUERY" in *prod*) exit 1;; esac"#.to_string()),
+            ..Default::default()
+        };
+        assert!(pre_query(&hooks, "list files").is_ok());
+        let err = pre_query(&hooks, "drop the prod database").unwrap_err();
+        assert!(format!("{:#}", err).contains("refused the query"));
+    }
+
+    #[test]
+    fn test_post_select_hook_gets_query_and_command() {
+        let dir = TempDir::new().unwrap();
+        let log = dir.path().join("picked");
+        let hooks = HooksConfig {
+            post_select: Some(format!(
+                r#"printf '%s|%s' "$QAI_QUERY" "$QAI_COMMAND" > '{}'"#,
+                log.display()
+            )),
+            ..Default::default()
+        };
+        post_select(&hooks, "list files", "ls -la");
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "list files|ls -la");
+    }
+}
@@ -0,0 +1,108 @@
+//! qai: natural language to shell commands via an OpenAI-compatible API
+//!
+//! The `qai` binary is a thin CLI over this library. Editor plugins, TUIs, and
+//! other tools can embed the same query pipeline without shelling out:
+//!
+//! - [`config`]: load `qai.yml` (user config, profiles, and project overrides)
+//! - [`prompt`]: load and render the system prompts
+//! - [`api`]: send the query to the chat completions endpoint
+//! - [`tools`]: split the answer into commands and check their binaries are installed
+//! - [`history`]: record queries and selections, and rank results by past picks
+//!
+//! ```no_run
+//! use qai::api::OpenAIClient;
+//! use qai::config::Config;
+//! use qai::prompt::{PromptContext, load_system_prompt, render_prompt};
+//!
+//! # async fn run() -> eyre::Result<()> {
+//! let config = Config::load(None)?;
+//! let system_prompt = render_prompt(&load_system_prompt()?, &PromptContext::default());
+//! let client = OpenAIClient::new(&config)?;
+//! let command = client.query(&system_prompt, "find files larger than 100MB").await?;
+//! println!("{}", command);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The remaining modules back the CLI and zsh integration; they are public so the
+//! binary can use them, but are not a stable API.
+
+pub mod api;
+pub mod config;
+pub mod history;
+pub mod prompt;
+pub mod tools;
+
+#[doc(hidden)]
+pub mod audit;
+#[doc(hidden)]
+pub mod bindings;
+#[doc(hidden)]
+pub mod budget;
+#[doc(hidden)]
+pub mod clipboard;
+#[doc(hidden)]
+pub mod context;
+#[doc(hidden)]
+pub mod cron;
+#[cfg(all(feature = "daemon", unix))]
+#[doc(hidden)]
+pub mod daemon;
+#[doc(hidden)]
+pub mod fixtures;
+#[doc(hidden)]
+pub mod generate;
+#[doc(hidden)]
+pub mod hooks;
+#[doc(hidden)]
+pub mod local;
+#[doc(hidden)]
+pub mod lock;
+#[doc(hidden)]
+pub mod migrate;
+#[doc(hidden)]
+pub mod offline;
+#[doc(hidden)]
+pub mod parse;
+#[doc(hidden)]
+pub mod paths;
+#[doc(hidden)]
+pub mod pin;
+#[doc(hidden)]
+pub mod privacy;
+#[doc(hidden)]
+pub mod rank;
+#[doc(hidden)]
+pub mod ratelimit;
+#[doc(hidden)]
+pub mod report;
+#[doc(hidden)]
+pub mod safety;
+#[doc(hidden)]
+pub mod sanitize;
+#[doc(hidden)]
+pub mod secrets;
+#[doc(hidden)]
+pub mod session;
+#[doc(hidden)]
+pub mod shell;
+#[doc(hidden)]
+pub mod slow;
+#[doc(hidden)]
+pub mod snippet;
+#[doc(hidden)]
+pub mod stream;
+#[doc(hidden)]
+pub mod suggestion;
+#[doc(hidden)]
+pub mod syntax;
+#[doc(hidden)]
+pub mod telemetry;
+#[doc(hidden)]
+pub mod theme;
+#[doc(hidden)]
+pub mod timing;
+#[doc(hidden)]
+pub mod tutorial;
+#[doc(hidden)]
+pub mod validate;
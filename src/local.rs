@@ -0,0 +1,385 @@
+//! In-process inference for `provider: local`
+//!
+//! Runs a small quantized GGUF model (llama-family, e.g. Llama 3.2, Mistral, or TinyLlama, and
+//! Qwen2/2.5) on the CPU with candle, so commands can be generated without a server or any
+//! network. Needs qai built with the `local` feature; without it, the provider reports how to
+//! get it. The model is loaded on the first query and kept for the life of the process.
+//!
+//! GGUF files don't carry a tokenizer candle can use, so `tokenizer.json` is read from
+//! `tokenizer-path`, or from next to the model. The chat format is picked from the special
+//! tokens the tokenizer knows.
+
+use eyre::{Result, eyre};
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+
+use crate::budget::TokenUsage;
+use crate::config::Config;
+
+/// The model and tokenizer files of `provider: local`
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalModelFiles {
+    pub model: PathBuf,
+    pub tokenizer: PathBuf,
+}
+
+impl LocalModelFiles {
+    /// The configured files, with a leading `~` expanded; Err when `model-path` is not set
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let model = config
+            .model_path
+            .as_deref()
+            .map(expand_home)
+            .ok_or_else(|| eyre!("provider: local needs model-path, the GGUF model file to run"))?;
+        let tokenizer = match &config.tokenizer_path {
+            Some(path) => expand_home(path),
+            None => model.with_file_name("tokenizer.json"),
+        };
+        Ok(Self { model, tokenizer })
+    }
+}
+
+/// `~/models/x.gguf` -> `$HOME/models/x.gguf`
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// How to sample an answer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sampling {
+    pub max_tokens: u32,
+    pub temperature: f32,
+    pub seed: Option<u64>,
+}
+
+/// Chat formats of the supported model families
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChatTemplate {
+    /// `<|im_start|>role ... <|im_end|>` (Qwen)
+    ChatMl,
+    /// `<|start_header_id|>role<|end_header_id|> ... <|eot_id|>` (Llama 3)
+    Llama3,
+    /// `<s>[INST] ... [/INST]` (Llama 2, Mistral, TinyLlama)
+    Inst,
+}
+
+impl ChatTemplate {
+    /// Pick the format from the special tokens a tokenizer knows
+    pub fn detect(has_token: impl Fn(&str) -> bool) -> Self {
+        if has_token("<|im_start|>") {
+            ChatTemplate::ChatMl
+        } else if has_token("<|start_header_id|>") {
+            ChatTemplate::Llama3
+        } else {
+            ChatTemplate::Inst
+        }
+    }
+
+    /// Tokens that end the answer
+    pub fn stop_tokens(self) -> &'static [&'static str] {
+        match self {
+            ChatTemplate::ChatMl => &["<|im_end|>", "<|endoftext|>"],
+            ChatTemplate::Llama3 => &["<|eot_id|>", "<|end_of_text|>"],
+            ChatTemplate::Inst => &["</s>"],
+        }
+    }
+
+    /// The prompt for a conversation: the system prompt, then (role, content) turns starting and
+    /// ending with the user, left open for the assistant's answer
+    pub fn render(self, system_prompt: &str, turns: &[(&str, &str)]) -> String {
+        let mut prompt = String::new();
+        match self {
+            ChatTemplate::ChatMl => {
+                prompt.push_str(&format!("<|im_start|>system\n{}<|im_end|>\n", system_prompt));
+                for (role, content) in turns {
+                    prompt.push_str(&format!("<|im_start|>{}\n{}<|im_end|>\n", role, content));
+                }
+                prompt.push_str("<|im_start|>assistant\n");
+            }
+            ChatTemplate::Llama3 => {
+                prompt.push_str("<|begin_of_text|>");
+                let system = [("system", system_prompt)];
+                for (role, content) in system.iter().chain(turns) {
+                    prompt.push_str(&format!(
+                        "<|start_header_id|>{}<|end_header_id|>\n\n{}<|eot_id|>",
+                        role, content
+                    ));
+                }
+                prompt.push_str("<|start_header_id|>assistant<|end_header_id|>\n\n");
+            }
+            ChatTemplate::Inst => {
+                // No system role: the system prompt leads the first instruction
+                for (i, (role, content)) in turns.iter().enumerate() {
+                    match *role {
+                        "user" if i == 0 => {
+                            prompt.push_str(&format!("<s>[INST] {}\n\n{} [/INST]", system_prompt, content))
+                        }
+                        "user" => prompt.push_str(&format!("[INST] {} [/INST]", content)),
+                        _ => prompt.push_str(&format!(" {}</s>", content)),
+                    }
+                }
+            }
+        }
+        prompt
+    }
+}
+
+/// Generate the answer to a conversation with the local model, calling `on_text` with each
+/// piece as it is generated (it breaks to stop early)
+#[cfg(feature = "local")]
+pub fn generate(
+    files: &LocalModelFiles,
+    system_prompt: &str,
+    turns: &[(&str, &str)],
+    sampling: Sampling,
+    on_text: &mut dyn FnMut(&str) -> ControlFlow<()>,
+) -> Result<(String, TokenUsage)> {
+    model::generate(files, system_prompt, turns, sampling, on_text)
+}
+
+/// Without the `local` feature there is no model to run
+#[cfg(not(feature = "local"))]
+pub fn generate(
+    _files: &LocalModelFiles,
+    _system_prompt: &str,
+    _turns: &[(&str, &str)],
+    _sampling: Sampling,
+    _on_text: &mut dyn FnMut(&str) -> ControlFlow<()>,
+) -> Result<(String, TokenUsage)> {
+    Err(eyre!(
+        "provider: local needs qai built with the `local` feature (cargo install --path . --features local)"
+    ))
+}
+
+#[cfg(feature = "local")]
+mod model {
+    use candle_core::quantized::gguf_file;
+    use candle_core::{Device, Tensor};
+    use candle_transformers::generation::LogitsProcessor;
+    use candle_transformers::models::{quantized_llama, quantized_qwen2};
+    use eyre::{Context, Result, eyre};
+    use std::fs::File;
+    use std::ops::ControlFlow;
+    use std::sync::Mutex;
+    use tokenizers::Tokenizer;
+
+    use super::{ChatTemplate, LocalModelFiles, Sampling};
+    use crate::budget::TokenUsage;
+
+    /// Seed used when none is configured
+    const DEFAULT_SEED: u64 = 299_792_458;
+
+    enum Weights {
+        Llama(quantized_llama::ModelWeights),
+        Qwen2(quantized_qwen2::ModelWeights),
+    }
+
+    impl Weights {
+        fn forward(&mut self, input: &Tensor, position: usize) -> candle_core::Result<Tensor> {
+            match self {
+                Weights::Llama(model) => model.forward(input, position),
+                Weights::Qwen2(model) => model.forward(input, position),
+            }
+        }
+    }
+
+    struct LocalModel {
+        files: LocalModelFiles,
+        weights: Weights,
+        tokenizer: Tokenizer,
+        template: ChatTemplate,
+    }
+
+    /// The loaded model, kept between queries of a long-running process
+    static MODEL: Mutex<Option<LocalModel>> = Mutex::new(None);
+
+    impl LocalModel {
+        fn load(files: &LocalModelFiles) -> Result<Self> {
+            let mut file =
+                File::open(&files.model).context(format!("Failed to open model file: {}", files.model.display()))?;
+            let content = gguf_file::Content::read(&mut file)
+                .map_err(|e| eyre!("Failed to read GGUF model {}: {}", files.model.display(), e))?;
+            let architecture = content
+                .metadata
+                .get("general.architecture")
+                .and_then(|value| value.to_string().ok())
+                .cloned()
+                .unwrap_or_default();
+            let device = Device::Cpu;
+            let weights = match architecture.as_str() {
+                "llama" => quantized_llama::ModelWeights::from_gguf(content, &mut file, &device).map(Weights::Llama),
+                "qwen2" => quantized_qwen2::ModelWeights::from_gguf(content, &mut file, &device).map(Weights::Qwen2),
+                other => {
+                    return Err(eyre!(
+                        "Unsupported model architecture '{}' (llama and qwen2 work)",
+                        other
+                    ));
+                }
+            }
+            .map_err(|e| eyre!("Failed to load model {}: {}", files.model.display(), e))?;
+
+            let tokenizer = Tokenizer::from_file(&files.tokenizer)
+                .map_err(|e| eyre!("Failed to load tokenizer {}: {}", files.tokenizer.display(), e))?;
+            let template = ChatTemplate::detect(|token| tokenizer.token_to_id(token).is_some());
+            log::info!(
+                "Loaded local model {} ({}, {:?} chat format)",
+                files.model.display(),
+                architecture,
+                template
+            );
+            Ok(Self {
+                files: files.clone(),
+                weights,
+                tokenizer,
+                template,
+            })
+        }
+
+        fn generate(
+            &mut self,
+            system_prompt: &str,
+            turns: &[(&str, &str)],
+            sampling: Sampling,
+            on_text: &mut dyn FnMut(&str) -> ControlFlow<()>,
+        ) -> Result<(String, TokenUsage)> {
+            let prompt = self.template.render(system_prompt, turns);
+            let prompt_tokens = self
+                .tokenizer
+                .encode(prompt, false)
+                .map_err(|e| eyre!("Failed to tokenize prompt: {}", e))?
+                .get_ids()
+                .to_vec();
+            let stop: Vec<u32> = self
+                .template
+                .stop_tokens()
+                .iter()
+                .filter_map(|token| self.tokenizer.token_to_id(token))
+                .collect();
+            let temperature = (sampling.temperature > 0.0).then_some(f64::from(sampling.temperature));
+            let mut sampler = LogitsProcessor::new(sampling.seed.unwrap_or(DEFAULT_SEED), temperature, None);
+
+            let device = Device::Cpu;
+            let mut input = prompt_tokens.clone();
+            let mut position = 0;
+            let mut answer: Vec<u32> = Vec::new();
+            let mut text = String::new();
+            while answer.len() < sampling.max_tokens as usize {
+                let tensor = Tensor::new(input.as_slice(), &device)
+                    .and_then(|tensor| tensor.unsqueeze(0))
+                    .map_err(|e| eyre!("Failed to build model input: {}", e))?;
+                let logits = self
+                    .weights
+                    .forward(&tensor, position)
+                    .and_then(|logits| logits.squeeze(0))
+                    .map_err(|e| eyre!("Local model failed: {}", e))?;
+                position += input.len();
+                let next = sampler.sample(&logits).map_err(|e| eyre!("Failed to sample: {}", e))?;
+                if stop.contains(&next) {
+                    break;
+                }
+                answer.push(next);
+                input = vec![next];
+
+                // Decode the whole answer so multi-token characters come out whole
+                let decoded = self
+                    .tokenizer
+                    .decode(&answer, true)
+                    .map_err(|e| eyre!("Failed to decode answer: {}", e))?;
+                if decoded.len() > text.len() && decoded.starts_with(&text) && !decoded.ends_with('\u{fffd}') {
+                    let piece = decoded[text.len()..].to_string();
+                    text = decoded;
+                    if on_text(&piece).is_break() {
+                        break;
+                    }
+                }
+            }
+            let text = self
+                .tokenizer
+                .decode(&answer, true)
+                .map_err(|e| eyre!("Failed to decode answer: {}", e))?;
+            let usage = TokenUsage {
+                prompt_tokens: prompt_tokens.len() as u64,
+                completion_tokens: answer.len() as u64,
+            };
+            Ok((text, usage))
+        }
+    }
+
+    pub fn generate(
+        files: &LocalModelFiles,
+        system_prompt: &str,
+        turns: &[(&str, &str)],
+        sampling: Sampling,
+        on_text: &mut dyn FnMut(&str) -> ControlFlow<()>,
+    ) -> Result<(String, TokenUsage)> {
+        let mut model = MODEL
+            .lock()
+            .map_err(|_| eyre!("Local model is unavailable after a failed query"))?;
+        if model.as_ref().is_none_or(|model| model.files != *files) {
+            *model = Some(LocalModel::load(files)?);
+        }
+        model
+            .as_mut()
+            .expect("model was just loaded")
+            .generate(system_prompt, turns, sampling, on_text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_model_files_default_tokenizer_next_to_model() {
+        let mut config = Config::default();
+        assert!(LocalModelFiles::from_config(&config).is_err());
+
+        config.model_path = Some(PathBuf::from("/models/qwen2.5-coder-1.5b-q4_k_m.gguf"));
+        let files = LocalModelFiles::from_config(&config).unwrap();
+        assert_eq!(files.tokenizer, PathBuf::from("/models/tokenizer.json"));
+
+        config.tokenizer_path = Some(PathBuf::from("/tokenizers/qwen.json"));
+        assert_eq!(
+            LocalModelFiles::from_config(&config).unwrap().tokenizer,
+            PathBuf::from("/tokenizers/qwen.json")
+        );
+
+        config.model_path = Some(PathBuf::from("~/models/qwen.gguf"));
+        let model = LocalModelFiles::from_config(&config).unwrap().model;
+        assert_eq!(model, dirs::home_dir().unwrap().join("models/qwen.gguf"));
+    }
+
+    #[test]
+    fn test_chat_template_detect() {
+        assert_eq!(
+            ChatTemplate::detect(|token| token == "<|im_start|>"),
+            ChatTemplate::ChatMl
+        );
+        assert_eq!(
+            ChatTemplate::detect(|token| token == "<|start_header_id|>"),
+            ChatTemplate::Llama3
+        );
+        assert_eq!(ChatTemplate::detect(|_| false), ChatTemplate::Inst);
+    }
+
+    #[test]
+    fn test_chat_template_render() {
+        let turns = [("user", "list files"), ("assistant", "ls"), ("user", "with sizes")];
+        assert_eq!(
+            ChatTemplate::ChatMl.render("Be brief.", &turns[..1]),
+            "<|im_start|>system\nBe brief.<|im_end|>\n<|im_start|>user\nlist files<|im_end|>\n<|im_start|>assistant\n"
+        );
+        assert!(
+            ChatTemplate::Llama3
+                .render("Be brief.", &turns[..1])
+                .ends_with("list files<|eot_id|><|start_header_id|>assistant<|end_header_id|>\n\n")
+        );
+        assert_eq!(
+            ChatTemplate::Inst.render("Be brief.", &turns),
+            "<s>[INST] Be brief.\n\nlist files [/INST] ls</s>[INST] with sizes [/INST]"
+        );
+    }
+}
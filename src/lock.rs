@@ -0,0 +1,43 @@
+//! Advisory locks serializing writes to state files across qai processes
+//!
+//! Shells running at the same time (tmux panes, the widget's background `record-exec` and
+//! `warmup` runs) update the same history, usage, and telemetry files. A writer holds an
+//! exclusive lock on a sibling `.lock` file for its whole read-modify-write, waiting up to
+//! [`TIMEOUT`] while another process holds it.
+
+use eyre::{Context, Result};
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// How long a writer waits for another qai process to release a lock
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Take the lock on `path`, creating the lock file if needed; `what` names the guarded state
+/// in errors. The lock is released when the returned file is dropped
+pub fn acquire(path: &Path, what: &str) -> Result<File> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .context(format!("Failed to open {} lock file", what))?;
+
+    let started = Instant::now();
+    loop {
+        match file.try_lock() {
+            Ok(()) => return Ok(file),
+            Err(TryLockError::WouldBlock) if started.elapsed() < TIMEOUT => {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            Err(TryLockError::WouldBlock) => {
+                return Err(eyre::eyre!(
+                    "The {} is locked by another qai process (waited {}s)",
+                    what,
+                    TIMEOUT.as_secs()
+                ));
+            }
+            Err(TryLockError::Error(e)) => return Err(e).context(format!("Failed to lock {}", what)),
+        }
+    }
+}
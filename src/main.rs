@@ -1,100 +1,1043 @@
 use eyre::{Context, Result};
 use log::info;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
+use std::io::Write;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use tracing_subscriber::util::SubscriberInitExt;
 
-mod api;
-mod bindings;
 mod cli;
-mod config;
-mod history;
-mod prompt;
-mod shell;
-mod tools;
+#[cfg(feature = "serve")]
+mod server;
+
+use qai::{
+    api, audit, budget, clipboard, config, context, cron, generate, history, hooks, offline, paths, pin, privacy,
+    prompt, rank, ratelimit, report, safety, sanitize, secrets, session, shell, slow, snippet, stream, suggestion,
+    syntax, telemetry, theme, timing, tools, tutorial, validate,
+};
 
 use api::{OpenAIClient, validate_api_key_from_config};
-use cli::{Cli, Commands, check_api_key_configured, check_fzf_status};
-use config::Config;
-use history::HistoryStore;
-use prompt::{PromptContext, load_system_prompt, render_prompt};
+use audit::{AuditEntry, AuditLog, ExportFormat};
+use budget::Budget;
+use cli::{
+    AuditAction, Cli, Commands, ConfigAction, ContextAction, HistoryAction, PromptAction, SessionAction, SnippetAction,
+    TelemetryAction, ToolsAction, check_api_key_configured, check_fzf_status,
+};
+use clipboard::Copied;
+use config::{Config, LoggingConfig, SecretScan, SyntaxCheck, find_project_config};
+use context::{
+    ContextProvider, DirectoryListingProvider, EnvVarsProvider, PromptSection, ScriptRun, ScriptStatus,
+    ShellHistoryProvider, attach_input, fit_to_budget, provider_sections, run_scripts, scripts_dir,
+};
+use cron::CronLine;
+use generate::{Generator, Tested, clean_expression, fix_request};
+use history::{HistoryStore, QueryRecord};
+use pin::{PinStore, Pinned};
+use privacy::Identity;
+use prompt::{
+    PromptContext, WindowsShell, append_example_to, detect_hostname, detect_shell, detect_user, examples_path,
+    language_directive, lint_prompts_in, load_cron_prompt, load_examples_from, load_multi_prompt, load_profile_prompt,
+    load_system_prompt, load_translate_prompt, prompts_dir, render_prompt, resolve_profile, shell_directive,
+    teach_directive, translation_request,
+};
+#[cfg(all(feature = "daemon", unix))]
+use qai::daemon;
+use safety::{Policy, Verdict};
+use sanitize::{sanitize_command, sanitize_response};
+use session::{SESSION_ENV, SessionEvent, SessionLog};
 use shell::generate_init_script;
-use tools::ToolCache;
+use snippet::SnippetStore;
+use stream::{CommandStream, StreamedCommand};
+use suggestion::{OutputFormat, Suggestion};
+use telemetry::Telemetry;
+use theme::{Cell, Status, Style, Table};
+use tools::{CLOUD_CONTEXT_KEYS, DualCommandList, PackageManager, ToolCache, cloud_contexts_for_prompt};
 
 #[cfg(not(tarpaulin_include))]
-fn setup_logging() -> Result<()> {
+fn setup_logging(logging: &LoggingConfig) -> Result<()> {
     let log_dir = get_log_dir();
     fs::create_dir_all(&log_dir).context("Failed to create log directory")?;
 
     let log_file = get_log_file();
-    let target = Box::new(
-        fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&log_file)
-            .context("Failed to open log file")?,
-    );
+    rotate_log(&log_file, logging.max_size_kb * 1024, logging.max_files).context("Failed to rotate log file")?;
+    let target = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_file)
+        .context("Failed to open log file")?;
 
-    env_logger::Builder::from_default_env()
-        .target(env_logger::Target::Pipe(target))
-        .filter_level(log::LevelFilter::Info)
-        .init();
+    // `log` records from qai and its dependencies are forwarded to the subscriber
+    log_subscriber(std::sync::Mutex::new(target)).init();
 
     info!("Logging initialized, writing to: {}", log_file.display());
     Ok(())
 }
 
-async fn handle_query(query: &str, config: &Config, multi: bool, count: usize) -> Result<()> {
-    info!("Processing query: {} (multi: {}, count: {})", query, multi, count);
+/// JSON lines, one per event, each carrying the fields of the query span it happened in
+/// (query id, model, latency, token counts). RUST_LOG overrides the default `info` level
+fn log_subscriber<W>(writer: W) -> impl tracing::Subscriber + Send + Sync
+where
+    W: for<'w> tracing_subscriber::fmt::MakeWriter<'w> + Send + Sync + 'static,
+{
+    let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"));
+    tracing_subscriber::fmt()
+        .json()
+        .flatten_event(true)
+        .with_current_span(true)
+        .with_span_list(false)
+        .with_env_filter(filter)
+        .with_writer(writer)
+        .finish()
+}
+
+/// Variables for prompt templates beyond the PromptContext fields: detected cloud
+/// contexts ("none" when not detected), {{language}}, and the user's prompt-vars
+fn template_vars(config: &Config, cloud_contexts: &BTreeMap<String, String>) -> BTreeMap<String, String> {
+    let mut vars = BTreeMap::new();
+    for key in CLOUD_CONTEXT_KEYS {
+        let value = cloud_contexts.get(*key).map(String::as_str).unwrap_or("none");
+        vars.insert(key.to_string(), value.to_string());
+    }
+    let language = config.language.as_deref().map(str::trim).filter(|l| !l.is_empty());
+    vars.insert("language".to_string(), language.unwrap_or("English").to_string());
+    // User-defined variables from config take precedence over detected ones
+    vars.extend(config.prompt_vars.clone());
+    vars
+}
+
+/// A query ready to send: the scrubbed query and rendered system prompt, with the policy and
+/// tool cache its answer is checked against
+struct PreparedQuery {
+    query: String,
+    /// The user message: the query, then any input attached with --context
+    message: String,
+    system_prompt: String,
+    /// Results the system prompt asks for (more than wanted in adaptive mode)
+    asked: usize,
+    policy: Policy,
+    tool_cache: ToolCache,
+}
+
+/// Queries of at most this many words count as simple in adaptive mode
+const SIMPLE_QUERY_WORDS: usize = 6;
+
+/// Adaptive mode: whether a query is simple, and settled in history, so that a single
+/// command answers it and alternatives aren't worth their tokens
+fn settled_query(query: &str, attached: Option<&str>, config: &Config) -> bool {
+    if !config.query.adaptive || attached.is_some() || query.split_whitespace().count() > SIMPLE_QUERY_WORDS {
+        return false;
+    }
+    let settled = HistoryStore::new().ok().is_some_and(|store| {
+        store
+            .confident_command(query, config.query.adaptive_min_picks)
+            .is_some()
+    });
+    if settled {
+        info!(
+            "History has settled on an answer to '{}', asking for a single command",
+            query
+        );
+    }
+    settled
+}
+
+/// Render the system prompt for a query, with as much context as fits the token budget
+fn prepare_query(
+    query: &str,
+    config: &Config,
+    multi: bool,
+    count: usize,
+    profile: Option<&str>,
+    attached: Option<&str>,
+) -> Result<PreparedQuery> {
+    hooks::pre_query(&config.hooks, query)?;
+    let policy = Policy::from_config(&config.safety)?;
 
     // Load and render system prompt
-    let system_prompt_template = if multi { load_multi_result_prompt(count)? } else { load_system_prompt()? };
-    let context = PromptContext::default();
+    // A profile replaces the system prompt; with --multi it is added as guidance to the multi-result format
+    let system_prompt_template = match (profile.map(load_profile_prompt).transpose()?, multi) {
+        (Some(persona), true) => format!("{}\n\nDomain guidance:\n{}", load_multi_prompt()?, persona),
+        (Some(persona), false) => persona,
+        (None, true) => load_multi_prompt()?,
+        (None, false) => load_system_prompt()?,
+    };
+    timing::mark("load prompt");
+    let mut tool_cache = ToolCache::load();
+    timing::mark("load tool cache");
+
+    // Expose container/cloud CLI contexts as {{kube_context}}, {{aws_profile}}, etc.
+    let cloud_contexts = tool_cache.cloud_contexts(config.context.kubernetes);
+    let asked = if multi { config.query.candidates(count) } else { 1 };
+    let mut context = PromptContext {
+        count: asked,
+        ..Default::default()
+    };
+    context.vars = template_vars(config, &cloud_contexts);
     let system_prompt = render_prompt(&system_prompt_template, &context);
 
+    // Extra context, trimmed lowest priority first to fit the token budget
+    let mut sections = vec![
+        // PowerShell or cmd.exe syntax on Windows, kept over everything else
+        PromptSection::new(10, shell_directive(&context.shell)),
+        PromptSection::new(9, language_directive(config.language.as_deref())),
+        PromptSection::new(9, teach_directive(config.query.teach)),
+        PromptSection::new(8, context.last_command_hint()),
+    ];
+    // Few-shot examples teach the model the user's conventions
+    if let Some(path) = examples_path() {
+        sections.push(PromptSection::new(7, load_examples_from(&path)));
+    }
+    sections.push(PromptSection::new(6, cloud_contexts_for_prompt(&cloud_contexts)));
+    // Hint the model towards installed modern tools over their standard equivalents
+    sections.push(PromptSection::new(
+        5,
+        tool_cache.substitution_hints_for_prompt(&config.tools.substitutions),
+    ));
+    if config.tools.tldr {
+        sections.push(PromptSection::new(
+            5,
+            tool_cache.tldr_context(query, config.tools.tldr_max_chars),
+        ));
+    }
+    sections.push(PromptSection::new(4, tool_cache.available_tools_for_prompt()));
+    if config.context.aliases {
+        sections.push(PromptSection::new(3, tool_cache.aliases_for_prompt()));
+    }
+
+    sections.extend(provider_sections(&context_providers(config, Path::new(&context.cwd))));
+    sections.extend(context_script_runs(config).iter().filter_map(ScriptRun::section));
+
+    let system_prompt = fit_to_budget(&system_prompt, sections, config.context.max_prompt_tokens);
+    let system_prompt = if config.context.privacy {
+        Identity::detect().scrub(&system_prompt)
+    } else {
+        system_prompt
+    };
+    let (query, system_prompt) = scrub_secrets(query, &system_prompt, config.safety.secrets)?;
+    // Attached input is scrubbed like the prompt, since it is sent along with the query
+    let message = match attached {
+        Some(input) => attach_input(
+            &query,
+            &scrub_attached(input, config)?,
+            config.context.attached_max_chars,
+        ),
+        None => query.clone(),
+    };
+    timing::mark("build prompt");
+
+    Ok(PreparedQuery {
+        query,
+        message,
+        system_prompt,
+        asked,
+        policy,
+        tool_cache,
+    })
+}
+
+/// The shell suggestions are syntax-checked with, if any
+fn syntax_shell(config: &Config) -> Option<&'static str> {
+    match config.safety.syntax_check {
+        SyntaxCheck::Off => None,
+        // zsh and bash can't parse PowerShell or cmd.exe commands
+        _ if WindowsShell::of(&detect_shell()).is_some() => None,
+        _ => syntax::check_shell(),
+    }
+}
+
+/// Send a prepared query and return its checked commands, best first
+async fn suggest(
+    prepared: &mut PreparedQuery,
+    client: &OpenAIClient,
+    config: &Config,
+    multi: bool,
+    count: usize,
+) -> Result<Vec<Suggestion>> {
+    let PreparedQuery {
+        query,
+        message,
+        system_prompt,
+        asked,
+        policy,
+        tool_cache,
+    } = prepared;
+    let query = query.as_str();
+    let answer = if multi {
+        client.query_multi(system_prompt, message, *asked).await
+    } else {
+        client.query(system_prompt, message).await
+    };
+    let mut result = match answer {
+        Ok(result) => result,
+        Err(e) => {
+            let shell = syntax_shell(config);
+            return Ok(offline_commands(e, query, config, tool_cache)?
+                .iter()
+                .filter_map(|cmd| vet_command(cmd, config, policy, shell))
+                .map(|command| Suggestion::new(command, policy))
+                .take(if multi { count } else { 1 })
+                .collect());
+        }
+    };
+    if multi && let Some(retry) = ask_for_more(client, system_prompt, message, count, &result).await {
+        result = retry;
+    }
+    timing::mark("api request");
+
+    // Multi-line commands stay whole, so heredocs and continuations insert intact
+    let result = sanitize_response(&result);
+    let mut dual = DualCommandList::parse(&result);
+    // Teach mode: keep the explanations apart, so tool and safety checks see bare commands
+    let mut explanations = HashMap::new();
+    if config.query.teach {
+        explanations.extend(suggestion::split_explanations(&mut dual.modern));
+        explanations.extend(suggestion::split_explanations(&mut dual.standard));
+    }
+    let commands = if multi {
+        // Keep only commands whose binaries exist, rewriting missing modern tools where possible
+        // Whitespace variants of one command count once
+        let commands = suggestion::dedup(tool_cache.process_response(&dual, &config.tools.substitutions));
+        // Commands the user picked for this query before are candidates too, padding a short answer
+        let answered = commands.len();
+        let store = HistoryStore::new().ok();
+        let mut candidates = commands;
+        if let Some(store) = &store {
+            candidates.extend(store.past_commands(query));
+        }
+        let candidates = suggestion::dedup(candidates)
+            .into_iter()
+            .enumerate()
+            .map(|(i, command)| rank::Candidate {
+                ai_rank: (i < answered).then_some(i),
+                history: store.as_ref().map_or(0.0, |store| store.preference(query, &command)),
+                available: tool_cache.command_available(&command),
+                risk: policy.risk_level(&command),
+                command,
+            })
+            .collect();
+        let mut commands = rank::rank(candidates, &config.rank);
+        if config.query.adaptive {
+            // More were asked for than wanted: keep the best that aren't near-duplicates
+            commands = suggestion::most_distinct(commands, count);
+        } else {
+            commands.truncate(count);
+        }
+
+        let (_, missing) = tool_cache.filter_commands(&dual.modern);
+        ratelimit::report_hints(&tool_cache.install_hints(&missing, PackageManager::detect()));
+
+        commands
+    } else {
+        // Rewrite commands using an unavailable modern tool to the standard equivalent
+        dual.all_commands()
+            .into_iter()
+            .map(|cmd| tool_cache.substitute(&cmd, &config.tools.substitutions).unwrap_or(cmd))
+            .collect()
+    };
+    let shell = syntax_shell(config);
+    // The safety policy's rewrites can make two commands the same
+    let mut seen = HashSet::new();
+    Ok(commands
+        .iter()
+        .filter_map(|cmd| {
+            let command = vet_command(cmd, config, policy, shell)?;
+            let explanation = explanations.get(cmd).cloned();
+            seen.insert(suggestion::dedup_key(&command)).then(|| {
+                let suggestion = Suggestion::new(command, policy);
+                if config.query.teach {
+                    suggestion.taught(explanation)
+                } else {
+                    suggestion
+                }
+            })
+        })
+        .collect())
+}
+
+/// When the API can't be reached, the commands of the built-in rule for a query whose tools
+/// are installed (see [`offline`]); other errors, and queries no rule knows, fail as they were
+fn offline_commands(
+    error: eyre::Report,
+    query: &str,
+    config: &Config,
+    tool_cache: &mut ToolCache,
+) -> Result<Vec<String>> {
+    if !config.query.offline_fallback || !api::is_unreachable(&error) {
+        return Err(error);
+    }
+    let commands: Vec<String> = offline::commands(query)
+        .into_iter()
+        .filter(|cmd| tool_cache.command_available(cmd))
+        .collect();
+    if commands.is_empty() {
+        return Err(error);
+    }
+    log::warn!("Answering from built-in rules: {:#}", error);
+    eprintln!("# API unreachable, answering from built-in rules");
+    Ok(commands)
+}
+
+#[tracing::instrument(
+    name = "query",
+    skip_all,
+    fields(
+        id = %uuid::Uuid::new_v4(),
+        model = %config.model,
+        multi = multi,
+        count = count,
+        latency_ms = tracing::field::Empty,
+        prompt_tokens = tracing::field::Empty,
+        completion_tokens = tracing::field::Empty,
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+async fn handle_query(
+    query: &str,
+    attached: Option<&str>,
+    config: &Config,
+    multi: bool,
+    count: usize,
+    profile: Option<&str>,
+    format: OutputFormat,
+    stream: bool,
+) -> Result<Vec<String>> {
+    let started = std::time::Instant::now();
+    info!(
+        "Processing query: {} (multi: {}, count: {}, profile: {:?}, stream: {})",
+        query, multi, count, profile, stream
+    );
+    let multi = multi && !settled_query(query, attached, config);
+    let mut prepared = prepare_query(query, config, multi, count, profile, attached)?;
+    let pinned = pinned_commands(query);
+    let limit = if multi { count } else { 1 };
+
     // Create API client and send query
     let client = OpenAIClient::new(config)?;
-    let result = if multi {
-        client.query_multi(&system_prompt, query, count).await?
+    let commands = if pinned.only && !pinned.commands.is_empty() {
+        // Pinned with --only: the pins are the answer, and the model isn't asked
+        let suggestions = pinned_first(&pinned.commands, Vec::new(), config, &prepared.policy, limit);
+        print!("{}", suggestion::render(&suggestions, format));
+        suggestions.into_iter().map(|s| s.command).collect()
+    } else if multi && stream && format != OutputFormat::Json {
+        let mut printer = StreamPrinter::new(
+            config,
+            &prepared.policy,
+            syntax_shell(config),
+            &mut prepared.tool_cache,
+            format,
+            count,
+        );
+        for command in &pinned.commands {
+            printer.print(command, None);
+        }
+        stream_multi(
+            &client,
+            &prepared.system_prompt,
+            &prepared.query,
+            &prepared.message,
+            printer,
+        )
+        .await?
     } else {
-        client.query(&system_prompt, query).await?
+        let suggestions = suggest(&mut prepared, &client, config, multi, count).await?;
+        let suggestions = pinned_first(&pinned.commands, suggestions, config, &prepared.policy, limit);
+
+        // Print result to stdout (ZLE widget captures this); with --null, each command is
+        // NUL-terminated so multi-line commands survive the picker
+        timing::mark("process results");
+        print!("{}", suggestion::render(&suggestions, format));
+        suggestions.into_iter().map(|s| s.command).collect()
     };
+    if let Err(e) = prepared.tool_cache.save() {
+        log::warn!("Failed to save tool cache: {}", e);
+    }
+    let result = commands.join("\n");
+
+    // Queries are recorded as they are made, for `qai report`; picks are recorded when run
+    let mut record = QueryRecord::new(prepared.query.clone(), commands.clone(), config.model.clone());
+    record.tokens = Some(client.tokens_used());
+    if let Err(e) = HistoryStore::new().and_then(|mut store| store.record_query(&record)) {
+        log::warn!("Failed to record query: {}", e);
+    }
 
-    // Print result to stdout (ZLE widget captures this)
-    println!("{}", result);
+    if config.session.transcript
+        && let Some(log) = SessionLog::current()
+        && let Err(e) = log.append(SessionEvent::Query {
+            query: prepared.query,
+            candidates: commands.clone(),
+        })
+    {
+        log::warn!("Failed to write session transcript: {}", e);
+    }
 
+    tracing::Span::current().record("latency_ms", started.elapsed().as_millis() as u64);
     info!("Query successful, result: {}", result);
+    Ok(commands)
+}
+
+/// The commands pinned to a query (`qai pin`); none when the pins can't be read
+fn pinned_commands(query: &str) -> Pinned {
+    let Some(path) = PinStore::default_path() else {
+        return Pinned::default();
+    };
+    PinStore::new(path).matching(query).unwrap_or_else(|e| {
+        log::warn!("Failed to read pins: {}", e);
+        Pinned::default()
+    })
+}
+
+/// Pinned commands, checked like any suggestion, ahead of the model's suggestions, keeping
+/// the first `limit`
+fn pinned_first(
+    pinned: &[String],
+    suggestions: Vec<Suggestion>,
+    config: &Config,
+    policy: &Policy,
+    limit: usize,
+) -> Vec<Suggestion> {
+    let shell = syntax_shell(config);
+    let pinned = pinned
+        .iter()
+        .filter_map(|command| vet_command(command, config, policy, shell))
+        .map(|command| Suggestion::new(command, policy));
+    let mut seen = HashSet::new();
+    pinned
+        .chain(suggestions)
+        .filter(|suggestion| seen.insert(suggestion::dedup_key(&suggestion.command)))
+        .take(limit)
+        .collect()
+}
+
+/// Handle pin: pin a command to a query, list pins, or remove a query's pins
+fn handle_pin(query: Option<&str>, command: Option<&str>, only: bool, list: bool, remove: bool) -> Result<()> {
+    let path = PinStore::default_path().ok_or_else(|| eyre::eyre!("Could not determine config directory"))?;
+    let store = PinStore::new(path);
+    match (query, command) {
+        _ if list => {
+            let pins = store.load()?;
+            if pins.is_empty() {
+                println!("No pinned commands; pin one with `qai pin <query> <command>`.");
+                return Ok(());
+            }
+            let mut table = Table::new().header(&["QUERY", "ONLY", "COMMAND"]);
+            for pin in pins {
+                let only = if pin.only { "yes" } else { "" };
+                table.row(vec![pin.query.into(), Cell::new(only, Style::Dim), pin.command.into()]);
+            }
+            table.print();
+        }
+        (Some(query), _) if remove => match store.remove(query)? {
+            0 => println!("No pins for '{}'", query),
+            removed => println!("Removed {} pin(s) for '{}'", removed, query),
+        },
+        (Some(query), Some(command)) => {
+            store.add(query, command, only)?;
+            let how = if only {
+                "the only answer"
+            } else {
+                "the first suggestion"
+            };
+            println!("Pinned '{}' as {} to '{}'", command, how, query);
+        }
+        _ => return Err(eyre::eyre!("Usage: qai pin <query> <command> [--only]")),
+    }
+    Ok(())
+}
+
+/// Convert a command to another shell or platform (default: this machine's) and print it
+async fn handle_translate(command: &str, from: Option<&str>, to: Option<&str>, config: &Config) -> Result<String> {
+    info!("Translating command: {} (from: {:?}, to: {:?})", command, from, to);
+    let policy = Policy::from_config(&config.safety)?;
+    let context = PromptContext::default();
+    let mut system_prompt = render_prompt(&load_translate_prompt()?, &context);
+    // Installed tools only matter when the command is for this machine
+    if to.is_none() {
+        system_prompt.push_str(&ToolCache::load().available_tools_for_prompt());
+    }
+    let message = translation_request(command, from, to, &context);
+    let (message, system_prompt) = scrub_secrets(&message, &system_prompt, config.safety.secrets)?;
+
+    let client = OpenAIClient::new(config)?;
+    let response = client.query(&system_prompt, &message).await?;
+    // The syntax check parses with this machine's shell, which another target may not match
+    let shell = if to.is_none() { syntax_shell(config) } else { None };
+    let translated = vet_command(&sanitize_response(&response), config, &policy, shell)
+        .ok_or_else(|| eyre::eyre!("No usable translation of: {}", command))?;
+    println!("{}", translated);
+    Ok(translated)
+}
+
+/// Generate a regex or jq filter and print it. With sample input it is tested, and the model
+/// gets one chance to fix an expression that fails
+async fn handle_generate(
+    generator: Generator,
+    description: &str,
+    flavor: Option<&str>,
+    sample: Option<&str>,
+    config: &Config,
+) -> Result<String> {
+    info!("Generating {}: {}", generator.name(), description);
+    let system_prompt = render_prompt(&generator.load_prompt()?, &PromptContext::default());
+    let (description, system_prompt) = scrub_secrets(description, &system_prompt, config.safety.secrets)?;
+    let mut request = generator.request(&description, flavor);
+    if let Some(sample) = sample {
+        request = attach_input(
+            &request,
+            &scrub_attached(sample, config)?,
+            config.context.attached_max_chars,
+        );
+    }
+
+    let client = OpenAIClient::new(config)?;
+    let mut expression = clean_expression(&client.query(&system_prompt, &request).await?);
+    if expression.is_empty() {
+        return Err(eyre::eyre!("No {} in the response", generator.name()));
+    }
+    if let Some(sample) = sample {
+        let mut tested = generator.test(&expression, sample);
+        if let Tested::Failed(error) = &tested {
+            eprint!("{}", generate::report(generator, &tested, sample));
+            let retry = fix_request(&request, &expression, error);
+            let fixed = clean_expression(&client.query(&system_prompt, &retry).await?);
+            if !fixed.is_empty() {
+                expression = fixed;
+                tested = generator.test(&expression, sample);
+            }
+        }
+        eprint!("{}", generate::report(generator, &tested, sample));
+    }
+    println!("{}", expression);
+    Ok(expression)
+}
+
+/// Write a crontab line for a schedule and task and print it, with the schedule in words on
+/// stderr; with `install`, append it to the user's crontab after asking
+async fn handle_cron(schedule: &str, task: &str, install: bool, config: &Config) -> Result<CronLine> {
+    info!("Generating crontab line: {} / {}", schedule, task);
+    let policy = Policy::from_config(&config.safety)?;
+    let system_prompt = render_prompt(&load_cron_prompt()?, &PromptContext::default());
+    let request = format!("Schedule: {}\nTask: {}", schedule, task);
+    let (request, system_prompt) = scrub_secrets(&request, &system_prompt, config.safety.secrets)?;
+
+    let client = OpenAIClient::new(config)?;
+    let answer = clean_expression(&client.query(&system_prompt, &request).await?);
+    let line = match CronLine::parse(&answer) {
+        Ok(line) => line,
+        Err(e) => {
+            // One more try, with what was wrong
+            eprintln!("# ✗ invalid crontab line ({:#}): {}", e, answer);
+            let retry = format!(
+                "{}\n\nYour answer `{}` is not a valid crontab line: {:#}\nReply with a corrected one.",
+                request, answer, e
+            );
+            let answer = clean_expression(&client.query(&system_prompt, &retry).await?);
+            CronLine::parse(&answer).context(format!("Invalid crontab line: {}", answer))?
+        }
+    };
+    // The command runs unattended, so it gets the checks of any suggestion
+    let command = vet_command(&line.command, config, &policy, syntax_shell(config))
+        .ok_or_else(|| eyre::eyre!("The command was rejected by the safety policy: {}", line.command))?;
+    let line = CronLine { command, ..line };
+    println!("{}", line);
+    eprintln!("# runs {}", line.describe());
+
+    if install {
+        install_cron_line(&line)?;
+    }
+    Ok(line)
+}
+
+/// Append a line to the user's crontab (`crontab -l`, then `crontab -`) once they confirm
+fn install_cron_line(line: &CronLine) -> Result<()> {
+    if !confirm("Add this line to your crontab?")? {
+        eprintln!("# not added");
+        return Ok(());
+    }
+    // crontab -l fails when the user has no crontab yet
+    let current = std::process::Command::new("crontab")
+        .arg("-l")
+        .output()
+        .context("Failed to run crontab -l")?;
+    let current = if current.status.success() {
+        String::from_utf8_lossy(&current.stdout).into_owned()
+    } else {
+        String::new()
+    };
+    let Some(updated) = cron::append_line(&current, line) else {
+        eprintln!("# already in your crontab");
+        return Ok(());
+    };
+
+    let mut child = std::process::Command::new("crontab")
+        .arg("-")
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+        .context("Failed to run crontab -")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(updated.as_bytes())
+            .context("Failed to write the crontab")?;
+    }
+    let status = child.wait().context("Failed to run crontab -")?;
+    if !status.success() {
+        return Err(eyre::eyre!("crontab - failed ({}); your crontab is unchanged", status));
+    }
+    eprintln!("# added to your crontab");
+    Ok(())
+}
+
+/// Ask a yes/no question on the terminal; anything but y/yes is no
+fn confirm(question: &str) -> Result<bool> {
+    let stdin = std::io::stdin();
+    if !std::io::IsTerminal::is_terminal(&stdin) {
+        return Err(eyre::eyre!("Confirmation needs a terminal: {}", question));
+    }
+    eprint!("{} [y/N] ", question);
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    stdin.read_line(&mut answer).context("Failed to read the answer")?;
+    Ok(matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Put the first suggestion on the clipboard (--copy)
+fn copy_suggestion(commands: &[String]) -> Result<()> {
+    let Some(command) = commands.first() else {
+        return Ok(());
+    };
+    match clipboard::copy(command)? {
+        Copied::System => eprintln!("# copied to the clipboard"),
+        Copied::Terminal => eprintln!("# copied through the terminal (OSC 52)"),
+    }
     Ok(())
 }
 
-/// Load multi-result system prompt
-fn load_multi_result_prompt(count: usize) -> Result<String> {
-    // Check for custom multi prompt
-    if let Some(config_dir) = dirs::config_dir() {
-        let prompt_file = config_dir.join("qai").join("system-prompt-multi.txt");
-        if prompt_file.exists() {
-            let template =
-                fs::read_to_string(&prompt_file).context("Failed to read custom multi-result system prompt")?;
-            return Ok(template.replace("{{count}}", &count.to_string()));
+/// Ask once more when a multi answer has fewer than `count` distinct commands; returns the
+/// second answer when it has more
+async fn ask_for_more(
+    client: &OpenAIClient,
+    system_prompt: &str,
+    query: &str,
+    count: usize,
+    answer: &str,
+) -> Option<String> {
+    let found = distinct_commands(answer).len();
+    if found >= count {
+        return None;
+    }
+    info!("Got {} of {} requested commands, asking again", found, count);
+    let correction = format!(
+        "That was {} distinct command(s), but {} were requested. Reply again in the same format \
+         with {} distinct, complete commands and nothing else.",
+        found, count, count
+    );
+    match client
+        .query_multi_retry(system_prompt, query, count, answer, &correction)
+        .await
+    {
+        Ok(retry) if distinct_commands(&retry).len() > found => Some(retry),
+        Ok(_) => None,
+        Err(e) => {
+            log::warn!("Retry for more commands failed: {}", e);
+            None
+        }
+    }
+}
+
+/// Clean a suggestion and check it: the safety policy rewrites it, commands the shell can't
+/// parse are dropped (or flagged; shellcheck errors are only flagged, since it doesn't know
+/// zsh syntax), denied ones are dropped, and risky ones are flagged for the zsh widget to confirm
+fn vet_command(cmd: &str, config: &Config, policy: &Policy, syntax_shell: Option<&str>) -> Option<String> {
+    let cmd = policy.rewrite(&sanitize_command(cmd));
+    if cmd.is_empty() {
+        return None;
+    }
+
+    if let Some(shell) = syntax_shell {
+        for error in syntax::shellcheck(&cmd) {
+            eprintln!("# shellcheck ({}): {}", error, cmd);
+        }
+        if let Err(error) = syntax::check_syntax(shell, &cmd) {
+            eprintln!("# ✗ syntax error ({}): {}", error, cmd);
+            if config.safety.syntax_check != SyntaxCheck::Flag {
+                return None;
+            }
+        }
+    }
+
+    match policy.verdict(&cmd) {
+        Verdict::Allow => {}
+        Verdict::Confirm(reason) => eprintln!("# ⚠ needs confirmation ({}): {}", reason, cmd),
+        Verdict::Deny(reason) => {
+            info!("Dropped suggestion ({}): {}", reason, cmd);
+            return None;
+        }
+    }
+    Some(cmd)
+}
+
+/// Prints the results of a streamed multi answer one at a time, each as soon as it passes the
+/// checks the results of a whole answer get
+struct StreamPrinter<'a> {
+    config: &'a Config,
+    policy: &'a Policy,
+    syntax_shell: Option<&'static str>,
+    tool_cache: &'a mut ToolCache,
+    format: OutputFormat,
+    count: usize,
+    printed: Vec<String>,
+    /// Modern commands whose tools aren't installed, for install hints
+    missing: Vec<String>,
+    /// Stdout was closed (the picker exited), so no more results are wanted
+    closed: bool,
+}
+
+impl<'a> StreamPrinter<'a> {
+    fn new(
+        config: &'a Config,
+        policy: &'a Policy,
+        syntax_shell: Option<&'static str>,
+        tool_cache: &'a mut ToolCache,
+        format: OutputFormat,
+        count: usize,
+    ) -> Self {
+        Self {
+            config,
+            policy,
+            syntax_shell,
+            tool_cache,
+            format,
+            count,
+            printed: Vec::new(),
+            missing: Vec::new(),
+            closed: false,
+        }
+    }
+
+    /// Whether no more results are wanted
+    fn done(&self) -> bool {
+        self.closed || self.printed.len() >= self.count
+    }
+
+    /// Print a command of the answer if its binaries exist, rewriting missing modern tools where possible
+    fn take(&mut self, streamed: StreamedCommand) {
+        let (command, explanation) = self.split(&streamed.command);
+        let command = if self.tool_cache.command_available(&command) {
+            Some(command)
+        } else if streamed.modern {
+            let substitute = self.tool_cache.substitute(&command, &self.config.tools.substitutions);
+            self.missing.push(command);
+            substitute
+        } else {
+            None
+        };
+        if let Some(command) = command {
+            self.print(&command, explanation);
+        }
+    }
+
+    /// In teach mode, split the explanation off a command
+    fn split(&self, command: &str) -> (String, Option<String>) {
+        if self.config.query.teach {
+            suggestion::split_explanation(command)
+        } else {
+            (command.to_string(), None)
+        }
+    }
+
+    /// Check and print a command, unless it was printed already or no more are wanted
+    fn print(&mut self, command: &str, explanation: Option<String>) {
+        if self.done() {
+            return;
+        }
+        let Some(command) = vet_command(command, self.config, self.policy, self.syntax_shell) else {
+            return;
+        };
+        let key = suggestion::dedup_key(&command);
+        if self.printed.iter().any(|printed| suggestion::dedup_key(printed) == key) {
+            return;
+        }
+        let suggestion = Suggestion::new(command.clone(), self.policy);
+        let suggestion = if self.config.query.teach {
+            suggestion.taught(explanation)
+        } else {
+            suggestion
+        };
+        let mut output = suggestion::render(&[suggestion], self.format);
+        if self.format == OutputFormat::Joined && !self.printed.is_empty() {
+            // The separator goes before each later command, so none follows the last
+            output.insert(0, '\n');
+        }
+        let mut stdout = std::io::stdout().lock();
+        if let Err(e) = stdout.write_all(output.as_bytes()).and_then(|()| stdout.flush()) {
+            log::debug!("Stopped printing results: {}", e);
+            self.closed = true;
+            return;
+        }
+        self.printed.push(command);
+    }
+}
+
+/// Stream a multi answer, printing each result as soon as it is complete. Results aren't
+/// re-ranked by history, but like a whole answer a short one is asked again and filled up with
+/// past picks. Returns the printed commands
+async fn stream_multi(
+    client: &OpenAIClient,
+    system_prompt: &str,
+    query: &str,
+    message: &str,
+    mut printer: StreamPrinter<'_>,
+) -> Result<Vec<String>> {
+    let mut commands = CommandStream::new();
+    let answer = client
+        .query_multi_stream(system_prompt, message, printer.count, &mut |piece| {
+            for command in commands.push(piece) {
+                printer.take(command);
+            }
+            if printer.done() {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        })
+        .await;
+    let answer = match answer {
+        Ok(answer) => answer,
+        Err(e) => {
+            for command in offline_commands(e, query, printer.config, printer.tool_cache)? {
+                printer.print(&command, None);
+            }
+            return Ok(printer.printed);
+        }
+    };
+    for command in commands.finish() {
+        printer.take(command);
+    }
+    // Standard commands should work even when none was found installed
+    if printer.printed.is_empty() {
+        for command in DualCommandList::parse(&sanitize_response(&answer)).standard {
+            let (command, explanation) = printer.split(&command);
+            printer.print(&command, explanation);
+        }
+    }
+    timing::mark("api request");
+
+    if !printer.done()
+        && let Some(retry) = ask_for_more(client, system_prompt, message, printer.count, &answer).await
+    {
+        let mut commands = CommandStream::new();
+        let mut retried = commands.push(&retry);
+        retried.extend(commands.finish());
+        for command in retried {
+            printer.take(command);
         }
     }
+    // Commands the user picked for this query before pad a short answer
+    if !printer.done()
+        && let Ok(store) = HistoryStore::new()
+    {
+        for command in store.past_commands(query) {
+            printer.print(&command, None);
+        }
+    }
+
+    ratelimit::report_hints(
+        &printer
+            .tool_cache
+            .install_hints(&printer.missing, PackageManager::detect()),
+    );
+    timing::mark("process results");
+    Ok(printer.printed)
+}
+
+/// Apply the config profile `name` (else the configured default profile, if any) and pick the prompt profile to use: the config
+/// profile's `prompt`, else a prompt profile of the same name (required only without a config profile)
+fn select_profile(config: &mut Config, name: Option<String>) -> Option<String> {
+    let name = name.or_else(|| config.profile.clone())?;
+    match config.apply_profile(&name) {
+        Some(profile) => profile.prompt.or_else(|| {
+            prompts_dir()
+                .is_some_and(|dir| dir.join(format!("{}.pmt", name)).exists())
+                .then_some(name)
+        }),
+        None => Some(name),
+    }
+}
+
+/// Distinct non-empty commands in a response, as they would be printed; whitespace variants
+/// of one command count once
+fn distinct_commands(response: &str) -> Vec<String> {
+    let commands = DualCommandList::parse(&sanitize_response(response))
+        .all_commands()
+        .iter()
+        .map(|cmd| sanitize_command(cmd))
+        .filter(|cmd| !cmd.is_empty())
+        .collect();
+    suggestion::dedup(commands)
+}
+
+/// Input to attach to a query: stdin for `-`, else the named file
+fn read_attached(source: &str) -> Result<String> {
+    if source == "-" {
+        read_piped_stdin("--context -")
+    } else {
+        fs::read_to_string(source).context(format!("Failed to read {}", source))
+    }
+}
+
+/// Read all of stdin, which must be piped; `reader` names the option reading it, for the error
+fn read_piped_stdin(reader: &str) -> Result<String> {
+    let mut stdin = std::io::stdin();
+    if std::io::IsTerminal::is_terminal(&stdin) {
+        return Err(eyre::eyre!("{} reads stdin, but nothing is piped in", reader));
+    }
+    let mut input = String::new();
+    std::io::Read::read_to_string(&mut stdin, &mut input).context("Failed to read stdin")?;
+    Ok(input)
+}
+
+/// The command `qai translate` was given: its words, or stdin for `-` (multi-line scripts)
+fn translation_input(words: &[String]) -> Result<String> {
+    match words {
+        [dash] if dash == "-" => Ok(read_piped_stdin("qai translate -")?.trim_end().to_string()),
+        _ => Ok(join_query(words)),
+    }
+}
 
-    // Default multi-result prompt
-    Ok(format!(
-        r#"You are a shell command assistant. Convert natural language queries into shell commands.
+/// Input sent along with a query, scrubbed like the prompt: privacy mode and secret redaction
+fn scrub_attached(input: &str, config: &Config) -> Result<String> {
+    let input = if config.context.privacy {
+        Identity::detect().scrub(input)
+    } else {
+        input.to_string()
+    };
+    let (input, _) = scrub_secrets(&input, "", config.safety.secrets)?;
+    Ok(input)
+}
 
-CRITICAL RULES:
-1. Return EXACTLY {} command options, one per line
-2. Return ONLY the commands, no explanations, no numbering, no backticks
-3. Commands should be variations that accomplish the user's goal
-4. Order from most likely/common to least
-5. Each command should be complete and executable
+/// Keep credentials from leaving the machine: redact them from the query and prompt context,
+/// or (with `refuse`) fail when the query itself contains one
+fn scrub_secrets(query: &str, system_prompt: &str, mode: SecretScan) -> Result<(String, String)> {
+    if mode == SecretScan::Off {
+        return Ok((query.to_string(), system_prompt.to_string()));
+    }
 
-Environment:
-- Shell: {{{{shell}}}}
-- OS: {{{{os}}}}
-- Working directory: {{{{cwd}}}}"#,
-        count
-    ))
+    let (clean_query, found) = secrets::redact_secrets(query);
+    if !found.is_empty() {
+        if mode == SecretScan::Refuse {
+            return Err(eyre::eyre!(
+                "Query not sent: it contains what looks like a secret ({})",
+                found.join(", ")
+            ));
+        }
+        eprintln!("# ⚠ redacted from the query before sending: {}", found.join(", "));
+    }
+    let (clean_prompt, found) = secrets::redact_secrets(system_prompt);
+    if !found.is_empty() {
+        log::warn!("Redacted from the prompt context before sending: {}", found.join(", "));
+    }
+    Ok((clean_query, clean_prompt))
 }
 
 fn handle_shell_init(shell: &str, config: &Config) -> Result<()> {
@@ -115,22 +1058,169 @@ fn handle_shell_init(shell: &str, config: &Config) -> Result<()> {
     }
 }
 
+/// `qai validate-api` exit status when the API can't be reached but queries fall back to the
+/// built-in offline rules: the widget enters AI mode anyway, with a warning
+const EXIT_OFFLINE: i32 = 3;
+
 async fn handle_validate_api(config: &Config) -> Result<()> {
     match validate_api_key_from_config(config).await {
         Ok(()) => {
             println!("✅ API key is valid");
             Ok(())
         }
-        Err(e) => Err(eyre::eyre!("{}", e)),
+        Err(e) => Err(e.into()),
     }
 }
 
+/// Read the prompts and the tool cache as a query would, saving the cache once it is
+/// re-validated; returns the number of installed tools
+fn preload() -> Result<usize> {
+    load_system_prompt()?;
+    load_multi_prompt()?;
+    let mut tool_cache = ToolCache::load();
+    tool_cache.save()?;
+    Ok(tool_cache.stats().available_count)
+}
+
+/// Validate the API key, preload prompts and the tool cache, and check for a daemon, concurrently
+async fn handle_warmup(config: &Config) -> Result<()> {
+    let validated = validate_api_key_from_config(config);
+    let preloaded = tokio::task::spawn_blocking(preload);
+    #[cfg(all(feature = "daemon", unix))]
+    let socket = daemon::socket_path();
+    #[cfg(all(feature = "daemon", unix))]
+    let (validated, preloaded, listening) = tokio::join!(validated, preloaded, daemon::ping(&socket));
+    #[cfg(not(all(feature = "daemon", unix)))]
+    let (validated, preloaded) = tokio::join!(validated, preloaded);
+
+    let mut failed = Vec::new();
+    match validated {
+        Ok(()) => println!("{}", theme::status("API KEY", Status::Ok, "valid")),
+        Err(e) => {
+            println!("{}", theme::status("API KEY", Status::Fail, &e.to_string()));
+            failed.push("API key");
+        }
+    }
+    match preloaded.context("Preload task failed")? {
+        Ok(tools) => println!(
+            "{}",
+            theme::status(
+                "PRELOAD",
+                Status::Ok,
+                &format!("prompts and tool cache ({} tools installed)", tools)
+            )
+        ),
+        Err(e) => {
+            println!("{}", theme::status("PRELOAD", Status::Fail, &format!("{:#}", e)));
+            failed.push("preload");
+        }
+    }
+    #[cfg(all(feature = "daemon", unix))]
+    if listening {
+        println!(
+            "{}",
+            theme::status("DAEMON", Status::Ok, &format!("listening on {}", socket.display()))
+        );
+    } else {
+        println!(
+            "{}",
+            theme::status("DAEMON", Status::Skip, "not running (start one with `qai daemon`)")
+        );
+    }
+    // Opted-in usage counts go out at most once a day; failing to send them isn't a warmup failure
+    let telemetry = Telemetry::new(Telemetry::default_path());
+    if let Some(endpoint) = &config.telemetry.endpoint
+        && telemetry.due()
+    {
+        match send_telemetry(&telemetry, endpoint, config.http_timeout_secs).await {
+            Ok(()) => println!("{}", theme::status("TELEMETRY", Status::Ok, "sent usage counts")),
+            Err(e) => println!("{}", theme::status("TELEMETRY", Status::Warn, &format!("{:#}", e))),
+        }
+    }
+
+    if !failed.is_empty() {
+        return Err(eyre::eyre!("Warmup failed: {}", failed.join(", ")));
+    }
+    Ok(())
+}
+
+/// POST the telemetry tally to the endpoint as JSON, and start a new tally once it is accepted
+async fn send_telemetry(telemetry: &Telemetry, endpoint: &str, timeout_secs: u64) -> Result<()> {
+    let Some(payload) = telemetry.payload() else {
+        return Ok(());
+    };
+    let response = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(timeout_secs))
+        .build()
+        .context("Failed to create HTTP client")?
+        .post(endpoint)
+        .json(&payload)
+        .send()
+        .await
+        .context(format!("Failed to send telemetry to {}", endpoint))?;
+    if !response.status().is_success() {
+        return Err(eyre::eyre!("{} rejected telemetry: {}", endpoint, response.status()));
+    }
+    telemetry.reset()
+}
+
+/// Handle telemetry: turn it on or off, or show its state or schema
+fn handle_telemetry(action: &TelemetryAction, config: &Config) -> Result<()> {
+    let telemetry = Telemetry::new(Telemetry::default_path());
+    match action {
+        TelemetryAction::Enable => {
+            telemetry.enable()?;
+            println!("Telemetry enabled. `qai telemetry status` shows what would be sent.");
+            if config.telemetry.endpoint.is_none() {
+                println!("No telemetry.endpoint is configured, so counts are only kept locally.");
+            }
+        }
+        TelemetryAction::Disable => {
+            telemetry.disable()?;
+            println!("Telemetry disabled; its id and unsent counts were deleted.");
+        }
+        TelemetryAction::Status => match telemetry.payload() {
+            Some(payload) => {
+                println!("{}", theme::status("TELEMETRY", Status::Ok, "enabled"));
+                let endpoint = config.telemetry.endpoint.as_deref().unwrap_or("none (kept locally)");
+                println!("{}", theme::status("ENDPOINT", Status::Skip, endpoint));
+                println!("\nNext report:");
+                println!("{}", serde_json::to_string_pretty(&payload)?);
+            }
+            None => println!("{}", theme::status("TELEMETRY", Status::Skip, "disabled (the default)")),
+        },
+        TelemetryAction::Schema => println!("{}", telemetry::SCHEMA),
+    }
+    Ok(())
+}
+
 /// Get the log directory path
 pub fn get_log_dir() -> PathBuf {
-    dirs::data_local_dir()
-        .unwrap_or_else(|| PathBuf::from("."))
-        .join("qai")
-        .join("logs")
+    paths::log_dir()
+}
+
+/// Rotate `log_file` once it has reached `max_bytes`: qai.log becomes qai.log.1, qai.log.1
+/// becomes qai.log.2, and so on, dropping files past `max_files`
+fn rotate_log(log_file: &Path, max_bytes: u64, max_files: usize) -> Result<()> {
+    let size = fs::metadata(log_file).map(|metadata| metadata.len()).unwrap_or(0);
+    if max_bytes == 0 || size < max_bytes {
+        return Ok(());
+    }
+
+    let rotated = |n: usize| PathBuf::from(format!("{}.{}", log_file.display(), n));
+    if max_files == 0 {
+        return fs::remove_file(log_file).context("Failed to remove log file");
+    }
+    let oldest = rotated(max_files);
+    if oldest.exists() {
+        fs::remove_file(&oldest).context("Failed to remove oldest log file")?;
+    }
+    for n in (1..max_files).rev() {
+        if rotated(n).exists() {
+            fs::rename(rotated(n), rotated(n + 1)).context("Failed to rotate log file")?;
+        }
+    }
+    fs::rename(log_file, rotated(1)).context("Failed to rotate log file")
 }
 
 /// Get the log file path
@@ -145,21 +1235,68 @@ pub fn build_status_footer() -> String {
     // fzf status
     let (fzf_available, fzf_version) = check_fzf_status();
     if fzf_available {
-        lines.push(format!("TOOLS: ✅ fzf       {}", fzf_version.unwrap_or_default()));
+        let detail = format!("fzf {}", fzf_version.unwrap_or_default());
+        lines.push(theme::status("TOOLS", Status::Ok, detail.trim_end()));
     } else {
-        lines.push("TOOLS: ⚠️  fzf       not found (single-result mode only)".to_string());
+        lines.push(theme::status(
+            "TOOLS",
+            Status::Warn,
+            "fzf not found (single-result mode only)",
+        ));
     }
 
     // API key status
     if check_api_key_configured() {
-        lines.push("API:   ✅ key configured".to_string());
+        lines.push(theme::status("API", Status::Ok, "key configured"));
     } else {
-        lines.push("API:   ❌ key not configured (set QAI_API_KEY or add to config)".to_string());
+        lines.push(theme::status(
+            "API",
+            Status::Fail,
+            "key not configured (set QAI_API_KEY or add to config)",
+        ));
+    }
+
+    // Today's spend, when a daily budget is set
+    if let Ok(config) = Config::load(None)
+        && config.budget.is_enabled()
+    {
+        let budget = Budget::new(config.budget.clone(), &config.model, Budget::default_path());
+        let usage = budget.today();
+        let used = budget.used_fraction(&usage).unwrap_or(0.0);
+        let warn_at = config
+            .budget
+            .warn_at
+            .iter()
+            .min()
+            .map_or(1.0, |&percent| f64::from(percent) / 100.0);
+        let status = if used >= 1.0 {
+            Status::Fail
+        } else if used >= warn_at {
+            Status::Warn
+        } else {
+            Status::Ok
+        };
+        let detail = format!(
+            "{:.0}% of daily budget used ({})",
+            used * 100.0,
+            budget.describe(&usage)
+        );
+        lines.push(theme::status("BUDGET", status, &detail));
     }
 
     lines.join("\n")
 }
 
+/// Apply the --seed and --deterministic flags over the query: defaults
+fn apply_sampling(config: &mut Config, seed: Option<u64>, deterministic: bool) {
+    if seed.is_some() {
+        config.query.seed = seed;
+    }
+    if deterministic {
+        config.query.make_deterministic();
+    }
+}
+
 /// Join query words into a single string
 pub fn join_query(words: &[String]) -> String {
     words.join(" ")
@@ -177,10 +1314,19 @@ fn handle_history(limit: usize, patterns: bool, stats: bool, clear: bool) -> Res
 
     if stats {
         let stats = store.stats()?;
-        println!("History Statistics:");
-        println!("  Total queries:    {}", stats.total_queries);
-        println!("  Unique patterns:  {}", stats.unique_patterns);
-        println!("  With preferences: {}", stats.patterns_with_preference);
+        println!("{}", theme::heading("History Statistics"));
+        let mut table = Table::new();
+        table.row(vec!["Total queries".into(), stats.total_queries.to_string().into()]);
+        table.row(vec!["Unique patterns".into(), stats.unique_patterns.to_string().into()]);
+        table.row(vec![
+            "With preferences".into(),
+            stats.patterns_with_preference.to_string().into(),
+        ]);
+        table.row(vec![
+            "Failing commands".into(),
+            stats.failing_commands.to_string().into(),
+        ]);
+        table.print();
         return Ok(());
     }
 
@@ -191,17 +1337,20 @@ fn handle_history(limit: usize, patterns: bool, stats: bool, clear: bool) -> Res
             return Ok(());
         }
 
-        println!("Query Patterns (by usage):\n");
+        println!("{}", theme::heading("Query Patterns (by usage)"));
+        let mut table = Table::new().header(&["QUERY", "USES", "PREFERRED"]);
         for pattern in patterns.iter().take(limit) {
-            println!(
-                "  \"{}\" (used {} times)",
-                pattern.normalized_query, pattern.query_count
-            );
-            if let Some(preferred) = &pattern.preferred_command {
-                println!("    → preferred: {}", preferred);
-            }
-            println!();
+            let preferred = match &pattern.preferred_command {
+                Some(preferred) => Cell::new(preferred.as_str(), Style::Ok),
+                None => Cell::new("-", Style::Dim),
+            };
+            table.row(vec![
+                pattern.normalized_query.as_str().into(),
+                pattern.query_count.to_string().into(),
+                preferred,
+            ]);
         }
+        table.print();
         return Ok(());
     }
 
@@ -212,36 +1361,462 @@ fn handle_history(limit: usize, patterns: bool, stats: bool, clear: bool) -> Res
         return Ok(());
     }
 
-    println!("Recent Queries:\n");
+    println!("{}", theme::heading("Recent Queries"));
+    let mut table = Table::new().header(&["TIME", "QUERY", "", "COMMAND"]);
     for record in records {
-        let time = record.timestamp.format("%Y-%m-%d %H:%M");
-        println!("  [{}] \"{}\"", time, record.query);
-        if let Some(cmd) = record.final_command() {
-            let status = if record.executed { "✓" } else { " " };
-            println!("    {} {}", status, cmd);
-        }
-        println!();
+        let status = match (record.executed, record.exit_code) {
+            (true, Some(code)) if code != 0 => Cell::new("✗", Style::Error),
+            (true, _) => Cell::new("✓", Style::Ok),
+            _ => Cell::default(),
+        };
+        table.row(vec![
+            Cell::new(record.timestamp.format("%Y-%m-%d %H:%M").to_string(), Style::Dim),
+            record.query.as_str().into(),
+            status,
+            record.final_command().unwrap_or_default().into(),
+        ]);
     }
+    table.print();
 
     Ok(())
 }
 
-/// Handle tools command
-fn handle_tools(refresh: bool, clear: bool) -> Result<()> {
-    let mut cache = ToolCache::load();
-
-    if clear {
-        cache.clear();
-        cache.save()?;
-        println!("Tool cache cleared.");
+/// Show the final commands used most, across all queries
+fn handle_history_top(limit: usize) -> Result<()> {
+    let store = HistoryStore::new().context("Failed to open history store")?;
+    let top = store.top_commands(limit)?;
+    if top.is_empty() {
+        println!("No commands used yet.");
         return Ok(());
     }
 
-    if refresh {
-        cache.clear();
-        // Probe some common modern tools
-        let tools_to_check = [
-            "eza",
+    println!("{}", theme::heading("Top Commands"));
+    let mut table = Table::new().header(&["USES", "LAST USED", "COMMAND"]);
+    for usage in top {
+        table.row(vec![
+            usage.count.to_string().into(),
+            Cell::new(usage.last_used.format("%Y-%m-%d %H:%M").to_string(), Style::Dim),
+            usage.command.into(),
+        ]);
+    }
+    table.print();
+    Ok(())
+}
+
+/// Handle snippet add/list/run
+async fn handle_snippet(action: &SnippetAction, config: &mut Config) -> Result<()> {
+    let path = SnippetStore::default_path().ok_or_else(|| eyre::eyre!("Could not determine config directory"))?;
+    let store = SnippetStore::new(path);
+    match action {
+        SnippetAction::Add { name, template } => {
+            let template = template.join(" ");
+            let replaced = store.add(name, &template)?;
+            println!(
+                "{} snippet '{}' in {}",
+                if replaced { "Replaced" } else { "Saved" },
+                name,
+                store.path().display()
+            );
+        }
+        SnippetAction::List => {
+            let snippets = store.load()?;
+            if snippets.is_empty() {
+                println!("No snippets yet; save one with `qai snippet add <name> <query>`.");
+                return Ok(());
+            }
+            let mut table = Table::new().header(&["NAME", "PLACEHOLDERS", "QUERY"]);
+            for (name, template) in snippets {
+                let placeholders = snippet::placeholders(&template).join(", ");
+                table.row(vec![name.into(), Cell::new(placeholders, Style::Dim), template.into()]);
+            }
+            table.print();
+        }
+        SnippetAction::Run {
+            name,
+            args,
+            multi,
+            single,
+        } => {
+            let query = snippet::fill(&store.get(name)?, args)
+                .map_err(|e| eyre::eyre!("Can't run snippet '{}': {}", name, e))?;
+            let profile = select_profile(config, resolve_profile(None));
+            let (multi, count) = config.query.resolve(*multi, *single, None);
+            let format = OutputFormat::from_flags(false, false, false, false);
+            handle_query(
+                &query,
+                None,
+                config,
+                multi,
+                count,
+                profile.as_deref(),
+                format,
+                config.query.stream,
+            )
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Summarize the history of the past `days` days
+fn handle_report(days: u32) -> Result<()> {
+    let store = HistoryStore::new().context("Failed to open history store")?;
+    let since = chrono::Utc::now() - chrono::Duration::days(days.into());
+    let report = report::Report::from_records(&store.read_records()?, since);
+
+    let title = if days == 7 {
+        "Weekly Report".to_string()
+    } else {
+        format!("Report (last {} days)", days)
+    };
+    println!("{}", theme::heading(&title));
+    let mut table = Table::new();
+    table.row(vec!["Queries".into(), report.queries.to_string().into()]);
+    table.row(vec!["Tokens".into(), report.tokens.to_string().into()]);
+    let acceptance = match report.acceptance_rate() {
+        Some(rate) => format!("{:.0}% ({} run)", rate * 100.0, report.accepted).into(),
+        None => Cell::new("-", Style::Dim),
+    };
+    table.row(vec!["Acceptance".into(), acceptance]);
+    let topics: Vec<String> = report
+        .topics
+        .iter()
+        .map(|(tool, count)| format!("{} ({})", tool, count))
+        .collect();
+    table.row(vec!["Top topics".into(), list_cell(&topics)]);
+    table.row(vec!["New tools".into(), list_cell(&report.new_tools)]);
+    table.print();
+    Ok(())
+}
+
+/// A comma-separated list, or a dim `-` when empty
+fn list_cell(items: &[String]) -> Cell {
+    if items.is_empty() {
+        Cell::new("-", Style::Dim)
+    } else {
+        items.join(", ").into()
+    }
+}
+
+/// Handle record-exec: log a suggested command the shell ran, learning from successful runs
+fn handle_record_exec(
+    config: &Config,
+    query: &str,
+    suggested: &str,
+    command: &str,
+    exit_code: i32,
+    cwd: Option<&Path>,
+) -> Result<()> {
+    if config.audit.enabled {
+        audit_log(config).append(&AuditEntry {
+            timestamp: chrono::Utc::now(),
+            user: detect_user(),
+            hostname: detect_hostname(),
+            cwd: cwd.map(Path::to_path_buf).or_else(|| std::env::current_dir().ok()),
+            query: query.to_string(),
+            suggested: suggested.to_string(),
+            command: command.to_string(),
+            exit_code,
+            model: config.model.clone(),
+        })?;
+    }
+    if config.session.transcript
+        && let Some(log) = SessionLog::current()
+    {
+        log.append(SessionEvent::Execution {
+            query: query.to_string(),
+            suggested: suggested.to_string(),
+            command: command.to_string(),
+            exit_code,
+        })?;
+    }
+    let mut store = HistoryStore::new().context("Failed to open history store")?;
+    store.record_execution(query, suggested, command, exit_code, &config.model)
+}
+
+/// The configured audit log
+fn audit_log(config: &Config) -> AuditLog {
+    AuditLog::new(config.audit.path.clone().unwrap_or_else(AuditLog::default_path))
+}
+
+/// Parse a --since value: a date (midnight UTC) or an RFC 3339 timestamp
+fn parse_since(since: &str) -> Result<chrono::DateTime<chrono::Utc>> {
+    if let Ok(date) = chrono::NaiveDate::parse_from_str(since, "%Y-%m-%d") {
+        return Ok(date.and_time(chrono::NaiveTime::MIN).and_utc());
+    }
+    chrono::DateTime::parse_from_rfc3339(since)
+        .map(|time| time.with_timezone(&chrono::Utc))
+        .map_err(|_| eyre::eyre!("Invalid --since '{}': use YYYY-MM-DD or RFC 3339", since))
+}
+
+/// Handle audit export: print the audit log in the chosen format
+fn handle_audit_export(config: &Config, format: ExportFormat, since: Option<&str>) -> Result<()> {
+    let since = since.map(parse_since).transpose()?;
+    let log = audit_log(config);
+    if !config.audit.enabled && !log.path().exists() {
+        eprintln!("The audit log is disabled; enable it with `audit: {{ enabled: true }}` in qai.yml.");
+    }
+    log.export(format, since, &mut std::io::stdout().lock())
+}
+
+/// Validate the user config and the project config, if any: each file with its problems
+fn config_problems(config_path: Option<&PathBuf>) -> Result<Vec<(PathBuf, Vec<validate::Problem>)>> {
+    let mut results = Vec::new();
+    if let Some(path) = Config::user_config_path(config_path) {
+        let problems = validate::validate_file(&path)?;
+        results.push((path, problems));
+    }
+    if let Some(path) = std::env::current_dir().ok().and_then(|cwd| find_project_config(&cwd)) {
+        let problems = validate::validate_project_file(&path)?;
+        results.push((path, problems));
+    }
+    Ok(results)
+}
+
+/// Handle config validate: print each problem as `file:line: message`
+fn handle_config_validate(config_path: Option<&PathBuf>) -> Result<()> {
+    let results = config_problems(config_path)?;
+    if results.is_empty() {
+        println!("No config file found, using defaults.");
+    }
+    for (path, problems) in &results {
+        if problems.is_empty() {
+            println!("{}: ok", path.display());
+        }
+        for problem in problems {
+            match problem.line {
+                Some(line) => println!("{}:{}: {}", path.display(), line, problem.message),
+                None => println!("{}: {}", path.display(), problem.message),
+            }
+        }
+    }
+
+    let problems: usize = results.iter().map(|(_, problems)| problems.len()).sum();
+    if problems > 0 {
+        return Err(eyre::eyre!("{} problem(s) in config", problems));
+    }
+    Ok(())
+}
+
+/// Handle config schema: print the JSON Schema of the config file
+#[cfg(feature = "schema")]
+fn handle_config_schema() -> Result<()> {
+    println!("{}", config::config_schema()?);
+    Ok(())
+}
+
+/// Handle doctor: the installation status, then the config validation
+fn handle_doctor(config_path: Option<&PathBuf>) -> Result<()> {
+    println!("{}", build_status_footer());
+    let results = config_problems(config_path)?;
+    if results.is_empty() {
+        println!("{}", theme::status("CONFIG", Status::Ok, "none found (using defaults)"));
+    }
+    for (path, problems) in &results {
+        if problems.is_empty() {
+            println!("{}", theme::status("CONFIG", Status::Ok, &path.display().to_string()));
+            continue;
+        }
+        println!("{}", theme::status("CONFIG", Status::Fail, &path.display().to_string()));
+        for problem in problems {
+            println!("            {}", problem);
+        }
+    }
+
+    let problems: usize = results.iter().map(|(_, problems)| problems.len()).sum();
+    if problems > 0 {
+        return Err(eyre::eyre!(
+            "{} problem(s) in config; see `qai config validate`",
+            problems
+        ));
+    }
+    Ok(())
+}
+
+/// Walk a new user through qai one step at a time, checking that each part works here
+async fn handle_tutorial(config: &Config) -> Result<()> {
+    if !std::io::IsTerminal::is_terminal(&std::io::stdin()) {
+        return Err(eyre::eyre!("qai tutorial is interactive and needs a terminal"));
+    }
+    println!("{}", theme::heading("qai tutorial"));
+    println!("Five short steps; each one checks that its part of qai works on this machine.");
+
+    for (i, step) in tutorial::Step::ALL.into_iter().enumerate() {
+        println!(
+            "\n{}",
+            theme::heading(&format!("{}/{}: {}", i + 1, tutorial::Step::ALL.len(), step.title()))
+        );
+        println!("{}\n", step.lesson(&config.bindings));
+        let (status, detail) = tutorial_check(step, config).await?;
+        println!("{}", theme::status("CHECK", status, &detail));
+        if i + 1 < tutorial::Step::ALL.len() {
+            read_line("\nPress Enter to continue ")?;
+        }
+    }
+    println!("\nThat's it. `qai doctor` checks your config, and `qai --help` lists everything else.");
+    Ok(())
+}
+
+/// Check that a tutorial step works in this environment, with what to do when it doesn't
+async fn tutorial_check(step: tutorial::Step, config: &Config) -> Result<(Status, String)> {
+    Ok(match step {
+        tutorial::Step::FirstQuery => {
+            let query = read_line("Describe a command (Enter for \"list files by size\"): ")?;
+            let query = if query.is_empty() {
+                "list files by size"
+            } else {
+                query.as_str()
+            };
+            match handle_query(query, None, config, false, 1, None, OutputFormat::Lines, false).await {
+                Ok(commands) if !commands.is_empty() => (Status::Ok, "got a suggestion".to_string()),
+                Ok(_) => (
+                    Status::Warn,
+                    "no usable command came back; try describing it differently".to_string(),
+                ),
+                Err(e) => (
+                    Status::Fail,
+                    format!("{:#} (`qai doctor` and `qai validate-api` can help)", e),
+                ),
+            }
+        }
+        tutorial::Step::Trigger => {
+            if tutorial::integration_loaded(std::env::var(SESSION_ENV).ok().as_deref()) {
+                (Status::Ok, "the zsh integration is loaded in this shell".to_string())
+            } else {
+                (
+                    Status::Fail,
+                    "not loaded here: add `eval \"$(qai shell-init zsh)\"` to ~/.zshrc and open a new shell"
+                        .to_string(),
+                )
+            }
+        }
+        tutorial::Step::Picker => match check_fzf_status() {
+            (true, version) => (Status::Ok, format!("fzf {}", version.unwrap_or_default())),
+            (false, _) => (
+                Status::Warn,
+                "fzf is not installed, so AI mode inserts a single suggestion; install fzf to choose".to_string(),
+            ),
+        },
+        tutorial::Step::History => {
+            let store = HistoryStore::new().context("Failed to open history store")?;
+            match store.get_recent_queries(1)?.pop() {
+                Some(record) => (Status::Ok, format!("recorded \"{}\"", record.query)),
+                None => (Status::Fail, "nothing recorded yet; run a query first".to_string()),
+            }
+        }
+        tutorial::Step::Feedback => {
+            let store = HistoryStore::new().context("Failed to open history store")?;
+            if tutorial::feedback_recorded(&store.read_records()?) {
+                (Status::Ok, "runs of suggestions are being reported".to_string())
+            } else {
+                (
+                    Status::Skip,
+                    "no runs reported yet: run a suggestion from AI mode, and `qai history` marks it ✓ or ✗"
+                        .to_string(),
+                )
+            }
+        }
+    })
+}
+
+/// Ask for a line on the terminal, returned trimmed
+fn read_line(question: &str) -> Result<String> {
+    print!("{}", question);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin()
+        .read_line(&mut answer)
+        .context("Failed to read the answer")?;
+    Ok(answer.trim().to_string())
+}
+
+/// Handle daemon: serve queries until stopped
+#[cfg(all(feature = "daemon", unix))]
+async fn handle_daemon(socket: Option<&PathBuf>) -> Result<()> {
+    let socket = socket.cloned().unwrap_or_else(daemon::socket_path);
+    eprintln!("qai daemon listening on {}", socket.display());
+    daemon::serve(&socket).await
+}
+
+/// Handle check-command: print why a command needs confirmation (or is denied), if it does
+fn handle_check_command(config: &Config, command: &str) -> Result<()> {
+    match Policy::from_config(&config.safety)?.verdict(command) {
+        Verdict::Allow => {}
+        Verdict::Confirm(reason) => println!("{}", reason),
+        Verdict::Deny(reason) => println!("denied: {}", reason),
+    }
+    Ok(())
+}
+
+/// Handle check-command --slow: print why a command may run long, or nothing
+fn handle_check_slow(command: &str) {
+    if let Some(slow) = slow::check(command) {
+        println!("{}", slow);
+    }
+}
+
+/// Handle session show: print the transcript of a shell session
+fn handle_session_show(config: &Config, id: Option<&str>) -> Result<()> {
+    let id = match id {
+        Some(id) => id.to_string(),
+        None => std::env::var(SESSION_ENV)
+            .map_err(|_| eyre::eyre!("{} is not set; load the zsh integration or pass --id", SESSION_ENV))?,
+    };
+    let log = SessionLog::with_dir(&SessionLog::default_dir(), &id)
+        .ok_or_else(|| eyre::eyre!("Invalid session id: {}", id))?;
+
+    let entries = log.entries()?;
+    if entries.is_empty() {
+        println!("No transcript for session {}.", id);
+        if !config.session.transcript {
+            println!("Enable it with `session: {{ transcript: true }}` in qai.yml.");
+        }
+        return Ok(());
+    }
+
+    println!("Session {}:\n", id);
+    for entry in entries {
+        let time = entry.timestamp.format("%H:%M:%S");
+        match entry.event {
+            SessionEvent::Query { query, candidates } => {
+                println!("  [{}] \"{}\"", time, query);
+                for candidate in candidates {
+                    println!("      {}", candidate);
+                }
+            }
+            SessionEvent::Execution {
+                suggested,
+                command,
+                exit_code,
+                ..
+            } => {
+                let status = if exit_code == 0 { "✓" } else { "✗" };
+                println!("  [{}] {} {} (exit {})", time, status, command, exit_code);
+                if command.trim() != suggested.trim() {
+                    println!("      edited from: {}", suggested);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Handle tools command
+fn handle_tools(refresh: bool, clear: bool, json: bool) -> Result<()> {
+    let mut cache = ToolCache::load();
+
+    if clear {
+        cache.clear();
+        cache.save()?;
+        println!("Tool cache cleared.");
+        return Ok(());
+    }
+
+    if refresh {
+        cache.clear();
+        // Probe some common modern tools
+        let tools_to_check = [
+            "eza",
             "exa",
             "rg",
             "fd",
@@ -285,60 +1860,430 @@ fn handle_tools(refresh: bool, clear: bool) -> Result<()> {
         for tool in tools_to_check {
             cache.is_available(tool);
         }
+        cache.probe_versions();
         cache.save()?;
-        println!("Tool cache refreshed.");
+        if !json {
+            println!("Tool cache refreshed.");
+        }
+    }
+
+    if json {
+        let report = serde_json::to_string_pretty(&cache.report()).context("Failed to serialize tool report")?;
+        println!("{}", report);
+        return Ok(());
     }
 
     // Display cache contents
     let stats = cache.stats();
-    println!("Tool Cache Statistics:");
-    println!("  Available tools:     {}", stats.available_count);
-    println!("  Unavailable tools:   {}", stats.unavailable_count);
-    println!("  Modern tools found:  {}", stats.modern_tools_count);
+    println!("{}", theme::heading("Tool Cache Statistics"));
+    let mut table = Table::new();
+    table.row(vec!["Available tools".into(), stats.available_count.to_string().into()]);
+    table.row(vec![
+        "Unavailable tools".into(),
+        stats.unavailable_count.to_string().into(),
+    ]);
+    table.row(vec![
+        "Modern tools found".into(),
+        stats.modern_tools_count.to_string().into(),
+    ]);
+    if !cache.aliases.is_empty() || !cache.functions.is_empty() {
+        table.row(vec!["Imported aliases".into(), cache.aliases.len().to_string().into()]);
+        table.row(vec![
+            "Imported functions".into(),
+            cache.functions.len().to_string().into(),
+        ]);
+    }
+    table.print();
 
     if stats.available_count > 0 {
-        println!("\nAvailable modern tools:");
+        println!("\n{}", theme::heading("Available modern tools"));
         let prompt_hint = cache.available_tools_for_prompt();
         if !prompt_hint.is_empty() {
-            // Extract just the tool names from the hint
-            if let Some(tools_part) = prompt_hint.strip_prefix("User has these modern tools installed: ")
-                && let Some(tools_only) = tools_part.strip_suffix("\nPrefer these when appropriate.\n")
-            {
-                println!("  {}", tools_only);
+            for line in prompt_hint.lines() {
+                println!("  {}", line);
             }
         } else {
-            println!("  (only standard tools detected)");
+            println!("  {}", theme::dim("(only standard tools detected)"));
+        }
+    }
+
+    println!(
+        "\n{}",
+        theme::dim(&format!("Cache location: {}", ToolCache::cache_path().display()))
+    );
+
+    Ok(())
+}
+
+/// Handle tools import-aliases: read `alias` output and function names from stdin
+fn handle_import_aliases() -> Result<()> {
+    use std::io::Read;
+
+    let mut listing = String::new();
+    std::io::stdin()
+        .read_to_string(&mut listing)
+        .context("Failed to read aliases from stdin")?;
+
+    let (aliases, functions) = ToolCache::parse_alias_listing(&listing);
+    let (alias_count, function_count) = (aliases.len(), functions.len());
+
+    let mut cache = ToolCache::load();
+    cache.import_aliases(aliases, functions);
+    cache.save()?;
+
+    println!(
+        "Imported {} aliases and {} shell functions.",
+        alias_count, function_count
+    );
+    Ok(())
+}
+
+/// Handle prompt add-example: append a query -> command pair to the examples file
+fn handle_add_example(query: &str, command: &str) -> Result<()> {
+    let path = examples_path().ok_or_else(|| eyre::eyre!("Could not determine config directory"))?;
+    append_example_to(&path, query, command)?;
+    println!("Added example to {}", path.display());
+    Ok(())
+}
+
+/// Handle prompt lint: check the prompt templates and report problems
+fn handle_prompt_lint(config: &Config) -> Result<()> {
+    let dir = prompts_dir().ok_or_else(|| eyre::eyre!("Could not determine config directory"))?;
+    let lints = lint_prompts_in(&dir, &template_vars(config, &BTreeMap::new()));
+
+    let budget = config.context.max_prompt_tokens;
+    for lint in &lints {
+        print!("{} ({}): ", lint.name, lint.source);
+        if let Some(error) = &lint.error {
+            println!("error: {}", error);
+            continue;
+        }
+        print!("~{} tokens", lint.tokens);
+        if budget > 0 && lint.tokens > budget {
+            print!(" (over max-prompt-tokens {})", budget);
+        }
+        if lint.unknown.is_empty() {
+            println!(", ok");
+        } else {
+            println!(", unknown placeholders: {}", lint.unknown.join(", "));
         }
     }
 
-    println!("\nCache location: {}", ToolCache::cache_path().display());
+    let problems = lints.iter().filter(|lint| !lint.is_ok()).count();
+    if problems > 0 {
+        return Err(eyre::eyre!("{} prompt template(s) have problems", problems));
+    }
+    Ok(())
+}
+
+/// Handle __copy-hold: serve the clipboard text read from stdin until something else is copied
+#[cfg(all(feature = "clipboard", target_os = "linux"))]
+fn handle_copy_hold() -> Result<()> {
+    let mut text = String::new();
+    std::io::Read::read_to_string(&mut std::io::stdin(), &mut text).context("Failed to read stdin")?;
+    clipboard::hold(&text)
+}
+
+/// The opt-in context providers enabled in the config
+fn context_providers(config: &Config, cwd: &Path) -> Vec<Box<dyn ContextProvider>> {
+    let mut providers: Vec<Box<dyn ContextProvider>> = Vec::new();
+    if config.context.shell_history {
+        providers.push(Box::new(ShellHistoryProvider::from_env(
+            config.context.shell_history_entries,
+        )));
+    }
+    if !config.context.env_vars.is_empty() {
+        providers.push(Box::new(EnvVarsProvider::from_env(&config.context.env_vars)));
+    }
+    // Privacy mode never sends file names
+    if config.context.directory_listing && !config.context.privacy {
+        providers.push(Box::new(DirectoryListingProvider::new(
+            cwd,
+            config.context.directory_listing_max,
+        )));
+    }
+    providers
+}
+
+/// Run the drop-in context scripts, if there are any
+fn context_script_runs(config: &Config) -> Vec<ScriptRun> {
+    let Some(dir) = scripts_dir() else {
+        return Vec::new();
+    };
+    let runs = run_scripts(
+        &dir,
+        std::time::Duration::from_millis(config.context.scripts_timeout_ms),
+    );
+    for run in runs.iter().filter(|run| !matches!(run.status, ScriptStatus::Ok(_))) {
+        info!("Context script {} left out: {:?}", run.path.display(), run.status);
+    }
+    runs
+}
+
+/// Handle context show: run the context scripts, then print every context section a query
+/// from here would get
+fn handle_context_show(config: &Config) -> Result<()> {
+    let dir = scripts_dir().ok_or_else(|| eyre::eyre!("Could not determine config directory"))?;
+    println!("{}", theme::heading(&format!("Context scripts ({})", dir.display())));
+    let runs = context_script_runs(config);
+    if runs.is_empty() {
+        println!("No *.sh scripts found.");
+    }
+    for run in &runs {
+        let elapsed = format!("{} ms", run.elapsed.as_millis());
+        let line = match &run.status {
+            ScriptStatus::Ok(output) if output.is_empty() => {
+                theme::status(&run.name, Status::Skip, &format!("no output, {}", elapsed))
+            }
+            ScriptStatus::Ok(output) => theme::status(
+                &run.name,
+                Status::Ok,
+                &format!("{} lines, {}", output.lines().count(), elapsed),
+            ),
+            ScriptStatus::Failed(status) => theme::status(&run.name, Status::Fail, &format!("{}, left out", status)),
+            ScriptStatus::TimedOut => theme::status(
+                &run.name,
+                Status::Warn,
+                &format!("still running after {}, killed and left out", elapsed),
+            ),
+            ScriptStatus::Error(e) => theme::status(&run.name, Status::Fail, &format!("could not start: {}", e)),
+        };
+        println!("{}", line);
+    }
 
+    let cwd = std::env::current_dir().context("Failed to get current directory")?;
+    let mut sections = provider_sections(&context_providers(config, &cwd));
+    sections.extend(runs.iter().filter_map(ScriptRun::section));
+    println!("\n{}", theme::heading("Context sections"));
+    if sections.is_empty() {
+        println!("None; shell history, directory listing, and env vars are enabled under `context` in qai.yml.");
+    }
+    for section in &sections {
+        println!("{}", theme::dim(&format!("[priority {}]", section.priority)));
+        println!("{}", section.text.trim_end());
+    }
     Ok(())
 }
 
 /// Process a command and return result (for testing)
 pub async fn run_command(command: Option<&Commands>, config_path: Option<&PathBuf>) -> Result<()> {
     match command {
-        Some(Commands::Query { query, multi, count }) => {
-            let config = Config::load(config_path).context("Failed to load configuration")?;
+        Some(Commands::Query {
+            query,
+            multi,
+            single,
+            count,
+            profile,
+            null,
+            no_trailing_newline,
+            annotate,
+            json,
+            stream,
+            context,
+            copy,
+            seed,
+            deterministic,
+            teach,
+            adaptive,
+        }) => {
+            let mut config = Config::load(config_path).context("Failed to load configuration")?;
             let query_str = join_query(query);
-            handle_query(&query_str, &config, *multi, *count).await
+            let attached = context.as_deref().map(read_attached).transpose()?;
+            let profile = select_profile(&mut config, resolve_profile(profile.as_deref()));
+            let (multi, count) = config.query.resolve(*multi, *single, *count);
+            let format = OutputFormat::from_flags(*null, *annotate, *json, *no_trailing_newline);
+            let stream = *stream || config.query.stream;
+            config.query.teach |= *teach;
+            config.query.adaptive |= *adaptive;
+            apply_sampling(&mut config, *seed, *deterministic);
+            let commands = handle_query(
+                &query_str,
+                attached.as_deref(),
+                &config,
+                multi,
+                count,
+                profile.as_deref(),
+                format,
+                stream,
+            )
+            .await?;
+            if *copy {
+                copy_suggestion(&commands)?;
+            }
+            Ok(())
         }
         Some(Commands::ShellInit { shell }) => {
             let config = Config::load(config_path).context("Failed to load configuration")?;
             handle_shell_init(shell, &config)
         }
         Some(Commands::ValidateApi) => {
-            let config = Config::load(config_path).context("Failed to load configuration")?;
+            let mut config = Config::load(config_path).context("Failed to load configuration")?;
+            select_profile(&mut config, resolve_profile(None));
             handle_validate_api(&config).await
         }
+        Some(Commands::Warmup) => {
+            let mut config = Config::load(config_path).context("Failed to load configuration")?;
+            select_profile(&mut config, resolve_profile(None));
+            handle_warmup(&config).await
+        }
+        Some(Commands::Translate { to, from, command }) => {
+            let config = Config::load(config_path).context("Failed to load configuration")?;
+            let command = translation_input(command)?;
+            handle_translate(&command, from.as_deref(), to.as_deref(), &config)
+                .await
+                .map(|_| ())
+        }
+        Some(Commands::Regex {
+            flavor,
+            test,
+            description,
+        }) => {
+            let config = Config::load(config_path).context("Failed to load configuration")?;
+            let sample = test.then(|| read_piped_stdin("--test")).transpose()?;
+            handle_generate(
+                Generator::Regex,
+                &join_query(description),
+                flavor.as_deref(),
+                sample.as_deref(),
+                &config,
+            )
+            .await
+            .map(|_| ())
+        }
+        Some(Commands::Cron {
+            install,
+            schedule,
+            task,
+        }) => {
+            let config = Config::load(config_path).context("Failed to load configuration")?;
+            handle_cron(schedule, task, *install, &config).await.map(|_| ())
+        }
+        Some(Commands::Jq { test, description }) => {
+            let config = Config::load(config_path).context("Failed to load configuration")?;
+            let sample = test.then(|| read_piped_stdin("--test")).transpose()?;
+            handle_generate(
+                Generator::Jq,
+                &join_query(description),
+                None,
+                sample.as_deref(),
+                &config,
+            )
+            .await
+            .map(|_| ())
+        }
+        Some(Commands::History {
+            action: Some(HistoryAction::Top { limit }),
+            ..
+        }) => handle_history_top(*limit),
         Some(Commands::History {
             limit,
             patterns,
             stats,
             clear,
+            ..
         }) => handle_history(*limit, *patterns, *stats, *clear),
-        Some(Commands::Tools { refresh, clear }) => handle_tools(*refresh, *clear),
+        Some(Commands::Report { days, .. }) => handle_report(days.unwrap_or(7)),
+        Some(Commands::Telemetry { action }) => {
+            let config = Config::load(config_path).context("Failed to load configuration")?;
+            handle_telemetry(action, &config)
+        }
+        Some(Commands::Tools {
+            action: Some(ToolsAction::ImportAliases),
+            ..
+        }) => handle_import_aliases(),
+        Some(Commands::Tools {
+            refresh, clear, json, ..
+        }) => handle_tools(*refresh, *clear, *json),
+        Some(Commands::RecordExec {
+            query,
+            suggested,
+            command,
+            exit_code,
+            cwd,
+        }) => {
+            let config = Config::load(config_path).context("Failed to load configuration")?;
+            handle_record_exec(&config, query, suggested, command, *exit_code, cwd.as_deref())
+        }
+        Some(Commands::RecordSelect { query, command }) => {
+            let config = Config::load(config_path).context("Failed to load configuration")?;
+            hooks::post_select(&config.hooks, query, command);
+            Ok(())
+        }
+        Some(Commands::Audit {
+            action: AuditAction::Export { format, since },
+        }) => {
+            let config = Config::load(config_path).context("Failed to load configuration")?;
+            handle_audit_export(&config, *format, since.as_deref())
+        }
+        Some(Commands::Audit {
+            action: AuditAction::Path,
+        }) => {
+            let config = Config::load(config_path).context("Failed to load configuration")?;
+            println!("{}", audit_log(&config).path().display());
+            Ok(())
+        }
+        Some(Commands::Prompt {
+            action: PromptAction::AddExample { query, command },
+        }) => handle_add_example(query, command),
+        Some(Commands::Prompt {
+            action: PromptAction::Lint,
+        }) => {
+            let config = Config::load(config_path).context("Failed to load configuration")?;
+            handle_prompt_lint(&config)
+        }
+        Some(Commands::Context {
+            action: ContextAction::Show,
+        }) => {
+            let config = Config::load(config_path).context("Failed to load configuration")?;
+            handle_context_show(&config)
+        }
+        Some(Commands::Session {
+            action: SessionAction::Show { id },
+        }) => {
+            let config = Config::load(config_path).context("Failed to load configuration")?;
+            handle_session_show(&config, id.as_deref())
+        }
+        Some(Commands::CheckCommand { command, slow: true }) => {
+            handle_check_slow(&command.join(" "));
+            Ok(())
+        }
+        Some(Commands::CheckCommand { command, .. }) => {
+            let config = Config::load(config_path).context("Failed to load configuration")?;
+            handle_check_command(&config, &command.join(" "))
+        }
+        Some(Commands::Config {
+            action: ConfigAction::Validate,
+        }) => handle_config_validate(config_path),
+        #[cfg(feature = "schema")]
+        Some(Commands::Config {
+            action: ConfigAction::Schema,
+        }) => handle_config_schema(),
+        Some(Commands::Doctor) => handle_doctor(config_path),
+        Some(Commands::Pin {
+            query,
+            command,
+            only,
+            list,
+            remove,
+        }) => handle_pin(query.as_deref(), command.as_deref(), *only, *list, *remove),
+        Some(Commands::Snippet { action }) => {
+            let mut config = Config::load(config_path).context("Failed to load configuration")?;
+            handle_snippet(action, &mut config).await
+        }
+        Some(Commands::Tutorial) => {
+            let mut config = Config::load(config_path).context("Failed to load configuration")?;
+            select_profile(&mut config, resolve_profile(None));
+            handle_tutorial(&config).await
+        }
+        #[cfg(all(feature = "daemon", unix))]
+        Some(Commands::Daemon { socket }) => handle_daemon(socket.as_ref()).await,
+        #[cfg(feature = "serve")]
+        Some(Commands::Serve { port }) => {
+            let config = Config::load(config_path).context("Failed to load configuration")?;
+            server::serve(config, *port).await
+        }
+        #[cfg(all(feature = "clipboard", target_os = "linux"))]
+        Some(Commands::CopyHold) => handle_copy_hold(),
         None => {
             use clap::CommandFactory;
             let after_help = build_status_footer();
@@ -352,28 +2297,90 @@ pub async fn run_command(command: Option<&Commands>, config_path: Option<&PathBu
 #[tokio::main]
 #[cfg(not(tarpaulin_include))]
 async fn main() -> Result<()> {
-    // Setup logging first (errors go to file, not interfere with stdout)
-    if let Err(e) = setup_logging() {
-        // Don't fail if logging setup fails, just continue
-        eprintln!("Warning: Failed to setup logging: {}", e);
-    }
+    let started = std::time::Instant::now();
 
     // Parse CLI arguments with status info
     let cli = Cli::parse_with_status();
+    if cli.profile_startup {
+        timing::enable(started);
+    }
+    theme::init(cli.no_color);
+    timing::mark("parse args");
+
+    // Resolve state paths, then setup logging (errors go to file, not interfere with stdout)
+    paths::init(Config::load_section(cli.config.as_ref(), "paths"));
+    if let Err(e) = setup_logging(&Config::load_section(cli.config.as_ref(), "logging")) {
+        // Don't fail if logging setup fails, just continue
+        eprintln!("Warning: Failed to setup logging: {}", e);
+    }
+    timing::mark("setup logging");
 
     // Handle commands
     match &cli.command {
-        Some(Commands::Query { query, multi, count }) => {
+        Some(Commands::Query {
+            query,
+            multi,
+            single,
+            count,
+            profile,
+            null,
+            no_trailing_newline,
+            annotate,
+            json,
+            stream,
+            context,
+            copy,
+            seed,
+            deterministic,
+            teach,
+            adaptive,
+        }) => {
             // Load configuration
-            let config = Config::load(cli.config.as_ref()).context("Failed to load configuration")?;
+            let mut config = Config::load(cli.config.as_ref()).context("Failed to load configuration")?;
+            timing::mark("load config");
 
             // Join query words into single string
             let query_str = query.join(" ");
+            let attached = match context.as_deref().map(read_attached).transpose() {
+                Ok(attached) => attached,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            };
+
+            // Apply the config profile and pick the prompt profile (--profile or QAI_PROFILE)
+            let profile = select_profile(&mut config, resolve_profile(profile.as_deref()));
+            // Flags override the query: defaults from the config
+            let (multi, count) = config.query.resolve(*multi, *single, *count);
+            let format = OutputFormat::from_flags(*null, *annotate, *json, *no_trailing_newline);
+            let stream = *stream || config.query.stream;
+            config.query.teach |= *teach;
+            config.query.adaptive |= *adaptive;
+            apply_sampling(&mut config, *seed, *deterministic);
 
             // Handle the query
-            if let Err(e) = handle_query(&query_str, &config, *multi, *count).await {
-                eprintln!("Error: {}", e);
-                std::process::exit(1);
+            let commands = match handle_query(
+                &query_str,
+                attached.as_deref(),
+                &config,
+                multi,
+                count,
+                profile.as_deref(),
+                format,
+                stream,
+            )
+            .await
+            {
+                Ok(commands) => commands,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            // The command was printed: a clipboard failure is only reported
+            if *copy && let Err(e) = copy_suggestion(&commands) {
+                eprintln!("Warning: {:#}", e);
             }
         }
         Some(Commands::ShellInit { shell }) => {
@@ -384,123 +2391,691 @@ async fn main() -> Result<()> {
             }
         }
         Some(Commands::ValidateApi) => {
-            let config = Config::load(cli.config.as_ref()).context("Failed to load configuration")?;
+            let mut config = Config::load(cli.config.as_ref()).context("Failed to load configuration")?;
+            select_profile(&mut config, resolve_profile(None));
             if let Err(e) = handle_validate_api(&config).await {
+                let unreachable = e
+                    .downcast_ref::<api::ApiValidationError>()
+                    .is_some_and(api::ApiValidationError::is_unreachable);
+                if unreachable && config.query.offline_fallback {
+                    eprintln!("# API unreachable, answering from built-in rules ({})", e);
+                    std::process::exit(EXIT_OFFLINE);
+                }
                 eprintln!("Error: {}", e);
                 std::process::exit(1);
             }
         }
-        Some(Commands::History {
-            limit,
-            patterns,
-            stats,
-            clear,
-        }) => {
-            if let Err(e) = handle_history(*limit, *patterns, *stats, *clear) {
+        Some(Commands::Warmup) => {
+            let mut config = Config::load(cli.config.as_ref()).context("Failed to load configuration")?;
+            select_profile(&mut config, resolve_profile(None));
+            if let Err(e) = handle_warmup(&config).await {
                 eprintln!("Error: {}", e);
                 std::process::exit(1);
             }
         }
-        Some(Commands::Tools { refresh, clear }) => {
-            if let Err(e) = handle_tools(*refresh, *clear) {
+        Some(Commands::Translate { to, from, command }) => {
+            let config = Config::load(cli.config.as_ref()).context("Failed to load configuration")?;
+            let command = translation_input(command)?;
+            if let Err(e) = handle_translate(&command, from.as_deref(), to.as_deref(), &config).await {
                 eprintln!("Error: {}", e);
                 std::process::exit(1);
             }
         }
-        None => {
-            // No command provided, show help with status
-            use clap::CommandFactory;
-            let after_help = build_status_footer();
-            Cli::command().after_help(after_help).print_help()?;
-            println!();
-        }
+        Some(Commands::Regex {
+            flavor,
+            test,
+            description,
+        }) => {
+            let config = Config::load(cli.config.as_ref()).context("Failed to load configuration")?;
+            let sample = test.then(|| read_piped_stdin("--test")).transpose()?;
+            let description = join_query(description);
+            let generated = handle_generate(
+                Generator::Regex,
+                &description,
+                flavor.as_deref(),
+                sample.as_deref(),
+                &config,
+            )
+            .await;
+            if let Err(e) = generated {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Cron {
+            install,
+            schedule,
+            task,
+        }) => {
+            let config = Config::load(cli.config.as_ref()).context("Failed to load configuration")?;
+            if let Err(e) = handle_cron(schedule, task, *install, &config).await {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Jq { test, description }) => {
+            let config = Config::load(cli.config.as_ref()).context("Failed to load configuration")?;
+            let sample = test.then(|| read_piped_stdin("--test")).transpose()?;
+            let description = join_query(description);
+            if let Err(e) = handle_generate(Generator::Jq, &description, None, sample.as_deref(), &config).await {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::History {
+            action: Some(HistoryAction::Top { limit }),
+            ..
+        }) => {
+            if let Err(e) = handle_history_top(*limit) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::History {
+            limit,
+            patterns,
+            stats,
+            clear,
+            ..
+        }) => {
+            if let Err(e) = handle_history(*limit, *patterns, *stats, *clear) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Report { days, .. }) => {
+            if let Err(e) = handle_report(days.unwrap_or(7)) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Telemetry { action }) => {
+            let config = Config::load(cli.config.as_ref()).context("Failed to load configuration")?;
+            if let Err(e) = handle_telemetry(action, &config) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Tools {
+            action: Some(ToolsAction::ImportAliases),
+            ..
+        }) => {
+            if let Err(e) = handle_import_aliases() {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Tools {
+            refresh, clear, json, ..
+        }) => {
+            if let Err(e) = handle_tools(*refresh, *clear, *json) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::RecordExec {
+            query,
+            suggested,
+            command,
+            exit_code,
+            cwd,
+        }) => {
+            let config = Config::load(cli.config.as_ref()).context("Failed to load configuration")?;
+            if let Err(e) = handle_record_exec(&config, query, suggested, command, *exit_code, cwd.as_deref()) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::RecordSelect { query, command }) => {
+            let config = Config::load(cli.config.as_ref()).context("Failed to load configuration")?;
+            hooks::post_select(&config.hooks, query, command);
+        }
+        Some(Commands::Audit {
+            action: AuditAction::Export { format, since },
+        }) => {
+            let config = Config::load(cli.config.as_ref()).context("Failed to load configuration")?;
+            if let Err(e) = handle_audit_export(&config, *format, since.as_deref()) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Audit {
+            action: AuditAction::Path,
+        }) => {
+            let config = Config::load(cli.config.as_ref()).context("Failed to load configuration")?;
+            println!("{}", audit_log(&config).path().display());
+        }
+        Some(Commands::Prompt {
+            action: PromptAction::AddExample { query, command },
+        }) => {
+            if let Err(e) = handle_add_example(query, command) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Prompt {
+            action: PromptAction::Lint,
+        }) => {
+            let config = Config::load(cli.config.as_ref()).context("Failed to load configuration")?;
+            if let Err(e) = handle_prompt_lint(&config) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Context {
+            action: ContextAction::Show,
+        }) => {
+            let config = Config::load(cli.config.as_ref()).context("Failed to load configuration")?;
+            if let Err(e) = handle_context_show(&config) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Session {
+            action: SessionAction::Show { id },
+        }) => {
+            let config = Config::load(cli.config.as_ref()).context("Failed to load configuration")?;
+            if let Err(e) = handle_session_show(&config, id.as_deref()) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::CheckCommand { command, slow: true }) => handle_check_slow(&command.join(" ")),
+        Some(Commands::CheckCommand { command, .. }) => {
+            let config = Config::load(cli.config.as_ref()).context("Failed to load configuration")?;
+            if let Err(e) = handle_check_command(&config, &command.join(" ")) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Config {
+            action: ConfigAction::Validate,
+        }) => {
+            if let Err(e) = handle_config_validate(cli.config.as_ref()) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        #[cfg(feature = "schema")]
+        Some(Commands::Config {
+            action: ConfigAction::Schema,
+        }) => {
+            if let Err(e) = handle_config_schema() {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Doctor) => {
+            if let Err(e) = handle_doctor(cli.config.as_ref()) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Pin {
+            query,
+            command,
+            only,
+            list,
+            remove,
+        }) => {
+            if let Err(e) = handle_pin(query.as_deref(), command.as_deref(), *only, *list, *remove) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Snippet { action }) => {
+            let mut config = Config::load(cli.config.as_ref()).context("Failed to load configuration")?;
+            if let Err(e) = handle_snippet(action, &mut config).await {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Tutorial) => {
+            let mut config = Config::load(cli.config.as_ref()).context("Failed to load configuration")?;
+            select_profile(&mut config, resolve_profile(None));
+            if let Err(e) = handle_tutorial(&config).await {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        #[cfg(all(feature = "daemon", unix))]
+        Some(Commands::Daemon { socket }) => {
+            if let Err(e) = handle_daemon(socket.as_ref()).await {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        #[cfg(feature = "serve")]
+        Some(Commands::Serve { port }) => {
+            let config = Config::load(cli.config.as_ref()).context("Failed to load configuration")?;
+            if let Err(e) = server::serve(config, *port).await {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        #[cfg(all(feature = "clipboard", target_os = "linux"))]
+        Some(Commands::CopyHold) => {
+            if let Err(e) = handle_copy_hold() {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        None => {
+            // No command provided, show help with status
+            use clap::CommandFactory;
+            let after_help = build_status_footer();
+            Cli::command().after_help(after_help).print_help()?;
+            println!();
+        }
+    }
+
+    if let Some(report) = timing::report() {
+        info!("Startup profile:\n{}", report);
+        eprintln!("{}", report);
+    }
+    // Only the subcommand's name and time, and only when opted in
+    if let Some(name) = &cli.command_name
+        && let Err(e) = Telemetry::new(Telemetry::default_path()).record(name, started.elapsed())
+    {
+        log::debug!("Failed to record telemetry: {}", e);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{body_string_contains, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn create_success_response(content: &str) -> String {
+        format!(r#"{{"choices": [{{"message": {{"content": "{}"}}}}]}}"#, content)
+    }
+
+    #[test]
+    fn test_get_log_dir() {
+        let log_dir = get_log_dir();
+        assert!(log_dir.ends_with("qai/logs"));
+    }
+
+    #[test]
+    fn test_rotate_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_file = dir.path().join("qai.log");
+        let read = |name: &str| fs::read_to_string(dir.path().join(name)).ok();
+
+        // Below the limit: left alone
+        fs::write(&log_file, "one").unwrap();
+        rotate_log(&log_file, 10, 2).unwrap();
+        assert_eq!(read("qai.log").as_deref(), Some("one"));
+
+        rotate_log(&log_file, 3, 2).unwrap();
+        assert_eq!(read("qai.log"), None);
+        assert_eq!(read("qai.log.1").as_deref(), Some("one"));
+
+        fs::write(&log_file, "two").unwrap();
+        rotate_log(&log_file, 3, 2).unwrap();
+        fs::write(&log_file, "three").unwrap();
+        rotate_log(&log_file, 3, 2).unwrap();
+        assert_eq!(read("qai.log.1").as_deref(), Some("three"));
+        assert_eq!(read("qai.log.2").as_deref(), Some("two"));
+        assert_eq!(read("qai.log.3"), None);
+
+        // No size limit
+        fs::write(&log_file, "four").unwrap();
+        rotate_log(&log_file, 0, 2).unwrap();
+        assert_eq!(read("qai.log").as_deref(), Some("four"));
+
+        // No files kept
+        rotate_log(&log_file, 1, 0).unwrap();
+        assert_eq!(read("qai.log"), None);
+        assert_eq!(read("qai.log.1").as_deref(), Some("three"));
+    }
+
+    #[test]
+    fn test_get_log_file() {
+        let log_file = get_log_file();
+        assert!(log_file.ends_with("qai.log"));
+    }
+
+    #[test]
+    fn test_handle_shell_init_zsh() {
+        let config = Config::default();
+        let result = handle_shell_init("zsh", &config);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_handle_shell_init_unsupported() {
+        let config = Config::default();
+        let result = handle_shell_init("fish", &config);
+        assert!(result.is_err());
+        let error = result.unwrap_err().to_string();
+        assert!(error.contains("Unsupported shell"));
+        assert!(error.contains("fish"));
+    }
+
+    #[test]
+    fn test_handle_shell_init_error_lists_supported() {
+        let config = Config::default();
+        let result = handle_shell_init("invalid", &config);
+        let error = result.unwrap_err().to_string();
+        assert!(error.contains("zsh"));
+    }
+
+    #[test]
+    fn test_handle_shell_init_with_custom_keybinding() {
+        let config = Config {
+            bindings: config::BindingsConfig {
+                trigger: "ctrl-space".to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let result = handle_shell_init("zsh", &config);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_handle_shell_init_with_invalid_keybinding() {
+        let config = Config {
+            bindings: config::BindingsConfig {
+                trigger: "invalid-key".to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let result = handle_shell_init("zsh", &config);
+        assert!(result.is_err());
+        let error = result.unwrap_err().to_string();
+        assert!(error.contains("Invalid bindings configuration"));
+    }
+
+    #[tokio::test]
+    async fn test_handle_query_success() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(create_success_response("ls -la")))
+            .mount(&mock_server)
+            .await;
+
+        let config = Config {
+            api_key: Some("test-key".to_string()),
+            api_base: mock_server.uri(),
+            model: "gpt-4o-mini".to_string(),
+            debug: false,
+            ..Default::default()
+        };
+
+        let result = handle_query("list files", None, &config, false, 1, None, OutputFormat::Lines, false).await;
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_apply_sampling() {
+        let mut config = Config::default();
+        config.query.temperature = 0.5;
+        apply_sampling(&mut config, None, false);
+        assert_eq!((config.query.temperature, config.query.seed), (0.5, None));
+
+        apply_sampling(&mut config, Some(9), false);
+        assert_eq!((config.query.temperature, config.query.seed), (0.5, Some(9)));
+
+        let mut config = Config::default();
+        config.query.temperature = 0.5;
+        apply_sampling(&mut config, None, true);
+        assert_eq!(
+            (config.query.temperature, config.query.seed),
+            (0.0, Some(qai::config::DETERMINISTIC_SEED))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_suggest_teach_mode_keeps_explanations() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_string_contains("Teach mode"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(create_success_response("ls -la  # list everything here")),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let mut config = Config {
+            api_key: Some("test-key".to_string()),
+            api_base: mock_server.uri(),
+            ..Default::default()
+        };
+        config.query.teach = true;
+
+        let mut prepared = prepare_query("list files", &config, false, 1, None, None).unwrap();
+        let client = OpenAIClient::new(&config).unwrap();
+        let suggestions = suggest(&mut prepared, &client, &config, false, 1).await.unwrap();
+        assert_eq!(suggestions[0].command, "ls -la");
+        assert_eq!(suggestions[0].explanation.as_deref(), Some("list everything here"));
+    }
+
+    #[tokio::test]
+    async fn test_suggest_answers_offline_when_unreachable() {
+        // Nothing listens on port 1: the connection is refused
+        let mut config = Config {
+            api_key: Some("test-key".to_string()),
+            api_base: "http://127.0.0.1:1".to_string(),
+            ..Default::default()
+        };
+
+        let mut prepared = prepare_query("how do I make run.sh executable?", &config, false, 1, None, None).unwrap();
+        let client = OpenAIClient::new(&config).unwrap();
+        let suggestions = suggest(&mut prepared, &client, &config, false, 1).await.unwrap();
+        assert_eq!(suggestions[0].command, "chmod +x run.sh");
+
+        // Queries no rule knows still fail, as does any query with the fallback off
+        let mut prepared = prepare_query("translate this poem to french", &config, false, 1, None, None).unwrap();
+        let err = suggest(&mut prepared, &client, &config, false, 1).await.unwrap_err();
+        assert!(qai::api::is_unreachable(&err));
+        config.query.offline_fallback = false;
+        let mut prepared = prepare_query("make run.sh executable", &config, false, 1, None, None).unwrap();
+        assert!(suggest(&mut prepared, &client, &config, false, 1).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_handle_translate_names_the_target() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_string_contains("from bash to fish"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(create_success_response("set -gx EDITOR vim")))
+            .mount(&mock_server)
+            .await;
+
+        let config = Config {
+            api_key: Some("test-key".to_string()),
+            api_base: mock_server.uri(),
+            ..Default::default()
+        };
+
+        let translated = handle_translate("export EDITOR=vim", Some("bash"), Some("fish"), &config)
+            .await
+            .unwrap();
+        assert_eq!(translated, "set -gx EDITOR vim");
     }
 
-    Ok(())
-}
+    #[tokio::test]
+    async fn test_handle_generate_fixes_a_failing_regex() {
+        let mock_server = MockServer::start().await;
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use wiremock::matchers::{method, path};
-    use wiremock::{Mock, MockServer, ResponseTemplate};
+        // The first answer uses lookbehind, which fails on the sample; the fix request names the error
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_string_contains("Reply with a corrected one"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(create_success_response("\\\\d+ms")))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(create_success_response("(?<=took )\\\\d+")))
+            .mount(&mock_server)
+            .await;
 
-    fn create_success_response(content: &str) -> String {
-        format!(r#"{{"choices": [{{"message": {{"content": "{}"}}}}]}}"#, content)
-    }
+        let config = Config {
+            api_key: Some("test-key".to_string()),
+            api_base: mock_server.uri(),
+            ..Default::default()
+        };
 
-    #[test]
-    fn test_get_log_dir() {
-        let log_dir = get_log_dir();
-        assert!(log_dir.ends_with("qai/logs"));
+        let sample = "request took 15ms\nidle\n";
+        let regex = handle_generate(Generator::Regex, "durations", None, Some(sample), &config)
+            .await
+            .unwrap();
+        assert_eq!(regex, "\\d+ms");
     }
 
-    #[test]
-    fn test_get_log_file() {
-        let log_file = get_log_file();
-        assert!(log_file.ends_with("qai.log"));
-    }
+    #[tokio::test]
+    async fn test_handle_cron_retries_an_invalid_line() {
+        let mock_server = MockServer::start().await;
 
-    #[test]
-    fn test_handle_shell_init_zsh() {
-        let config = Config::default();
-        let result = handle_shell_init("zsh", &config);
-        assert!(result.is_ok());
-    }
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_string_contains("is not a valid crontab line"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(create_success_response(
+                "30 2 * * 1-5 tar czf /backup/notes-$(date +%F).tgz ~/notes",
+            )))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(create_success_response("30 25 * * 1-5 backup")))
+            .mount(&mock_server)
+            .await;
 
-    #[test]
-    fn test_handle_shell_init_unsupported() {
-        let config = Config::default();
-        let result = handle_shell_init("fish", &config);
-        assert!(result.is_err());
-        let error = result.unwrap_err().to_string();
-        assert!(error.contains("Unsupported shell"));
-        assert!(error.contains("fish"));
-    }
+        let config = Config {
+            api_key: Some("test-key".to_string()),
+            api_base: mock_server.uri(),
+            ..Default::default()
+        };
 
-    #[test]
-    fn test_handle_shell_init_error_lists_supported() {
-        let config = Config::default();
-        let result = handle_shell_init("invalid", &config);
-        let error = result.unwrap_err().to_string();
-        assert!(error.contains("zsh"));
+        let line = handle_cron("weekdays at 2:30am", "back up ~/notes", false, &config)
+            .await
+            .unwrap();
+        assert_eq!(line.schedule, vec!["30", "2", "*", "*", "1-5"]);
+        assert_eq!(line.command, "tar czf /backup/notes-$(date +\\%F).tgz ~/notes");
+        assert_eq!(line.describe(), "at 02:30 on Monday through Friday");
     }
 
-    #[test]
-    fn test_handle_shell_init_with_custom_keybinding() {
+    #[tokio::test]
+    async fn test_handle_query_multi_success() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(create_success_response("ls -la\\nls -lh\\nls")))
+            .mount(&mock_server)
+            .await;
+
         let config = Config {
-            bindings: config::BindingsConfig {
-                trigger: "ctrl-space".to_string(),
-                ..Default::default()
-            },
+            api_key: Some("test-key".to_string()),
+            api_base: mock_server.uri(),
+            model: "gpt-4o-mini".to_string(),
+            debug: false,
             ..Default::default()
         };
-        let result = handle_shell_init("zsh", &config);
+
+        let result = handle_query("list files", None, &config, true, 3, None, OutputFormat::Lines, false).await;
         assert!(result.is_ok());
     }
 
     #[test]
-    fn test_handle_shell_init_with_invalid_keybinding() {
-        let config = Config {
-            bindings: config::BindingsConfig {
-                trigger: "invalid-key".to_string(),
+    fn test_select_profile() {
+        let mut config = Config::default();
+        config.profiles.insert(
+            "qai-test-work".to_string(),
+            config::ProfileConfig {
+                model: Some("gpt-4o".to_string()),
                 ..Default::default()
             },
-            ..Default::default()
-        };
-        let result = handle_shell_init("zsh", &config);
-        assert!(result.is_err());
-        let error = result.unwrap_err().to_string();
-        assert!(error.contains("Invalid bindings configuration"));
+        );
+        config.profiles.insert(
+            "qai-test-k8s-work".to_string(),
+            config::ProfileConfig {
+                prompt: Some("k8s".to_string()),
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(select_profile(&mut config, None), None);
+        // Not a config profile: a prompt profile
+        assert_eq!(
+            select_profile(&mut config, Some("k8s".to_string())).as_deref(),
+            Some("k8s")
+        );
+        assert_eq!(config.model, "gpt-4o-mini");
+        // A config profile without a prompt profile of its name
+        assert_eq!(select_profile(&mut config, Some("qai-test-work".to_string())), None);
+        assert_eq!(config.model, "gpt-4o");
+        assert_eq!(
+            select_profile(&mut config, Some("qai-test-k8s-work".to_string())).as_deref(),
+            Some("k8s")
+        );
+    }
+
+    #[test]
+    fn test_distinct_commands() {
+        assert_eq!(
+            distinct_commands("MODERN:\n1. eza -la\nSTANDARD:\n1. ls -la\n2. ls -la\n\n"),
+            vec!["eza -la", "ls -la"]
+        );
+        assert_eq!(
+            distinct_commands("STANDARD:\n1. ps aux | grep node\n2. ps aux|grep  node\n\n"),
+            vec!["ps aux | grep node"]
+        );
+        assert!(distinct_commands("Sorry, I can't help with that.").is_empty());
+    }
+
+    #[test]
+    fn test_pinned_first() {
+        let mut config = Config::default();
+        config.safety.syntax_check = SyntaxCheck::Off;
+        config.safety.deny = vec!["kubeconfig-prod".to_string()];
+        let policy = Policy::from_config(&config.safety).unwrap();
+        let suggestions: Vec<Suggestion> = ["ls -la", "eza -la", "ls"]
+            .iter()
+            .map(|command| Suggestion::new(command.to_string(), &policy))
+            .collect();
+        let pinned = vec!["eza  -la".to_string(), "kubectl --kubeconfig-prod get pods".to_string()];
+
+        let commands: Vec<String> = pinned_first(&pinned, suggestions, &config, &policy, 3)
+            .into_iter()
+            .map(|s| s.command)
+            .collect();
+        // Pins are checked like suggestions: the denied one is dropped, and the model's copy
+        // of the other is not repeated
+        assert_eq!(commands, vec!["eza  -la", "ls -la", "ls"]);
+
+        let only = pinned_first(&pinned, Vec::new(), &config, &policy, 1);
+        assert_eq!(only.len(), 1);
     }
 
     #[tokio::test]
-    async fn test_handle_query_success() {
+    async fn test_handle_query_multi_retries_short_answer() {
         let mock_server = MockServer::start().await;
 
         Mock::given(method("POST"))
             .and(path("/chat/completions"))
-            .respond_with(ResponseTemplate::new(200).set_body_string(create_success_response("ls -la")))
+            .respond_with(ResponseTemplate::new(200).set_body_string(create_success_response("ls")))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_string_contains("but 3 were requested"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(create_success_response("ls -la\\nls -lh\\nls")))
+            .expect(1)
             .mount(&mock_server)
             .await;
 
@@ -508,34 +3083,38 @@ mod tests {
             api_key: Some("test-key".to_string()),
             api_base: mock_server.uri(),
             model: "gpt-4o-mini".to_string(),
-            debug: false,
             ..Default::default()
         };
 
-        let result = handle_query("list files", &config, false, 1).await;
+        let result = handle_query("list files", None, &config, true, 3, None, OutputFormat::Lines, false).await;
         assert!(result.is_ok());
     }
 
     #[tokio::test]
-    async fn test_handle_query_multi_success() {
+    async fn test_handle_query_adaptive_keeps_most_distinct() {
         let mock_server = MockServer::start().await;
 
         Mock::given(method("POST"))
             .and(path("/chat/completions"))
-            .respond_with(ResponseTemplate::new(200).set_body_string(create_success_response("ls -la\\nls -lh\\nls")))
+            .and(body_string_contains("EXACTLY 5 command options"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(create_success_response("ls -la\\nls -la -h\\ndu -sh .")),
+            )
+            .expect(1)
             .mount(&mock_server)
             .await;
 
-        let config = Config {
+        let mut config = Config {
             api_key: Some("test-key".to_string()),
             api_base: mock_server.uri(),
             model: "gpt-4o-mini".to_string(),
-            debug: false,
             ..Default::default()
         };
+        config.query.adaptive = true;
 
-        let result = handle_query("list files", &config, true, 3).await;
-        assert!(result.is_ok());
+        let query = "qai adaptive test: list files and their sizes";
+        let result = handle_query(query, None, &config, true, 2, None, OutputFormat::Lines, false).await;
+        assert_eq!(result.unwrap(), vec!["ls -la", "du -sh ."]);
     }
 
     #[tokio::test]
@@ -556,10 +3135,99 @@ mod tests {
             ..Default::default()
         };
 
-        let result = handle_query("test query", &config, false, 1).await;
+        let result = handle_query("test query", None, &config, false, 1, None, OutputFormat::Lines, false).await;
         assert!(result.is_err());
     }
 
+    #[tokio::test]
+    async fn test_handle_query_sends_attached_input() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_string_contains(
+                "delete the .tmp ones\\n\\nInput I'm referring to:",
+            ))
+            .and(body_string_contains("-rw-r--r-- a.tmp"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(create_success_response("rm a.tmp")))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let config = Config {
+            api_key: Some("test-key".to_string()),
+            api_base: mock_server.uri(),
+            model: "gpt-4o-mini".to_string(),
+            ..Default::default()
+        };
+        let listing = "-rw-r--r-- a.tmp\n-rw-r--r-- b.rs\n";
+        let result = handle_query(
+            "delete the .tmp ones",
+            Some(listing),
+            &config,
+            false,
+            1,
+            None,
+            OutputFormat::Lines,
+            false,
+        )
+        .await;
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_read_attached_file() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(file, "a.tmp\nb.tmp\n").unwrap();
+        assert_eq!(read_attached(file.path().to_str().unwrap()).unwrap(), "a.tmp\nb.tmp\n");
+        assert!(read_attached("/nonexistent/qai-input").is_err());
+    }
+
+    #[test]
+    fn test_scrub_secrets() {
+        let query = "deploy with token=abc123";
+        let prompt = "Environment variables:\n- GITHUB_TOKEN=ghp_abcdefghijklmnop1234";
+
+        let (query_out, prompt_out) = scrub_secrets(query, prompt, SecretScan::Redact).unwrap();
+        assert_eq!(query_out, "deploy with token=***");
+        assert_eq!(prompt_out, "Environment variables:\n- GITHUB_TOKEN=***");
+
+        let err = scrub_secrets(query, prompt, SecretScan::Refuse).unwrap_err();
+        assert!(err.to_string().contains("Query not sent"));
+        // Secrets only in the context are masked, not refused
+        let (_, prompt_out) = scrub_secrets("list files", prompt, SecretScan::Refuse).unwrap();
+        assert!(!prompt_out.contains("ghp_"));
+
+        let (query_out, _) = scrub_secrets(query, prompt, SecretScan::Off).unwrap();
+        assert_eq!(query_out, query);
+    }
+
+    #[tokio::test]
+    async fn test_handle_query_refuses_secret() {
+        let config = Config {
+            api_key: Some("test-key".to_string()),
+            api_base: "http://127.0.0.1:9".to_string(),
+            safety: config::SafetyConfig {
+                secrets: SecretScan::Refuse,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        // Fails before any request is made
+        let result = handle_query(
+            "login with password=hunter2",
+            None,
+            &config,
+            false,
+            1,
+            None,
+            OutputFormat::Lines,
+            false,
+        )
+        .await;
+        assert!(result.unwrap_err().to_string().contains("Query not sent"));
+    }
+
     #[test]
     fn test_join_query_single_word() {
         let words = vec!["test".to_string()];
@@ -646,7 +3314,20 @@ mod tests {
         let cmd = Commands::Query {
             query: vec!["print".to_string(), "hello".to_string()],
             multi: false,
-            count: 5,
+            single: false,
+            count: None,
+            profile: None,
+            null: false,
+            no_trailing_newline: false,
+            annotate: false,
+            json: false,
+            stream: false,
+            context: None,
+            copy: false,
+            seed: None,
+            deterministic: false,
+            teach: false,
+            adaptive: false,
         };
         let result = run_command(Some(&cmd), Some(&config_file.path().to_path_buf())).await;
         assert!(result.is_ok());
@@ -677,7 +3358,120 @@ mod tests {
         let cmd = Commands::Query {
             query: vec!["print".to_string(), "letters".to_string()],
             multi: true,
-            count: 3,
+            single: false,
+            count: Some(3),
+            profile: None,
+            null: false,
+            no_trailing_newline: false,
+            annotate: false,
+            json: false,
+            stream: false,
+            context: None,
+            copy: false,
+            seed: None,
+            deterministic: false,
+            teach: false,
+            adaptive: false,
+        };
+        let result = run_command(Some(&cmd), Some(&config_file.path().to_path_buf())).await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_query_log_lines_carry_span_fields() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"choices": [{"message": {"content": "echo traced"}}],
+                    "usage": {"prompt_tokens": 42, "completion_tokens": 3}}"#,
+            ))
+            .mount(&mock_server)
+            .await;
+        let config: Config = serde_yaml::from_str(&format!(
+            "api-key: test-key\napi-base: {}\nmodel: gpt-4o-mini",
+            mock_server.uri()
+        ))
+        .unwrap();
+
+        let log_file = tempfile::NamedTempFile::new().unwrap();
+        log_subscriber(std::sync::Mutex::new(log_file.reopen().unwrap()))
+            .try_init()
+            .unwrap();
+        handle_query(
+            "trace this query",
+            None,
+            &config,
+            false,
+            1,
+            None,
+            OutputFormat::Lines,
+            false,
+        )
+        .await
+        .unwrap();
+
+        let logs = fs::read_to_string(log_file.path()).unwrap();
+        let line: serde_json::Value = logs
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .find(|line: &serde_json::Value| line["message"] == "Query successful, result: echo traced")
+            .expect("query log line");
+        assert_eq!(line["level"], "INFO");
+        let span = &line["span"];
+        assert_eq!(span["name"], "query");
+        assert!(uuid::Uuid::parse_str(span["id"].as_str().unwrap()).is_ok());
+        assert_eq!(span["model"], "gpt-4o-mini");
+        assert_eq!(span["multi"], false);
+        assert_eq!(span["prompt_tokens"], 42);
+        assert_eq!(span["completion_tokens"], 3);
+        assert!(span["latency_ms"].is_u64());
+    }
+
+    #[tokio::test]
+    async fn test_run_command_query_stream_with_mock() {
+        let mock_server = MockServer::start().await;
+
+        let events = ["echo a\\necho", " b\\n", "echo c"].map(|piece| {
+            format!(
+                "data: {{\"choices\":[{{\"delta\":{{\"content\":\"{}\"}}}}]}}\n\n",
+                piece
+            )
+        });
+        let body = format!("{}data: [DONE]\n\n", events.concat());
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_string_contains(r#""stream":true"#))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let mut config_file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            config_file,
+            "api-key: test-key\napi-base: {}\nmodel: gpt-4o-mini\nquery:\n  stream: true",
+            mock_server.uri()
+        )
+        .unwrap();
+
+        let cmd = Commands::Query {
+            query: vec!["print".to_string(), "letters".to_string()],
+            multi: true,
+            single: false,
+            count: Some(3),
+            profile: None,
+            null: false,
+            no_trailing_newline: false,
+            annotate: false,
+            json: false,
+            stream: false,
+            context: None,
+            copy: false,
+            seed: None,
+            deterministic: false,
+            teach: false,
+            adaptive: false,
         };
         let result = run_command(Some(&cmd), Some(&config_file.path().to_path_buf())).await;
         assert!(result.is_ok());
@@ -708,6 +3502,38 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[tokio::test]
+    async fn test_run_command_warmup_with_mock() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/models"))
+            .and(header("Authorization", "Bearer test-key"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"data": []}"#))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/models"))
+            .respond_with(ResponseTemplate::new(401))
+            .mount(&mock_server)
+            .await;
+
+        let mut config_file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            config_file,
+            "api-key: test-key\napi-base: {}\nmodel: gpt-4o-mini",
+            mock_server.uri()
+        )
+        .unwrap();
+        let result = run_command(Some(&Commands::Warmup), Some(&config_file.path().to_path_buf())).await;
+        assert!(result.is_ok());
+
+        let mut config_file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(config_file, "api-key: revoked-key\napi-base: {}", mock_server.uri()).unwrap();
+        let result = run_command(Some(&Commands::Warmup), Some(&config_file.path().to_path_buf())).await;
+        assert_eq!(result.unwrap_err().to_string(), "Warmup failed: API key");
+    }
+
     #[test]
     fn test_build_status_footer_contains_sections() {
         let footer = build_status_footer();
@@ -716,22 +3542,6 @@ mod tests {
         assert!(footer.contains("API:"));
     }
 
-    #[test]
-    fn test_load_multi_result_prompt_default() {
-        let prompt = load_multi_result_prompt(5).unwrap();
-        assert!(prompt.contains("EXACTLY 5 command options"));
-        assert!(prompt.contains("one per line"));
-        assert!(prompt.contains("{{shell}}"));
-        assert!(prompt.contains("{{os}}"));
-        assert!(prompt.contains("{{cwd}}"));
-    }
-
-    #[test]
-    fn test_load_multi_result_prompt_different_count() {
-        let prompt = load_multi_result_prompt(3).unwrap();
-        assert!(prompt.contains("EXACTLY 3 command options"));
-    }
-
     #[tokio::test]
     async fn test_handle_validate_api_unauthorized() {
         let mock_server = MockServer::start().await;
@@ -802,17 +3612,7 @@ mod tests {
         assert!(result.is_ok());
     }
 
-    #[test]
-    fn test_load_multi_result_prompt_single() {
-        let prompt = load_multi_result_prompt(1).unwrap();
-        assert!(prompt.contains("EXACTLY 1 command options"));
-    }
 
-    #[test]
-    fn test_load_multi_result_prompt_ten() {
-        let prompt = load_multi_result_prompt(10).unwrap();
-        assert!(prompt.contains("EXACTLY 10 command options"));
-    }
 
     #[test]
     fn test_build_status_footer_fzf_status() {
@@ -854,6 +3654,7 @@ mod tests {
             patterns: false,
             stats: true,
             clear: false,
+            action: None,
         };
         let result = run_command(Some(&cmd), None).await;
         assert!(result.is_ok());
@@ -866,6 +3667,30 @@ mod tests {
             patterns: false,
             stats: false,
             clear: false,
+            action: None,
+        };
+        let result = run_command(Some(&cmd), None).await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_run_command_history_top() {
+        let cmd = Commands::History {
+            limit: 10,
+            patterns: false,
+            stats: false,
+            clear: false,
+            action: Some(HistoryAction::Top { limit: 5 }),
+        };
+        let result = run_command(Some(&cmd), None).await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_run_command_report() {
+        let cmd = Commands::Report {
+            weekly: true,
+            days: None,
         };
         let result = run_command(Some(&cmd), None).await;
         assert!(result.is_ok());
@@ -878,6 +3703,7 @@ mod tests {
             patterns: true,
             stats: false,
             clear: false,
+            action: None,
         };
         let result = run_command(Some(&cmd), None).await;
         assert!(result.is_ok());
@@ -888,6 +3714,8 @@ mod tests {
         let cmd = Commands::Tools {
             refresh: false,
             clear: false,
+            json: false,
+            action: None,
         };
         let result = run_command(Some(&cmd), None).await;
         assert!(result.is_ok());
@@ -898,6 +3726,8 @@ mod tests {
         let cmd = Commands::Tools {
             refresh: true,
             clear: false,
+            json: false,
+            action: None,
         };
         let result = run_command(Some(&cmd), None).await;
         assert!(result.is_ok());
@@ -908,6 +3738,8 @@ mod tests {
         let cmd = Commands::Tools {
             refresh: false,
             clear: true,
+            json: false,
+            action: None,
         };
         let result = run_command(Some(&cmd), None).await;
         assert!(result.is_ok());
@@ -916,19 +3748,25 @@ mod tests {
     #[test]
     fn test_handle_tools_display() {
         // Just verify the function runs without crashing
-        let result = handle_tools(false, false);
+        let result = handle_tools(false, false, false);
         assert!(result.is_ok());
     }
 
     #[test]
     fn test_handle_tools_clear() {
-        let result = handle_tools(false, true);
+        let result = handle_tools(false, true, false);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_handle_tools_json() {
+        let result = handle_tools(false, false, true);
         assert!(result.is_ok());
     }
 
     #[test]
     fn test_handle_tools_refresh() {
-        let result = handle_tools(true, false);
+        let result = handle_tools(true, false, false);
         assert!(result.is_ok());
     }
 }
@@ -0,0 +1,210 @@
+//! Config format migrations
+//!
+//! A config file records its layout in `version:` (missing means 0, from before
+//! versioning). Loading runs the migrations from that version up to
+//! `CONFIG_VERSION`; when one changes the file, the original is kept next to it
+//! as `qai.yml.v<N>.bak` and the upgraded config is written in its place.
+//! Files that only lack the version are left alone.
+
+use eyre::{Context, Result};
+use serde_yaml::{Mapping, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Current config layout version
+pub const CONFIG_VERSION: u64 = 1;
+
+/// One step up from the previous version; returns whether it changed anything
+type Migration = fn(&mut Mapping) -> bool;
+
+/// `MIGRATIONS[n]` upgrades version n to n + 1
+const MIGRATIONS: &[Migration] = &[bindings_section];
+
+/// v1: `trigger` and `submit` moved from the top level into `bindings:`
+fn bindings_section(config: &mut Mapping) -> bool {
+    let mut moved = Vec::new();
+    for (old, new) in [
+        ("trigger", "trigger"),
+        ("trigger-key", "trigger"),
+        ("trigger_key", "trigger"),
+        ("submit", "submit"),
+        ("submit-key", "submit"),
+        ("submit_key", "submit"),
+    ] {
+        if let Some(value) = config.remove(old) {
+            moved.push((new, value));
+        }
+    }
+    if moved.is_empty() {
+        return false;
+    }
+
+    let bindings = config
+        .entry(Value::from("bindings"))
+        .or_insert_with(|| Value::Mapping(Mapping::new()));
+    if !bindings.is_mapping() {
+        *bindings = Value::Mapping(Mapping::new());
+    }
+    if let Value::Mapping(bindings) = bindings {
+        for (key, value) in moved {
+            // An explicit bindings entry wins over the old top-level key
+            bindings.entry(Value::from(key)).or_insert(value);
+        }
+    }
+    true
+}
+
+/// The layout version a config declares (0 when it has none)
+fn version_of(config: &Mapping) -> u64 {
+    config.get("version").and_then(Value::as_u64).unwrap_or(0)
+}
+
+/// Run the migrations `config` needs; returns whether any changed it
+pub fn migrate(config: &mut Mapping) -> bool {
+    let version = version_of(config);
+    if version > CONFIG_VERSION {
+        log::warn!(
+            "Config version {} is newer than this qai supports ({}); some settings may be ignored",
+            version,
+            CONFIG_VERSION
+        );
+        return false;
+    }
+
+    let mut changed = false;
+    for migration in &MIGRATIONS[version as usize..] {
+        changed |= migration(config);
+    }
+    if changed {
+        config.insert(Value::from("version"), Value::from(CONFIG_VERSION));
+    }
+    changed
+}
+
+/// Where the original of a config migrated from `version` is kept
+pub fn backup_path(path: &Path, version: u64) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".v{}.bak", version));
+    path.with_file_name(name)
+}
+
+/// Upgrade the config file at `path` in place, backing up the original first.
+/// Returns the backup path when the file was rewritten
+pub fn migrate_file(path: &Path) -> Result<Option<PathBuf>> {
+    let content = fs::read_to_string(path).context("Failed to read config file")?;
+    let toml = path.extension().is_some_and(|ext| ext == "toml");
+    let value: Value = if toml {
+        toml::from_str(&content).context("Failed to parse config file")?
+    } else {
+        serde_yaml::from_str(&content).context("Failed to parse config file")?
+    };
+    let Value::Mapping(mut config) = value else {
+        return Ok(None);
+    };
+
+    let version = version_of(&config);
+    if !migrate(&mut config) {
+        return Ok(None);
+    }
+
+    let migrated = if toml {
+        toml::to_string(&config).context("Failed to serialize migrated config")?
+    } else {
+        serde_yaml::to_string(&config).context("Failed to serialize migrated config")?
+    };
+    let backup = backup_path(path, version);
+    fs::copy(path, &backup).context(format!("Failed to back up config to {}", backup.display()))?;
+    fs::write(path, migrated).context("Failed to write migrated config")?;
+    Ok(Some(backup))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn mapping(yaml: &str) -> Mapping {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_migrate_moves_bindings() {
+        let mut config = mapping("model: gpt-4o\ntrigger: ctrl-g\nsubmit_key: enter\n");
+        assert!(migrate(&mut config));
+        assert_eq!(
+            config,
+            mapping("model: gpt-4o\nbindings:\n  trigger: ctrl-g\n  submit: enter\nversion: 1\n")
+        );
+
+        // Existing bindings entries win
+        let mut config = mapping("trigger: ctrl-g\nbindings:\n  trigger: tab\n");
+        assert!(migrate(&mut config));
+        assert_eq!(config.get("bindings"), Some(&Value::from(mapping("trigger: tab"))));
+    }
+
+    #[test]
+    fn test_migrate_leaves_current_configs_alone() {
+        let mut config = mapping("model: gpt-4o\nbindings:\n  trigger: tab\n");
+        assert!(!migrate(&mut config));
+        assert!(config.get("version").is_none());
+
+        // Already at the current version: old-looking keys are not touched
+        let mut config = mapping("version: 1\ntrigger: ctrl-g\n");
+        assert!(!migrate(&mut config));
+
+        // Newer than supported
+        let mut config = mapping("version: 99\ntrigger: ctrl-g\n");
+        assert!(!migrate(&mut config));
+    }
+
+    #[test]
+    fn test_backup_path() {
+        assert_eq!(
+            backup_path(Path::new("/home/u/.config/qai/qai.yml"), 0),
+            PathBuf::from("/home/u/.config/qai/qai.yml.v0.bak")
+        );
+    }
+
+    #[test]
+    fn test_migrate_file_yaml() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("qai.yml");
+        let original = "# mine\nmodel: gpt-4o\ntrigger: ctrl-g\n";
+        fs::write(&path, original).unwrap();
+
+        let backup = migrate_file(&path).unwrap().unwrap();
+        assert_eq!(backup, dir.path().join("qai.yml.v0.bak"));
+        assert_eq!(fs::read_to_string(&backup).unwrap(), original);
+
+        let config: Config = serde_yaml::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(config.bindings.trigger, "ctrl-g");
+        assert_eq!(config.model, "gpt-4o");
+        assert_eq!(config.version, CONFIG_VERSION);
+
+        // Nothing left to do the second time
+        assert!(migrate_file(&path).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_migrate_file_toml() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("qai.toml");
+        fs::write(&path, "trigger = \"ctrl-g\"\n\n[safety]\ndeny = [\"prod\"]\n").unwrap();
+
+        assert!(migrate_file(&path).unwrap().is_some());
+        let config: Config = toml::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(config.bindings.trigger, "ctrl-g");
+        assert_eq!(config.safety.deny, vec!["prod".to_string()]);
+    }
+
+    #[test]
+    fn test_migrate_file_untouched() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("qai.yml");
+        fs::write(&path, "model: gpt-4o\n").unwrap();
+
+        assert!(migrate_file(&path).unwrap().is_none());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "model: gpt-4o\n");
+        assert!(!backup_path(&path, 0).exists());
+    }
+}
@@ -0,0 +1,455 @@
+//! Built-in answers for when the API can't be reached
+//!
+//! A small rules engine for everyday tasks: each rule is a query pattern and the commands
+//! that do the task, with `{name}` placeholders filled from the pattern's named groups (or
+//! `{name:default}` when the group didn't match). Rules are tried in order and the first
+//! match answers, so specific patterns come before general ones. With
+//! `query.offline-fallback`, `qai query` answers from them instead of failing when the API
+//! is unreachable: no context, ranking, or explanations, but an instant answer.
+
+use regex::{Captures, Regex};
+use std::sync::LazyLock;
+
+/// A task the fallback knows: a query pattern (matched ignoring case), and the commands that
+/// do it, best first
+struct Rule {
+    pattern: &'static str,
+    commands: &'static [&'static str],
+}
+
+const RULES: &[Rule] = &[
+    // Finding files
+    Rule {
+        pattern: r"\bfind (?:all )?(?:the )?(?:files? )?(?:named|called) (?P<name>\S+)",
+        commands: &["find . -name '{name}'", "find . -iname '*{name}*'"],
+    },
+    Rule {
+        pattern: r"\b(?:largest|biggest) files\b",
+        commands: &[
+            "find . -type f -exec du -h {} + | sort -rh | head -n 10",
+            "du -ah . | sort -rh | head -n 10",
+        ],
+    },
+    Rule {
+        pattern: r"\b(?:recently )?(?:modified|changed) files\b|\bfiles (?:modified|changed)\b",
+        commands: &["find . -type f -mtime -1", "ls -lt | head -n 20"],
+    },
+    Rule {
+        pattern: r"\bempty (?:files|directories|dirs|folders)\b",
+        commands: &["find . -empty"],
+    },
+    Rule {
+        pattern: r"\b(?:delete|remove) (?:all )?(?:the )?\.?(?P<ext>\w+) files\b",
+        commands: &["find . -type f -name '*.{ext}' -delete"],
+    },
+    Rule {
+        pattern: r"\bfind (?:all )?(?:the )?\.?(?P<ext>\w+) files\b",
+        commands: &["find . -type f -name '*.{ext}'"],
+    },
+    Rule {
+        pattern: r#"\bfind (?:all )?files containing ['"]?(?P<text>[^'"]+?)['"]?$"#,
+        commands: &["grep -rl '{text}' .", "rg -l '{text}'"],
+    },
+    Rule {
+        pattern: r#"\b(?:search|grep|look)(?: for)? ['"]?(?P<text>[^'"]+?)['"]? in (?:all |the )?(?:files|here)"#,
+        commands: &["grep -rn '{text}' .", "rg -n '{text}'"],
+    },
+    Rule {
+        pattern: r"\bcount (?:the )?lines (?:in|of) (?P<file>\S+)",
+        commands: &["wc -l {file}"],
+    },
+    Rule {
+        pattern: r"\bcount (?:the )?files\b|\bhow many files\b",
+        commands: &["find . -type f | wc -l"],
+    },
+    // Disk and memory
+    Rule {
+        pattern: r"\b(?:folder|dir\w*) sizes?\b|\bsizes? of (?:each|every|all) (?:folder|dir)|\btaking (?:up )?space\b",
+        commands: &["du -sh * | sort -h", "du -h --max-depth=1 . | sort -h"],
+    },
+    Rule {
+        pattern: r"\b(?:size of|how big is) (?:the )?(?:file |folder |directory )?(?P<path>\S+)",
+        commands: &["du -sh {path}"],
+    },
+    Rule {
+        pattern: r"\b(?:disk (?:space|usage)|free space)\b",
+        commands: &["df -h"],
+    },
+    Rule {
+        pattern: r"\bprocess(?:es)? (?:using|with) (?:the )?most (?:memory|ram)\b",
+        commands: &["ps aux --sort=-%mem | head -n 10", "ps aux -m | head -n 10"],
+    },
+    Rule {
+        pattern: r"\bprocess(?:es)? (?:using|with) (?:the )?most cpu\b|\btop processes\b",
+        commands: &["ps aux --sort=-%cpu | head -n 10", "ps aux -r | head -n 10"],
+    },
+    Rule {
+        pattern: r"\b(?:memory|ram)\b",
+        commands: &["free -h", "vm_stat"],
+    },
+    // Processes and ports
+    Rule {
+        pattern: r"\bkill\b.*\bport (?P<port>\d+)",
+        commands: &["kill $(lsof -t -i :{port})", "fuser -k {port}/tcp"],
+    },
+    Rule {
+        pattern: r"\bport (?P<port>\d+)",
+        commands: &["lsof -i :{port}", "ss -ltnp 'sport = :{port}'"],
+    },
+    Rule {
+        pattern: r"\b(?:open|listening) ports\b|\bports? (?:in use|open)\b",
+        commands: &["ss -tulpn", "lsof -i -P -n | grep LISTEN"],
+    },
+    Rule {
+        pattern: r"\bkill (?:all )?(?:the )?(?:process(?:es)? )?(?:named |called )?(?P<name>[\w.-]+)",
+        commands: &["pkill {name}", "killall {name}"],
+    },
+    Rule {
+        pattern: r"\b(?:find|show|list) (?:the )?(?P<name>[\w.-]+) process(?:es)?\b",
+        commands: &["pgrep -a {name}", "ps aux | grep {name}"],
+    },
+    Rule {
+        pattern: r"\b(?:list|show) (?:all |the |running )*process(?:es)?\b",
+        commands: &["ps aux"],
+    },
+    // Network
+    Rule {
+        pattern: r"\b(?:public|external) ip\b",
+        commands: &["curl -s https://ifconfig.me"],
+    },
+    Rule {
+        pattern: r"\b(?:my|local) ip\b|\bip address(?:es)?\b",
+        commands: &["ip -brief address", "ifconfig"],
+    },
+    Rule {
+        pattern: r"\bping (?P<host>\S+)",
+        commands: &["ping -c 4 {host}"],
+    },
+    Rule {
+        pattern: r"\b(?:dns|look ?up|resolve) (?:for |of )?(?P<host>[\w.-]+\.[a-z]{2,})",
+        commands: &["dig +short {host}", "nslookup {host}"],
+    },
+    Rule {
+        pattern: r"\bdownload (?P<url>\S+)",
+        commands: &["curl -LO {url}", "wget {url}"],
+    },
+    Rule {
+        pattern: r"\bcopy (?P<src>\S+) to (?P<host>[\w.@-]+):(?P<dst>\S*)",
+        commands: &["scp -r {src} {host}:{dst}", "rsync -av {src} {host}:{dst}"],
+    },
+    Rule {
+        pattern: r"\b(?:ssh|connect) (?:in)?to (?P<host>\S+)",
+        commands: &["ssh {host}"],
+    },
+    // Archives
+    Rule {
+        pattern: r"\b(?:list|show) (?:the )?(?:contents of|files in) (?P<file>\S+\.zip)\b",
+        commands: &["unzip -l {file}"],
+    },
+    Rule {
+        pattern: r"\b(?:list|show) (?:the )?(?:contents of|files in) (?P<file>\S+\.(?:tar|tar\.\w+|tgz))\b",
+        commands: &["tar -tf {file}"],
+    },
+    Rule {
+        pattern: r"\b(?:extract|unzip|unpack|decompress) (?P<file>\S+\.zip)\b",
+        commands: &["unzip {file}"],
+    },
+    Rule {
+        pattern: r"\b(?:extract|untar|unpack|decompress) (?P<file>\S+)",
+        commands: &["tar -xf {file}"],
+    },
+    Rule {
+        pattern: r"\b(?:compress|archive|zip up|tar up) (?:this|the current) (?:folder|directory|dir)\b",
+        commands: &["tar -czf ../archive.tar.gz .", "zip -r ../archive.zip ."],
+    },
+    Rule {
+        pattern: r"\b(?:compress|archive|zip up|tar up) (?:the )?(?:folder |directory |dir )?(?P<dir>[^\s/]+)",
+        commands: &["tar -czf {dir}.tar.gz {dir}", "zip -r {dir}.zip {dir}"],
+    },
+    // Git
+    Rule {
+        pattern: r"\bundo (?:the )?last commit\b",
+        commands: &["git reset --soft HEAD~1"],
+    },
+    Rule {
+        pattern: r"\b(?:current|which) branch\b",
+        commands: &["git branch --show-current"],
+    },
+    Rule {
+        pattern: r"\b(?:recent|last) commits\b|\bgit (?:log|history)\b|\bcommit history\b",
+        commands: &["git log --oneline -n 20"],
+    },
+    Rule {
+        pattern: r"\b(?:create|new|make) (?:a )?(?:new )?branch (?:called |named )?(?P<branch>\S+)",
+        commands: &["git switch -c {branch}", "git checkout -b {branch}"],
+    },
+    Rule {
+        pattern: r"\b(?:switch|check ?out|change) (?:to )?(?:the )?branch (?P<branch>\S+)",
+        commands: &["git switch {branch}", "git checkout {branch}"],
+    },
+    Rule {
+        pattern: r"\bdelete (?:the )?(?:local )?branch (?P<branch>\S+)",
+        commands: &["git branch -d {branch}"],
+    },
+    Rule {
+        pattern: r"\b(?:discard|throw away|undo) (?:all )?(?:my )?(?:local |uncommitted )?changes\b",
+        commands: &["git restore .", "git checkout -- ."],
+    },
+    Rule {
+        pattern: r"\bstash\b",
+        commands: &["git stash"],
+    },
+    Rule {
+        pattern: r"\b(?:uncommitted|unstaged|local) changes\b|\bwhat (?:have i )?changed\b",
+        commands: &["git status -s", "git diff"],
+    },
+    // Files
+    Rule {
+        pattern: r"\b(?:create|make) (?:a )?(?:new )?(?:directory|folder|dir) (?:called |named )?(?P<dir>\S+)",
+        commands: &["mkdir -p {dir}"],
+    },
+    Rule {
+        pattern: r"\b(?:create|make) (?:an? )?(?:new |empty )*file (?:called |named )?(?P<file>\S+)",
+        commands: &["touch {file}"],
+    },
+    Rule {
+        pattern: r"\bmake (?P<file>\S+) executable\b",
+        commands: &["chmod +x {file}"],
+    },
+    Rule {
+        pattern: r"\bcopy (?P<src>\S+) to (?P<dst>\S+)",
+        commands: &["cp -r {src} {dst}"],
+    },
+    Rule {
+        pattern: r"\b(?:move|rename) (?P<src>\S+) (?:to|as) (?P<dst>\S+)",
+        commands: &["mv {src} {dst}"],
+    },
+    Rule {
+        pattern: r"\b(?:sym)?link (?P<target>\S+) (?:to|as|at) (?P<link>\S+)",
+        commands: &["ln -s {target} {link}"],
+    },
+    Rule {
+        pattern: r"\b(?:delete|remove) (?:the )?(?:folder|directory|dir) (?P<path>\S+)",
+        commands: &["rm -r {path}"],
+    },
+    Rule {
+        pattern: r"\b(?:delete|remove) (?:the )?(?:file )?(?P<path>\S+\.\w+)",
+        commands: &["rm {path}"],
+    },
+    Rule {
+        pattern: r"\b(?:first|top) (?:(?P<n>\d+) )?lines (?:of|in) (?P<file>\S+)",
+        commands: &["head -n {n:10} {file}"],
+    },
+    Rule {
+        pattern: r"\blast (?:(?P<n>\d+) )?lines (?:of|in) (?P<file>\S+)",
+        commands: &["tail -n {n:10} {file}"],
+    },
+    Rule {
+        pattern: r"\b(?:follow|tail|watch) (?:the )?(?:log (?:file )?)?(?P<file>\S+)",
+        commands: &["tail -f {file}"],
+    },
+    Rule {
+        pattern: r"\b(?:compare|diff) (?P<a>\S+) (?:and|with|to) (?P<b>\S+)",
+        commands: &["diff -u {a} {b}"],
+    },
+    Rule {
+        pattern: r"\breplace (?P<old>\S+) with (?P<new>\S+) in (?P<file>\S+)",
+        commands: &["sed -i 's/{old}/{new}/g' {file}"],
+    },
+    Rule {
+        pattern: r"\b(?:unique|dedup(?:licate)?|remove duplicate) lines (?:in|of|from) (?P<file>\S+)",
+        commands: &["sort -u {file}"],
+    },
+    Rule {
+        pattern: r"\b(?:sha256|checksum|hash) (?:of )?(?P<file>\S+)",
+        commands: &["sha256sum {file}", "shasum -a 256 {file}"],
+    },
+    Rule {
+        pattern: r"\b(?:pretty[- ]?print|format) (?:the )?json(?: (?:in |file )?(?P<file>\S+))?",
+        commands: &["jq . {file:}"],
+    },
+    Rule {
+        pattern: r"\b(?:show|print|display|read) (?:the )?(?:contents of )?(?:file )?(?P<file>\S+\.\w+)",
+        commands: &["cat {file}"],
+    },
+    // System
+    Rule {
+        pattern: r"\b(?:os|linux|kernel) version\b|\bwhich (?:os|distro)\b",
+        commands: &["uname -a", "cat /etc/os-release"],
+    },
+    Rule {
+        pattern: r"\buptime\b",
+        commands: &["uptime"],
+    },
+    Rule {
+        pattern: r"\bcpu (?:info|cores|count)\b|\bhow many (?:cpus|cores)\b",
+        commands: &["nproc", "lscpu", "sysctl -n hw.ncpu"],
+    },
+    Rule {
+        pattern: r"\benv(?:ironment)? var(?:iable)?s?\b",
+        commands: &["env | sort"],
+    },
+    Rule {
+        pattern: r"\b(?:show|print) (?:my |the )?path\b",
+        commands: &[r#"echo "$PATH" | tr ':' '\n'"#],
+    },
+    Rule {
+        pattern: r"\bwhere is (?P<cmd>[\w.-]+)(?: installed)?\b",
+        commands: &["command -v {cmd}", "which -a {cmd}"],
+    },
+    Rule {
+        pattern: r"\b(?:current|working) directory\b|\bwhere am i\b",
+        commands: &["pwd"],
+    },
+    Rule {
+        pattern: r"\bwho(?: is|'s) logged in\b|\blogged[- ]in users\b",
+        commands: &["who"],
+    },
+    Rule {
+        pattern: r"\bcron ?(?:jobs|tab)\b",
+        commands: &["crontab -l"],
+    },
+    Rule {
+        pattern: r"\brestart (?:the )?(?P<service>[\w.@-]+)(?: service)?\b",
+        commands: &["sudo systemctl restart {service}"],
+    },
+    Rule {
+        pattern: r"\bstatus of (?:the )?(?P<service>[\w.@-]+)(?: service)?\b",
+        commands: &["systemctl status {service}"],
+    },
+    Rule {
+        pattern: r"\b(?:search|grep)(?: in)? (?:my )?(?:shell |command )?history for (?P<text>.+)",
+        commands: &["history | grep '{text}'"],
+    },
+    Rule {
+        pattern: r"\bdocker images\b",
+        commands: &["docker images"],
+    },
+    Rule {
+        pattern: r"\b(?:docker )?containers\b",
+        commands: &["docker ps", "docker ps -a"],
+    },
+    Rule {
+        pattern: r"\b(?:random|generate an?|new) (?:random )?password\b",
+        commands: &["openssl rand -base64 24"],
+    },
+    Rule {
+        pattern: r"\bhostname\b|\bcomputer name\b",
+        commands: &["hostname"],
+    },
+    Rule {
+        pattern: r"\b(?:date|time)\b",
+        commands: &["date"],
+    },
+    Rule {
+        pattern: r"\b(?:list|show) (?:all )?(?:the )?(?:hidden )?files\b",
+        commands: &["ls -la", "ls -lah"],
+    },
+];
+
+/// The rules, compiled once
+static COMPILED: LazyLock<Vec<(Regex, &'static [&'static str])>> = LazyLock::new(|| {
+    RULES
+        .iter()
+        .map(|rule| {
+            let pattern = Regex::new(&format!("(?i){}", rule.pattern)).expect("valid offline rule pattern");
+            (pattern, rule.commands)
+        })
+        .collect()
+});
+
+/// Politeness in front of the task itself ("how do I", "please")
+static FILLER: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)^(?:(?:how (?:do|can|would) i|how to|can you|could you|please|i want to|i need to)\s+)+")
+        .expect("valid filler regex")
+});
+
+/// A `{name}` or `{name:default}` placeholder in a command
+static PLACEHOLDER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\{(\w+)(?::([^}]*))?\}").expect("valid placeholder regex"));
+
+/// The commands of the first rule matching a query, best first; none when no rule matches
+pub fn commands(query: &str) -> Vec<String> {
+    let query = query.trim().trim_end_matches(['?', '.', '!']);
+    let query = FILLER.replace(query, "");
+    let Some((captures, commands)) = COMPILED
+        .iter()
+        .find_map(|(pattern, commands)| pattern.captures(&query).map(|captures| (captures, *commands)))
+    else {
+        return Vec::new();
+    };
+    commands.iter().map(|command| fill(command, &captures)).collect()
+}
+
+/// Fill a command's placeholders from a rule's captures, without any quotes around them
+fn fill(command: &str, captures: &Captures) -> String {
+    PLACEHOLDER
+        .replace_all(command, |placeholder: &Captures| match captures.name(&placeholder[1]) {
+            Some(value) => value.as_str().trim_matches(['\'', '"']).to_string(),
+            None => placeholder.get(2).map_or("", |default| default.as_str()).to_string(),
+        })
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rules_compile() {
+        assert_eq!(COMPILED.len(), RULES.len());
+        assert!(RULES.len() >= 50);
+    }
+
+    #[test]
+    fn test_commands_fill_captures() {
+        assert_eq!(commands("find files named Cargo.toml")[0], "find . -name 'Cargo.toml'");
+        assert_eq!(commands("what is running on port 8080?")[0], "lsof -i :8080");
+        assert_eq!(commands("kill whatever is on port 3000")[0], "kill $(lsof -t -i :3000)");
+        assert_eq!(commands("How do I extract backup.tar.gz")[0], "tar -xf backup.tar.gz");
+        assert_eq!(commands("unzip photos.zip"), vec!["unzip photos.zip"]);
+        assert_eq!(
+            commands("please create a branch named fix-login")[0],
+            "git switch -c fix-login"
+        );
+        assert_eq!(commands("search for 'TODO' in all files")[0], "grep -rn 'TODO' .");
+        assert_eq!(
+            commands("copy notes.txt to host:backup/")[0],
+            "scp -r notes.txt host:backup/"
+        );
+        assert_eq!(commands("copy notes.txt to backup/"), vec!["cp -r notes.txt backup/"]);
+    }
+
+    #[test]
+    fn test_commands_use_defaults() {
+        assert_eq!(commands("show the first lines of app.log"), vec!["head -n 10 app.log"]);
+        assert_eq!(commands("last 50 lines of app.log"), vec!["tail -n 50 app.log"]);
+        assert_eq!(commands("pretty print json"), vec!["jq ."]);
+    }
+
+    #[test]
+    fn test_specific_rules_win() {
+        assert_eq!(commands("list hidden files"), vec!["ls -la", "ls -lah"]);
+        assert_eq!(
+            commands("show the largest files")[0],
+            "find . -type f -exec du -h {} + | sort -rh | head -n 10"
+        );
+        assert_eq!(
+            commands("delete all .tmp files"),
+            vec!["find . -type f -name '*.tmp' -delete"]
+        );
+        assert_eq!(
+            commands("what's taking up space"),
+            vec!["du -sh * | sort -h", "du -h --max-depth=1 . | sort -h"]
+        );
+        assert_eq!(commands("size of node_modules"), vec!["du -sh node_modules"]);
+        assert_eq!(commands("how much free disk space"), vec!["df -h"]);
+    }
+
+    #[test]
+    fn test_no_rule_matches() {
+        assert!(commands("convert every heic photo to jpeg and upload them").is_empty());
+        assert!(commands("").is_empty());
+    }
+}
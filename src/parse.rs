@@ -0,0 +1,346 @@
+//! Shell command parsing for qai
+//!
+//! Lexes command lines with shell quoting rules so that tool validation
+//! and safety checks see the same words the shell would.
+
+/// Words to skip when extracting binary from command
+pub const SKIP_WORDS: &[&str] = &[
+    "sudo", "env", "time", "nice", "nohup", "strace", "ltrace", "doas", "xargs", "exec", "command",
+];
+
+/// Shell builtins and keywords that never resolve on PATH but are always available
+pub const SHELL_BUILTINS: &[&str] = &[
+    "cd", "export", "source", ".", "alias", "unalias", "set", "unset", "eval", "exit", "return", "read", "local",
+    "pushd", "popd", "type", "wait", "shift", "[", "[[", "if", "then", "else", "elif", "fi", "for", "while", "until",
+    "do", "done", "case", "esac", "in", "function", "{", "}", "!",
+];
+
+/// cmd.exe builtins and default PowerShell aliases, which aren't programs on PATH
+pub const WINDOWS_BUILTINS: &[&str] = &[
+    "dir", "copy", "move", "del", "erase", "ren", "rename", "type", "md", "mkdir", "rd", "rmdir", "cd", "chdir", "cls",
+    "echo", "set", "setlocal", "endlocal", "if", "for", "call", "start", "pushd", "popd", "ver", "mklink", "exit",
+    "ls", "cat", "cp", "mv", "rm", "pwd", "ps", "kill", "sleep", "gci", "gc", "gi", "sls", "select", "where",
+    "foreach", "sort", "measure", "ni", "ri", "iwr", "irm", "%", "?",
+];
+
+/// Verbs of the built-in PowerShell cmdlets, e.g. the `Get` of `Get-ChildItem`
+#[rustfmt::skip]
+const POWERSHELL_VERBS: &[&str] = &[
+    "add", "clear", "compare", "compress", "convertfrom", "convertto", "copy", "expand", "export", "find",
+    "foreach", "format", "get", "group", "import", "invoke", "join", "measure", "move", "new", "out", "pop", "push",
+    "read", "remove", "rename", "resolve", "restart", "select", "set", "sort", "split", "start", "stop", "tee",
+    "test", "update", "wait", "where", "write",
+];
+
+/// Check if a word is a shell builtin or keyword (on Windows, also of cmd.exe and PowerShell)
+pub fn is_shell_builtin(word: &str) -> bool {
+    SHELL_BUILTINS.contains(&word) || (cfg!(windows) && is_windows_builtin(word))
+}
+
+/// Whether a word is a cmd.exe builtin, PowerShell alias, or PowerShell cmdlet (`Verb-Noun`),
+/// case-insensitively like both shells
+pub fn is_windows_builtin(word: &str) -> bool {
+    let word = word.to_ascii_lowercase();
+    if WINDOWS_BUILTINS.contains(&word.as_str()) {
+        return true;
+    }
+    word.split_once('-').is_some_and(|(verb, noun)| {
+        POWERSHELL_VERBS.contains(&verb) && !noun.is_empty() && noun.chars().all(|c| c.is_ascii_alphanumeric())
+    })
+}
+
+/// A Windows program name without its executable extension (`rg.exe` -> `rg`), as PATHEXT
+/// lookups and the tool categories expect
+pub fn strip_windows_extension(binary: &str) -> &str {
+    for ext in [".exe", ".cmd", ".bat", ".com", ".ps1"] {
+        if let Some(stem) = binary
+            .len()
+            .checked_sub(ext.len())
+            .filter(|&i| i > 0 && binary.is_char_boundary(i))
+            && binary[stem..].eq_ignore_ascii_case(ext)
+        {
+            return &binary[..stem];
+        }
+    }
+    binary
+}
+
+/// Split a command line into the simple commands of its pipelines and lists
+/// Splits on |, ||, &&, ;, &, newlines, subshells, and command substitution ($(...) and backticks),
+/// ignoring separators inside quotes
+pub fn split_segments(cmd: &str) -> Vec<&str> {
+    let mut segments = Vec::new();
+    let mut start = 0;
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+
+    for (i, c) in cmd.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match (quote, c) {
+            (Some('\''), '\'') => quote = None,
+            (Some('\''), _) => {}
+            (_, '\\') => escaped = true,
+            (Some('"'), '"') => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '|' | '&' | ';' | '\n' | '(' | ')' | '`') => {
+                segments.push(&cmd[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    segments.push(&cmd[start..]);
+
+    segments
+        .into_iter()
+        .map(|segment| segment.trim_end_matches('$').trim())
+        .filter(|segment| !segment.is_empty())
+        .collect()
+}
+
+/// Split a simple command into words using shell quoting rules
+/// Falls back to whitespace splitting when quotes are unbalanced
+pub fn split_words(segment: &str) -> Vec<String> {
+    shlex::split(segment).unwrap_or_else(|| segment.split_whitespace().map(str::to_string).collect())
+}
+
+/// Extract the primary binary from a simple command
+/// Handles: sudo, env VAR=x, time, nice, flags like -n, numeric args, redirections, etc.
+pub fn extract_binary(segment: &str) -> Option<String> {
+    split_words(segment).into_iter().find(|word| {
+        !word.is_empty()
+            && !word.contains('=')
+            && !word.starts_with('-')
+            && !word.starts_with(['<', '>'])
+            && !word.chars().next().map(|c| c.is_ascii_digit()).unwrap_or(false)
+            && !SKIP_WORDS.contains(&word.as_str())
+    })
+}
+
+/// Extract every binary in a command's pipelines, lists, and substitutions
+/// e.g. `cat log | jq '.foo' && echo $(date)` -> [cat, jq, echo, date]
+pub fn extract_binaries(cmd: &str) -> Vec<String> {
+    split_segments(cmd)
+        .into_iter()
+        .filter_map(extract_binary)
+        .filter(|bin| !bin.starts_with('$'))
+        .map(|bin| {
+            if cfg!(windows) {
+                strip_windows_extension(&bin).to_string()
+            } else {
+                bin
+            }
+        })
+        .collect()
+}
+
+/// Replace the first standalone occurrence of `word` in `cmd` with `replacement`
+/// Returns None if `word` never appears as a whole word
+pub fn replace_word(cmd: &str, word: &str, replacement: &str) -> Option<String> {
+    let is_boundary = |c: Option<char>| c.is_none_or(|c| c.is_whitespace() || "|&;()`".contains(c));
+
+    cmd.match_indices(word)
+        .find(|(start, _)| {
+            let before = cmd[..*start].chars().next_back();
+            let after = cmd[start + word.len()..].chars().next();
+            is_boundary(before) && is_boundary(after)
+        })
+        .map(|(start, _)| format!("{}{}{}", &cmd[..start], replacement, &cmd[start + word.len()..]))
+}
+
+/// Whether a command continues on the next line: a trailing backslash or pipe/list
+/// operator, an unterminated quote, or a heredoc whose delimiter hasn't appeared yet
+pub fn is_incomplete(cmd: &str) -> bool {
+    let trimmed = cmd.trim_end();
+    if trimmed.ends_with('\\') || trimmed.ends_with('|') || trimmed.ends_with("&&") {
+        return true;
+    }
+
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    let mut delimiters = Vec::new();
+    let mut chars = cmd.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match (quote, c) {
+            (Some('\''), '\'') => quote = None,
+            (Some('\''), _) => {}
+            (_, '\\') => escaped = true,
+            (Some('"'), '"') => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            // Here-string, not a heredoc
+            (None, '<') if cmd[i..].starts_with("<<<") => {
+                chars.nth(1);
+            }
+            (None, '<') if cmd[i..].starts_with("<<") => {
+                chars.next();
+                if let Some(delimiter) = heredoc_delimiter(&cmd[i + 2..]) {
+                    delimiters.push(delimiter);
+                }
+            }
+            _ => {}
+        }
+    }
+    if quote.is_some() {
+        return true;
+    }
+
+    // Every heredoc needs its delimiter on a line of its own (leading tabs allowed for <<-)
+    let mut lines = cmd.lines().skip(1);
+    delimiters
+        .iter()
+        .any(|delimiter| !lines.any(|line| line.trim_start_matches('\t').trim_end() == delimiter))
+}
+
+/// The delimiter word after `<<` (or `<<-`), without its quotes
+fn heredoc_delimiter(rest: &str) -> Option<String> {
+    let rest = rest.strip_prefix('-').unwrap_or(rest).trim_start();
+    let word: String = rest
+        .chars()
+        .take_while(|c| !c.is_whitespace() && !";|&()<>".contains(*c))
+        .filter(|c| !matches!(c, '\'' | '"' | '\\'))
+        .collect();
+    (!word.is_empty()).then_some(word)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_segments_pipeline_and_lists() {
+        assert_eq!(
+            split_segments("cat log | jq '.foo' && echo done; ls"),
+            vec!["cat log", "jq '.foo'", "echo done", "ls"]
+        );
+        assert_eq!(split_segments("a || b & c"), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_split_segments_command_substitution() {
+        assert_eq!(split_segments("echo $(date +%s)"), vec!["echo", "date +%s"]);
+        assert_eq!(split_segments("echo `whoami`"), vec!["echo", "whoami"]);
+        assert_eq!(split_segments("(cd /tmp; ls)"), vec!["cd /tmp", "ls"]);
+    }
+
+    #[test]
+    fn test_split_segments_ignores_quoted_separators() {
+        assert_eq!(split_segments("grep 'a|b' file"), vec!["grep 'a|b' file"]);
+        assert_eq!(split_segments(r#"echo "x && y; z""#), vec![r#"echo "x && y; z""#]);
+        assert_eq!(split_segments(r"echo a\|b"), vec![r"echo a\|b"]);
+    }
+
+    #[test]
+    fn test_split_words_quoted_arguments() {
+        assert_eq!(
+            split_words(r#"grep "hello world" 'file name.txt'"#),
+            vec!["grep", "hello world", "file name.txt"]
+        );
+    }
+
+    #[test]
+    fn test_split_words_unbalanced_quotes_fallback() {
+        assert_eq!(split_words("echo 'oops"), vec!["echo", "'oops"]);
+    }
+
+    #[test]
+    fn test_extract_binary_env_assignment_with_spaces() {
+        assert_eq!(extract_binary(r#"FOO="a b" cmd arg"#).as_deref(), Some("cmd"));
+        assert_eq!(
+            extract_binary(r#"env MSG='hi there' printenv MSG"#).as_deref(),
+            Some("printenv")
+        );
+    }
+
+    #[test]
+    fn test_extract_binary_skips_redirections() {
+        assert_eq!(extract_binary("<input.txt sort").as_deref(), Some("sort"));
+    }
+
+    #[test]
+    fn test_extract_binary_quoted_binary() {
+        assert_eq!(extract_binary(r#""ls" -la"#).as_deref(), Some("ls"));
+    }
+
+    #[test]
+    fn test_extract_binaries_subshell_and_substitution() {
+        assert_eq!(
+            extract_binaries("(cd /tmp && tar czf out.tgz dir) && echo $(date)"),
+            vec!["cd", "tar", "echo", "date"]
+        );
+    }
+
+    #[test]
+    fn test_is_shell_builtin() {
+        assert!(is_shell_builtin("cd"));
+        assert!(is_shell_builtin("[["));
+        assert!(!is_shell_builtin("ls"));
+    }
+
+    #[test]
+    fn test_is_windows_builtin() {
+        assert!(is_windows_builtin("dir"));
+        assert!(is_windows_builtin("Get-ChildItem"));
+        assert!(is_windows_builtin("where-object"));
+        assert!(is_windows_builtin("ConvertTo-Json"));
+        assert!(!is_windows_builtin("git-lfs"));
+        assert!(!is_windows_builtin("Get-"));
+        assert!(!is_windows_builtin("rg"));
+    }
+
+    #[test]
+    fn test_strip_windows_extension() {
+        assert_eq!(strip_windows_extension("rg.exe"), "rg");
+        assert_eq!(strip_windows_extension("npm.CMD"), "npm");
+        assert_eq!(strip_windows_extension("build.bat"), "build");
+        assert_eq!(strip_windows_extension("rg"), "rg");
+        assert_eq!(strip_windows_extension(".exe"), ".exe");
+        assert_eq!(strip_windows_extension("archive.tar"), "archive.tar");
+    }
+
+    #[test]
+    fn test_replace_word_first_standalone_occurrence() {
+        assert_eq!(replace_word("rg foo", "rg", "grep"), Some("grep foo".to_string()));
+        assert_eq!(
+            replace_word("FOO=rg rg foo", "rg", "grep"),
+            Some("FOO=rg grep foo".to_string())
+        );
+        assert_eq!(replace_word("ls | rg x", "rg", "grep"), Some("ls | grep x".to_string()));
+    }
+
+    #[test]
+    fn test_is_incomplete_continuations() {
+        assert!(is_incomplete("find . -name '*.rs' \\"));
+        assert!(is_incomplete("cat log |"));
+        assert!(is_incomplete("make &&"));
+        assert!(is_incomplete("echo 'unterminated"));
+        assert!(is_incomplete("echo \"a\nb"));
+        assert!(!is_incomplete("echo 'a\nb'"));
+        assert!(!is_incomplete("ls -la | wc -l"));
+        assert!(!is_incomplete("echo \\'"));
+    }
+
+    #[test]
+    fn test_is_incomplete_heredoc() {
+        assert!(is_incomplete("cat <<EOF > notes.txt"));
+        assert!(is_incomplete("cat <<'EOF'\nhello"));
+        assert!(!is_incomplete("cat <<'EOF'\nhello\nEOF"));
+        assert!(!is_incomplete("cat <<-END\n\thello\n\tEND"));
+        // Here-strings and quoted << are not heredocs
+        assert!(!is_incomplete("grep x <<< \"$text\""));
+        assert!(!is_incomplete("echo '<<EOF'"));
+    }
+
+    #[test]
+    fn test_replace_word_not_found() {
+        assert_eq!(replace_word("cargo build", "rg", "grep"), None);
+    }
+}
@@ -0,0 +1,98 @@
+//! Where qai keeps its state
+//!
+//! History, usage, sessions, the audit log, and telemetry state default to the XDG data
+//! directory, the tool cache to the cache directory, and logs to the state directory. The
+//! `paths:` config section moves them (and the fixtures of `provider: mock`), and the
+//! `QAI_DATA_DIR`, `QAI_HISTORY_DIR`, `QAI_CACHE_DIR`, `QAI_LOG_DIR`, and
+//! `QAI_FIXTURES_DIR` environment variables take precedence over both. `init` is called
+//! once at startup, before logging is set up, so every module resolves the same locations.
+
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use crate::config::PathsConfig;
+
+static PATHS: OnceLock<PathsConfig> = OnceLock::new();
+
+/// Use these configured paths for the rest of the process
+pub fn init(paths: PathsConfig) {
+    let _ = PATHS.set(paths);
+}
+
+fn configured() -> &'static PathsConfig {
+    PATHS.get_or_init(PathsConfig::default)
+}
+
+/// The environment variable if set, else the configured path, else the default
+fn resolve(env: Option<OsString>, configured: Option<&PathBuf>, default: impl FnOnce() -> PathBuf) -> PathBuf {
+    env.filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| configured.cloned())
+        .unwrap_or_else(default)
+}
+
+/// Directory of usage.json, sessions, the audit log, telemetry.json, and (by default) history
+/// and fixtures
+/// (default: ~/.local/share/qai)
+pub fn data_dir() -> PathBuf {
+    resolve(std::env::var_os("QAI_DATA_DIR"), configured().data.as_ref(), || {
+        dirs::data_local_dir().unwrap_or_else(|| PathBuf::from(".")).join("qai")
+    })
+}
+
+/// Directory of history.jsonl and patterns.json (default: <data>/history)
+pub fn history_dir() -> PathBuf {
+    resolve(
+        std::env::var_os("QAI_HISTORY_DIR"),
+        configured().history.as_ref(),
+        || data_dir().join("history"),
+    )
+}
+
+/// Directory of the tool cache (default: ~/.cache/qai)
+pub fn cache_dir() -> PathBuf {
+    resolve(std::env::var_os("QAI_CACHE_DIR"), configured().cache.as_ref(), || {
+        dirs::cache_dir().unwrap_or_else(|| PathBuf::from(".")).join("qai")
+    })
+}
+
+/// Directory of qai.log (default: ~/.local/state/qai/logs, or the data directory where
+/// the platform has no state directory)
+pub fn log_dir() -> PathBuf {
+    resolve(std::env::var_os("QAI_LOG_DIR"), configured().logs.as_ref(), || {
+        dirs::state_dir()
+            .or_else(dirs::data_local_dir)
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("qai")
+            .join("logs")
+    })
+}
+
+/// Directory of the fixtures replayed by `provider: mock` (default: <data>/fixtures)
+pub fn fixtures_dir() -> PathBuf {
+    resolve(
+        std::env::var_os("QAI_FIXTURES_DIR"),
+        configured().fixtures.as_ref(),
+        || data_dir().join("fixtures"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_precedence() {
+        let configured = PathBuf::from("/srv/qai/history");
+        let default = || PathBuf::from("/home/u/.local/share/qai/history");
+
+        assert_eq!(
+            resolve(Some("/tmp/qai".into()), Some(&configured), default),
+            PathBuf::from("/tmp/qai")
+        );
+        assert_eq!(resolve(None, Some(&configured), default), configured);
+        assert_eq!(resolve(Some("".into()), Some(&configured), default), configured);
+        assert_eq!(resolve(None, None, default), default());
+    }
+}
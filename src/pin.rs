@@ -0,0 +1,180 @@
+//! Pinned commands (`qai pin`)
+//!
+//! A pin is a hard preference for a query: whenever the same query is asked again (compared
+//! like history patterns, ignoring case and surrounding whitespace), the pinned command is
+//! the first suggestion. A pin made with `--only` is the whole answer, and the model isn't
+//! asked at all. Pins are kept in `~/.config/qai/pins.yml`.
+
+use eyre::{Context, Result, eyre};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::history::normalize_query;
+
+/// A command pinned to a query
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Pin {
+    pub query: String,
+    pub command: String,
+    /// Answer with pinned commands only
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub only: bool,
+}
+
+/// The pins that apply to a query
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Pinned {
+    /// Pinned commands, in the order they were pinned
+    pub commands: Vec<String>,
+    /// Some pin was made with `--only`, so the model isn't asked
+    pub only: bool,
+}
+
+/// The pins file
+#[derive(Debug)]
+pub struct PinStore {
+    path: PathBuf,
+}
+
+impl PinStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Get the default pins file path
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join(env!("CARGO_PKG_NAME")).join("pins.yml"))
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    /// All pins, oldest first; none when the file doesn't exist yet
+    pub fn load(&self) -> Result<Vec<Pin>> {
+        match fs::read_to_string(&self.path) {
+            Ok(content) if content.trim().is_empty() => Ok(Vec::new()),
+            Ok(content) => {
+                serde_yaml::from_str(&content).context(format!("Failed to parse pins file: {}", self.path.display()))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e).context(format!("Failed to read pins file: {}", self.path.display())),
+        }
+    }
+
+    fn save(&self, pins: &[Pin]) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).context("Failed to create config directory")?;
+        }
+        let content = serde_yaml::to_string(pins).context("Failed to serialize pins")?;
+        // Write then rename, so a failed write never loses the existing pins
+        let tmp = self.path.with_extension(format!("yml.{}.tmp", std::process::id()));
+        fs::write(&tmp, content).context("Failed to write pins file")?;
+        fs::rename(&tmp, &self.path).context("Failed to replace pins file")
+    }
+
+    /// Pin a command to a query; pinning it again only updates `only`. True when it was new
+    pub fn add(&self, query: &str, command: &str, only: bool) -> Result<bool> {
+        let (query, command) = (query.trim(), command.trim());
+        if query.is_empty() || command.is_empty() {
+            return Err(eyre!("Both a query and a command are needed to pin"));
+        }
+        let mut pins = self.load()?;
+        let normalized = normalize_query(query);
+        let existing = pins
+            .iter_mut()
+            .find(|pin| normalize_query(&pin.query) == normalized && pin.command == command);
+        let added = match existing {
+            Some(pin) => {
+                pin.only = only;
+                false
+            }
+            None => {
+                pins.push(Pin {
+                    query: query.to_string(),
+                    command: command.to_string(),
+                    only,
+                });
+                true
+            }
+        };
+        self.save(&pins)?;
+        Ok(added)
+    }
+
+    /// Remove every pin of a query, returning how many there were
+    pub fn remove(&self, query: &str) -> Result<usize> {
+        let mut pins = self.load()?;
+        let before = pins.len();
+        let normalized = normalize_query(query);
+        pins.retain(|pin| normalize_query(&pin.query) != normalized);
+        let removed = before - pins.len();
+        if removed > 0 {
+            self.save(&pins)?;
+        }
+        Ok(removed)
+    }
+
+    /// The pins of a query
+    pub fn matching(&self, query: &str) -> Result<Pinned> {
+        let normalized = normalize_query(query);
+        let mut pinned = Pinned::default();
+        for pin in self.load()? {
+            if normalize_query(&pin.query) == normalized {
+                pinned.only |= pin.only;
+                pinned.commands.push(pin.command);
+            }
+        }
+        Ok(pinned)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn store(dir: &TempDir) -> PinStore {
+        PinStore::new(dir.path().join("qai").join("pins.yml"))
+    }
+
+    #[test]
+    fn test_pins_match_normalized_query() {
+        let dir = TempDir::new().unwrap();
+        let store = store(&dir);
+        assert_eq!(store.matching("list files").unwrap(), Pinned::default());
+
+        assert!(store.add("List files", "eza -la", false).unwrap());
+        assert!(store.add("list files", "ls -la", false).unwrap());
+        assert!(store.add("show disk usage", "dust", true).unwrap());
+
+        let pinned = store.matching("  LIST FILES ").unwrap();
+        assert_eq!(pinned.commands, vec!["eza -la", "ls -la"]);
+        assert!(!pinned.only);
+        assert!(store.matching("show disk usage").unwrap().only);
+    }
+
+    #[test]
+    fn test_pin_again_updates_only() {
+        let dir = TempDir::new().unwrap();
+        let store = store(&dir);
+        store.add("list files", "ls -la", false).unwrap();
+        assert!(!store.add("list files", "ls -la", true).unwrap());
+        assert_eq!(store.load().unwrap().len(), 1);
+        assert!(store.matching("list files").unwrap().only);
+    }
+
+    #[test]
+    fn test_remove_pins_of_query() {
+        let dir = TempDir::new().unwrap();
+        let store = store(&dir);
+        store.add("list files", "ls -la", false).unwrap();
+        store.add("list files", "eza", false).unwrap();
+        store.add("show disk usage", "dust", false).unwrap();
+        assert_eq!(store.remove("LIST FILES").unwrap(), 2);
+        assert_eq!(store.remove("list files").unwrap(), 0);
+        assert_eq!(store.load().unwrap().len(), 1);
+        assert!(store.add("list files", "  ", false).is_err());
+    }
+}
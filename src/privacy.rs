@@ -0,0 +1,99 @@
+//! Privacy mode for outbound context
+//!
+//! With `context.privacy` set, the system prompt is scrubbed before it is sent:
+//! the home directory becomes `~`, and the login and machine names become
+//! `$USER` and `$HOST`, which still work in the commands the model suggests.
+
+use regex::{NoExpand, Regex};
+
+use crate::prompt::{detect_hostname, detect_user};
+
+/// Placeholder names that identify nobody
+const ANONYMOUS: &[&str] = &["unknown", "localhost"];
+
+/// Who and where qai runs, to be kept out of prompts
+#[derive(Debug, Clone)]
+pub struct Identity {
+    pub home: Option<String>,
+    pub user: String,
+    pub hostname: String,
+}
+
+impl Identity {
+    pub fn detect() -> Self {
+        Self {
+            home: dirs::home_dir().map(|home| home.display().to_string()),
+            user: detect_user(),
+            hostname: detect_hostname(),
+        }
+    }
+
+    /// Replace the home directory with `~`, and whole-word occurrences of the user name
+    /// with `$USER` and of the hostname (or its short form) with `$HOST`
+    pub fn scrub(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        if let Some(home) = self.home.as_deref().filter(|home| home.len() > 1)
+            && let Ok(re) = Regex::new(&format!(r"{}\b", regex::escape(home.trim_end_matches('/'))))
+        {
+            text = re.replace_all(&text, "~").into_owned();
+        }
+
+        let short_host = self.hostname.split('.').next().unwrap_or_default();
+        for (name, placeholder) in [
+            (self.hostname.as_str(), "$HOST"),
+            (short_host, "$HOST"),
+            (self.user.as_str(), "$USER"),
+        ] {
+            if name.is_empty() || ANONYMOUS.contains(&name) {
+                continue;
+            }
+            if let Ok(re) = Regex::new(&format!(r"\b{}\b", regex::escape(name))) {
+                text = re.replace_all(&text, NoExpand(placeholder)).into_owned();
+            }
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity() -> Identity {
+        Identity {
+            home: Some("/home/alice".to_string()),
+            user: "alice".to_string(),
+            hostname: "build-01.corp.example.com".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_scrub_home_and_names() {
+        let prompt = "Current directory: /home/alice/src/app\nUser: alice on build-01.corp.example.com\nssh build-01";
+        assert_eq!(
+            identity().scrub(prompt),
+            "Current directory: ~/src/app\nUser: $USER on $HOST\nssh $HOST"
+        );
+    }
+
+    #[test]
+    fn test_scrub_whole_words_only() {
+        let identity = identity();
+        assert_eq!(identity.scrub("/home/alicex/data"), "/home/alicex/data");
+        assert_eq!(identity.scrub("malice and alice"), "malice and $USER");
+        assert_eq!(identity.scrub("/srv/alice/logs"), "/srv/$USER/logs");
+    }
+
+    #[test]
+    fn test_scrub_skips_placeholder_names() {
+        let identity = Identity {
+            home: None,
+            user: "unknown".to_string(),
+            hostname: "localhost".to_string(),
+        };
+        assert_eq!(
+            identity.scrub("curl localhost:8080 as unknown"),
+            "curl localhost:8080 as unknown"
+        );
+    }
+}
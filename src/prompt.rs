@@ -1,33 +1,328 @@
-use eyre::{Context, Result};
+//! System prompts and their `{{variable}}` templates
+//!
+//! Prompts come from `~/.config/qai/prompts/` when present, else the embedded
+//! defaults, and are rendered with a [`PromptContext`] describing the shell,
+//! OS, directory, and previous command.
+
+use eyre::{Context, Result, eyre};
+use std::collections::BTreeMap;
 use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::context::{detect_project_type, estimate_tokens};
 
 /// Default system prompt embedded at compile time
 const DEFAULT_SYSTEM_PROMPT: &str = include_str!("../prompts/system.pmt");
 
+/// Default multi-result (--multi) prompt embedded at compile time
+const DEFAULT_MULTI_PROMPT: &str = include_str!("../prompts/system-multi.pmt");
+
+/// Default prompt for explaining a command, embedded at compile time
+const DEFAULT_EXPLAIN_PROMPT: &str = include_str!("../prompts/explain.pmt");
+
+/// Default prompt for translating a command (`qai translate`), embedded at compile time
+const DEFAULT_TRANSLATE_PROMPT: &str = include_str!("../prompts/translate.pmt");
+
+/// Default prompts of the `qai regex` and `qai jq` generators, embedded at compile time
+const DEFAULT_REGEX_PROMPT: &str = include_str!("../prompts/regex.pmt");
+const DEFAULT_JQ_PROMPT: &str = include_str!("../prompts/jq.pmt");
+
+/// Default prompt for writing a crontab line (`qai cron`), embedded at compile time
+const DEFAULT_CRON_PROMPT: &str = include_str!("../prompts/cron.pmt");
+
+/// Prompts for tasks other than queries, by file name: they are not profiles, and are linted with
+/// the system prompts
+const TASK_PROMPTS: &[(&str, &str)] = &[
+    ("explain", DEFAULT_EXPLAIN_PROMPT),
+    ("translate", DEFAULT_TRANSLATE_PROMPT),
+    ("regex", DEFAULT_REGEX_PROMPT),
+    ("jq", DEFAULT_JQ_PROMPT),
+    ("cron", DEFAULT_CRON_PROMPT),
+];
+
 /// Context variables for prompt template substitution
 pub struct PromptContext {
     pub shell: String,
     pub os: String,
     pub cwd: String,
+    /// Machine name, for ssh/scp suggestions
+    pub hostname: String,
+    /// Login name of the current user
+    pub user: String,
+    /// Whether qai runs as root (so suggestions don't need sudo)
+    pub is_root: bool,
+    /// Project type of the cwd hierarchy, e.g. "rust" or "node (pnpm)"
+    pub project_type: String,
+    /// Previous command line, passed by the shell integration via QAI_LAST_COMMAND
+    pub last_command: Option<String>,
+    /// Exit status of the previous command, passed via QAI_LAST_EXIT
+    pub last_exit_code: Option<i32>,
+    /// Number of commands requested (1 unless --multi)
+    pub count: usize,
+    /// Additional `{{name}}` variables (e.g. detected cloud contexts)
+    pub vars: BTreeMap<String, String>,
 }
 
 impl Default for PromptContext {
     fn default() -> Self {
+        let cwd = std::env::current_dir().ok();
         Self {
-            shell: std::env::var("SHELL").unwrap_or_else(|_| "bash".to_string()),
-            os: std::env::consts::OS.to_string(),
-            cwd: std::env::current_dir()
+            shell: detect_shell(),
+            os: detect_os(),
+            cwd: cwd
+                .as_ref()
                 .map(|p| p.display().to_string())
-                .unwrap_or_else(|_| ".".to_string()),
+                .unwrap_or_else(|| ".".to_string()),
+            hostname: detect_hostname(),
+            user: detect_user(),
+            is_root: detect_is_root(),
+            project_type: cwd
+                .as_deref()
+                .and_then(detect_project_type)
+                .unwrap_or_else(|| "unknown".to_string()),
+            last_command: std::env::var("QAI_LAST_COMMAND")
+                .ok()
+                .filter(|cmd| !cmd.trim().is_empty()),
+            last_exit_code: std::env::var("QAI_LAST_EXIT")
+                .ok()
+                .and_then(|code| code.trim().parse().ok()),
+            count: 1,
+            vars: BTreeMap::new(),
+        }
+    }
+}
+
+impl PromptContext {
+    /// Describe the previous command and its exit status for the prompt,
+    /// so follow-ups like "fix that" have something to refer to
+    pub fn last_command_hint(&self) -> String {
+        let Some(command) = &self.last_command else {
+            return String::new();
+        };
+
+        match self.last_exit_code {
+            Some(0) => format!("Previous command (succeeded): {}\n", command),
+            Some(code) => format!("Previous command (failed with exit status {}): {}\n", code, command),
+            None => format!("Previous command: {}\n", command),
+        }
+    }
+}
+
+/// The user's shell from $SHELL; without it, PowerShell on Windows (set `SHELL=cmd` for
+/// cmd.exe) and bash elsewhere
+pub fn detect_shell() -> String {
+    std::env::var("SHELL")
+        .ok()
+        .filter(|shell| !shell.is_empty())
+        .unwrap_or_else(|| if cfg!(windows) { "powershell" } else { "bash" }.to_string())
+}
+
+/// Windows shell a `{{shell}}` value names, by its program name (e.g. `C:\...\pwsh.exe`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowsShell {
+    PowerShell,
+    Cmd,
+}
+
+impl WindowsShell {
+    pub fn of(shell: &str) -> Option<Self> {
+        let name = shell.rsplit(['/', '\\']).next().unwrap_or(shell).to_ascii_lowercase();
+        match name.strip_suffix(".exe").unwrap_or(&name) {
+            "powershell" | "pwsh" => Some(Self::PowerShell),
+            "cmd" => Some(Self::Cmd),
+            _ => None,
         }
     }
 }
 
-/// Load prompt from a specific file path
+/// Ask for the syntax of a Windows shell instead of the POSIX commands the prompts describe.
+/// Empty for other shells
+pub fn shell_directive(shell: &str) -> String {
+    match WindowsShell::of(shell) {
+        Some(WindowsShell::PowerShell) => "Write PowerShell commands (cmdlets like Get-ChildItem, Select-String, \
+             Where-Object), not POSIX shell syntax or Unix utilities.\n"
+            .to_string(),
+        Some(WindowsShell::Cmd) => "Write cmd.exe commands (dir, copy, findstr, for /f), not POSIX shell syntax \
+             or Unix utilities.\n"
+            .to_string(),
+        None => String::new(),
+    }
+}
+
+/// Tell the model which language to write explanations in; commands stay as-is.
+/// Empty for English or when no language is configured
+pub fn language_directive(language: Option<&str>) -> String {
+    match language.map(str::trim) {
+        Some(language) if !language.is_empty() && !language.eq_ignore_ascii_case("english") => format!(
+            "Write any explanations and comments in {}. Never translate commands, flags, paths, or file names.\n",
+            language
+        ),
+        _ => String::new(),
+    }
+}
+
+/// Ask for a one-line explanation after each command (teach mode); empty when off
+pub fn teach_directive(teach: bool) -> String {
+    if !teach {
+        return String::new();
+    }
+    "Teach mode: unlike the rules above, end each single-line command with two spaces, `# `, and a one-line \
+     explanation of what it does, e.g. `du -sh * | sort -h  # size of each entry here, largest last`. \
+     Multi-line commands get no comment.\n"
+        .to_string()
+}
+
+/// Output of a command's first line, if it ran successfully
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = std::process::Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let line = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()?
+        .trim()
+        .to_string();
+    (!line.is_empty()).then_some(line)
+}
+
+/// Machine hostname from $HOSTNAME, /etc/hostname, %COMPUTERNAME%, or `hostname`
+pub fn detect_hostname() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .or_else(|| command_output("hostname", &[]))
+        .unwrap_or_else(|| "localhost".to_string())
+}
+
+/// Current user from $USER, $LOGNAME, %USERNAME%, or `whoami`
+pub fn detect_user() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("LOGNAME"))
+        .or_else(|_| std::env::var("USERNAME"))
+        .ok()
+        .filter(|user| !user.is_empty())
+        .or_else(|| command_output("whoami", &[]))
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Whether the effective user is root (uid 0)
+pub fn detect_is_root() -> bool {
+    match command_output("id", &["-u"]) {
+        Some(uid) => uid == "0",
+        None => detect_user() == "root",
+    }
+}
+
+/// OS name with distribution and version when known
+/// e.g. "linux (Ubuntu 22.04.3 LTS, like debian)", "macos (macOS 14.2)", or "windows (Windows 10.0.22631)"
+pub fn detect_os() -> String {
+    let os = std::env::consts::OS;
+    let release = match os {
+        "linux" => fs::read_to_string("/etc/os-release")
+            .ok()
+            .and_then(|content| parse_os_release(&content)),
+        "macos" => std::process::Command::new("sw_vers")
+            .arg("-productVersion")
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| format!("macOS {}", String::from_utf8_lossy(&output.stdout).trim())),
+        "windows" => command_output("cmd", &["/c", "ver"]).and_then(|ver| parse_windows_ver(&ver)),
+        _ => None,
+    };
+
+    match release {
+        Some(release) => format!("{} ({})", os, release),
+        None => os.to_string(),
+    }
+}
+
+/// Describe a distribution from `/etc/os-release` content
+/// Uses PRETTY_NAME (or NAME and VERSION_ID), plus ID_LIKE for derivatives
+pub fn parse_os_release(content: &str) -> Option<String> {
+    let fields: BTreeMap<&str, &str> = content
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.trim(), value.trim().trim_matches(['"', '\''])))
+        .collect();
+
+    let name = match (fields.get("PRETTY_NAME"), fields.get("NAME"), fields.get("VERSION_ID")) {
+        (Some(pretty), _, _) => pretty.to_string(),
+        (None, Some(name), Some(version)) => format!("{} {}", name, version),
+        (None, Some(name), None) => name.to_string(),
+        (None, None, _) => return None,
+    };
+
+    match fields.get("ID_LIKE") {
+        Some(like) => Some(format!("{}, like {}", name, like)),
+        None => Some(name),
+    }
+}
+
+/// Describe Windows from `ver` output, e.g. "Microsoft Windows [Version 10.0.22631.3155]" -> "Windows 10.0.22631"
+pub fn parse_windows_ver(output: &str) -> Option<String> {
+    let version = output.split("Version ").nth(1)?.split(']').next()?.trim();
+    let build: Vec<&str> = version.split('.').take(3).collect();
+    (!build.is_empty() && build.iter().all(|part| part.parse::<u32>().is_ok()))
+        .then(|| format!("Windows {}", build.join(".")))
+}
+
+/// Maximum nesting of `{{include}}` directives (guards against include cycles)
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+/// Load prompt from a specific file path, resolving `{{include "..."}}` directives
 pub fn load_prompt_from_file(path: &std::path::Path) -> Result<String> {
+    load_prompt_with_includes(path, 0)
+}
+
+/// Load a prompt file and splice in the files it includes, relative to its own directory
+fn load_prompt_with_includes(path: &Path, depth: usize) -> Result<String> {
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(eyre!(
+            "Prompt includes nested more than {} levels deep at {} (include cycle?)",
+            MAX_INCLUDE_DEPTH,
+            path.display()
+        ));
+    }
+
     log::info!("Loading prompt from: {}", path.display());
     let content = fs::read_to_string(path).context(format!("Failed to read prompt file: {}", path.display()))?;
-    Ok(content)
+    let base_dir = path.parent().unwrap_or(Path::new("."));
+
+    let mut rendered = String::with_capacity(content.len());
+    let mut rest = content.as_str();
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start..].find("}}") else {
+            break;
+        };
+        let directive = &rest[start + 2..start + len];
+
+        rendered.push_str(&rest[..start]);
+        match parse_include(directive) {
+            Some(include) => rendered.push_str(&load_prompt_with_includes(&base_dir.join(include), depth + 1)?),
+            // Not an include (e.g. a {{variable}}), keep it for render_prompt
+            None => rendered.push_str(&rest[start..start + len + 2]),
+        }
+        rest = &rest[start + len + 2..];
+    }
+    rendered.push_str(rest);
+
+    Ok(rendered)
+}
+
+/// Parse the path out of an `include "path"` directive
+fn parse_include(directive: &str) -> Option<&str> {
+    let path = directive.trim().strip_prefix("include")?.trim();
+    path.strip_prefix('"')?.strip_suffix('"')
+}
+
+/// Directory holding user prompts: ~/.config/qai/prompts
+pub fn prompts_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join(env!("CARGO_PKG_NAME")).join("prompts"))
 }
 
 /// Load the system prompt with the following priority:
@@ -35,11 +330,8 @@ pub fn load_prompt_from_file(path: &std::path::Path) -> Result<String> {
 /// 2. Embedded default
 pub fn load_system_prompt() -> Result<String> {
     // Check for user override
-    if let Some(config_dir) = dirs::config_dir() {
-        let user_prompt = config_dir
-            .join(env!("CARGO_PKG_NAME"))
-            .join("prompts")
-            .join("system.pmt");
+    if let Some(prompts_dir) = prompts_dir() {
+        let user_prompt = prompts_dir.join("system.pmt");
 
         if user_prompt.exists() {
             return load_prompt_from_file(&user_prompt);
@@ -51,12 +343,311 @@ pub fn load_system_prompt() -> Result<String> {
     Ok(DEFAULT_SYSTEM_PROMPT.to_string())
 }
 
+/// Load the multi-result (--multi) prompt with the following priority:
+/// 1. User override: ~/.config/qai/prompts/system-multi.pmt
+/// 2. Legacy user override: ~/.config/qai/system-prompt-multi.txt
+/// 3. Embedded default
+pub fn load_multi_prompt() -> Result<String> {
+    if let Some(prompts_dir) = prompts_dir() {
+        let user_prompt = prompts_dir.join("system-multi.pmt");
+        if user_prompt.exists() {
+            return load_prompt_from_file(&user_prompt);
+        }
+
+        if let Some(legacy_prompt) = prompts_dir.parent().map(|dir| dir.join("system-prompt-multi.txt"))
+            && legacy_prompt.exists()
+        {
+            return load_prompt_from_file(&legacy_prompt);
+        }
+    }
+
+    log::debug!("Using embedded default multi-result prompt");
+    Ok(DEFAULT_MULTI_PROMPT.to_string())
+}
+
+/// Load a task prompt with the following priority:
+/// 1. User override: ~/.config/qai/prompts/<name>.pmt
+/// 2. Embedded default
+fn load_task_prompt(name: &str, default: &str) -> Result<String> {
+    if let Some(prompts_dir) = prompts_dir() {
+        let user_prompt = prompts_dir.join(format!("{}.pmt", name));
+        if user_prompt.exists() {
+            return load_prompt_from_file(&user_prompt);
+        }
+    }
+
+    log::debug!("Using embedded default {} prompt", name);
+    Ok(default.to_string())
+}
+
+/// Load the prompt for explaining a command (~/.config/qai/prompts/explain.pmt, else the default)
+pub fn load_explain_prompt() -> Result<String> {
+    load_task_prompt("explain", DEFAULT_EXPLAIN_PROMPT)
+}
+
+/// Load the prompt for translating a command (~/.config/qai/prompts/translate.pmt, else the default)
+pub fn load_translate_prompt() -> Result<String> {
+    load_task_prompt("translate", DEFAULT_TRANSLATE_PROMPT)
+}
+
+/// Load the prompt for generating a regex (~/.config/qai/prompts/regex.pmt, else the default)
+pub fn load_regex_prompt() -> Result<String> {
+    load_task_prompt("regex", DEFAULT_REGEX_PROMPT)
+}
+
+/// Load the prompt for generating a jq filter (~/.config/qai/prompts/jq.pmt, else the default)
+pub fn load_jq_prompt() -> Result<String> {
+    load_task_prompt("jq", DEFAULT_JQ_PROMPT)
+}
+
+/// Load the prompt for writing a crontab line (~/.config/qai/prompts/cron.pmt, else the default)
+pub fn load_cron_prompt() -> Result<String> {
+    load_task_prompt("cron", DEFAULT_CRON_PROMPT)
+}
+
+/// The user message asking to translate `command`; without a target, for this machine's shell and OS
+pub fn translation_request(command: &str, from: Option<&str>, to: Option<&str>, context: &PromptContext) -> String {
+    let target = match to {
+        Some(target) => target.to_string(),
+        None => {
+            let shell = context.shell.rsplit(['/', '\\']).next().unwrap_or(&context.shell);
+            format!("{} on {}", shell, context.os)
+        }
+    };
+    match from {
+        Some(source) => format!("Translate this command from {} to {}:\n{}", source, target, command),
+        None => format!("Translate this command to {}:\n{}", target, command),
+    }
+}
+
+/// Pick the prompt profile: the --profile flag, else QAI_PROFILE
+pub fn resolve_profile(flag: Option<&str>) -> Option<String> {
+    flag.map(str::to_string)
+        .or_else(|| std::env::var("QAI_PROFILE").ok())
+        .filter(|profile| !profile.trim().is_empty())
+}
+
+/// Load a named prompt profile from `~/.config/qai/prompts/<profile>.pmt`
+pub fn load_profile_prompt(profile: &str) -> Result<String> {
+    let dir = prompts_dir().ok_or_else(|| eyre!("Could not determine config directory"))?;
+    load_profile_prompt_from(&dir, profile)
+}
+
+/// Load a named prompt profile from `<dir>/<profile>.pmt`
+pub fn load_profile_prompt_from(dir: &Path, profile: &str) -> Result<String> {
+    if profile.is_empty() || profile.contains(['/', '\\']) || profile.starts_with('.') {
+        return Err(eyre!("Invalid prompt profile name: '{}'", profile));
+    }
+
+    let path = dir.join(format!("{}.pmt", profile));
+    if !path.exists() {
+        let available = list_profiles_in(dir);
+        let available = if available.is_empty() {
+            "none".to_string()
+        } else {
+            available.join(", ")
+        };
+        return Err(eyre!(
+            "Prompt profile '{}' not found at {} (available: {})",
+            profile,
+            path.display(),
+            available
+        ));
+    }
+
+    load_prompt_from_file(&path)
+}
+
+/// Names of the prompt profiles in `dir` (excluding the system and examples prompts), sorted
+pub fn list_profiles_in(dir: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut profiles: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "pmt"))
+        .filter_map(|path| path.file_stem().map(|stem| stem.to_string_lossy().into_owned()))
+        .filter(|name| !matches!(name.as_str(), "system" | "system-multi" | "examples"))
+        .filter(|name| TASK_PROMPTS.iter().all(|(task, _)| task != name))
+        .collect();
+    profiles.sort();
+    profiles
+}
+
+/// Path of the few-shot examples file: ~/.config/qai/prompts/examples.pmt
+pub fn examples_path() -> Option<PathBuf> {
+    prompts_dir().map(|dir| dir.join("examples.pmt"))
+}
+
+/// Load few-shot examples as a prompt section, or an empty string if there are none
+pub fn load_examples_from(path: &Path) -> String {
+    let Ok(content) = fs::read_to_string(path) else {
+        return String::new();
+    };
+    let content = content.trim();
+    if content.is_empty() {
+        return String::new();
+    }
+
+    log::debug!("Including examples from: {}", path.display());
+    format!(
+        "Examples of how this user wants queries answered (follow their conventions):\n\n{}\n",
+        content
+    )
+}
+
+/// Append a query -> command pair to an examples file, creating it if needed
+pub fn append_example_to(path: &Path, query: &str, command: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("Failed to create prompts directory")?;
+    }
+
+    let mut content = fs::read_to_string(path).unwrap_or_default().trim_end().to_string();
+    if !content.is_empty() {
+        content.push_str("\n\n");
+    }
+    content.push_str(&format!("query: {}\ncommand: {}\n", query.trim(), command.trim()));
+    fs::write(path, content).context(format!("Failed to write examples file: {}", path.display()))
+}
+
 /// Substitute template variables in the prompt
 pub fn render_prompt(template: &str, context: &PromptContext) -> String {
-    template
+    let mut rendered = template
         .replace("{{shell}}", &context.shell)
         .replace("{{os}}", &context.os)
         .replace("{{cwd}}", &context.cwd)
+        .replace("{{hostname}}", &context.hostname)
+        .replace("{{user}}", &context.user)
+        .replace("{{is_root}}", if context.is_root { "yes" } else { "no" })
+        .replace("{{project_type}}", &context.project_type)
+        .replace("{{count}}", &context.count.to_string())
+        .replace("{{last_command}}", context.last_command.as_deref().unwrap_or(""))
+        .replace(
+            "{{last_exit_code}}",
+            &context.last_exit_code.map(|code| code.to_string()).unwrap_or_default(),
+        );
+
+    for (name, value) in &context.vars {
+        rendered = rendered.replace(&format!("{{{{{}}}}}", name), value);
+    }
+
+    rendered
+}
+
+/// Result of checking one prompt template with `qai prompt lint`
+pub struct PromptLint {
+    /// Template name: "system", "system-multi", or a profile name
+    pub name: String,
+    /// Where the template came from ("built-in" for embedded defaults)
+    pub source: String,
+    /// Placeholders left over after rendering with every known variable
+    pub unknown: Vec<String>,
+    /// Estimated token size of the rendered template
+    pub tokens: usize,
+    /// Why the template could not be loaded (e.g. a missing include)
+    pub error: Option<String>,
+}
+
+impl PromptLint {
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none() && self.unknown.is_empty()
+    }
+}
+
+/// Lint the system, multi-result, task (explain, translate, ...), and profile prompts in `dir`, using the embedded
+/// defaults where there is no user override. `vars` are the extra variables available at query time
+pub fn lint_prompts_in(dir: &Path, vars: &BTreeMap<String, String>) -> Vec<PromptLint> {
+    let mut lints = Vec::new();
+
+    let system = dir.join("system.pmt");
+    lints.push(if system.exists() {
+        lint_prompt_file("system", &system, vars)
+    } else {
+        lint_template("system", "built-in", DEFAULT_SYSTEM_PROMPT, vars)
+    });
+
+    let multi = dir.join("system-multi.pmt");
+    let legacy_multi = dir.parent().map(|parent| parent.join("system-prompt-multi.txt"));
+    lints.push(if multi.exists() {
+        lint_prompt_file("system-multi", &multi, vars)
+    } else if let Some(legacy) = legacy_multi.filter(|path| path.exists()) {
+        lint_prompt_file("system-multi", &legacy, vars)
+    } else {
+        lint_template("system-multi", "built-in", DEFAULT_MULTI_PROMPT, vars)
+    });
+
+    for (name, default) in TASK_PROMPTS {
+        let path = dir.join(format!("{}.pmt", name));
+        lints.push(if path.exists() {
+            lint_prompt_file(name, &path, vars)
+        } else {
+            lint_template(name, "built-in", default, vars)
+        });
+    }
+
+    for profile in list_profiles_in(dir) {
+        lints.push(lint_prompt_file(&profile, &dir.join(format!("{}.pmt", profile)), vars));
+    }
+
+    lints
+}
+
+fn lint_prompt_file(name: &str, path: &Path, vars: &BTreeMap<String, String>) -> PromptLint {
+    match load_prompt_from_file(path) {
+        Ok(template) => lint_template(name, &path.display().to_string(), &template, vars),
+        Err(e) => PromptLint {
+            name: name.to_string(),
+            source: path.display().to_string(),
+            unknown: Vec::new(),
+            tokens: 0,
+            error: Some(format!("{:#}", e)),
+        },
+    }
+}
+
+/// Render a template with dummy context and report placeholders nothing filled in
+pub fn lint_template(name: &str, source: &str, template: &str, vars: &BTreeMap<String, String>) -> PromptLint {
+    let context = PromptContext {
+        shell: "/bin/zsh".to_string(),
+        os: "linux".to_string(),
+        cwd: "/home/user/project".to_string(),
+        hostname: "host".to_string(),
+        user: "user".to_string(),
+        is_root: false,
+        project_type: "rust".to_string(),
+        last_command: Some("ls".to_string()),
+        last_exit_code: Some(0),
+        count: 5,
+        vars: vars.clone(),
+    };
+    let rendered = render_prompt(template, &context);
+
+    PromptLint {
+        name: name.to_string(),
+        source: source.to_string(),
+        unknown: placeholders(&rendered),
+        tokens: estimate_tokens(&rendered),
+        error: None,
+    }
+}
+
+/// The distinct `{{...}}` placeholders in `text`, in order of appearance
+fn placeholders(text: &str) -> Vec<String> {
+    let mut found: Vec<String> = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start..].find("}}") else {
+            break;
+        };
+        let placeholder = rest[start..start + len + 2].to_string();
+        if !found.contains(&placeholder) {
+            found.push(placeholder);
+        }
+        rest = &rest[start + len + 2..];
+    }
+    found
 }
 
 #[cfg(test)]
@@ -90,6 +681,14 @@ mod tests {
             shell: "/bin/zsh".to_string(),
             os: "linux".to_string(),
             cwd: "/home/user".to_string(),
+            hostname: "box".to_string(),
+            user: "me".to_string(),
+            is_root: false,
+            project_type: "unknown".to_string(),
+            last_command: None,
+            last_exit_code: None,
+            count: 1,
+            vars: BTreeMap::new(),
         };
 
         let result = render_prompt(template, &context);
@@ -103,6 +702,14 @@ mod tests {
             shell: "zsh".to_string(),
             os: "linux".to_string(),
             cwd: "/tmp".to_string(),
+            hostname: "box".to_string(),
+            user: "me".to_string(),
+            is_root: false,
+            project_type: "unknown".to_string(),
+            last_command: None,
+            last_exit_code: None,
+            count: 1,
+            vars: BTreeMap::new(),
         };
 
         let result = render_prompt(template, &context);
@@ -116,6 +723,14 @@ mod tests {
             shell: "zsh".to_string(),
             os: "linux".to_string(),
             cwd: "/tmp".to_string(),
+            hostname: "box".to_string(),
+            user: "me".to_string(),
+            is_root: false,
+            project_type: "unknown".to_string(),
+            last_command: None,
+            last_exit_code: None,
+            count: 1,
+            vars: BTreeMap::new(),
         };
 
         let result = render_prompt(template, &context);
@@ -129,6 +744,14 @@ mod tests {
             shell: "zsh".to_string(),
             os: "linux".to_string(),
             cwd: "/tmp".to_string(),
+            hostname: "box".to_string(),
+            user: "me".to_string(),
+            is_root: false,
+            project_type: "unknown".to_string(),
+            last_command: None,
+            last_exit_code: None,
+            count: 1,
+            vars: BTreeMap::new(),
         };
 
         let result = render_prompt(template, &context);
@@ -142,12 +765,240 @@ mod tests {
             shell: "zsh".to_string(),
             os: "linux".to_string(),
             cwd: "/home/user/my project (1)/test".to_string(),
+            hostname: "box".to_string(),
+            user: "me".to_string(),
+            is_root: false,
+            project_type: "unknown".to_string(),
+            last_command: None,
+            last_exit_code: None,
+            count: 1,
+            vars: BTreeMap::new(),
         };
 
         let result = render_prompt(template, &context);
         assert_eq!(result, "Dir: /home/user/my project (1)/test");
     }
 
+    #[test]
+    fn test_render_prompt_user_host_root() {
+        let mut context = PromptContext {
+            hostname: "web-1".to_string(),
+            user: "deploy".to_string(),
+            is_root: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            render_prompt("{{user}}@{{hostname}} root={{is_root}}", &context),
+            "deploy@web-1 root=no"
+        );
+
+        context.is_root = true;
+        assert_eq!(render_prompt("root={{is_root}}", &context), "root=yes");
+    }
+
+    #[test]
+    fn test_render_prompt_project_type() {
+        let context = PromptContext {
+            project_type: "node (pnpm)".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            render_prompt("Project: {{project_type}}", &context),
+            "Project: node (pnpm)"
+        );
+    }
+
+    #[test]
+    fn test_detect_user_host_non_empty() {
+        assert!(!detect_hostname().is_empty());
+        assert!(!detect_user().is_empty());
+    }
+
+    #[test]
+    fn test_render_prompt_last_command() {
+        let context = PromptContext {
+            shell: "zsh".to_string(),
+            os: "linux".to_string(),
+            cwd: "/tmp".to_string(),
+            hostname: "box".to_string(),
+            user: "me".to_string(),
+            is_root: false,
+            project_type: "unknown".to_string(),
+            last_command: Some("git pus".to_string()),
+            last_exit_code: Some(1),
+            count: 1,
+            vars: BTreeMap::new(),
+        };
+
+        let result = render_prompt("Last: {{last_command}} ({{last_exit_code}})", &context);
+        assert_eq!(result, "Last: git pus (1)");
+    }
+
+    #[test]
+    fn test_lint_default_prompts_are_clean() {
+        let vars = BTreeMap::from([
+            ("docker".to_string(), "none".to_string()),
+            ("kube_context".to_string(), "none".to_string()),
+            ("kube_namespace".to_string(), "none".to_string()),
+            ("aws_profile".to_string(), "none".to_string()),
+            ("gcloud_project".to_string(), "none".to_string()),
+            ("az_subscription".to_string(), "none".to_string()),
+        ]);
+        for template in [DEFAULT_SYSTEM_PROMPT, DEFAULT_MULTI_PROMPT] {
+            let lint = lint_template("system", "built-in", template, &vars);
+            assert!(lint.is_ok(), "unknown placeholders: {:?}", lint.unknown);
+            assert!(lint.tokens > 0);
+        }
+    }
+
+    #[test]
+    fn test_lint_template_reports_unknown_placeholders() {
+        let vars = BTreeMap::from([("region".to_string(), "eu-west-1".to_string())]);
+        let lint = lint_template(
+            "k8s",
+            "k8s.pmt",
+            "{{os}} {{region}} {{namespace}} {{ typo }} {{namespace}}",
+            &vars,
+        );
+        assert!(!lint.is_ok());
+        assert_eq!(lint.unknown, vec!["{{namespace}}", "{{ typo }}"]);
+    }
+
+    #[test]
+    fn test_lint_prompts_in_reports_missing_include() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("system.pmt"), "OS: {{os}}\n").unwrap();
+        fs::write(temp_dir.path().join("k8s.pmt"), "{{include \"missing.pmt\"}}\n").unwrap();
+
+        let lints = lint_prompts_in(temp_dir.path(), &BTreeMap::new());
+        let names: Vec<&str> = lints.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "system",
+                "system-multi",
+                "explain",
+                "translate",
+                "regex",
+                "jq",
+                "cron",
+                "k8s"
+            ]
+        );
+
+        assert!(lints[0].is_ok());
+        assert!(lints[0].source.ends_with("system.pmt"));
+        assert_eq!(lints[1].source, "built-in");
+        assert!(lints[2..7].iter().all(PromptLint::is_ok));
+        assert!(lints[7].error.as_deref().unwrap().contains("missing.pmt"));
+    }
+
+    #[test]
+    fn test_language_directive() {
+        assert_eq!(language_directive(None), "");
+        assert_eq!(language_directive(Some("  ")), "");
+        assert_eq!(language_directive(Some("English")), "");
+        assert_eq!(
+            language_directive(Some("Deutsch")),
+            "Write any explanations and comments in Deutsch. Never translate commands, flags, paths, or file names.\n"
+        );
+    }
+
+    #[test]
+    fn test_teach_directive() {
+        assert_eq!(teach_directive(false), "");
+        assert!(teach_directive(true).contains("one-line explanation"));
+    }
+
+    #[test]
+    fn test_last_command_hint() {
+        let mut context = PromptContext {
+            shell: "zsh".to_string(),
+            os: "linux".to_string(),
+            cwd: "/tmp".to_string(),
+            hostname: "box".to_string(),
+            user: "me".to_string(),
+            is_root: false,
+            project_type: "unknown".to_string(),
+            last_command: None,
+            last_exit_code: Some(1),
+            count: 1,
+            vars: BTreeMap::new(),
+        };
+        assert_eq!(context.last_command_hint(), "");
+
+        context.last_command = Some("make test".to_string());
+        assert_eq!(
+            context.last_command_hint(),
+            "Previous command (failed with exit status 1): make test\n"
+        );
+
+        context.last_exit_code = Some(0);
+        assert_eq!(context.last_command_hint(), "Previous command (succeeded): make test\n");
+
+        context.last_exit_code = None;
+        assert_eq!(context.last_command_hint(), "Previous command: make test\n");
+    }
+
+    #[test]
+    fn test_render_prompt_extra_vars() {
+        let template = "Kube: {{kube_context}}, AWS: {{aws_profile}}, unknown: {{nope}}";
+        let mut vars = BTreeMap::new();
+        vars.insert("kube_context".to_string(), "prod".to_string());
+        vars.insert("aws_profile".to_string(), "dev".to_string());
+        let context = PromptContext {
+            shell: "zsh".to_string(),
+            os: "linux".to_string(),
+            cwd: "/tmp".to_string(),
+            hostname: "box".to_string(),
+            user: "me".to_string(),
+            is_root: false,
+            project_type: "unknown".to_string(),
+            last_command: None,
+            last_exit_code: None,
+            count: 1,
+            vars,
+        };
+
+        let result = render_prompt(template, &context);
+        assert_eq!(result, "Kube: prod, AWS: dev, unknown: {{nope}}");
+    }
+
+    #[test]
+    fn test_default_multi_prompt_exists() {
+        assert!(DEFAULT_MULTI_PROMPT.len() > 100);
+        assert!(DEFAULT_MULTI_PROMPT.contains("{{count}}"));
+        assert!(DEFAULT_MULTI_PROMPT.contains("one per line"));
+        assert!(DEFAULT_MULTI_PROMPT.contains("{{shell}}"));
+        assert!(DEFAULT_MULTI_PROMPT.contains("{{os}}"));
+        assert!(DEFAULT_MULTI_PROMPT.contains("{{cwd}}"));
+    }
+
+    #[test]
+    fn test_default_multi_prompt_requests_dual_format() {
+        assert!(DEFAULT_MULTI_PROMPT.contains("MODERN:"));
+        assert!(DEFAULT_MULTI_PROMPT.contains("STANDARD:"));
+    }
+
+    #[test]
+    fn test_render_multi_prompt_count() {
+        for count in [1, 3, 5, 10] {
+            let context = PromptContext {
+                count,
+                ..Default::default()
+            };
+            let prompt = render_prompt(DEFAULT_MULTI_PROMPT, &context);
+            assert!(prompt.contains(&format!("EXACTLY {} command options", count)));
+            assert!(!prompt.contains("{{"));
+        }
+    }
+
+    #[test]
+    fn test_load_multi_prompt_returns_content() {
+        let prompt = load_multi_prompt().unwrap();
+        assert!(!prompt.is_empty());
+    }
+
     #[test]
     fn test_prompt_context_default() {
         let context = PromptContext::default();
@@ -158,6 +1009,73 @@ mod tests {
         assert!(!context.cwd.is_empty());
     }
 
+    #[test]
+    fn test_parse_os_release_pretty_name() {
+        let content =
+            "NAME=\"Ubuntu\"\nVERSION_ID=\"22.04\"\nPRETTY_NAME=\"Ubuntu 22.04.3 LTS\"\nID=ubuntu\nID_LIKE=debian\n";
+        assert_eq!(
+            parse_os_release(content).as_deref(),
+            Some("Ubuntu 22.04.3 LTS, like debian")
+        );
+    }
+
+    #[test]
+    fn test_parse_os_release_name_and_version() {
+        let content = "NAME=Fedora\nVERSION_ID=39\nID=fedora\n";
+        assert_eq!(parse_os_release(content).as_deref(), Some("Fedora 39"));
+    }
+
+    #[test]
+    fn test_parse_os_release_empty() {
+        assert_eq!(parse_os_release(""), None);
+        assert_eq!(parse_os_release("# comment only\n"), None);
+    }
+
+    #[test]
+    fn test_translation_request() {
+        let context = PromptContext {
+            shell: "/bin/zsh".to_string(),
+            os: "macos (macOS 14.2)".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            translation_request("sed -i 's/a/b/' f", None, None, &context),
+            "Translate this command to zsh on macos (macOS 14.2):\nsed -i 's/a/b/' f"
+        );
+        assert_eq!(
+            translation_request("export A=1", Some("bash"), Some("fish"), &context),
+            "Translate this command from bash to fish:\nexport A=1"
+        );
+    }
+
+    #[test]
+    fn test_parse_windows_ver() {
+        assert_eq!(
+            parse_windows_ver("Microsoft Windows [Version 10.0.22631.3155]").as_deref(),
+            Some("Windows 10.0.22631")
+        );
+        assert_eq!(parse_windows_ver("Microsoft Windows [Version x]"), None);
+        assert_eq!(parse_windows_ver(""), None);
+    }
+
+    #[test]
+    fn test_shell_directive() {
+        assert_eq!(
+            WindowsShell::of("C:\\Program Files\\PowerShell\\7\\pwsh.exe"),
+            Some(WindowsShell::PowerShell)
+        );
+        assert_eq!(WindowsShell::of("CMD.EXE"), Some(WindowsShell::Cmd));
+        assert_eq!(WindowsShell::of("/bin/zsh"), None);
+        assert!(shell_directive("powershell").contains("PowerShell commands"));
+        assert!(shell_directive("cmd").contains("cmd.exe commands"));
+        assert_eq!(shell_directive("/usr/bin/bash"), "");
+    }
+
+    #[test]
+    fn test_detect_os_starts_with_family() {
+        assert!(detect_os().starts_with(std::env::consts::OS));
+    }
+
     #[test]
     fn test_prompt_context_os_is_valid() {
         let context = PromptContext::default();
@@ -171,6 +1089,122 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_load_profile_prompt_from() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        fs::write(
+            temp_dir.path().join("k8s.pmt"),
+            "You are a kubernetes expert. OS: {{os}}",
+        )
+        .unwrap();
+
+        let prompt = load_profile_prompt_from(temp_dir.path(), "k8s").unwrap();
+        assert_eq!(prompt, "You are a kubernetes expert. OS: {{os}}");
+    }
+
+    #[test]
+    fn test_load_profile_prompt_missing_lists_available() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("git.pmt"), "git").unwrap();
+        fs::write(temp_dir.path().join("ffmpeg.pmt"), "ffmpeg").unwrap();
+        fs::write(temp_dir.path().join("system.pmt"), "main").unwrap();
+
+        let err = load_profile_prompt_from(temp_dir.path(), "k8s")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("'k8s' not found"));
+        assert!(err.contains("available: ffmpeg, git)"));
+    }
+
+    #[test]
+    fn test_load_profile_prompt_rejects_paths() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        assert!(load_profile_prompt_from(temp_dir.path(), "../secrets").is_err());
+        assert!(load_profile_prompt_from(temp_dir.path(), ".hidden").is_err());
+        assert!(load_profile_prompt_from(temp_dir.path(), "").is_err());
+    }
+
+    #[test]
+    fn test_append_and_load_examples() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("prompts").join("examples.pmt");
+        assert_eq!(load_examples_from(&path), "");
+
+        append_example_to(&path, "list big files", "dust -d 1").unwrap();
+        append_example_to(&path, " deploy staging ", " make deploy ENV=staging ").unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "query: list big files\ncommand: dust -d 1\n\nquery: deploy staging\ncommand: make deploy ENV=staging\n"
+        );
+        let section = load_examples_from(&path);
+        assert!(section.starts_with("Examples of how this user wants queries answered"));
+        assert!(section.contains("query: deploy staging\ncommand: make deploy ENV=staging\n"));
+    }
+
+    #[test]
+    fn test_list_profiles_skips_builtin_prompts() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("examples.pmt"), "query: a\ncommand: b\n").unwrap();
+        fs::write(temp_dir.path().join("git.pmt"), "git").unwrap();
+        fs::write(temp_dir.path().join("explain.pmt"), "explain").unwrap();
+        fs::write(temp_dir.path().join("translate.pmt"), "translate").unwrap();
+        fs::write(temp_dir.path().join("jq.pmt"), "jq").unwrap();
+        assert_eq!(list_profiles_in(temp_dir.path()), vec!["git"]);
+    }
+
+    #[test]
+    fn test_load_prompt_resolves_includes() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let snippets = temp_dir.path().join("snippets");
+        fs::create_dir(&snippets).unwrap();
+        fs::write(
+            snippets.join("docker.pmt"),
+            "Use docker compose v2.\n{{include \"base.pmt\"}}",
+        )
+        .unwrap();
+        fs::write(snippets.join("base.pmt"), "Be concise.").unwrap();
+        let main = temp_dir.path().join("system.pmt");
+        fs::write(&main, "Shell: {{shell}}\n{{include \"snippets/docker.pmt\"}}\nDone").unwrap();
+
+        let prompt = load_prompt_from_file(&main).unwrap();
+        assert_eq!(prompt, "Shell: {{shell}}\nUse docker compose v2.\nBe concise.\nDone");
+    }
+
+    #[test]
+    fn test_load_prompt_include_missing_file() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let main = temp_dir.path().join("system.pmt");
+        fs::write(&main, "{{ include \"nope.pmt\" }}").unwrap();
+
+        let err = format!("{:#}", load_prompt_from_file(&main).unwrap_err());
+        assert!(err.contains("nope.pmt"));
+    }
+
+    #[test]
+    fn test_load_prompt_include_cycle() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let main = temp_dir.path().join("loop.pmt");
+        fs::write(&main, "{{include \"loop.pmt\"}}").unwrap();
+
+        let err = load_prompt_from_file(&main).unwrap_err().to_string();
+        assert!(err.contains("include cycle"));
+    }
+
+    #[test]
+    fn test_parse_include() {
+        assert_eq!(parse_include(r#"include "a/b.pmt""#), Some("a/b.pmt"));
+        assert_eq!(parse_include(r#" include  "x.pmt" "#), Some("x.pmt"));
+        assert_eq!(parse_include("shell"), None);
+        assert_eq!(parse_include("include x.pmt"), None);
+    }
+
+    #[test]
+    fn test_resolve_profile_flag_wins() {
+        assert_eq!(resolve_profile(Some("git")).as_deref(), Some("git"));
+        assert_eq!(resolve_profile(Some("")), None);
+    }
+
     #[test]
     fn test_load_system_prompt_returns_content() {
         let result = load_system_prompt();
@@ -213,6 +1247,14 @@ mod tests {
             shell: "zsh".to_string(),
             os: "linux".to_string(),
             cwd: "/tmp".to_string(),
+            hostname: "box".to_string(),
+            user: "me".to_string(),
+            is_root: false,
+            project_type: "unknown".to_string(),
+            last_command: None,
+            last_exit_code: None,
+            count: 1,
+            vars: BTreeMap::new(),
         };
         let result = render_prompt(template, &context);
         assert_eq!(result, "Test {regular} braces and zsh");
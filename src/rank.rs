@@ -0,0 +1,114 @@
+//! Ranking of multi results
+//!
+//! Each candidate gets one score from the signals known about it, weighted by the `rank`
+//! config: its place in the model's answer, how history favors it for the query, whether all
+//! of its tools are installed, and its risk level. Candidates are sorted by score, best first.
+
+use crate::config::RankConfig;
+use crate::safety::RiskLevel;
+
+/// A command to rank, with what is known about it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    pub command: String,
+    /// Position in the model's answer, best first; None for commands only from history
+    pub ai_rank: Option<usize>,
+    /// How much history favors it (see [`crate::history::HistoryStore::preference`])
+    pub history: f32,
+    /// All of its tools are installed
+    pub available: bool,
+    pub risk: RiskLevel,
+}
+
+impl Candidate {
+    /// Weighted score, where `answered` is the number of commands from the model
+    fn score(&self, answered: usize, weights: &RankConfig) -> f32 {
+        // From 1.0 for the model's first command down to 1/answered for its last
+        let ai_order = self.ai_rank.map_or(0.0, |rank| {
+            answered.saturating_sub(rank) as f32 / answered.max(1) as f32
+        });
+        let available = if self.available { 1.0 } else { 0.0 };
+        let risk = match self.risk {
+            RiskLevel::Safe => 0.0,
+            RiskLevel::Caution => 1.0,
+            RiskLevel::Dangerous => 2.0,
+        };
+        weights.ai_order * ai_order + weights.history * self.history + weights.tools * available - weights.risk * risk
+    }
+}
+
+/// Sort candidates by score, best first; the sort is stable, so ties keep their order
+pub fn rank(candidates: Vec<Candidate>, weights: &RankConfig) -> Vec<String> {
+    let answered = candidates.iter().filter(|c| c.ai_rank.is_some()).count();
+    let mut scored: Vec<(f32, String)> = candidates
+        .into_iter()
+        .map(|c| (c.score(answered, weights), c.command))
+        .collect();
+    scored.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+    scored.into_iter().map(|(_, command)| command).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(command: &str, ai_rank: Option<usize>) -> Candidate {
+        Candidate {
+            command: command.to_string(),
+            ai_rank,
+            history: 0.0,
+            available: true,
+            risk: RiskLevel::Safe,
+        }
+    }
+
+    fn answer(commands: &[&str]) -> Vec<Candidate> {
+        commands
+            .iter()
+            .enumerate()
+            .map(|(i, command)| candidate(command, Some(i)))
+            .collect()
+    }
+
+    #[test]
+    fn test_rank_keeps_model_order_without_other_signals() {
+        let ranked = rank(answer(&["eza -la", "ls -la", "ls"]), &RankConfig::default());
+        assert_eq!(ranked, vec!["eza -la", "ls -la", "ls"]);
+    }
+
+    #[test]
+    fn test_rank_past_pick_comes_first() {
+        let mut candidates = answer(&["eza -la", "ls -la", "ls"]);
+        // Picked once before: ln(2)
+        candidates[2].history = 2f32.ln();
+        let ranked = rank(candidates, &RankConfig::default());
+        assert_eq!(ranked[0], "ls");
+    }
+
+    #[test]
+    fn test_rank_history_only_commands_follow_the_answer() {
+        let mut candidates = answer(&["ls -la", "ls"]);
+        candidates.push(candidate("dir", None));
+        assert_eq!(rank(candidates, &RankConfig::default()), vec!["ls -la", "ls", "dir"]);
+    }
+
+    #[test]
+    fn test_rank_penalizes_risk_and_missing_tools() {
+        let mut candidates = answer(&["rm -rf build", "eza build", "ls build"]);
+        candidates[0].risk = RiskLevel::Dangerous;
+        candidates[1].available = false;
+        let ranked = rank(candidates, &RankConfig::default());
+        assert_eq!(ranked, vec!["ls build", "rm -rf build", "eza build"]);
+    }
+
+    #[test]
+    fn test_rank_zero_weight_ignores_signal() {
+        let mut candidates = answer(&["sudo rm -rf /tmp/x", "rm -rf /tmp/x"]);
+        candidates[0].risk = RiskLevel::Dangerous;
+        let weights = RankConfig {
+            risk: 0.0,
+            ..RankConfig::default()
+        };
+        assert_eq!(rank(candidates, &weights)[0], "sudo rm -rf /tmp/x");
+    }
+}
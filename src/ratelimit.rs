@@ -0,0 +1,442 @@
+//! Retrying and queueing requests under rate limits
+//!
+//! A request the API answers with 429 Too Many Requests isn't failed right away: it waits for
+//! the `Retry-After` the API asked for (else a backoff doubled on each retry) and is sent
+//! again, up to `rate-limit.retries` times. While the API is limiting, other requests don't
+//! add to the burst: they queue behind it in order and are released one at a time,
+//! `rate-limit.pace-ms` apart, until the queue has drained.
+//!
+//! `qai daemon` keeps one [`Pacer`] for every shell sending through it; without a daemon, each
+//! process has its own. Waiting requests report how many requests are ahead of them, which
+//! the zsh widget shows next to its spinner (see [`report_queued`]); the same status file
+//! carries install hints back to the widget (see [`report_hints`]).
+
+use eyre::{Context, Result};
+use reqwest::StatusCode;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+use crate::config::RateLimitConfig;
+
+/// File the queue status is written to instead of stderr (set by the zsh widget)
+pub const STATUS_FILE_ENV: &str = "QAI_STATUS_FILE";
+
+/// The process's queue, shared by all its clients
+static SHARED: LazyLock<Arc<Pacer>> = LazyLock::new(|| Arc::new(Pacer::new()));
+
+/// The queue shared by every client of this process
+pub fn shared() -> Arc<Pacer> {
+    SHARED.clone()
+}
+
+/// A queue of requests waiting out a rate limit
+#[derive(Debug, Default)]
+pub struct Pacer {
+    state: Mutex<State>,
+    /// Notified whenever a request leaves the queue
+    turn: Notify,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    next_ticket: u64,
+    /// Tickets of queued requests, the one sent next first
+    queue: VecDeque<u64>,
+    /// Nothing queued is sent before this
+    resume_at: Option<Instant>,
+    /// Wait after the next 429 without Retry-After (zero: `rate-limit.backoff-ms`)
+    backoff: Duration,
+}
+
+impl State {
+    fn limited(&self) -> bool {
+        !self.queue.is_empty() || self.resume_at.is_some_and(|at| at > Instant::now())
+    }
+}
+
+/// A place in the queue, given up when dropped
+struct Ticket<'a> {
+    pacer: &'a Pacer,
+    id: u64,
+}
+
+impl Drop for Ticket<'_> {
+    fn drop(&mut self) {
+        let mut state = self.pacer.lock();
+        state.queue.retain(|id| *id != self.id);
+        drop(state);
+        self.pacer.turn.notify_waiters();
+    }
+}
+
+impl Pacer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Requests currently queued
+    pub fn queued(&self) -> usize {
+        self.lock().queue.len()
+    }
+
+    fn join(&self) -> Ticket<'_> {
+        let mut state = self.lock();
+        let id = state.next_ticket;
+        state.next_ticket += 1;
+        state.queue.push_back(id);
+        Ticket { pacer: self, id }
+    }
+
+    /// A place in the queue when the API is limiting, else None (send right away)
+    fn join_if_limited(&self) -> Option<Ticket<'_>> {
+        let limited = self.lock().limited();
+        limited.then(|| self.join())
+    }
+
+    /// Wait until `ticket` is first in the queue and the limit has passed, calling `on_queued`
+    /// with the number of requests ahead whenever it changes
+    async fn wait_turn(&self, ticket: &Ticket<'_>, on_queued: &mut (dyn FnMut(usize) + Send)) {
+        let mut reported = None;
+        loop {
+            let notified = self.turn.notified();
+            let (ahead, resume_at) = {
+                let state = self.lock();
+                let ahead = state.queue.iter().position(|id| *id == ticket.id).unwrap_or(0);
+                (ahead, state.resume_at.filter(|at| *at > Instant::now()))
+            };
+            if ahead == 0 && resume_at.is_none() {
+                return;
+            }
+            if reported != Some(ahead) {
+                on_queued(ahead);
+                reported = Some(ahead);
+            }
+            match resume_at {
+                // Checked again after the wait: another 429 may have pushed it back
+                Some(at) if ahead == 0 => tokio::time::sleep_until(at).await,
+                _ => notified.await,
+            }
+        }
+    }
+
+    /// Note a 429 and hold queued requests back; returns how long to wait
+    fn limited(&self, config: &RateLimitConfig, retry_after: Option<Duration>) -> Duration {
+        let mut state = self.lock();
+        let backoff = if state.backoff.is_zero() {
+            Duration::from_millis(config.backoff_ms)
+        } else {
+            state.backoff
+        };
+        let wait = retry_after.unwrap_or(backoff);
+        state.backoff = backoff * 2;
+        let resume_at = Instant::now() + wait.min(Duration::from_secs(config.max_wait_secs));
+        state.resume_at = Some(state.resume_at.map_or(resume_at, |at| at.max(resume_at)));
+        wait
+    }
+
+    /// Note an answer that wasn't a 429: the limit is over, and the queue drains at the pace
+    fn answered(&self, config: &RateLimitConfig, queued: bool) {
+        let mut state = self.lock();
+        let waiting = state.queue.len() - usize::from(queued);
+        state.backoff = Duration::ZERO;
+        state.resume_at = (waiting > 0).then(|| Instant::now() + Duration::from_millis(config.pace_ms));
+    }
+
+    /// Send a request with `send`, queueing it while the API is limiting and sending it again
+    /// on 429s. Returns the last response, which is still a 429 once the retries are used up
+    pub async fn send<F, Fut>(
+        &self,
+        config: &RateLimitConfig,
+        mut send: F,
+        on_queued: &mut (dyn FnMut(usize) + Send),
+    ) -> Result<reqwest::Response>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<reqwest::Response>>,
+    {
+        let mut ticket = self.join_if_limited();
+        let mut retries = 0;
+        loop {
+            if let Some(ticket) = &ticket {
+                self.wait_turn(ticket, on_queued).await;
+            }
+            let response = send().await?;
+            if response.status() != StatusCode::TOO_MANY_REQUESTS {
+                self.answered(config, ticket.is_some());
+                return Ok(response);
+            }
+
+            let wait = self.limited(config, retry_after(&response));
+            if retries >= config.retries || wait > Duration::from_secs(config.max_wait_secs) {
+                return Ok(response);
+            }
+            retries += 1;
+            log::info!(
+                "Rate limited, retrying in {:?} ({} of {})",
+                wait,
+                retries,
+                config.retries
+            );
+            if ticket.is_none() {
+                ticket = Some(self.join());
+            }
+        }
+    }
+}
+
+/// The wait a 429 asks for, when given in seconds
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    let value = response.headers().get(reqwest::header::RETRY_AFTER)?;
+    value.to_str().ok()?.trim().parse().ok().map(Duration::from_secs)
+}
+
+/// What a request waiting its turn shows
+pub fn queued_status(ahead: usize) -> String {
+    match ahead {
+        0 => "rate limited, retrying soon".to_string(),
+        1 => "rate limited, 1 request ahead".to_string(),
+        n => format!("rate limited, {} requests ahead", n),
+    }
+}
+
+/// Show that a request is waiting: written to `QAI_STATUS_FILE` when set, else to stderr
+pub fn report_queued(ahead: usize) {
+    report(status_file().as_deref(), &[queued_status(ahead)]);
+}
+
+/// Show install hints for tools an answer needed but lacked: written to `QAI_STATUS_FILE`
+/// when set, where the zsh widget picks them up once the query is done, else to stderr
+pub fn report_hints(hints: &[String]) {
+    if !hints.is_empty() {
+        report(status_file().as_deref(), hints);
+    }
+}
+
+/// The status file named by `QAI_STATUS_FILE`, if set
+fn status_file() -> Option<PathBuf> {
+    std::env::var_os(STATUS_FILE_ENV)
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Write `lines` to the status file, one per line, else to stderr as comments
+fn report(status_file: Option<&Path>, lines: &[String]) {
+    match status_file {
+        Some(path) => {
+            let status: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+            if let Err(e) = write_status(path, &status) {
+                log::warn!("{:#}", e);
+            }
+        }
+        None => {
+            for line in lines {
+                eprintln!("# {}", line);
+            }
+        }
+    }
+}
+
+/// Clear the status left by [`report_queued`] once the request is on its way
+pub fn clear_status() {
+    if let Some(path) = status_file() {
+        let _ = write_status(&path, "");
+    }
+}
+
+/// Replace the status file's contents, without following a symlink or writing to anything
+/// but a regular file the user owns
+fn write_status(path: &Path, status: &str) -> Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        // Non-blocking so a FIFO in its place can't hang the request
+        options.mode(0o600).custom_flags(libc::O_NOFOLLOW | libc::O_NONBLOCK);
+    }
+    let mut file = options.open(path).context("Failed to open status file")?;
+    let metadata = file.metadata().context("Failed to read status file metadata")?;
+    if !metadata.is_file() {
+        eyre::bail!("Status file {} is not a regular file", path.display());
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        // SAFETY: geteuid has no preconditions and can't fail
+        if metadata.uid() != unsafe { libc::geteuid() } {
+            eyre::bail!("Status file {} is owned by another user", path.display());
+        }
+    }
+    file.set_len(0).context("Failed to truncate status file")?;
+    file.write_all(status.as_bytes()).context("Failed to write status file")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::method;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn config(retries: u32) -> RateLimitConfig {
+        RateLimitConfig {
+            retries,
+            backoff_ms: 10,
+            max_wait_secs: 5,
+            pace_ms: 10,
+        }
+    }
+
+    /// Built once: building a client takes long enough to upset the timing of the tests
+    static CLIENT: LazyLock<reqwest::Client> = LazyLock::new(reqwest::Client::new);
+
+    async fn post(pacer: &Pacer, config: &RateLimitConfig, url: &str, queued: &mut Vec<usize>) -> StatusCode {
+        let client = &*CLIENT;
+        pacer
+            .send(
+                config,
+                || {
+                    let request = client.post(url);
+                    async move { Ok(request.send().await?) }
+                },
+                &mut |ahead| queued.push(ahead),
+            )
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn test_send_retries_after_429() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "0"))
+            .up_to_n_times(2)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&mock_server)
+            .await;
+
+        let pacer = Pacer::new();
+        let mut queued = Vec::new();
+        assert_eq!(
+            post(&pacer, &config(3), &mock_server.uri(), &mut queued).await,
+            StatusCode::OK
+        );
+        assert_eq!(pacer.queued(), 0);
+        assert_eq!(mock_server.received_requests().await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_send_gives_up_after_retries() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(429))
+            .mount(&mock_server)
+            .await;
+
+        let pacer = Pacer::new();
+        let mut queued = Vec::new();
+        let status = post(&pacer, &config(2), &mock_server.uri(), &mut queued).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(mock_server.received_requests().await.unwrap().len(), 3);
+        // Backing off before each retry, first in the queue
+        assert_eq!(queued, vec![0, 0]);
+        assert_eq!(pacer.queued(), 0);
+
+        // A Retry-After longer than max-wait-secs fails right away
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "60"))
+            .mount(&mock_server)
+            .await;
+        let status = post(&Pacer::new(), &config(3), &mock_server.uri(), &mut queued).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(mock_server.received_requests().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_requests_queue_while_limited() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&mock_server)
+            .await;
+
+        let pacer = Pacer::new();
+        let config = config(3);
+        LazyLock::force(&CLIENT);
+        // A 429 was just answered: new requests wait in line
+        pacer.limited(&config, Some(Duration::from_millis(200)));
+        let first = pacer.join();
+        let mut queued = Vec::new();
+        let uri = mock_server.uri();
+        let waiting = post(&pacer, &config, &uri, &mut queued);
+        let releasing = async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            drop(first);
+        };
+        let (status, ()) = tokio::join!(waiting, releasing);
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(queued, vec![1, 0]);
+        assert_eq!(pacer.queued(), 0);
+
+        // Once answered, requests are sent right away again
+        queued.clear();
+        post(&pacer, &config, &uri, &mut queued).await;
+        assert!(queued.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_status() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("status");
+        write_status(&path, "rate limited, 2 requests ahead\n").unwrap();
+        write_status(&path, "rate limited\n").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "rate limited\n");
+
+        // A symlink planted in its place isn't followed
+        let target = dir.path().join("target");
+        std::fs::write(&target, "keep").unwrap();
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        assert!(write_status(&link, "rate limited\n").is_err());
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "keep");
+        assert!(write_status(dir.path(), "").is_err());
+    }
+
+    #[test]
+    fn test_report_lines_to_status_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("status");
+        report(Some(&path), &["rate limited".to_string()]);
+        let hints = [
+            "install with: brew install eza".to_string(),
+            "install with: brew install fd".to_string(),
+        ];
+        report(Some(&path), &hints);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "install with: brew install eza\ninstall with: brew install fd\n"
+        );
+    }
+
+    #[test]
+    fn test_queued_status() {
+        assert_eq!(queued_status(0), "rate limited, retrying soon");
+        assert_eq!(queued_status(1), "rate limited, 1 request ahead");
+        assert_eq!(queued_status(3), "rate limited, 3 requests ahead");
+    }
+}
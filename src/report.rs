@@ -0,0 +1,130 @@
+//! Personal usage report over the history store (`qai report --weekly`)
+//!
+//! Queries are recorded when they are made, with their candidates and token use, and again
+//! when a suggestion is run. A report counts both kinds of record within a period: queries
+//! made, tokens spent, the tools they were about, how many suggestions were run, and tools
+//! suggested for the first time.
+
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, HashSet};
+
+use crate::history::QueryRecord;
+use crate::parse;
+
+/// How many topics a report lists
+const TOP_TOPICS: usize = 5;
+
+/// Usage within one period
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub since: DateTime<Utc>,
+    /// Queries made
+    pub queries: usize,
+    /// Suggestions run
+    pub accepted: usize,
+    /// Tokens spent on the queries
+    pub tokens: u64,
+    /// Most common tools among the suggestions, with the number of queries they came up in
+    pub topics: Vec<(String, usize)>,
+    /// Tools suggested in the period but never before it, in the order they first came up
+    pub new_tools: Vec<String>,
+}
+
+impl Report {
+    /// Summarize the records (oldest first) from `since` on; older records only tell which
+    /// tools are not new
+    pub fn from_records(records: &[QueryRecord], since: DateTime<Utc>) -> Self {
+        let (before, period): (Vec<&QueryRecord>, Vec<&QueryRecord>) =
+            records.iter().partition(|record| record.timestamp < since);
+        let known: HashSet<String> = before.iter().flat_map(|record| tools(&record.results)).collect();
+
+        let queries: Vec<&QueryRecord> = period.iter().copied().filter(|record| !record.executed).collect();
+        let mut topics: HashMap<String, usize> = HashMap::new();
+        for record in &queries {
+            for tool in tools(&record.results) {
+                *topics.entry(tool).or_default() += 1;
+            }
+        }
+        let mut topics: Vec<(String, usize)> = topics.into_iter().collect();
+        topics.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        topics.truncate(TOP_TOPICS);
+
+        let mut new_tools: Vec<String> = Vec::new();
+        for tool in period.iter().flat_map(|record| tools(&record.results)) {
+            if !known.contains(&tool) && !new_tools.contains(&tool) {
+                new_tools.push(tool);
+            }
+        }
+
+        Self {
+            since,
+            queries: queries.len(),
+            accepted: period.iter().filter(|record| record.executed).count(),
+            tokens: queries.iter().filter_map(|record| record.tokens).sum(),
+            topics,
+            new_tools,
+        }
+    }
+
+    /// Share of queries whose suggestion was run, when any query was recorded
+    pub fn acceptance_rate(&self) -> Option<f64> {
+        (self.queries > 0).then(|| (self.accepted as f64 / self.queries as f64).min(1.0))
+    }
+}
+
+/// The distinct tools a query's commands run, builtins left out
+fn tools(commands: &[String]) -> Vec<String> {
+    let mut tools: Vec<String> = Vec::new();
+    for binary in commands.iter().flat_map(|command| parse::extract_binaries(command)) {
+        if !parse::is_shell_builtin(&binary) && !tools.contains(&binary) {
+            tools.push(binary);
+        }
+    }
+    tools
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn record(days_ago: i64, results: &[&str], executed: bool, tokens: Option<u64>) -> QueryRecord {
+        let mut record = QueryRecord::new(
+            "query".to_string(),
+            results.iter().map(|s| s.to_string()).collect(),
+            "model".to_string(),
+        );
+        record.timestamp = Utc::now() - Duration::days(days_ago);
+        record.executed = executed;
+        record.tokens = tokens;
+        record
+    }
+
+    #[test]
+    fn test_report_counts_the_period() {
+        let records = vec![
+            record(30, &["git log", "tar -xzf a.tgz"], false, Some(500)),
+            record(3, &["git status", "rg TODO | wc -l"], false, Some(120)),
+            record(3, &["git status"], true, None),
+            record(1, &["git diff", "cd src && jq . a.json"], false, Some(80)),
+        ];
+        let report = Report::from_records(&records, Utc::now() - Duration::days(7));
+        assert_eq!(report.queries, 2);
+        assert_eq!(report.accepted, 1);
+        assert_eq!(report.tokens, 200);
+        assert_eq!(report.acceptance_rate(), Some(0.5));
+        assert_eq!(report.topics[0], ("git".to_string(), 2));
+        // git came up before the period, and cd is a builtin
+        assert_eq!(report.new_tools, vec!["rg", "wc", "jq"]);
+    }
+
+    #[test]
+    fn test_report_empty_period() {
+        let records = vec![record(30, &["ls"], false, Some(10))];
+        let report = Report::from_records(&records, Utc::now() - Duration::days(7));
+        assert_eq!(report.queries, 0);
+        assert_eq!(report.tokens, 0);
+        assert_eq!(report.acceptance_rate(), None);
+        assert!(report.topics.is_empty() && report.new_tools.is_empty());
+    }
+}
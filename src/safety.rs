@@ -0,0 +1,428 @@
+//! Dangerous-command detection
+//!
+//! Scans suggested commands for destructive patterns (wiping the root or home
+//! directory, writing to block devices, formatting filesystems, world-writable
+//! recursive chmods, fork bombs) so they can be flagged or dropped before they
+//! reach the shell buffer, and applies the user's `safety.deny` / `safety.confirm`
+//! regex policies and `safety.allow-sudo` setting.
+
+use eyre::{Context, Result};
+use regex::Regex;
+use serde::Serialize;
+use std::sync::LazyLock;
+
+use crate::config::{AllowSudo, Dangerous, SafetyConfig};
+use crate::parse::{split_segments, split_words};
+
+/// Why a command was considered dangerous
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Risk {
+    pub reason: &'static str,
+}
+
+/// Targets whose recursive removal is never what the user meant
+const PROTECTED_PATHS: &[&str] = &[
+    "/", "/*", "~", "~/", "~/*", "$HOME", "$HOME/", "$HOME/*", "*", ".", "..",
+];
+
+/// `sudo`/`doas` (with its options) in command position: at the start, or after a
+/// separator, subshell, or command substitution
+static SUDO_PREFIX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(^|[;&|(`\n]|\$\()(\s*)(?:sudo|doas)(?:\s+(?:-[ugChp]\s+\S+|-\S+))*\s+").unwrap());
+
+/// What may happen to a suggested command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    /// Suggest it, but ask before the widget inserts or runs it
+    Confirm(String),
+    /// Never suggest it
+    Deny(String),
+}
+
+/// How risky a suggestion is, for triage in the picker and `--json` output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RiskLevel {
+    Safe,
+    /// Privileged, deleting, overwriting, or otherwise hard-to-undo commands
+    Caution,
+    /// Matches a destructive pattern (see [`check`])
+    Dangerous,
+}
+
+impl RiskLevel {
+    /// Two-column colored marker shown in front of a suggestion in the picker
+    pub fn marker(&self) -> &'static str {
+        match self {
+            RiskLevel::Safe => "  ",
+            RiskLevel::Caution => "\x1b[33m! \x1b[0m",
+            RiskLevel::Dangerous => "\x1b[31m!!\x1b[0m",
+        }
+    }
+}
+
+/// Safety policy: built-in dangerous-command checks plus the configured deny/confirm rules
+#[derive(Debug)]
+pub struct Policy {
+    dangerous: Dangerous,
+    deny: Vec<Regex>,
+    confirm: Vec<Regex>,
+    allow_sudo: AllowSudo,
+}
+
+impl Policy {
+    /// Compile the configured rules, failing on the first invalid regex
+    pub fn from_config(config: &SafetyConfig) -> Result<Self> {
+        let compile = |patterns: &[String], list: &str| -> Result<Vec<Regex>> {
+            patterns
+                .iter()
+                .map(|pattern| Regex::new(pattern).context(format!("Invalid safety.{} pattern '{}'", list, pattern)))
+                .collect()
+        };
+        Ok(Self {
+            dangerous: config.dangerous,
+            deny: compile(&config.deny, "deny")?,
+            confirm: compile(&config.confirm, "confirm")?,
+            allow_sudo: config.allow_sudo,
+        })
+    }
+
+    /// Rewrite a command before it is checked: with `allow-sudo: never`, sudo is dropped
+    pub fn rewrite(&self, cmd: &str) -> String {
+        match self.allow_sudo {
+            AllowSudo::Never => SUDO_PREFIX.replace_all(cmd, "$1$2").into_owned(),
+            _ => cmd.to_string(),
+        }
+    }
+
+    /// Risk level of a command; confirm rules make a command at least `Caution`
+    pub fn risk_level(&self, cmd: &str) -> RiskLevel {
+        if check(cmd).is_some() {
+            RiskLevel::Dangerous
+        } else if needs_caution(cmd) || self.confirm.iter().any(|rule| rule.is_match(cmd)) {
+            RiskLevel::Caution
+        } else {
+            RiskLevel::Safe
+        }
+    }
+
+    /// Decide what to do with a command; deny rules win over everything else
+    pub fn verdict(&self, cmd: &str) -> Verdict {
+        if let Some(rule) = self.deny.iter().find(|rule| rule.is_match(cmd)) {
+            return Verdict::Deny(format!("matches deny rule '{}'", rule));
+        }
+        if self.dangerous != Dangerous::Allow
+            && let Some(risk) = check(cmd)
+        {
+            return match self.dangerous {
+                Dangerous::Filter => Verdict::Deny(risk.reason.to_string()),
+                _ => Verdict::Confirm(risk.reason.to_string()),
+            };
+        }
+        if let Some(rule) = self.confirm.iter().find(|rule| rule.is_match(cmd)) {
+            return Verdict::Confirm(format!("matches confirm rule '{}'", rule));
+        }
+        if self.allow_sudo == AllowSudo::Ask && uses_sudo(cmd) {
+            return Verdict::Confirm("runs with sudo".to_string());
+        }
+        Verdict::Allow
+    }
+}
+
+/// Check a command for destructive patterns
+pub fn check(cmd: &str) -> Option<Risk> {
+    if is_fork_bomb(cmd) {
+        return Some(Risk { reason: "fork bomb" });
+    }
+    split_segments(cmd).into_iter().find_map(check_segment)
+}
+
+fn check_segment(segment: &str) -> Option<Risk> {
+    let words = split_words(segment);
+    let mut args = words
+        .iter()
+        .map(String::as_str)
+        .skip_while(|word| word.contains('=') || ["sudo", "doas", "env", "command", "exec"].contains(word));
+    let binary = args.next()?;
+    let args: Vec<&str> = args.collect();
+    let binary = binary.rsplit('/').next().unwrap_or(binary);
+
+    match binary {
+        "rm" if has_flag(&args, 'r', "--recursive") && args.iter().any(|arg| PROTECTED_PATHS.contains(arg)) => {
+            Some(Risk {
+                reason: "recursively deletes the root, home, or current directory",
+            })
+        }
+        "dd" if args
+            .iter()
+            .any(|arg| arg.starts_with("of=/dev/") && !is_pseudo_device(&arg[3..])) =>
+        {
+            Some(Risk {
+                reason: "writes directly to a device",
+            })
+        }
+        "chmod"
+            if has_flag(&args, 'R', "--recursive")
+                && args.iter().any(|arg| ["777", "0777", "a+rwx", "ugo+rwx"].contains(arg)) =>
+        {
+            Some(Risk {
+                reason: "makes a directory tree world-writable",
+            })
+        }
+        binary if binary == "mkfs" || binary.starts_with("mkfs.") || binary == "wipefs" => Some(Risk {
+            reason: "formats or wipes a filesystem",
+        }),
+        _ if writes_to_device(&words) => Some(Risk {
+            reason: "writes directly to a device",
+        }),
+        _ => None,
+    }
+}
+
+/// Whether any simple command in `cmd` runs through sudo or doas
+fn uses_sudo(cmd: &str) -> bool {
+    split_segments(cmd).into_iter().any(|segment| {
+        split_words(segment)
+            .first()
+            .is_some_and(|word| word == "sudo" || word == "doas")
+    })
+}
+
+/// Commands that are worth a second look: privilege escalation, deletion, killing processes,
+/// history-rewriting git, piping downloads into a shell, and truncating redirections
+fn needs_caution(cmd: &str) -> bool {
+    split_segments(cmd).into_iter().any(|segment| {
+        let words = split_words(segment);
+        let Some(first) = words.first().map(String::as_str) else {
+            return false;
+        };
+        let args: Vec<&str> = words.iter().skip(1).map(String::as_str).collect();
+        let truncates = words.iter().enumerate().any(|(i, word)| {
+            let target = match word.trim_start_matches(|c: char| c.is_ascii_digit()) {
+                ">" => words.get(i + 1).map(String::as_str),
+                redirect if !redirect.starts_with(">>") => redirect.strip_prefix('>'),
+                _ => None,
+            };
+            target.is_some_and(|target| !target.is_empty() && !target.starts_with('&') && !is_pseudo_device(target))
+        });
+
+        truncates
+            || match first.rsplit('/').next().unwrap_or(first) {
+                "sudo" | "doas" | "rm" | "rmdir" | "shred" | "truncate" | "dd" | "kill" | "pkill" | "killall" => true,
+                "chmod" | "chown" | "chgrp" => has_flag(&args, 'R', "--recursive"),
+                "git" => {
+                    (args.contains(&"push")
+                        && (has_flag(&args, 'f', "--force") || args.contains(&"--force-with-lease")))
+                        || (args.contains(&"reset") && args.contains(&"--hard"))
+                        || (args.contains(&"clean") && has_flag(&args, 'f', "--force"))
+                }
+                // The receiving end of `curl ... | sh`
+                "sh" | "bash" | "zsh" => args.is_empty() || args == ["-"] || args == ["-s"],
+                _ => false,
+            }
+    })
+}
+
+/// Whether a short flag cluster (e.g. `-rf`) or the long form is present
+pub(crate) fn has_flag(args: &[&str], short: char, long: &str) -> bool {
+    args.iter()
+        .any(|arg| *arg == long || (arg.starts_with('-') && !arg.starts_with("--") && arg[1..].contains(short)))
+}
+
+/// Devices that are safe to write to
+fn is_pseudo_device(path: &str) -> bool {
+    ["/dev/null", "/dev/zero", "/dev/stdout", "/dev/stderr", "/dev/tty"].contains(&path) || path.starts_with("/dev/fd/")
+}
+
+/// A redirection like `> /dev/sda` or `>/dev/nvme0n1`
+fn writes_to_device(words: &[String]) -> bool {
+    words.iter().enumerate().any(|(i, word)| {
+        let target = match word.trim_start_matches(|c: char| c.is_ascii_digit()) {
+            ">" | ">>" => words.get(i + 1).map(String::as_str),
+            redirect => redirect.strip_prefix(">>").or_else(|| redirect.strip_prefix('>')),
+        };
+        target.is_some_and(|target| target.starts_with("/dev/") && !is_pseudo_device(target))
+    })
+}
+
+/// A function that pipes into itself in the background, e.g. `:(){ :|:& };:`
+fn is_fork_bomb(cmd: &str) -> bool {
+    let compact: String = cmd.chars().filter(|c| !c.is_whitespace()).collect();
+    compact.match_indices("(){").any(|(start, _)| {
+        let name = compact[..start].rsplit([';', '&', '|']).next().unwrap_or_default();
+        !name.is_empty() && compact[start + 3..].starts_with(&format!("{}|{}&", name, name))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reason(cmd: &str) -> Option<&'static str> {
+        check(cmd).map(|risk| risk.reason)
+    }
+
+    fn policy(dangerous: Dangerous, deny: &[&str], confirm: &[&str]) -> Policy {
+        Policy::from_config(&SafetyConfig {
+            dangerous,
+            deny: deny.iter().map(|s| s.to_string()).collect(),
+            confirm: confirm.iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_policy_deny_and_confirm_rules() {
+        let policy = policy(
+            Dangerous::Warn,
+            &[r"--context[= ]prod", r"kubeconfig-prod"],
+            &[r"^git push"],
+        );
+
+        assert_eq!(
+            policy.verdict("kubectl --context=prod delete pod api"),
+            Verdict::Deny("matches deny rule '--context[= ]prod'".to_string())
+        );
+        assert_eq!(
+            policy.verdict("git push --force"),
+            Verdict::Confirm("matches confirm rule '^git push'".to_string())
+        );
+        assert_eq!(policy.verdict("kubectl --context=staging get pods"), Verdict::Allow);
+    }
+
+    #[test]
+    fn test_policy_dangerous_modes() {
+        let reason = "recursively deletes the root, home, or current directory".to_string();
+        assert_eq!(
+            policy(Dangerous::Warn, &[], &[]).verdict("rm -rf /"),
+            Verdict::Confirm(reason.clone())
+        );
+        assert_eq!(
+            policy(Dangerous::Filter, &[], &[]).verdict("rm -rf /"),
+            Verdict::Deny(reason)
+        );
+        assert_eq!(policy(Dangerous::Allow, &[], &[]).verdict("rm -rf /"), Verdict::Allow);
+        // Deny rules apply even with the built-in checks off
+        assert!(matches!(
+            policy(Dangerous::Allow, &["^rm "], &[]).verdict("rm -rf /"),
+            Verdict::Deny(_)
+        ));
+    }
+
+    #[test]
+    fn test_policy_allow_sudo() {
+        let policy = |allow_sudo| {
+            Policy::from_config(&SafetyConfig {
+                allow_sudo,
+                ..Default::default()
+            })
+            .unwrap()
+        };
+
+        let never = policy(AllowSudo::Never);
+        assert_eq!(never.rewrite("sudo apt upgrade"), "apt upgrade");
+        assert_eq!(never.rewrite("sudo -E -u postgres psql"), "psql");
+        assert_eq!(never.rewrite("echo x | sudo tee /etc/motd"), "echo x | tee /etc/motd");
+        assert_eq!(never.rewrite("make && doas make install"), "make && make install");
+        assert_eq!(
+            never.rewrite("echo 'sudo make me a sandwich'"),
+            "echo 'sudo make me a sandwich'"
+        );
+        assert_eq!(never.rewrite("sudoedit /etc/hosts"), "sudoedit /etc/hosts");
+
+        let ask = policy(AllowSudo::Ask);
+        assert_eq!(ask.rewrite("sudo apt upgrade"), "sudo apt upgrade");
+        assert_eq!(
+            ask.verdict("ls | sudo tee out"),
+            Verdict::Confirm("runs with sudo".to_string())
+        );
+        assert_eq!(ask.verdict("echo sudo"), Verdict::Allow);
+
+        let always = policy(AllowSudo::Always);
+        assert_eq!(always.rewrite("sudo apt upgrade"), "sudo apt upgrade");
+        assert_eq!(always.verdict("sudo apt upgrade"), Verdict::Allow);
+    }
+
+    #[test]
+    fn test_risk_level() {
+        let policy = policy(Dangerous::Warn, &[], &[r"^terraform apply"]);
+        assert_eq!(policy.risk_level("ls -la"), RiskLevel::Safe);
+        assert_eq!(policy.risk_level("grep foo log 2>/dev/null"), RiskLevel::Safe);
+        assert_eq!(policy.risk_level("echo done >> build.log"), RiskLevel::Safe);
+        assert_eq!(policy.risk_level("sudo apt upgrade"), RiskLevel::Caution);
+        assert_eq!(policy.risk_level("rm -rf build"), RiskLevel::Caution);
+        assert_eq!(policy.risk_level("git push --force origin main"), RiskLevel::Caution);
+        assert_eq!(policy.risk_level("git reset --hard HEAD~1"), RiskLevel::Caution);
+        assert_eq!(policy.risk_level("curl -fsSL https://x.sh | sh"), RiskLevel::Caution);
+        assert_eq!(policy.risk_level("echo hi > notes.txt"), RiskLevel::Caution);
+        assert_eq!(policy.risk_level("terraform apply -auto-approve"), RiskLevel::Caution);
+        assert_eq!(policy.risk_level("rm -rf /"), RiskLevel::Dangerous);
+        assert!(RiskLevel::Dangerous > RiskLevel::Caution);
+    }
+
+    #[test]
+    fn test_risk_level_marker() {
+        assert_eq!(RiskLevel::Safe.marker(), "  ");
+        assert!(RiskLevel::Caution.marker().contains("\x1b[33m"));
+        assert!(RiskLevel::Dangerous.marker().contains("\x1b[31m"));
+    }
+
+    #[test]
+    fn test_policy_invalid_pattern() {
+        let config = SafetyConfig {
+            deny: vec!["(unclosed".to_string()],
+            ..Default::default()
+        };
+        let err = Policy::from_config(&config).unwrap_err();
+        assert!(format!("{:#}", err).contains("Invalid safety.deny pattern '(unclosed'"));
+    }
+
+    #[test]
+    fn test_check_rm_protected_paths() {
+        assert!(check("rm -rf /").is_some());
+        assert!(check("sudo rm -fr /*").is_some());
+        assert!(check("rm --recursive --force ~").is_some());
+        assert!(check("cd /tmp && rm -r -f $HOME").is_some());
+        assert!(check("rm -rf ./build").is_none());
+        assert!(check("rm -f /").is_none());
+        assert!(check("rm -rf /tmp/cache").is_none());
+    }
+
+    #[test]
+    fn test_check_devices() {
+        assert_eq!(
+            reason("dd if=image.iso of=/dev/sdb bs=4M"),
+            Some("writes directly to a device")
+        );
+        assert!(check("dd if=/dev/zero of=/dev/null count=1").is_none());
+        assert!(check("dd if=/dev/urandom of=test.bin bs=1M count=10").is_none());
+        assert!(check("cat image > /dev/sda").is_some());
+        assert!(check("echo hi >/dev/nvme0n1").is_some());
+        assert!(check("make 2>/dev/null").is_none());
+    }
+
+    #[test]
+    fn test_check_mkfs_and_chmod() {
+        assert!(check("sudo mkfs.ext4 /dev/sdb1").is_some());
+        assert!(check("mkfs -t xfs /dev/sdc").is_some());
+        assert!(check("chmod -R 777 /var/www").is_some());
+        assert!(check("chmod --recursive a+rwx .").is_some());
+        assert!(check("chmod 777 script.sh").is_none());
+        assert!(check("chmod -R 755 public").is_none());
+    }
+
+    #[test]
+    fn test_check_fork_bomb() {
+        assert_eq!(reason(":(){ :|:& };:"), Some("fork bomb"));
+        assert!(check("bomb() { bomb | bomb & }; bomb").is_some());
+        assert!(check("greet() { echo hi; }; greet").is_none());
+    }
+
+    #[test]
+    fn test_check_ignores_quoted_text() {
+        assert!(check("echo 'rm -rf /'").is_none());
+        assert!(check("grep -r 'mkfs' /etc").is_none());
+    }
+}
@@ -0,0 +1,272 @@
+//! Cleanup of AI responses
+//!
+//! Models wrap commands in chat formatting: markdown fences, numbered or
+//! bulleted lists, "Here is the command:" preambles, and explanations after
+//! the commands. Every response is cleaned here before it is parsed into
+//! commands, and every command before it reaches stdout (and so the buffer
+//! and history).
+
+use crate::parse::is_incomplete;
+
+/// First words of prose lines (lowercase); none of them is a command
+const PROSE_WORDS: &[&str] = &[
+    "here",
+    "here's",
+    "sure",
+    "certainly",
+    "of",
+    "okay",
+    "ok",
+    "you",
+    "to",
+    "this",
+    "that",
+    "these",
+    "the",
+    "note",
+    "explanation",
+    "alternatively",
+    "i",
+    "i'll",
+    "i'm",
+    "it",
+    "replace",
+    "output",
+    "example",
+    "usage",
+];
+
+/// Clean a whole response: drops code fences, prose preambles, and commentary after the
+/// commands, keeping section markers, comments, and multi-line commands intact
+pub fn sanitize_response(response: &str) -> String {
+    let mut kept = Vec::new();
+    let mut in_fence = false;
+    let mut in_commentary = false;
+    let mut pending: Option<String> = None;
+
+    for line in response.lines() {
+        let trimmed = line.trim();
+
+        // Lines of an unfinished multi-line command (heredoc bodies, continuations) stay verbatim
+        if let Some(mut command) = pending.take()
+            && !trimmed.starts_with("```")
+        {
+            command.push('\n');
+            command.push_str(line);
+            if is_incomplete(&command) {
+                pending = Some(command);
+            }
+            kept.push(line);
+            continue;
+        }
+
+        if trimmed.starts_with("```") {
+            in_fence = !in_fence;
+            in_commentary = false;
+            continue;
+        }
+        if is_section_marker(trimmed) || is_numbered(trimmed) {
+            in_commentary = false;
+        }
+        if !in_fence && is_prose(trimmed) {
+            // Prose after a command starts commentary, which runs until the next section
+            // marker, fence, or numbered item
+            in_commentary = kept.iter().any(|line: &&str| is_command_line(line));
+            continue;
+        }
+        if in_commentary {
+            continue;
+        }
+
+        if is_command_line(trimmed) && is_incomplete(trimmed) {
+            pending = Some(trimmed.to_string());
+        }
+        kept.push(line);
+    }
+
+    kept.join("\n")
+}
+
+/// Clean one suggested command for insertion into the shell buffer: drops list numbering,
+/// bullets, and a `$ ` prompt before it, a trailing `# comment` on single-line commands,
+/// carriage returns, ANSI escape sequences, other control characters (keeping newlines and
+/// tabs), trailing whitespace, and backticks wrapped around the whole command
+pub fn sanitize_command(cmd: &str) -> String {
+    let mut clean = String::with_capacity(cmd.len());
+    let mut chars = cmd.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            // CSI sequence: ESC [ parameters final-byte
+            '\x1b' => {
+                if chars.next_if_eq(&'[').is_some() {
+                    while chars.next().is_some_and(|c| !('@'..='~').contains(&c)) {}
+                }
+            }
+            '\n' | '\t' => clean.push(c),
+            c if c.is_control() => {}
+            c => clean.push(c),
+        }
+    }
+
+    let clean = clean.lines().map(str::trim_end).collect::<Vec<_>>().join("\n");
+    let clean = strip_list_marker(clean.trim());
+    let clean = match clean.strip_prefix('`').and_then(|c| c.strip_suffix('`')) {
+        Some(inner) if !inner.contains('`') => inner.trim(),
+        _ => clean,
+    };
+    if clean.contains('\n') {
+        clean.to_string()
+    } else {
+        strip_trailing_comment(clean).to_string()
+    }
+}
+
+fn is_section_marker(line: &str) -> bool {
+    let lower = line.to_lowercase();
+    lower.starts_with("modern:") || lower.starts_with("standard:")
+}
+
+/// `1. cmd` or `2) cmd`
+fn is_numbered(line: &str) -> bool {
+    let rest = line.trim_start_matches(|c: char| c.is_ascii_digit());
+    rest.len() < line.len() && (rest.starts_with(". ") || rest.starts_with(") "))
+}
+
+/// A line that holds a command (not blank, a comment, or a section marker)
+fn is_command_line(line: &str) -> bool {
+    let line = line.trim();
+    !line.is_empty() && !line.starts_with('#') && !is_section_marker(line)
+}
+
+/// Sentences rather than commands: starts with a typical prose word, or is a capitalized
+/// sentence of several words ending in `.` or `:`
+fn is_prose(line: &str) -> bool {
+    let line = strip_list_marker(line);
+    if line.is_empty() || line.starts_with('#') || line.starts_with('`') || is_section_marker(line) {
+        return false;
+    }
+    let first = line.split_whitespace().next().unwrap_or_default().to_lowercase();
+    if PROSE_WORDS.contains(&first.trim_end_matches([':', '!', ','])) {
+        return true;
+    }
+    line.starts_with(|c: char| c.is_uppercase()) && line.ends_with(['.', ':']) && line.split_whitespace().count() >= 3
+}
+
+/// Drop `1. `, `2) `, `- `, `* `, `• `, or a `$ ` prompt before a command
+fn strip_list_marker(line: &str) -> &str {
+    if is_numbered(line) {
+        let rest = line.trim_start_matches(|c: char| c.is_ascii_digit());
+        return rest[1..].trim_start();
+    }
+    ["- ", "* ", "• ", "$ "]
+        .iter()
+        .find_map(|marker| line.strip_prefix(marker))
+        .map(str::trim_start)
+        .unwrap_or(line)
+}
+
+/// Cut an unquoted ` # comment` off the end of a command
+fn strip_trailing_comment(cmd: &str) -> &str {
+    split_trailing_comment(cmd).0
+}
+
+/// A command and its unquoted trailing ` # comment` (starting at the `#`), if it has one
+pub fn split_trailing_comment(cmd: &str) -> (&str, Option<&str>) {
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    let mut previous = ' ';
+    for (i, c) in cmd.char_indices() {
+        if escaped {
+            escaped = false;
+            previous = c;
+            continue;
+        }
+        match (quote, c) {
+            (Some('\''), '\'') => quote = None,
+            (Some('\''), _) => {}
+            (_, '\\') => escaped = true,
+            (Some('"'), '"') => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '#') if i > 0 && previous.is_whitespace() => return (cmd[..i].trim_end(), Some(&cmd[i..])),
+            _ => {}
+        }
+        previous = c;
+    }
+    (cmd, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_response_fences_and_preamble() {
+        let response = "Here is the command:\n```bash\nls -la\n```";
+        assert_eq!(sanitize_response(response), "ls -la");
+
+        let response = "Sure! To list files, run:\n\n    du -sh *";
+        assert_eq!(sanitize_response(response).trim(), "du -sh *");
+    }
+
+    #[test]
+    fn test_sanitize_response_trailing_commentary() {
+        let response =
+            "ls -la\nThis lists all files, including hidden ones.\n- `-l` uses the long format\n- `-a` shows dotfiles";
+        assert_eq!(sanitize_response(response), "ls -la");
+
+        // Commentary ends at the next section or numbered item
+        let response = "MODERN:\n1. eza -la\nThis uses eza.\nSTANDARD:\n1. ls -la\nThe standard way.\n2. ls -al";
+        assert_eq!(
+            sanitize_response(response),
+            "MODERN:\n1. eza -la\nSTANDARD:\n1. ls -la\n2. ls -al"
+        );
+    }
+
+    #[test]
+    fn test_sanitize_response_keeps_commands_and_heredocs() {
+        let response = "MODERN:\n# none\nSTANDARD:\nfind . -name '*.rs'\ncat <<EOF > notes.txt\nThis is a note.\nEOF";
+        assert_eq!(sanitize_response(response), response);
+    }
+
+    #[test]
+    fn test_is_prose() {
+        assert!(is_prose("Here's how to do it:"));
+        assert!(is_prose("Note: this needs root"));
+        assert!(is_prose("Lists the largest files first."));
+        assert!(!is_prose("ls -la"));
+        assert!(!is_prose("find . -name '*.log' -delete"));
+        assert!(!is_prose("Modern:"));
+        assert!(!is_prose("# comment"));
+    }
+
+    #[test]
+    fn test_sanitize_command() {
+        assert_eq!(sanitize_command("ls -la\r\n"), "ls -la");
+        assert_eq!(sanitize_command("\x1b[1;32mls\x1b[0m -la"), "ls -la");
+        assert_eq!(sanitize_command("`du -sh *`"), "du -sh *");
+        assert_eq!(sanitize_command("echo `date` `id`"), "echo `date` `id`");
+        assert_eq!(sanitize_command("a\0b\x07"), "ab");
+        assert_eq!(
+            sanitize_command("cat <<EOF   \n\tline \"quoted\"\nEOF\n"),
+            "cat <<EOF\n\tline \"quoted\"\nEOF"
+        );
+    }
+
+    #[test]
+    fn test_sanitize_command_list_markers() {
+        assert_eq!(sanitize_command("1. ls -la"), "ls -la");
+        assert_eq!(sanitize_command("12) git status"), "git status");
+        assert_eq!(sanitize_command("- `du -sh *`"), "du -sh *");
+        assert_eq!(sanitize_command("$ make test"), "make test");
+        assert_eq!(sanitize_command("-la"), "-la");
+    }
+
+    #[test]
+    fn test_sanitize_command_trailing_comment() {
+        assert_eq!(sanitize_command("ls -la  # list everything"), "ls -la");
+        assert_eq!(sanitize_command("echo '# not a comment'"), "echo '# not a comment'");
+        assert_eq!(sanitize_command("echo ${#PATH} $#"), "echo ${#PATH} $#");
+        assert_eq!(sanitize_command("# only a comment"), "# only a comment");
+    }
+}
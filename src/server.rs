@@ -0,0 +1,422 @@
+//! `qai serve`: a local REST API for launchers and browser extensions
+//!
+//! Listens on 127.0.0.1 only, with JSON in and out:
+//! - `POST /query` `{"query", "multi"?, "count"?, "profile"?, "context"?}` returns
+//!   `{"suggestions": [{"command", "risk", "explanation"?, "slow"?}]}` (an explanation with `query.teach`), through the same
+//!   prompt, tool, and safety checks as `qai query`
+//! - `POST /explain` `{"command"}` returns `{"command", "risk", "explanation"}`
+//! - `GET /history?limit=N` returns `{"queries": [...]}`, the most recent first
+//!
+//! Errors are `{"error"}` with a 4xx/5xx status. A POST must be `application/json`, which
+//! a web page can't send cross-origin without a CORS preflight (answered without CORS
+//! headers), and the Host must be local, so a DNS-rebound page can't read the answers.
+
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use eyre::{Context, Result};
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::{Bytes, Incoming};
+use hyper::header::{CONTENT_TYPE, HOST, HeaderMap, HeaderValue};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode, Uri};
+use hyper_util::rt::TokioIo;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use tokio::net::TcpListener;
+
+use qai::api::OpenAIClient;
+use qai::config::Config;
+use qai::history::HistoryStore;
+use qai::prompt::{PromptContext, load_explain_prompt, render_prompt, resolve_profile};
+use qai::safety::Policy;
+
+use crate::{prepare_query, scrub_secrets, select_profile, settled_query, suggest};
+
+/// Largest request body accepted
+const MAX_BODY_BYTES: usize = 64 * 1024;
+
+/// Queries returned by `GET /history` without a `limit`
+const DEFAULT_HISTORY_LIMIT: usize = 20;
+
+#[derive(Debug, Deserialize)]
+struct QueryRequest {
+    query: String,
+    /// Defaults to `query.multi` from the config
+    multi: Option<bool>,
+    /// Defaults to `query.count` from the config
+    count: Option<usize>,
+    /// Config or prompt profile, like `qai query --profile`
+    profile: Option<String>,
+    /// Input the query refers to, like `qai query --context`
+    context: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExplainRequest {
+    command: String,
+}
+
+/// A status and JSON body to send back
+type Reply = (StatusCode, Value);
+
+fn error(status: StatusCode, message: impl std::fmt::Display) -> Reply {
+    (status, json!({ "error": message.to_string() }))
+}
+
+/// Serve the API on 127.0.0.1:`port` until the process is stopped
+pub async fn serve(config: Config, port: u16) -> Result<()> {
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    let listener = TcpListener::bind(addr)
+        .await
+        .context(format!("Failed to listen on {}", addr))?;
+    eprintln!("qai serve listening on http://{}", listener.local_addr()?);
+    run(listener, Server::new(config)).await
+}
+
+async fn run(listener: TcpListener, server: Server) -> Result<()> {
+    let server = Arc::new(server);
+    loop {
+        let (stream, _) = listener.accept().await.context("Failed to accept connection")?;
+        let server = server.clone();
+        tokio::spawn(async move {
+            let service = service_fn(move |request| {
+                let server = server.clone();
+                async move { Ok::<_, Infallible>(server.handle(request).await) }
+            });
+            if let Err(e) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                log::warn!("Server connection failed: {}", e);
+            }
+        });
+    }
+}
+
+struct Server {
+    config: Config,
+}
+
+impl Server {
+    fn new(config: Config) -> Self {
+        Self { config }
+    }
+
+    async fn handle(&self, request: Request<Incoming>) -> Response<Full<Bytes>> {
+        let (parts, body) = request.into_parts();
+        let (status, body) = match Limited::new(body, MAX_BODY_BYTES).collect().await {
+            Ok(body) => {
+                self.respond(&parts.method, &parts.uri, &parts.headers, &body.to_bytes())
+                    .await
+            }
+            Err(e) => error(StatusCode::PAYLOAD_TOO_LARGE, e),
+        };
+        log::info!("{} {} -> {}", parts.method, parts.uri, status);
+
+        let mut response = Response::new(Full::new(Bytes::from(body.to_string())));
+        *response.status_mut() = status;
+        response
+            .headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        response
+    }
+
+    async fn respond(&self, method: &Method, uri: &Uri, headers: &HeaderMap, body: &[u8]) -> Reply {
+        if !is_local_host(headers) {
+            return error(StatusCode::FORBIDDEN, "Host must be localhost or 127.0.0.1");
+        }
+        if *method == Method::POST && !is_json(headers) {
+            return error(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "Content-Type must be application/json",
+            );
+        }
+
+        match (method, uri.path()) {
+            (&Method::POST, "/query") => match serde_json::from_slice(body) {
+                Ok(request) => self.query(request).await,
+                Err(e) => error(StatusCode::BAD_REQUEST, format!("Invalid request: {}", e)),
+            },
+            (&Method::POST, "/explain") => match serde_json::from_slice(body) {
+                Ok(request) => self.explain(request).await,
+                Err(e) => error(StatusCode::BAD_REQUEST, format!("Invalid request: {}", e)),
+            },
+            (&Method::GET, "/history") => match history_limit(uri.query()) {
+                Some(limit) => history(limit),
+                None => error(StatusCode::BAD_REQUEST, "limit must be a number"),
+            },
+            (_, "/query" | "/explain" | "/history") => error(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed"),
+            (_, path) => error(StatusCode::NOT_FOUND, format!("No endpoint {}", path)),
+        }
+    }
+
+    #[tracing::instrument(
+        name = "query",
+        skip_all,
+        fields(
+            id = %uuid::Uuid::new_v4(),
+            model = tracing::field::Empty,
+            multi = tracing::field::Empty,
+            count = tracing::field::Empty,
+            latency_ms = tracing::field::Empty,
+            prompt_tokens = tracing::field::Empty,
+            completion_tokens = tracing::field::Empty,
+        )
+    )]
+    async fn query(&self, request: QueryRequest) -> Reply {
+        if request.query.trim().is_empty() {
+            return error(StatusCode::BAD_REQUEST, "query is empty");
+        }
+        let started = Instant::now();
+        let mut config = self.config.clone();
+        let profile = select_profile(&mut config, resolve_profile(request.profile.as_deref()));
+        let (multi, count) =
+            config
+                .query
+                .resolve(request.multi == Some(true), request.multi == Some(false), request.count);
+        let multi = multi && !settled_query(&request.query, request.context.as_deref(), &config);
+        let span = tracing::Span::current();
+        span.record("model", config.model.as_str());
+        span.record("multi", multi);
+        span.record("count", count);
+
+        let mut prepared = match prepare_query(
+            &request.query,
+            &config,
+            multi,
+            count,
+            profile.as_deref(),
+            request.context.as_deref(),
+        ) {
+            Ok(prepared) => prepared,
+            Err(e) => return error(StatusCode::BAD_REQUEST, format!("{:#}", e)),
+        };
+        let client = match OpenAIClient::new(&config) {
+            Ok(client) => client,
+            Err(e) => return error(StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)),
+        };
+        let suggestions = match suggest(&mut prepared, &client, &config, multi, count).await {
+            Ok(suggestions) => suggestions,
+            Err(e) => return error(StatusCode::BAD_GATEWAY, format!("{:#}", e)),
+        };
+        if let Err(e) = prepared.tool_cache.save() {
+            log::warn!("Failed to save tool cache: {}", e);
+        }
+        span.record("latency_ms", started.elapsed().as_millis() as u64);
+        log::info!("Served {} suggestion(s)", suggestions.len());
+        (StatusCode::OK, json!({ "suggestions": suggestions }))
+    }
+
+    async fn explain(&self, request: ExplainRequest) -> Reply {
+        let command = request.command.trim();
+        if command.is_empty() {
+            return error(StatusCode::BAD_REQUEST, "command is empty");
+        }
+        let policy = match Policy::from_config(&self.config.safety) {
+            Ok(policy) => policy,
+            Err(e) => return error(StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)),
+        };
+        let system_prompt = match load_explain_prompt() {
+            Ok(template) => render_prompt(&template, &PromptContext::default()),
+            Err(e) => return error(StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)),
+        };
+        let (scrubbed, system_prompt) = match scrub_secrets(command, &system_prompt, self.config.safety.secrets) {
+            Ok(scrubbed) => scrubbed,
+            Err(e) => return error(StatusCode::BAD_REQUEST, format!("{:#}", e)),
+        };
+
+        let explanation = match OpenAIClient::new(&self.config) {
+            Ok(client) => client.query(&system_prompt, &scrubbed).await,
+            Err(e) => return error(StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)),
+        };
+        match explanation {
+            Ok(explanation) => (
+                StatusCode::OK,
+                json!({
+                    "command": command,
+                    "risk": policy.risk_level(command),
+                    "explanation": explanation,
+                }),
+            ),
+            Err(e) => error(StatusCode::BAD_GATEWAY, format!("{:#}", e)),
+        }
+    }
+}
+
+fn history(limit: usize) -> Reply {
+    match HistoryStore::new().and_then(|store| store.get_recent_queries(limit)) {
+        Ok(queries) => (StatusCode::OK, json!({ "queries": queries })),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)),
+    }
+}
+
+/// The `limit` query parameter, or the default; None when it isn't a number
+fn history_limit(query: Option<&str>) -> Option<usize> {
+    let limit = query
+        .unwrap_or_default()
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("limit="));
+    match limit {
+        Some(limit) => limit.parse().ok(),
+        None => Some(DEFAULT_HISTORY_LIMIT),
+    }
+}
+
+/// Whether the Host header names this machine's loopback interface
+fn is_local_host(headers: &HeaderMap) -> bool {
+    let Some(host) = headers.get(HOST).and_then(|host| host.to_str().ok()) else {
+        return false;
+    };
+    let name = match host.strip_prefix('[') {
+        // [::1]:port
+        Some(rest) => rest.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    };
+    matches!(name.to_ascii_lowercase().as_str(), "localhost" | "127.0.0.1" | "::1")
+}
+
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn headers(host: &str, content_type: Option<&str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(HOST, HeaderValue::from_str(host).unwrap());
+        if let Some(content_type) = content_type {
+            headers.insert(CONTENT_TYPE, HeaderValue::from_str(content_type).unwrap());
+        }
+        headers
+    }
+
+    fn server(api_base: &str) -> Server {
+        let config: Config = serde_yaml::from_str(&format!(
+            "api-key: test-key\napi-base: {}\nmodel: gpt-4o-mini",
+            api_base
+        ))
+        .unwrap();
+        Server::new(config)
+    }
+
+    async fn mock_answer(content: &str) -> MockServer {
+        let mock_server = MockServer::start().await;
+        let body = json!({ "choices": [{ "message": { "content": content } }] });
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(body))
+            .mount(&mock_server)
+            .await;
+        mock_server
+    }
+
+    #[test]
+    fn test_is_local_host() {
+        for host in [
+            "localhost",
+            "localhost:8711",
+            "127.0.0.1:8711",
+            "[::1]:8711",
+            "LOCALHOST",
+        ] {
+            assert!(is_local_host(&headers(host, None)), "{}", host);
+        }
+        for host in ["evil.example.com", "evil.example.com:8711", "127.0.0.1.nip.io"] {
+            assert!(!is_local_host(&headers(host, None)), "{}", host);
+        }
+        assert!(!is_local_host(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn test_history_limit() {
+        assert_eq!(history_limit(None), Some(DEFAULT_HISTORY_LIMIT));
+        assert_eq!(history_limit(Some("limit=5")), Some(5));
+        assert_eq!(history_limit(Some("x=1&limit=3")), Some(3));
+        assert_eq!(history_limit(Some("limit=lots")), None);
+    }
+
+    #[tokio::test]
+    async fn test_respond_rejects_bad_requests() {
+        let server = server("http://127.0.0.1:9");
+        let json = headers("localhost:8711", Some("application/json"));
+        let cases = [
+            (
+                Method::POST,
+                "/query",
+                headers("evil.example.com", Some("application/json")),
+                "{}",
+                403,
+            ),
+            (
+                Method::POST,
+                "/query",
+                headers("localhost", Some("text/plain")),
+                "{}",
+                415,
+            ),
+            (Method::POST, "/query", json.clone(), "not json", 400),
+            (Method::POST, "/query", json.clone(), r#"{"query": "  "}"#, 400),
+            (Method::POST, "/explain", json.clone(), r#"{"cmd": "ls"}"#, 400),
+            (Method::GET, "/query", json.clone(), "", 405),
+            (Method::GET, "/history?limit=lots", json.clone(), "", 400),
+            (Method::GET, "/nope", json, "", 404),
+        ];
+        for (method, uri, headers, body, status) in cases {
+            let (got, body) = server
+                .respond(&method, &uri.parse().unwrap(), &headers, body.as_bytes())
+                .await;
+            assert_eq!(got.as_u16(), status, "{} {}: {}", method, uri, body);
+            assert!(body["error"].is_string());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_respond_explain() {
+        let mock_server = mock_answer("Deletes the build directory.").await;
+        let server = server(&mock_server.uri());
+        let (status, body) = server
+            .respond(
+                &Method::POST,
+                &"/explain".parse().unwrap(),
+                &headers("127.0.0.1:8711", Some("application/json; charset=utf-8")),
+                br#"{"command": "rm -rf build"}"#,
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["command"], "rm -rf build");
+        assert_eq!(body["explanation"], "Deletes the build directory.");
+        assert!(body["risk"].is_string());
+    }
+
+    #[tokio::test]
+    async fn test_serve_query_over_http() {
+        let mock_server = mock_answer("echo hello").await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(run(listener, server(&mock_server.uri())));
+
+        let response = reqwest::Client::new()
+            .post(format!("http://{}/query", addr))
+            .json(&json!({ "query": "print hello", "multi": false }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(response.headers()["content-type"], "application/json");
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["suggestions"][0]["command"], "echo hello");
+        assert_eq!(body["suggestions"][0]["risk"], "safe");
+    }
+}
@@ -0,0 +1,174 @@
+//! Per-session transcripts of AI mode
+//!
+//! When `session.transcript` is enabled, every query (with its candidates) and every
+//! executed suggestion is appended to a JSON Lines file named after the shell session
+//! (`QAI_SESSION`, exported by the zsh integration), so `qai session show` can replay
+//! what happened in the current terminal.
+
+use chrono::{DateTime, Utc};
+use eyre::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// Environment variable holding the id of the current shell session
+pub const SESSION_ENV: &str = "QAI_SESSION";
+
+/// One step of a session
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum SessionEvent {
+    /// A query and the commands suggested for it
+    Query { query: String, candidates: Vec<String> },
+    /// A suggestion that was run, possibly edited first, and how it went
+    Execution {
+        query: String,
+        suggested: String,
+        command: String,
+        exit_code: i32,
+    },
+}
+
+/// A timestamped session event
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionEntry {
+    pub timestamp: DateTime<Utc>,
+    /// Working directory at the time of the event
+    pub cwd: Option<PathBuf>,
+    #[serde(flatten)]
+    pub event: SessionEvent,
+}
+
+impl SessionEntry {
+    pub fn new(event: SessionEvent) -> Self {
+        Self {
+            timestamp: Utc::now(),
+            cwd: std::env::current_dir().ok(),
+            event,
+        }
+    }
+}
+
+/// Transcript file of one shell session
+#[derive(Debug)]
+pub struct SessionLog {
+    path: PathBuf,
+}
+
+impl SessionLog {
+    /// Transcript of the current shell session, if the shell integration set one
+    pub fn current() -> Option<Self> {
+        let id = std::env::var(SESSION_ENV).ok()?;
+        Self::with_dir(&Self::default_dir(), &id)
+    }
+
+    /// Transcript of session `id` in `dir`; None for ids that aren't plain file names
+    pub fn with_dir(dir: &Path, id: &str) -> Option<Self> {
+        let valid = !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        valid.then(|| Self {
+            path: dir.join(format!("{}.jsonl", id)),
+        })
+    }
+
+    /// Get the default transcript directory
+    pub fn default_dir() -> PathBuf {
+        crate::paths::data_dir().join("sessions")
+    }
+
+    /// Append an event to the transcript
+    pub fn append(&self, event: SessionEvent) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).context("Failed to create session directory")?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .context("Failed to open session transcript")?;
+
+        let json = serde_json::to_string(&SessionEntry::new(event)).context("Failed to serialize session entry")?;
+        writeln!(file, "{}", json).context("Failed to write session transcript")?;
+        Ok(())
+    }
+
+    /// All events of the session, oldest first (skipping unreadable lines)
+    pub fn entries(&self) -> Result<Vec<SessionEntry>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let file = File::open(&self.path).context("Failed to open session transcript")?;
+        Ok(BufReader::new(file)
+            .lines()
+            .map_while(|line| line.ok())
+            .filter_map(|line| serde_json::from_str(&line).ok())
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_session_log_rejects_path_ids() {
+        let dir = TempDir::new().unwrap();
+        assert!(SessionLog::with_dir(dir.path(), "1234-1700000000").is_some());
+        assert!(SessionLog::with_dir(dir.path(), "../history").is_none());
+        assert!(SessionLog::with_dir(dir.path(), "").is_none());
+    }
+
+    #[test]
+    fn test_session_log_append_and_read() {
+        let dir = TempDir::new().unwrap();
+        let log = SessionLog::with_dir(dir.path(), "s1").unwrap();
+        assert!(log.entries().unwrap().is_empty());
+
+        log.append(SessionEvent::Query {
+            query: "list files".to_string(),
+            candidates: vec!["ls -la".to_string(), "eza -la".to_string()],
+        })
+        .unwrap();
+        log.append(SessionEvent::Execution {
+            query: "list files".to_string(),
+            suggested: "ls -la".to_string(),
+            command: "ls -la /tmp".to_string(),
+            exit_code: 0,
+        })
+        .unwrap();
+
+        let entries = log.entries().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(matches!(&entries[0].event, SessionEvent::Query { candidates, .. } if candidates.len() == 2));
+        assert!(matches!(&entries[1].event, SessionEvent::Execution { command, .. } if command == "ls -la /tmp"));
+    }
+
+    #[test]
+    fn test_session_logs_are_separate() {
+        let dir = TempDir::new().unwrap();
+        let one = SessionLog::with_dir(dir.path(), "one").unwrap();
+        let two = SessionLog::with_dir(dir.path(), "two").unwrap();
+        one.append(SessionEvent::Query {
+            query: "q".to_string(),
+            candidates: vec![],
+        })
+        .unwrap();
+
+        assert_eq!(one.entries().unwrap().len(), 1);
+        assert!(two.entries().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_session_entry_serialization() {
+        let entry = SessionEntry::new(SessionEvent::Execution {
+            query: "q".to_string(),
+            suggested: "ls".to_string(),
+            command: "ls".to_string(),
+            exit_code: 1,
+        });
+        let json = serde_json::to_string(&entry).unwrap();
+        assert!(json.contains(r#""event":"execution""#));
+        assert_eq!(serde_json::from_str::<SessionEntry>(&json).unwrap(), entry);
+    }
+}
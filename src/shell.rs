@@ -7,12 +7,57 @@
 use crate::bindings::key_name_to_sequence;
 use crate::config::Config;
 
+/// Escape text for use inside a double-quoted zsh string
+fn zsh_quote(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '"' | '\\' | '$' | '`') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted
+}
+
 /// Generate ZSH init script with configurable trigger and submit keys
 ///
-/// The trigger and submit keys are read from the config and converted to zsh bindkey sequences.
+/// The trigger, submit, and cancel keys are read from the config and converted to zsh bindkey sequences.
 pub fn generate_zsh_init_script(config: &Config) -> Result<String, String> {
     let trigger_sequence = key_name_to_sequence(&config.bindings.trigger)?;
     let submit_sequence = key_name_to_sequence(&config.bindings.submit)?;
+    let cancel_sequence = key_name_to_sequence(&config.bindings.cancel)?;
+    if cancel_sequence == trigger_sequence || cancel_sequence == submit_sequence {
+        return Err(format!(
+            "Cancel key '{}' must differ from the trigger and submit keys",
+            config.bindings.cancel
+        ));
+    }
+    let quick_accept = match &config.bindings.quick_accept {
+        Some(key) => {
+            let sequence = key_name_to_sequence(key)?;
+            if [trigger_sequence, submit_sequence, cancel_sequence].contains(&sequence) {
+                return Err(format!(
+                    "Quick-accept key '{}' must differ from the trigger, submit, and cancel keys",
+                    key
+                ));
+            }
+            quick_accept_script(key, sequence)
+        }
+        None => String::new(),
+    };
+    // Only with a post-select hook does a pick start a qai process
+    let post_select = if config.hooks.post_select.is_some() {
+        "    qai record-select --query=\"$3\" --command=\"$command\" >/dev/null 2>&1 &!\n"
+    } else {
+        ""
+    };
+    let picker_header = format!(
+        "enter: {}  {}: execute  {}: edit  {}: regenerate",
+        config.picker.on_select.as_str(),
+        config.bindings.execute,
+        config.bindings.edit,
+        config.bindings.regenerate
+    );
 
     Ok(format!(
         r#"
@@ -20,15 +65,56 @@ pub fn generate_zsh_init_script(config: &Config) -> Result<String, String> {
 # Add to your .zshrc: eval "$(qai shell-init zsh)"
 # Trigger key: {trigger_name} ({trigger_seq})
 # Submit key: {submit_name} ({submit_seq})
+# Cancel key: {cancel_name} ({cancel_seq})
+# Picker keys: enter = {on_select}, {execute_key} = execute, {edit_key} = edit
 
 # State variable: are we in AI mode?
 _qai_in_ai_mode=0
 _qai_saved_prompt=""
-_qai_ai_prompt="🤖 ai> "
+_qai_ai_prompt="{ai_prompt}"
+_qai_fetching_message="{fetching_message}"
+_qai_error_prefix="{error_prefix}"
+_qai_spinner="{spinner}"
+_qai_highlight="{highlight}"
+
+# Previous command and its exit status, passed to qai for follow-up queries ("fix that")
+_qai_last_command=""
+_qai_last_exit=""
+# Command qai inserted into the buffer, and its query; reported back if it is run next
+_qai_inserted_command=""
+_qai_inserted_query=""
+_qai_inserted_ran=0
+_qai_inserted_cwd=""
+# Id of this shell session, naming its transcript (qai session show)
+zmodload -F zsh/datetime p:EPOCHSECONDS 2>/dev/null
+export QAI_SESSION="$$-$EPOCHSECONDS"
+_qai_preexec() {{
+    _qai_last_command="$1"
+    if [[ -n "$_qai_inserted_command" ]]; then
+        _qai_inserted_ran=1
+        _qai_inserted_cwd="$PWD"
+    fi
+}}
+_qai_precmd() {{
+    _qai_last_exit=$?
+    if (( _qai_inserted_ran )); then
+        qai record-exec --query="$_qai_inserted_query" --suggested="$_qai_inserted_command" \
+            --command="$_qai_last_command" --exit-code="$_qai_last_exit" --cwd="$_qai_inserted_cwd" \
+            >/dev/null 2>&1 &!
+    fi
+    # Only the command run right after insertion counts
+    _qai_inserted_command=""
+    _qai_inserted_query=""
+    _qai_inserted_ran=0
+}}
+autoload -Uz add-zsh-hook
+add-zsh-hook preexec _qai_preexec
+add-zsh-hook precmd _qai_precmd
 
 # Store original binding for trigger key (parse the widget name from bindkey output)
 # bindkey '{trigger_seq}' outputs: "{trigger_seq}" widget-name
 # We extract the widget name using parameter expansion
+_qai_previous_trigger_widget="$_qai_original_trigger_widget"
 _qai_original_trigger_widget=""
 if (( ${{+widgets[.expand-or-complete]}} )); then
     _qai_original_trigger_widget=".expand-or-complete"
@@ -41,29 +127,296 @@ fi
 # Fallback to .expand-or-complete (built-in, not wrapped) if nothing found
 # The dot prefix bypasses plugin wrappers that may break completion
 [[ -z "$_qai_original_trigger_widget" ]] && _qai_original_trigger_widget=".expand-or-complete"
-unset _qai_trigger_binding
+# Evaluating the init script twice must not chain the handler to itself
+if [[ "$_qai_original_trigger_widget" == _qai_trigger_handler ]]; then
+    _qai_original_trigger_widget="${{_qai_previous_trigger_widget:-.expand-or-complete}}"
+fi
+unset _qai_trigger_binding _qai_previous_trigger_widget
+
+# Conflict detection: plugins (fzf-tab, zsh-vi-mode, ...) may own the trigger key when
+# qai loads, or rebind it later. qai chains to their widget outside AI mode, re-installs
+# its own binding if taken over, and warns once per widget (bindings.warn-conflicts)
+_qai_warn_conflicts={warn_conflicts}
+typeset -gA _qai_warned_widgets
+_qai_conflict_warning=""
+_qai_note_conflict() {{
+    (( _qai_warn_conflicts )) || return
+    [[ -n "${{_qai_warned_widgets[$1]}}" ]] && return
+    _qai_warned_widgets[$1]=1
+    _qai_conflict_warning="qai: $2"
+}}
+# A user-defined widget on the trigger key comes from a plugin or the user's config
+if [[ "${{widgets[$_qai_original_trigger_widget]}}" == user:* ]]; then
+    _qai_note_conflict "$_qai_original_trigger_widget" \
+        "{trigger_name} is also bound to $_qai_original_trigger_widget; it still runs when not starting AI mode"
+fi
+
+# Before each prompt, take the trigger and submit keys back if a plugin rebound them
+_qai_check_bindings() {{
+    local binding widget
+    binding="$(bindkey '{trigger_seq}' 2>/dev/null)"
+    widget="${{binding##*\" }}"
+    if [[ "$binding" == *'" '* && "$widget" != _qai_trigger_handler ]]; then
+        [[ "$widget" != undefined-key ]] && _qai_original_trigger_widget="$widget"
+        bindkey '{trigger_seq}' _qai_trigger_handler
+        _qai_note_conflict "$widget" "{trigger_name} was rebound to $widget; qai re-bound it and chains to $widget"
+    fi
+    binding="$(bindkey '{submit_seq}' 2>/dev/null)"
+    widget="${{binding##*\" }}"
+    if [[ "$binding" == *'" '* && "$widget" != _qai_submit ]]; then
+        bindkey '{submit_seq}' _qai_submit
+        _qai_note_conflict "$widget" "{submit_name} was rebound to $widget; qai re-bound it"
+    fi
+}}
+add-zsh-hook precmd _qai_check_bindings
+
+# Show a pending conflict warning when the line editor starts
+_qai_line_init() {{
+    if [[ -n "$_qai_conflict_warning" ]]; then
+        zle -M "$_qai_conflict_warning"
+        _qai_conflict_warning=""
+    fi
+}}
+
+# Highlight of the inserted command (a region_highlight entry), and the buffer it covers
+_qai_highlight_entry=""
+_qai_highlight_buffer=""
+_qai_highlight_clear() {{
+    [[ -z "$_qai_highlight_entry" ]] && return
+    region_highlight=("${{(@)region_highlight:#$_qai_highlight_entry}}")
+    _qai_highlight_entry=""
+    _qai_highlight_buffer=""
+}}
+
+# Drop the highlight as soon as the inserted command is edited
+_qai_line_pre_redraw() {{
+    [[ -n "$_qai_highlight_entry" && "$BUFFER" != "$_qai_highlight_buffer" ]] && _qai_highlight_clear
+}}
+
+# ...or run, so it doesn't stay colored in the scrollback
+_qai_line_finish() {{
+    if [[ -n "$_qai_highlight_entry" ]]; then
+        _qai_highlight_clear
+        zle -R
+    fi
+}}
 
 # Trigger key handler - dispatch based on buffer content and mode
 _qai_trigger_handler() {{
     if [[ "$BUFFER" == "ai" && $_qai_in_ai_mode -eq 0 ]]; then
         _qai_start
+    elif [[ $_qai_in_ai_mode -eq 0 ]] && _qai_inline; then
+        # "ai: <query>" ran directly from the buffer
+        return
     else
         # Normal completion/action for this key
         zle "${{_qai_original_trigger_widget:-.expand-or-complete}}"
     fi
 }}
 
+# Run the query after an "ai:" prefix (e.g. "ai: list big files") straight from the buffer,
+# without entering the AI-mode prompt first ($1 = top: insert the best result directly).
+# Fails if the buffer has no such query
+_qai_inline() {{
+    [[ "$BUFFER" == ai:* ]] || return 1
+    local query="${{BUFFER#ai:}}"
+    # Trim leading whitespace
+    query="${{query#"${{query%%[![:space:]]*}}"}}"
+    [[ -n "$query" ]] || return 1
+
+    _qai_in_ai_mode=1
+    _qai_saved_prompt="$PROMPT"
+    zle -M "$_qai_fetching_message"
+    _qai_fetch_query "$query" "$1"
+}}
+
+# Pick one of the NUL-terminated suggestions in $1 (for query $2) with fzf
+# With $3 = -, the suggestions are read from stdin instead, shown as they arrive
+# Prints the key pressed ({execute_key}, {edit_key}, or empty for enter), then the selection,
+# each NUL-terminated so multi-line commands (heredocs, continuations) stay whole
+# {regenerate_key} reloads the list with a fresh batch of suggestions for the same query
+# With picker.tmux-popup, fzf opens in a tmux popup instead of under the prompt
+_qai_tmux_popup={tmux_popup}
+_qai_pick_hints={pick_hints}
+_qai_pick() {{
+    local -a fzf_opts=(
+        --reverse --read0 --print0 --ansi --expect='{execute_key},{edit_key}' --header='{picker_header}'
+        --prompt='Select command: ' --bind='{regenerate_key}:reload(qai query --multi --annotate "$QAI_PICK_QUERY" 2>/dev/null)'
+    )
+    # Below the list: why the highlighted command may run long, if it may
+    (( _qai_pick_hints )) && fzf_opts+=(
+        --delimiter='\t' --preview='qai check-command --slow -- {{2}} 2>/dev/null' --preview-window='down:1:wrap'
+    )
+    if (( _qai_tmux_popup )) && [[ -n "$TMUX" ]]; then
+        local input output
+        input="$(mktemp)"
+        output="$(mktemp)"
+        print -rn -- "$1" > "$input"
+        tmux display-popup -E -w '{popup_width}' -h '{popup_height}' \
+            "QAI_PICK_QUERY=${{(q)2}} fzf ${{(j: :)${{(q)fzf_opts[@]}}}} < ${{(q)input}} > ${{(q)output}}"
+        cat "$output"
+        rm -f "$input" "$output"
+    elif [[ "$3" == - ]]; then
+        QAI_PICK_QUERY="$2" fzf --height=10 "${{fzf_opts[@]}}"
+    else
+        print -rn -- "$1" | QAI_PICK_QUERY="$2" fzf --height=10 "${{fzf_opts[@]}}"
+    fi
+}}
+
+# Act on what _qai_pick printed ($1) for query $2: accept the selection with the pressed key's action
+_qai_picked() {{
+    local picked key selected action='{on_select}'
+    local query="$2"
+    picked="$1"
+    key="${{picked%%$'\0'*}}"
+    selected=""
+    if [[ "$picked" == *$'\0'* ]]; then
+        selected="${{picked#*$'\0'}}"
+        selected="${{selected%%$'\0'*}}"
+        # Drop the risk marker
+        selected="${{selected#*$'\t'}}"
+    fi
+    case "$key" in
+        '{execute_key}') action=execute ;;
+        '{edit_key}') action=edit ;;
+    esac
+
+    if [[ -n "$selected" ]]; then
+        _qai_accept "$action" "$selected" "$query"
+    else
+        # User cancelled fzf
+        zle -M "Cancelled"
+    fi
+}}
+
+# Keep the install hints qai left in the status file, shown once a command is picked
+_qai_read_hints() {{
+    _qai_pending_hints=""
+    [[ -s "$_qai_status_file" ]] && _qai_pending_hints="$(<"$_qai_status_file")"
+}}
+
+# With query.stream, run query $1 with its results piped straight into the picker, which
+# fills in as qai prints them (in place of the background fetch)
+_qai_stream={stream}
+_qai_pick_stream() {{
+    local recent_history picked
+    recent_history="$(fc -ln -50 2>/dev/null)"
+    [[ -n "$_qai_status_file" ]] && : >| "$_qai_status_file"
+    picked=$(QAI_STATUS_FILE="$_qai_status_file" QAI_RECENT_HISTORY="$recent_history" QAI_LAST_COMMAND="$_qai_last_command" QAI_LAST_EXIT="$_qai_last_exit" \
+        qai query --multi --annotate --stream "$1" 2>/dev/null | _qai_pick "" "$1" -)
+    zle reset-prompt
+    _qai_read_hints
+    _qai_picked "$picked" "$1"
+}}
+
+# Open the command in $1 in $EDITOR (for multi-line editing) and print the result
+_qai_edit() {{
+    local file edited
+    file="$(mktemp "${{TMPDIR:-/tmp}}/qai-XXXXXX.sh")"
+    print -r -- "$1" > "$file"
+    ${{=EDITOR:-vi}} "$file" < /dev/tty > /dev/tty
+    edited="$(<"$file")"
+    rm -f "$file"
+    print -r -- "$edited"
+}}
+
+# Accept suggestion $2 for query $3: insert it, execute it, or edit it first ($1)
+# In teach mode a single-line command is followed by a tab and a `# explanation`, which is
+# shown below the prompt instead of inserted
+_qai_accept() {{
+    local command="$2" explanation=""
+    if [[ "$command" != *$'\n'* && "$command" == *$'\t'\#* ]]; then
+        command="${{2%$'\t'\#*}}"
+        explanation="${{2#"$command"$'\t'}}"
+    fi
+    local inserted="$command"
+    if [[ "$1" == edit ]]; then
+        zle -I
+        inserted="$(_qai_edit "$command")"
+        if [[ -z "$inserted" ]]; then
+            zle -M "Cancelled"
+            return
+        fi
+    fi
+    if ! _qai_confirm_risky "$inserted"; then
+        zle -M "Cancelled"
+        return
+    fi
+
+    _qai_in_ai_mode=0
+    PROMPT="$_qai_saved_prompt"
+    BUFFER="$inserted"
+    CURSOR=${{#BUFFER}}
+    _qai_highlight_clear
+    if [[ -n "$_qai_highlight" ]]; then
+        _qai_highlight_entry="0 ${{#BUFFER}} $_qai_highlight"
+        _qai_highlight_buffer="$BUFFER"
+        region_highlight+=("$_qai_highlight_entry")
+    fi
+    # Remember the suggestion itself, so an edit is recorded as one
+    _qai_inserted_command="$command"
+    _qai_inserted_query="$3"
+{post_select}    zle reset-prompt
+    # Install hints from the query go below the explanation
+    if [[ -n "$_qai_pending_hints" ]]; then
+        [[ -n "$explanation" ]] && explanation+=$'\n'
+        explanation+="$_qai_pending_hints"
+        _qai_pending_hints=""
+    fi
+    zle -M "$explanation"
+    [[ "$1" == execute ]] && zle .accept-line
+}}
+
+# Ask before inserting or running a command qai flags as dangerous (safety.dangerous: warn)
+_qai_confirm_risky() {{
+    local risk key
+    risk="$(qai check-command -- "$1" 2>/dev/null)"
+    [[ -z "$risk" ]] && return 0
+    zle -R "${{_qai_error_prefix:+$_qai_error_prefix }}Dangerous command ($risk). Use it anyway? [y/N]"
+    read -k 1 key
+    [[ "$key" == [yY] ]]
+}}
+
+# Original binding of the cancel key, used outside AI mode (none if undefined)
+_qai_previous_cancel_widget="$_qai_original_cancel_widget"
+_qai_original_cancel_widget=""
+_qai_cancel_binding="$(bindkey '{cancel_seq}' 2>/dev/null)"
+if [[ "$_qai_cancel_binding" == *'" '* ]]; then
+    _qai_original_cancel_widget="${{_qai_cancel_binding##*\" }}"
+fi
+[[ "$_qai_original_cancel_widget" == undefined-key ]] && _qai_original_cancel_widget=""
+[[ "$_qai_original_cancel_widget" == _qai_cancel_handler ]] && _qai_original_cancel_widget="$_qai_previous_cancel_widget"
+unset _qai_cancel_binding _qai_previous_cancel_widget
+
+# Cancel key handler - leave AI mode (and cancel a running query) without SIGINT
+_qai_cancel_handler() {{
+    if [[ $_qai_in_ai_mode -eq 1 ]]; then
+        _qai_exit
+        zle -M ""
+    elif [[ -n "$_qai_original_cancel_widget" ]]; then
+        zle "$_qai_original_cancel_widget"
+    fi
+}}
+
 # Start AI mode session
 _qai_start() {{
-    # Validate API key first (calls OpenAI /v1/models, no token usage)
-    local validation_result
-    validation_result=$(qai validate-api 2>&1)
-    local exit_code=$?
-
-    if [[ $exit_code -ne 0 ]]; then
-        zle -M "❌ $validation_result"
-        BUFFER=""
-        return 1
+    # Validate API key first (calls OpenAI /v1/models, no token usage), unless the warmup
+    # at shell start already has
+    if [[ ! -e "$_qai_warmup_file" ]]; then
+        local validation_result
+        validation_result=$(qai validate-api 2>&1)
+        local exit_code=$?
+
+        if [[ $exit_code -eq 3 ]]; then
+            # The API is unreachable, but queries fall back to the offline rules: warn and go on
+            zle -M "$validation_result"
+        elif [[ $exit_code -ne 0 ]]; then
+            zle -M "${{_qai_error_prefix:+$_qai_error_prefix }}$validation_result"
+            BUFFER=""
+            return 1
+        elif [[ -n "$_qai_warmup_file" ]]; then
+            : >| "$_qai_warmup_file"
+        fi
     fi
 
     # Enter AI mode
@@ -78,6 +431,7 @@ _qai_start() {{
 # Exit AI mode session
 _qai_exit() {{
     if [[ $_qai_in_ai_mode -eq 1 ]]; then
+        _qai_fetch_stop
         _qai_in_ai_mode=0
         PROMPT="$_qai_saved_prompt"
         BUFFER=""
@@ -87,6 +441,7 @@ _qai_exit() {{
 }}
 
 # Submit query in AI mode
+# The query runs in the background so the shell stays responsive; Ctrl+C cancels it
 _qai_submit() {{
     if [[ $_qai_in_ai_mode -eq 1 ]]; then
         local query="$BUFFER"
@@ -98,55 +453,139 @@ _qai_submit() {{
         fi
 
         # Show fetching indicator
-        zle -M "🔄 Fetching..."
-
-        local result
-        local exit_code
-
-        # Check if fzf is available
-        if command -v fzf >/dev/null 2>&1; then
-            # Get multiple results
-            result=$(qai query --multi "$query" 2>/dev/null)
-            exit_code=$?
-
-            if [[ $exit_code -eq 0 && -n "$result" ]]; then
-                # Use fzf to select
-                local selected
-                selected=$(echo "$result" | fzf --height=10 --reverse --prompt="Select command: ")
-
-                if [[ -n "$selected" ]]; then
-                    _qai_in_ai_mode=0
-                    PROMPT="$_qai_saved_prompt"
-                    BUFFER="$selected"
-                    CURSOR=${{#BUFFER}}
-                    zle reset-prompt
-                    zle -M ""
-                else
-                    # User cancelled fzf
-                    zle -M "Cancelled"
-                fi
-            else
-                zle -M "❌ No results"
-            fi
+        zle -M "$_qai_fetching_message"
+
+        _qai_fetch_query "$query"
+    elif ! _qai_inline; then
+        # Not in AI mode and no "ai:" query, normal enter (accept-line)
+        zle accept-line
+    fi
+}}
+
+# Query $1 in the background; with fzf available get multiple results to pick from
+# With $2 = top, insert the best (history-ranked) result without the picker
+_qai_fetch_query() {{
+    _qai_pending_top=0
+    if [[ "$2" == top ]]; then
+        _qai_pending_top=1
+        _qai_fetch_start "$1" --multi --null
+    elif command -v fzf >/dev/null 2>&1; then
+        # Not in a tmux popup, which can't read from the pipe
+        if (( _qai_stream )) && ! (( _qai_tmux_popup && ${{#TMUX}} )); then
+            _qai_pick_stream "$1"
         else
-            # No fzf, single result mode
-            result=$(qai query "$query" 2>/dev/null)
-            exit_code=$?
-
-            if [[ $exit_code -eq 0 && -n "$result" ]]; then
-                _qai_in_ai_mode=0
-                PROMPT="$_qai_saved_prompt"
-                BUFFER="$result"
-                CURSOR=${{#BUFFER}}
-                zle reset-prompt
-                zle -M ""
-            else
-                zle -M "❌ No results"
-            fi
+            # Each result is prefixed with its risk marker (! caution, !! dangerous) and a tab
+            _qai_fetch_start "$1" --multi --annotate
         fi
     else
-        # Not in AI mode, normal enter (accept-line)
-        zle accept-line
+        # Even when the config asks for multiple results by default
+        _qai_fetch_start "$1" --single
+    fi
+}}
+
+# Background query state: result fd, pid of the qai process, spinner tick fd
+_qai_fetch_fd=""
+_qai_fetch_pid=""
+_qai_pending_query=""
+_qai_pending_multi=0
+_qai_pending_top=0
+_qai_pending_hints=""
+_qai_spinner_fd=""
+_qai_spinner_frame=0
+_qai_spinner_frames=(${{(s::)_qai_spinner}})
+# Files shared with qai live in a directory only this user can enter (mktemp -d makes it 0700)
+_qai_run_dir="$(mktemp -d "${{XDG_RUNTIME_DIR:-${{TMPDIR:-/tmp}}}}/qai.XXXXXX" 2>/dev/null)"
+# qai writes its rate-limit queue position here while a request waits its turn, and install
+# hints for tools its answer lacked once it is done
+_qai_status_file="${{_qai_run_dir:+$_qai_run_dir/status}}"
+zmodload zsh/system 2>/dev/null
+
+# Start `qai query --multi|--single $1` in the background, handled by _qai_fetch_done
+_qai_fetch_start() {{
+    _qai_fetch_stop
+    _qai_pending_query="$1"
+    [[ "$2" == --multi ]] && _qai_pending_multi=1 || _qai_pending_multi=0
+
+    # Recent history, used by qai only if context.shell-history is enabled
+    local recent_history
+    recent_history="$(fc -ln -50 2>/dev/null)"
+    [[ -n "$_qai_status_file" ]] && : >| "$_qai_status_file"
+
+    exec {{_qai_fetch_fd}}< <(
+        # Tell the shell our pid, then become qai so Ctrl+C can kill the request
+        print -r -- $sysparams[pid]
+        QAI_STATUS_FILE="$_qai_status_file" QAI_RECENT_HISTORY="$recent_history" QAI_LAST_COMMAND="$_qai_last_command" QAI_LAST_EXIT="$_qai_last_exit" exec qai query "${{@:2}}" "$1" 2>/dev/null
+    )
+    read -r _qai_fetch_pid <&$_qai_fetch_fd
+    zle -F -w "$_qai_fetch_fd" _qai_fetch_done
+
+    # Animate a spinner in the prompt while waiting
+    if (( ${{#_qai_spinner_frames}} )); then
+        exec {{_qai_spinner_fd}}< <(while sleep 0.1; do print; done)
+        zle -F -w "$_qai_spinner_fd" _qai_spinner_tick
+    fi
+}}
+
+# Stop a running background query and its spinner
+_qai_fetch_stop() {{
+    if [[ -n "$_qai_fetch_fd" ]]; then
+        zle -F "$_qai_fetch_fd" 2>/dev/null
+        exec {{_qai_fetch_fd}}<&-
+        _qai_fetch_fd=""
+    fi
+    if [[ -n "$_qai_fetch_pid" ]]; then
+        kill -TERM "$_qai_fetch_pid" 2>/dev/null
+        _qai_fetch_pid=""
+    fi
+    if [[ -n "$_qai_spinner_fd" ]]; then
+        zle -F "$_qai_spinner_fd" 2>/dev/null
+        exec {{_qai_spinner_fd}}<&-
+        _qai_spinner_fd=""
+    fi
+}}
+
+# Advance the prompt spinner (fd handler for the tick stream), with the queue status if any
+_qai_spinner_tick() {{
+    local tick qai_status=""
+    if ! read -r -u "$1" tick; then
+        zle -F "$1" 2>/dev/null
+        exec {{_qai_spinner_fd}}<&-
+        _qai_spinner_fd=""
+        return
+    fi
+    [[ -s "$_qai_status_file" ]] && read -r qai_status < "$_qai_status_file"
+    (( _qai_spinner_frame = _qai_spinner_frame % ${{#_qai_spinner_frames}} + 1 ))
+    PROMPT="$_qai_spinner_frames[$_qai_spinner_frame] ${{qai_status:+($qai_status) }}$_qai_ai_prompt"
+    zle reset-prompt
+}}
+
+# Background query finished (fd handler): pick from the results, then accept one
+_qai_fetch_done() {{
+    local result
+    result="$(cat <&$1)"
+    # The process has exited; don't signal a recycled pid
+    _qai_fetch_pid=""
+    _qai_fetch_stop
+    PROMPT="$_qai_ai_prompt"
+    zle reset-prompt
+    _qai_read_hints
+
+    local query="$_qai_pending_query"
+    if [[ -z "$result" ]]; then
+        local message="${{_qai_error_prefix:+$_qai_error_prefix }}No results"
+        [[ -n "$_qai_pending_hints" ]] && message+=$'\n'"$_qai_pending_hints"
+        zle -M "$message"
+    elif (( _qai_pending_top )); then
+        # Quick-accept: the first result is the best ranked
+        _qai_accept insert "${{result%%$'\0'*}}" "$query"
+    elif (( _qai_pending_multi )); then
+        # Use fzf to select; the pressed key picks the action
+        local picked
+        picked=$(_qai_pick "$result" "$query")
+        _qai_picked "$picked" "$query"
+    else
+        # No fzf, single result mode
+        _qai_accept '{on_select}' "$result" "$query"
     fi
 }}
 
@@ -155,6 +594,8 @@ _qai_submit() {{
 # NOTE: Cannot modify BUFFER here - it's read-only in signal trap context
 TRAPINT() {{
     if [[ $_qai_in_ai_mode -eq 1 ]]; then
+        # Cancel a running query along with AI mode
+        _qai_fetch_stop
         _qai_in_ai_mode=0
         PROMPT="$_qai_saved_prompt"
         print ""  # newline
@@ -165,26 +606,100 @@ TRAPINT() {{
     return $((128 + $1))
 }}
 
+# Share aliases and function names with qai so suggestions can use them
+# Runs in the background (disowned) to keep shell startup fast
+_qai_import_aliases() {{
+    {{ alias; print -l ${{(k)functions:#_*}} }} | qai tools import-aliases >/dev/null 2>&1
+}}
+_qai_import_aliases &!
+
+# Validate the API key and preload prompts and the tool cache in the background, so entering
+# AI mode doesn't wait on the network; the file marks success for _qai_start
+_qai_warmup_file="${{_qai_run_dir:+$_qai_run_dir/warmup}}"
+[[ -n "$_qai_warmup_file" ]] && ( qai warmup >/dev/null 2>&1 && : >| "$_qai_warmup_file" ) &!
+_qai_warmup_cleanup() {{
+    [[ -n "$_qai_run_dir" ]] && rm -rf "$_qai_run_dir"
+}}
+add-zsh-hook zshexit _qai_warmup_cleanup
+
 # Register widgets
 zle -N _qai_trigger_handler
 zle -N _qai_start
 zle -N _qai_exit
 zle -N _qai_submit
+zle -N _qai_cancel_handler
+zle -N _qai_line_init
+autoload -Uz add-zle-hook-widget && add-zle-hook-widget line-init _qai_line_init
+zle -N _qai_line_pre_redraw
+add-zle-hook-widget line-pre-redraw _qai_line_pre_redraw
+zle -N _qai_line_finish
+add-zle-hook-widget line-finish _qai_line_finish
+zle -N _qai_fetch_done
+zle -N _qai_spinner_tick
 
 # Bind keys
 # Trigger: activates AI mode when buffer is "ai", otherwise falls through to original binding
 bindkey '{trigger_seq}' _qai_trigger_handler
 # Submit: submits query in AI mode, otherwise normal accept-line
 bindkey '{submit_seq}' _qai_submit
-# Ctrl+C is handled by TRAPINT above (signal level, not bindkey)
+# Cancel: leaves AI mode, otherwise falls through to original binding
+bindkey '{cancel_seq}' _qai_cancel_handler
+{quick_accept}# Ctrl+C is handled by TRAPINT above (signal level, not bindkey)
 "#,
         trigger_name = config.bindings.trigger,
         trigger_seq = trigger_sequence,
         submit_name = config.bindings.submit,
-        submit_seq = submit_sequence
+        submit_seq = submit_sequence,
+        cancel_name = config.bindings.cancel,
+        cancel_seq = cancel_sequence,
+        ai_prompt = zsh_quote(&config.ui.ai_prompt),
+        fetching_message = zsh_quote(&config.ui.fetching_message),
+        error_prefix = zsh_quote(&config.ui.error_prefix),
+        spinner = zsh_quote(&config.ui.spinner),
+        highlight = zsh_quote(&config.ui.highlight),
+        warn_conflicts = u8::from(config.bindings.warn_conflicts),
+        quick_accept = quick_accept,
+        post_select = post_select,
+        edit_key = config.bindings.edit,
+        execute_key = config.bindings.execute,
+        on_select = config.picker.on_select.as_str(),
+        regenerate_key = config.bindings.regenerate,
+        picker_header = picker_header,
+        tmux_popup = u8::from(config.picker.tmux_popup),
+        pick_hints = u8::from(config.picker.hints),
+        stream = u8::from(config.query.stream),
+        popup_width = config.picker.popup_width,
+        popup_height = config.picker.popup_height
     ))
 }
 
+/// Binding for the quick-accept key: in AI mode (or on an "ai:" line) it submits the query
+/// and inserts the best result without the picker; otherwise the key keeps its original widget
+fn quick_accept_script(key: &str, sequence: &str) -> String {
+    format!(
+        r#"# Quick-accept: {key} inserts the best result without the picker
+_qai_original_quick_widget="$(bindkey '{sequence}' 2>/dev/null)"
+if [[ "$_qai_original_quick_widget" == *'" '* ]]; then
+    _qai_original_quick_widget="${{_qai_original_quick_widget##*\" }}"
+else
+    _qai_original_quick_widget=""
+fi
+[[ "$_qai_original_quick_widget" == (undefined-key|_qai_quick_accept) ]] && _qai_original_quick_widget=""
+_qai_quick_accept() {{
+    if [[ $_qai_in_ai_mode -eq 1 ]]; then
+        [[ -z "$BUFFER" ]] && return
+        zle -M "$_qai_fetching_message"
+        _qai_fetch_query "$BUFFER" top
+    elif ! _qai_inline top && [[ -n "$_qai_original_quick_widget" ]]; then
+        zle "$_qai_original_quick_widget"
+    fi
+}}
+zle -N _qai_quick_accept
+bindkey '{sequence}' _qai_quick_accept
+"#
+    )
+}
+
 /// Generate shell init script for the specified shell
 ///
 /// # Arguments
@@ -253,6 +768,22 @@ mod tests {
         assert!(script.contains("_qai_start"));
     }
 
+    #[test]
+    fn test_zsh_init_script_inline_prefix() {
+        let script = generate_zsh_init_script(&default_config()).unwrap();
+
+        // "ai: <query>" runs the query from the buffer, skipping the AI-mode prompt
+        assert!(script.contains("_qai_inline()"));
+        assert!(script.contains(r#"[[ "$BUFFER" == ai:* ]] || return 1"#));
+        assert!(script.contains(r#"local query="${BUFFER#ai:}""#));
+        assert!(script.contains(r#"_qai_fetch_query "$query""#));
+
+        // Both the trigger and the submit key accept it
+        let trigger = &script[script.find("_qai_trigger_handler() {").unwrap()..];
+        assert!(trigger.contains("_qai_inline; then"));
+        assert!(script.contains("elif ! _qai_inline; then"));
+    }
+
     #[test]
     fn test_zsh_init_script_start_function() {
         let script = generate_zsh_init_script(&default_config()).unwrap();
@@ -260,8 +791,9 @@ mod tests {
         // Must have start function
         assert!(script.contains("_qai_start()"));
 
-        // Validates API key before entering mode
+        // Validates API key before entering mode, unless the warmup already has
         assert!(script.contains("qai validate-api"));
+        assert!(script.contains(r#"if [[ ! -e "$_qai_warmup_file" ]]; then"#));
 
         // Sets AI mode flag
         assert!(script.contains("_qai_in_ai_mode=1"));
@@ -297,11 +829,175 @@ mod tests {
 
         // Has fzf integration
         assert!(script.contains("command -v fzf"));
-        assert!(script.contains("qai query --multi"));
-        assert!(script.contains("| fzf"));
+        assert!(script.contains(r#"_qai_fetch_start "$1" --multi --annotate"#));
+        assert!(script.contains(r#"| QAI_PICK_QUERY="$2" fzf"#));
 
         // Has fallback for no fzf
-        assert!(script.contains("qai query \"$query\""));
+        assert!(script.contains(r#"_qai_fetch_start "$1" --single"#));
+        assert!(script.contains(r#"[[ "$2" == --multi ]] && _qai_pending_multi=1"#));
+        assert!(script.contains(r#"exec qai query "${@:2}" "$1""#));
+    }
+
+    #[test]
+    fn test_zsh_init_script_multi_line_safe_picker() {
+        let script = generate_zsh_init_script(&default_config()).unwrap();
+
+        // Suggestions travel NUL-terminated from qai through fzf and back
+        assert!(script.contains("--read0 --print0"));
+        assert!(script.contains(r#"print -rn -- "$1" | QAI_PICK_QUERY="$2" fzf"#));
+        assert!(script.contains(r#"key="${picked%%$'\0'*}""#));
+        assert!(script.contains(r#"selected="${selected%%$'\0'*}""#));
+    }
+
+    #[test]
+    fn test_zsh_init_script_picker_shows_risk() {
+        let script = generate_zsh_init_script(&default_config()).unwrap();
+
+        // Markers are colored for fzf and stripped from the selection
+        assert!(script.contains("--read0 --print0 --ansi"));
+        assert!(script.contains(r#"selected="${selected#*$'\t'}""#));
+        // Quick-accept output is inserted directly, so it stays unannotated
+        assert!(script.contains(
+            r#"_qai_pending_top=1
+        _qai_fetch_start "$1" --multi --null"#
+        ));
+    }
+
+    #[test]
+    fn test_zsh_init_script_fetches_in_background() {
+        let script = generate_zsh_init_script(&default_config()).unwrap();
+
+        // The query runs behind an fd handler instead of blocking the widget
+        assert!(script.contains("exec {_qai_fetch_fd}< <("));
+        assert!(script.contains("print -r -- $sysparams[pid]"));
+        assert!(script.contains(r#"zle -F -w "$_qai_fetch_fd" _qai_fetch_done"#));
+        assert!(script.contains("zle -N _qai_fetch_done"));
+        assert!(script.contains(r#"result="$(cat <&$1)""#));
+
+        // Spinner ticks animate the prompt
+        assert!(script.contains("exec {_qai_spinner_fd}< <(while sleep 0.1; do print; done)"));
+        assert!(script.contains(r#"zle -F -w "$_qai_spinner_fd" _qai_spinner_tick"#));
+        assert!(script.contains(r#"_qai_spinner="⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏""#));
+
+        // ...and show the rate-limit queue position qai writes to the status file
+        assert!(script.contains(r#"QAI_STATUS_FILE="$_qai_status_file" QAI_RECENT_HISTORY="$recent_history""#));
+        assert!(script.contains(r#"${qai_status:+($qai_status) }$_qai_ai_prompt"#));
+        // ...kept in a private directory rather than a predictable name in /tmp
+        assert!(
+            script.contains(
+                r#"_qai_run_dir="$(mktemp -d "${XDG_RUNTIME_DIR:-${TMPDIR:-/tmp}}/qai.XXXXXX" 2>/dev/null)""#
+            )
+        );
+        assert!(script.contains(r#"_qai_status_file="${_qai_run_dir:+$_qai_run_dir/status}""#));
+
+        // Ctrl+C and leaving AI mode cancel the request
+        assert!(script.contains(r#"kill -TERM "$_qai_fetch_pid""#));
+        let trapint = &script[script.find("TRAPINT()").unwrap()..];
+        assert!(trapint.contains("_qai_fetch_stop"));
+    }
+
+    #[test]
+    fn test_zsh_init_script_highlights_inserted_command() {
+        let script = generate_zsh_init_script(&default_config()).unwrap();
+
+        assert!(script.contains(r#"_qai_highlight="fg=cyan""#));
+        let accept = &script[script.find("_qai_accept() {").unwrap()..];
+        assert!(accept.contains(r#"_qai_highlight_entry="0 ${#BUFFER} $_qai_highlight""#));
+        assert!(accept.contains(r#"region_highlight+=("$_qai_highlight_entry")"#));
+
+        // Cleared once the buffer changes or the line is run
+        assert!(script.contains(r#""$BUFFER" != "$_qai_highlight_buffer" ]] && _qai_highlight_clear"#));
+        assert!(script.contains("add-zle-hook-widget line-pre-redraw _qai_line_pre_redraw"));
+        assert!(script.contains("add-zle-hook-widget line-finish _qai_line_finish"));
+    }
+
+    #[test]
+    fn test_zsh_init_script_strips_teach_explanations() {
+        let script = generate_zsh_init_script(&default_config()).unwrap();
+        let accept = &script[script.find("_qai_accept() {").unwrap()..];
+        assert!(accept.contains(r#"command="${2%$'\t'\#*}""#));
+        assert!(accept.contains(r#"explanation="${2#"$command"$'\t'}""#));
+        assert!(accept.contains(r#"zle -M "$explanation""#));
+    }
+
+    #[test]
+    fn test_zsh_init_script_highlight_disabled() {
+        let mut config = default_config();
+        config.ui.highlight = String::new();
+        let script = generate_zsh_init_script(&config).unwrap();
+
+        assert!(script.contains(r#"_qai_highlight="""#));
+        assert!(script.contains(r#"if [[ -n "$_qai_highlight" ]]; then"#));
+    }
+
+    #[test]
+    fn test_zsh_init_script_exports_session_id() {
+        let script = generate_zsh_init_script(&default_config()).unwrap();
+        assert!(script.contains(r#"export QAI_SESSION="$$-$EPOCHSECONDS""#));
+    }
+
+    #[test]
+    fn test_zsh_init_script_confirms_dangerous_commands() {
+        let script = generate_zsh_init_script(&default_config()).unwrap();
+
+        assert!(script.contains(r#"risk="$(qai check-command -- "$1" 2>/dev/null)""#));
+        assert!(script.contains(r#"[[ "$key" == [yY] ]]"#));
+        // Checked after editing, before the command reaches the buffer
+        let accept = &script[script.find("_qai_accept() {").unwrap()..];
+        let confirm = accept.find(r#"_qai_confirm_risky "$inserted""#).unwrap();
+        assert!(confirm > accept.find("_qai_edit").unwrap());
+        assert!(confirm < accept.find(r#"BUFFER="$inserted""#).unwrap());
+    }
+
+    #[test]
+    fn test_zsh_init_script_spinner_disabled() {
+        let mut config = default_config();
+        config.ui.spinner = String::new();
+        let script = generate_zsh_init_script(&config).unwrap();
+
+        assert!(script.contains(r#"_qai_spinner="""#));
+        assert!(script.contains("if (( ${#_qai_spinner_frames} )); then"));
+    }
+
+    #[test]
+    fn test_zsh_init_script_passes_last_command() {
+        let script = generate_zsh_init_script(&default_config()).unwrap();
+
+        assert!(script.contains("add-zsh-hook preexec _qai_preexec"));
+        assert!(script.contains("add-zsh-hook precmd _qai_precmd"));
+        assert!(script.contains("_qai_last_exit=$?"));
+        assert!(
+            script.contains(r#"QAI_LAST_COMMAND="$_qai_last_command" QAI_LAST_EXIT="$_qai_last_exit" exec qai query"#)
+        );
+    }
+
+    #[test]
+    fn test_zsh_init_script_reports_executed_suggestions() {
+        let script = generate_zsh_init_script(&default_config()).unwrap();
+
+        // Accepted suggestions are remembered with their query
+        assert!(script.contains(r#"_qai_inserted_command="$command""#));
+        assert!(script.contains(r#"_qai_inserted_query="$3""#));
+        assert!(script.contains(r#"_qai_accept "$action" "$selected" "$query""#));
+        assert!(script.contains(r#"_qai_accept 'insert' "$result" "$query""#));
+
+        // preexec notices the run; precmd reports the exit status in the background
+        assert!(script.contains(r#"if [[ -n "$_qai_inserted_command" ]]; then"#));
+        assert!(script.contains("_qai_inserted_ran=1"));
+        assert!(script.contains("qai record-exec"));
+        assert!(script.contains(r#"--exit-code="$_qai_last_exit""#));
+        // The directory it ran in, before a cd in the command itself could change it
+        assert!(script.contains(r#"_qai_inserted_cwd="$PWD""#));
+        assert!(script.contains(r#"--cwd="$_qai_inserted_cwd""#));
+        assert!(script.contains(">/dev/null 2>&1 &!"));
+    }
+
+    #[test]
+    fn test_zsh_init_script_passes_recent_history() {
+        let script = generate_zsh_init_script(&default_config()).unwrap();
+
+        assert!(script.contains(r#"recent_history="$(fc -ln -50 2>/dev/null)""#));
+        assert!(script.contains(r#"QAI_RECENT_HISTORY="$recent_history""#));
     }
 
     #[test]
@@ -324,6 +1020,83 @@ mod tests {
         assert!(script.contains("bindkey '^M' _qai_submit")); // Enter
     }
 
+    #[test]
+    fn test_zsh_init_script_cancel_key() {
+        let script = generate_zsh_init_script(&default_config()).unwrap();
+
+        // Default is Esc (^[)
+        assert!(script.contains("bindkey '^[' _qai_cancel_handler"));
+        assert!(script.contains("zle -N _qai_cancel_handler"));
+
+        // Leaves AI mode, otherwise keeps the key's original behavior
+        let handler = &script[script.find("_qai_cancel_handler() {").unwrap()..];
+        assert!(handler.contains("_qai_exit"));
+        assert!(handler.contains(r#"zle "$_qai_original_cancel_widget""#));
+    }
+
+    #[test]
+    fn test_zsh_init_script_custom_cancel_key() {
+        let mut config = default_config();
+        config.bindings.cancel = "ctrl-g".to_string();
+        let script = generate_zsh_init_script(&config).unwrap();
+
+        assert!(script.contains("bindkey '^G' _qai_cancel_handler"));
+        assert!(script.contains("Cancel key: ctrl-g (^G)"));
+    }
+
+    #[test]
+    fn test_zsh_init_script_cancel_key_conflict() {
+        let mut config = default_config();
+        config.bindings.cancel = "tab".to_string();
+        let err = generate_zsh_init_script(&config).unwrap_err();
+        assert!(err.contains("Cancel key 'tab' must differ"));
+
+        config.bindings.cancel = "not-a-key".to_string();
+        assert!(generate_zsh_init_script(&config).is_err());
+    }
+
+    #[test]
+    fn test_zsh_init_script_post_select_hook() {
+        let script = generate_zsh_init_script(&default_config()).unwrap();
+        assert!(!script.contains("qai record-select"));
+
+        let mut config = default_config();
+        config.hooks.post_select = Some("notify-send \"$QAI_COMMAND\"".to_string());
+        let script = generate_zsh_init_script(&config).unwrap();
+        assert!(script.contains(r#"qai record-select --query="$3" --command="$command" >/dev/null 2>&1 &!"#));
+    }
+
+    #[test]
+    fn test_zsh_init_script_quick_accept_unbound_by_default() {
+        let script = generate_zsh_init_script(&default_config()).unwrap();
+        assert!(!script.contains("_qai_quick_accept"));
+    }
+
+    #[test]
+    fn test_zsh_init_script_quick_accept() {
+        let mut config = default_config();
+        config.bindings.quick_accept = Some("ctrl-o".to_string());
+        let script = generate_zsh_init_script(&config).unwrap();
+
+        assert!(script.contains("bindkey '^O' _qai_quick_accept"));
+        assert!(script.contains("zle -N _qai_quick_accept"));
+        // Works in AI mode and on "ai:" lines, fetching the ranked list but skipping fzf
+        assert!(script.contains(r#"_qai_fetch_query "$BUFFER" top"#));
+        assert!(script.contains("_qai_inline top"));
+        assert!(script.contains(r#"_qai_accept insert "${result%%$'\0'*}" "$query""#));
+    }
+
+    #[test]
+    fn test_zsh_init_script_quick_accept_conflict() {
+        let mut config = default_config();
+        config.bindings.quick_accept = Some("enter".to_string());
+        let err = generate_zsh_init_script(&config).unwrap_err();
+        assert!(err.contains("Quick-accept key 'enter' must differ"));
+
+        config.bindings.quick_accept = Some("bogus".to_string());
+        assert!(generate_zsh_init_script(&config).is_err());
+    }
+
     #[test]
     fn test_zsh_init_script_custom_ctrl_space_binding() {
         let config = config_with_trigger("ctrl-space");
@@ -392,12 +1165,46 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_zsh_init_script_conflict_detection() {
+        let script = generate_zsh_init_script(&default_config()).unwrap();
+
+        // Re-evaluating the script never chains the handler to itself
+        assert!(script.contains(r#"if [[ "$_qai_original_trigger_widget" == _qai_trigger_handler ]]; then"#));
+        assert!(script.contains(r#"[[ "$_qai_original_cancel_widget" == _qai_cancel_handler ]]"#));
+
+        // Plugin widgets on the trigger key are detected at load time
+        assert!(script.contains(r#"[[ "${widgets[$_qai_original_trigger_widget]}" == user:* ]]"#));
+
+        // Later rebinds are taken back before each prompt, chaining to the new widget
+        assert!(script.contains("add-zsh-hook precmd _qai_check_bindings"));
+        assert!(script.contains(r#"_qai_original_trigger_widget="$widget""#));
+        assert!(script.contains("bindkey '^I' _qai_trigger_handler\n"));
+        assert!(script.contains("bindkey '^M' _qai_submit\n"));
+
+        // Warnings show once via zle -M
+        assert!(script.contains("_qai_warn_conflicts=1"));
+        assert!(script.contains(r#"zle -M "$_qai_conflict_warning""#));
+        assert!(script.contains("add-zle-hook-widget line-init _qai_line_init"));
+    }
+
+    #[test]
+    fn test_zsh_init_script_conflict_warnings_disabled() {
+        let mut config = default_config();
+        config.bindings.warn_conflicts = false;
+        let script = generate_zsh_init_script(&config).unwrap();
+
+        assert!(script.contains("_qai_warn_conflicts=0"));
+        assert!(script.contains("(( _qai_warn_conflicts )) || return"));
+    }
+
     #[test]
     fn test_zsh_init_script_api_validation_error_handling() {
         let script = generate_zsh_init_script(&default_config()).unwrap();
 
         // Shows error message on validation failure
-        assert!(script.contains(r#"zle -M "❌ $validation_result""#));
+        assert!(script.contains(r#"zle -M "${_qai_error_prefix:+$_qai_error_prefix }$validation_result""#));
+        assert!(script.contains(r#"_qai_error_prefix="❌""#));
 
         // Clears buffer on failure
         assert!(script.contains(r#"BUFFER="""#));
@@ -408,7 +1215,8 @@ mod tests {
         let script = generate_zsh_init_script(&default_config()).unwrap();
 
         // Shows fetching indicator when querying
-        assert!(script.contains("🔄 Fetching"));
+        assert!(script.contains(r#"_qai_fetching_message="🔄 Fetching...""#));
+        assert!(script.contains(r#"zle -M "$_qai_fetching_message""#));
     }
 
     #[test]
@@ -420,6 +1228,161 @@ mod tests {
         assert!(script.contains("--reverse"));
     }
 
+    #[test]
+    fn test_zsh_init_script_picker_inline_by_default() {
+        let script = generate_zsh_init_script(&default_config()).unwrap();
+
+        assert!(script.contains("_qai_tmux_popup=0"));
+        assert!(script.contains(r#"picked=$(_qai_pick "$result" "$query")"#));
+    }
+
+    #[test]
+    fn test_zsh_init_script_tmux_popup_picker() {
+        let mut config = default_config();
+        config.picker.tmux_popup = true;
+        config.picker.popup_height = "70%".to_string();
+        let script = generate_zsh_init_script(&config).unwrap();
+
+        assert!(script.contains("_qai_tmux_popup=1"));
+        // Only used inside tmux
+        assert!(script.contains(r#"(( _qai_tmux_popup )) && [[ -n "$TMUX" ]]"#));
+        assert!(script.contains("tmux display-popup -E -w '80%' -h '70%'"));
+    }
+
+    #[test]
+    fn test_zsh_init_script_picker_slow_hints() {
+        let script = generate_zsh_init_script(&default_config()).unwrap();
+        assert!(script.contains("_qai_pick_hints=1"));
+        assert!(script.contains(r"--delimiter='\t' --preview='qai check-command --slow -- {2} 2>/dev/null'"));
+
+        let mut config = default_config();
+        config.picker.hints = false;
+        let script = generate_zsh_init_script(&config).unwrap();
+        assert!(script.contains("_qai_pick_hints=0"));
+    }
+
+    #[test]
+    fn test_zsh_init_script_stream_picker() {
+        let script = generate_zsh_init_script(&default_config()).unwrap();
+        assert!(script.contains("_qai_stream=0"));
+
+        let mut config = default_config();
+        config.query.stream = true;
+        let script = generate_zsh_init_script(&config).unwrap();
+        assert!(script.contains("_qai_stream=1"));
+        // Results are piped straight into fzf, except in a tmux popup
+        assert!(script.contains("if (( _qai_stream )) && ! (( _qai_tmux_popup && ${#TMUX} )); then"));
+        assert!(script.contains(r#"qai query --multi --annotate --stream "$1" 2>/dev/null | _qai_pick "" "$1" -)"#));
+        assert!(script.contains(r#"QAI_PICK_QUERY="$2" fzf --height=10 "${fzf_opts[@]}""#));
+        assert!(script.contains(r#"_qai_picked "$picked" "$1""#));
+    }
+
+    #[test]
+    fn test_zsh_init_script_shows_install_hints() {
+        let script = generate_zsh_init_script(&default_config()).unwrap();
+
+        // qai leaves install hints in the status file, since its stderr is discarded
+        assert!(script.contains(r#"[[ -s "$_qai_status_file" ]] && _qai_pending_hints="$(<"$_qai_status_file")""#));
+        let fetch_done = &script[script.find("_qai_fetch_done() {").unwrap()..];
+        assert!(fetch_done.contains("_qai_read_hints"));
+        let stream = &script[script.find("_qai_pick_stream() {").unwrap()..];
+        assert!(stream.contains(r#"picked=$(QAI_STATUS_FILE="$_qai_status_file" QAI_RECENT_HISTORY"#));
+        assert!(stream.contains("_qai_read_hints"));
+
+        // ...shown below the accepted command's explanation, or with "No results"
+        let accept = &script[script.find("_qai_accept() {").unwrap()..];
+        assert!(accept.contains(r#"explanation+="$_qai_pending_hints""#));
+        assert!(fetch_done.contains(r#"message+=$'\n'"$_qai_pending_hints""#));
+    }
+
+    #[test]
+    fn test_zsh_init_script_edit_key() {
+        let script = generate_zsh_init_script(&default_config()).unwrap();
+
+        // fzf reports the edit key, which opens the selection in $EDITOR
+        assert!(script.contains("--expect='ctrl-x,ctrl-e'"));
+        assert!(script.contains("_qai_edit()"));
+        assert!(script.contains(r#"${=EDITOR:-vi} "$file" < /dev/tty > /dev/tty"#));
+        assert!(script.contains("'ctrl-e') action=edit ;;"));
+        assert!(script.contains(r#"inserted="$(_qai_edit "$command")""#));
+
+        // The edited command goes in the buffer, the original suggestion is remembered
+        assert!(script.contains(r#"BUFFER="$inserted""#));
+        assert!(script.contains(r#"_qai_inserted_command="$command""#));
+    }
+
+    #[test]
+    fn test_zsh_init_script_custom_edit_key() {
+        let mut config = default_config();
+        config.bindings.edit = "alt-e".to_string();
+        let script = generate_zsh_init_script(&config).unwrap();
+
+        assert!(script.contains("--expect='ctrl-x,alt-e'"));
+        assert!(script.contains("Picker keys: enter = insert, ctrl-x = execute, alt-e = edit"));
+    }
+
+    #[test]
+    fn test_zsh_init_script_execute_key() {
+        let script = generate_zsh_init_script(&default_config()).unwrap();
+
+        assert!(script.contains("'ctrl-x') action=execute ;;"));
+        assert!(script.contains(r#"[[ "$1" == execute ]] && zle .accept-line"#));
+        assert!(script.contains("--header='enter: insert  ctrl-x: execute  ctrl-e: edit  ctrl-r: regenerate'"));
+    }
+
+    #[test]
+    fn test_zsh_init_script_regenerate_key() {
+        let script = generate_zsh_init_script(&default_config()).unwrap();
+
+        // The query reaches fzf's reload command through the environment, never the command string
+        assert!(
+            script.contains(r#"--bind='ctrl-r:reload(qai query --multi --annotate "$QAI_PICK_QUERY" 2>/dev/null)'"#)
+        );
+        assert!(script.contains(r#"QAI_PICK_QUERY="$2" fzf --height=10 "${fzf_opts[@]}""#));
+        assert!(script.contains("QAI_PICK_QUERY=${(q)2} fzf ${(j: :)${(q)fzf_opts[@]}}"));
+
+        let mut config = default_config();
+        config.bindings.regenerate = "alt-r".to_string();
+        let script = generate_zsh_init_script(&config).unwrap();
+        assert!(script.contains("--bind='alt-r:reload("));
+    }
+
+    #[test]
+    fn test_zsh_init_script_on_select() {
+        use crate::config::OnSelect;
+
+        let mut config = default_config();
+        config.picker.on_select = OnSelect::Execute;
+        let script = generate_zsh_init_script(&config).unwrap();
+
+        // Enter in the picker and the single-result path both use on-select
+        assert!(script.contains("local picked key selected action='execute'"));
+        assert!(script.contains(r#"_qai_accept 'execute' "$result" "$query""#));
+    }
+
+    #[test]
+    fn test_zsh_init_script_custom_ui_text() {
+        let mut config = default_config();
+        config.ui.ai_prompt = "%F{cyan}ai>%f ".to_string();
+        config.ui.fetching_message = "...".to_string();
+        config.ui.error_prefix = "".to_string();
+        let script = generate_zsh_init_script(&config).unwrap();
+
+        assert!(script.contains(r#"_qai_ai_prompt="%F{cyan}ai>%f ""#));
+        assert!(script.contains(r#"_qai_fetching_message="...""#));
+        assert!(script.contains(r#"_qai_error_prefix="""#));
+        assert!(!script.contains("🤖"));
+    }
+
+    #[test]
+    fn test_zsh_quote() {
+        assert_eq!(zsh_quote("🤖 ai> "), "🤖 ai> ");
+        assert_eq!(
+            zsh_quote(r#"say "$(rm -rf ~)" `x` \"#),
+            r#"say \"\$(rm -rf ~)\" \`x\` \\"#
+        );
+    }
+
     #[test]
     fn test_generate_init_script_zsh() {
         let result = generate_init_script("zsh", &default_config());
@@ -481,6 +1444,46 @@ mod tests {
         assert!(script.contains("Trigger key: ctrl-space"));
     }
 
+    #[test]
+    fn test_zsh_init_script_imports_aliases() {
+        let script = generate_zsh_init_script(&default_config()).unwrap();
+
+        // Feeds aliases and non-private function names to qai in the background
+        assert!(script.contains("qai tools import-aliases"));
+        assert!(script.contains("${(k)functions:#_*}"));
+        assert!(script.contains("_qai_import_aliases &!"));
+    }
+
+    #[test]
+    fn test_zsh_init_script_warmup() {
+        let script = generate_zsh_init_script(&default_config()).unwrap();
+
+        // Warms up in the background at shell start, marking success for _qai_start
+        assert!(script.contains(r#"( qai warmup >/dev/null 2>&1 && : >| "$_qai_warmup_file" ) &!"#));
+        assert!(script.contains("add-zsh-hook zshexit _qai_warmup_cleanup"));
+        // The marker is only trusted from this shell's own private directory
+        assert!(script.contains(r#"_qai_warmup_file="${_qai_run_dir:+$_qai_run_dir/warmup}""#));
+        let run_dir = script.find("_qai_run_dir=\"$(mktemp -d").unwrap();
+        assert!(run_dir < script.find("_qai_warmup_file=").unwrap());
+    }
+
+    #[test]
+    fn test_zsh_init_script_enters_ai_mode_offline() {
+        let script = generate_zsh_init_script(&default_config()).unwrap();
+        let start = &script[script.find("_qai_start() {").unwrap()..];
+        let start = &start[..start.find("\n}\n").unwrap()];
+
+        // An unreachable API (validate-api exits 3) only warns, so queries reach the offline rules
+        let offline = start.find("if [[ $exit_code -eq 3 ]]; then").unwrap();
+        let refused = start.find("elif [[ $exit_code -ne 0 ]]; then").unwrap();
+        assert!(offline < refused);
+        assert!(!start[offline..refused].contains("return 1"));
+        assert!(start[refused..].contains("return 1"));
+        // ...and isn't remembered as a successful warmup
+        assert!(start[refused..].contains(r#"elif [[ -n "$_qai_warmup_file" ]]; then"#));
+        assert!(start.contains("_qai_in_ai_mode=1"));
+    }
+
     #[test]
     fn test_zsh_init_script_case_insensitive_key() {
         // Test that "TAB" works same as "tab"
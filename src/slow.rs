@@ -0,0 +1,215 @@
+//! Long-running command detection
+//!
+//! Flags suggested commands that are likely to run for a long time or never finish on their
+//! own: walking the whole filesystem or home directory (`find /`, `grep -r ~`, `du /`), scanning
+//! networks, and capturing or pinging until interrupted. Each hint names a way to bound the
+//! command, e.g. `-maxdepth`, for teach mode and the picker's preview line.
+
+use std::fmt;
+
+use crate::parse::{split_segments, split_words};
+use crate::safety::has_flag;
+
+/// Why a command may run long, and how to bound it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Slow {
+    pub reason: &'static str,
+    pub limit: &'static str,
+}
+
+impl fmt::Display for Slow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "may run long: {}; {}", self.reason, self.limit)
+    }
+}
+
+/// The root directory, however it's written
+const ROOT_PATHS: &[&str] = &["/", "/*", "/."];
+
+/// The home directory, however it's written
+const HOME_PATHS: &[&str] = &["~", "~/", "~/*", "$HOME", "$HOME/", "$HOME/*", "${HOME}"];
+
+/// Words before the command itself that don't change what it does
+const PREFIXES: &[&str] = &["sudo", "doas", "env", "command", "exec", "time", "nohup"];
+
+/// Check a command for operations that are likely slow or blocking; the first one found
+pub fn check(cmd: &str) -> Option<Slow> {
+    split_segments(cmd).into_iter().find_map(check_segment)
+}
+
+fn check_segment(segment: &str) -> Option<Slow> {
+    let words = split_words(segment);
+    let mut args = words
+        .iter()
+        .map(String::as_str)
+        .skip_while(|word| word.contains('=') || PREFIXES.contains(word));
+    let binary = args.next()?;
+    let args: Vec<&str> = args.collect();
+    let binary = binary.rsplit('/').next().unwrap_or(binary);
+    let has = |names: &[&str]| args.iter().any(|arg| names.iter().any(|name| arg.starts_with(name)));
+
+    match binary {
+        "find" => {
+            // Starting points come first, after the symlink options
+            let starts: Vec<&str> = args
+                .iter()
+                .copied()
+                .skip_while(|arg| ["-H", "-L", "-P"].contains(arg))
+                .take_while(|arg| !arg.starts_with(['-', '(', '!']))
+                .collect();
+            if has(&["-maxdepth", "-xdev", "-mount", "-prune", "-quit"]) {
+                None
+            } else if starts.iter().any(|start| ROOT_PATHS.contains(start)) {
+                Some(Slow {
+                    reason: "walks the whole filesystem",
+                    limit: "limit it with -maxdepth or -xdev, or start from a narrower directory",
+                })
+            } else if starts.iter().any(|start| HOME_PATHS.contains(start)) {
+                Some(Slow {
+                    reason: "walks the whole home directory",
+                    limit: "limit it with -maxdepth, or start from a narrower directory",
+                })
+            } else {
+                None
+            }
+        }
+        "grep" | "egrep" | "fgrep"
+            if (has_flag(&args, 'r', "--recursive") || has_flag(&args, 'R', "--dereference-recursive"))
+                && !has(&["--include", "--exclude-dir"]) =>
+        {
+            searches_wide_tree(
+                &args,
+                "skip large directories with --exclude-dir, or search a narrower one",
+            )
+        }
+        "rg" | "ag" | "ack" if !has(&["--max-depth", "--depth", "-d", "-g", "--glob"]) => searches_wide_tree(
+            &args,
+            "limit it with --max-depth or a --glob, or search a narrower directory",
+        ),
+        "du" if !has_flag(&args, 'x', "--one-file-system") && args.iter().any(|arg| ROOT_PATHS.contains(arg)) => {
+            Some(Slow {
+                reason: "sizes every file on every mounted filesystem",
+                limit: "stay on one filesystem with -x, or size a narrower directory",
+            })
+        }
+        "ls" if has_flag(&args, 'R', "--recursive") && args.iter().any(|arg| is_wide(arg)) => Some(Slow {
+            reason: "lists a huge directory tree",
+            limit: "use find with -maxdepth or tree -L 2 instead",
+        }),
+        "tree" if !has(&["-L"]) && args.iter().any(|arg| is_wide(arg)) => Some(Slow {
+            reason: "prints a huge directory tree",
+            limit: "limit the depth with -L 2",
+        }),
+        "nmap" if has(&["-p-"]) || args.iter().any(|arg| is_wide_scan(arg)) => Some(Slow {
+            reason: "scans many hosts or every port",
+            limit: "scan a smaller range, or only common ports with --top-ports 100",
+        }),
+        "masscan" | "zmap" => Some(Slow {
+            reason: "scans a network range",
+            limit: "narrow the range and cap the rate with --rate",
+        }),
+        "ping" if !has(&["-c", "--count", "-w"]) => Some(Slow {
+            reason: "pings until interrupted",
+            limit: "stop it after a few packets with -c 4",
+        }),
+        "tcpdump" | "tshark" if !has(&["-c", "-a"]) => Some(Slow {
+            reason: "captures until interrupted",
+            limit: "stop it after some packets with -c 100",
+        }),
+        _ => None,
+    }
+}
+
+/// The root or home directory
+fn is_wide(path: &str) -> bool {
+    ROOT_PATHS.contains(&path) || HOME_PATHS.contains(&path)
+}
+
+/// A recursive search of the root or home directory
+fn searches_wide_tree(args: &[&str], limit: &'static str) -> Option<Slow> {
+    args.iter().any(|arg| is_wide(arg)).then_some(Slow {
+        reason: "searches a huge directory tree",
+        limit,
+    })
+}
+
+/// An nmap target covering many hosts: a CIDR block wider than a /24, or an octet range
+fn is_wide_scan(target: &str) -> bool {
+    if target.starts_with('-') {
+        return false;
+    }
+    if let Some((address, bits)) = target.split_once('/') {
+        return address.contains('.') && bits.parse::<u8>().is_ok_and(|bits| bits < 24);
+    }
+    let octets: Vec<&str> = target.split('.').collect();
+    octets.len() == 4 && octets[..3].iter().any(|octet| octet.contains('-') || *octet == "*")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reason(cmd: &str) -> Option<&'static str> {
+        check(cmd).map(|slow| slow.reason)
+    }
+
+    #[test]
+    fn test_find_from_root_or_home() {
+        assert_eq!(reason("find / -name '*.conf'"), Some("walks the whole filesystem"));
+        assert_eq!(reason("sudo find -L / -type f"), Some("walks the whole filesystem"));
+        assert_eq!(
+            reason("find ~ -name '*.bak' -delete"),
+            Some("walks the whole home directory")
+        );
+        assert_eq!(reason("find / -maxdepth 2 -name '*.conf'"), None);
+        assert_eq!(reason("find / -xdev -size +1G"), None);
+        assert_eq!(reason("find . -name '*.rs'"), None);
+        assert_eq!(reason("find /etc -name '*.conf'"), None);
+    }
+
+    #[test]
+    fn test_recursive_search_of_huge_trees() {
+        assert_eq!(reason("grep -rn TODO /"), Some("searches a huge directory tree"));
+        assert_eq!(reason("rg password ~"), Some("searches a huge directory tree"));
+        assert_eq!(reason("grep -rn TODO ."), None);
+        assert_eq!(reason("grep TODO ~/notes.txt"), None);
+        assert_eq!(reason("rg --max-depth 2 password ~"), None);
+        assert_eq!(
+            reason("cat log | grep -r error /"),
+            Some("searches a huge directory tree")
+        );
+    }
+
+    #[test]
+    fn test_disk_and_tree_walks() {
+        assert!(check("du -sh /").is_some());
+        assert_eq!(reason("du -xsh /"), None);
+        assert_eq!(reason("du -sh ~"), None);
+        assert!(check("ls -laR ~").is_some());
+        assert!(check("tree /").is_some());
+        assert_eq!(reason("tree -L 2 /"), None);
+    }
+
+    #[test]
+    fn test_network_scans_and_endless_commands() {
+        assert_eq!(reason("nmap -sV 10.0.0.0/8"), Some("scans many hosts or every port"));
+        assert_eq!(reason("nmap 192.168.1-255.1"), Some("scans many hosts or every port"));
+        assert_eq!(reason("nmap -p- 192.168.1.10"), Some("scans many hosts or every port"));
+        assert_eq!(reason("nmap -sn 192.168.1.0/24"), None);
+        assert!(check("masscan 10.0.0.0/8 -p80").is_some());
+        assert_eq!(reason("ping example.com"), Some("pings until interrupted"));
+        assert_eq!(reason("ping -c 4 example.com"), None);
+        assert_eq!(
+            reason("sudo tcpdump -i eth0 port 53"),
+            Some("captures until interrupted")
+        );
+        assert_eq!(reason("ls -la"), None);
+    }
+
+    #[test]
+    fn test_hint_suggests_a_limit() {
+        let hint = check("find / -name foo").unwrap().to_string();
+        assert!(hint.starts_with("may run long: walks the whole filesystem;"));
+        assert!(hint.contains("-maxdepth"));
+    }
+}
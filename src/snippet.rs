@@ -0,0 +1,190 @@
+//! Saved query templates (`qai snippet add/list/run`)
+//!
+//! A snippet is a named natural-language query with `{placeholders}`, e.g.
+//! `compress {dir} into {name}.tar.gz`. Running it fills the placeholders from arguments,
+//! given in order or as `name=value`, and sends the result like any other query. Snippets
+//! are kept in `~/.config/qai/snippets.yml`, a name -> template map that can also be edited
+//! by hand.
+
+use eyre::{Context, Result, eyre};
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::LazyLock;
+
+/// A `{name}` placeholder
+static PLACEHOLDER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\{([A-Za-z_][A-Za-z0-9_-]*)\}").expect("valid placeholder regex"));
+
+/// The distinct placeholders of a template, in the order they first appear
+pub fn placeholders(template: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for capture in PLACEHOLDER.captures_iter(template) {
+        let name = capture[1].to_string();
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    names
+}
+
+/// Fill a template's placeholders from arguments: `name=value` for a placeholder by name,
+/// anything else for the next unfilled placeholder in order
+pub fn fill(template: &str, args: &[String]) -> Result<String> {
+    let names = placeholders(template);
+    let mut values: HashMap<String, String> = HashMap::new();
+    let mut positional = Vec::new();
+    for arg in args {
+        match arg.split_once('=') {
+            Some((name, value)) if names.iter().any(|n| n == name) => {
+                values.insert(name.to_string(), value.to_string());
+            }
+            _ => positional.push(arg.clone()),
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    for name in &names {
+        if !values.contains_key(name)
+            && let Some(value) = positional.next()
+        {
+            values.insert(name.clone(), value);
+        }
+    }
+    let extra: Vec<String> = positional.collect();
+    if !extra.is_empty() {
+        return Err(eyre!("Too many arguments: {}", extra.join(" ")));
+    }
+    let missing: Vec<&str> = names
+        .iter()
+        .filter(|name| !values.contains_key(*name))
+        .map(String::as_str)
+        .collect();
+    if !missing.is_empty() {
+        return Err(eyre!("Missing value for {}", missing.join(", ")));
+    }
+
+    Ok(PLACEHOLDER
+        .replace_all(template, |capture: &regex::Captures| values[&capture[1]].clone())
+        .into_owned())
+}
+
+/// The snippets file
+#[derive(Debug)]
+pub struct SnippetStore {
+    path: PathBuf,
+}
+
+impl SnippetStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Get the default snippets file path
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join(env!("CARGO_PKG_NAME")).join("snippets.yml"))
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    /// All snippets by name; none when the file doesn't exist yet
+    pub fn load(&self) -> Result<BTreeMap<String, String>> {
+        match fs::read_to_string(&self.path) {
+            Ok(content) if content.trim().is_empty() => Ok(BTreeMap::new()),
+            Ok(content) => serde_yaml::from_str(&content)
+                .context(format!("Failed to parse snippets file: {}", self.path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(e).context(format!("Failed to read snippets file: {}", self.path.display())),
+        }
+    }
+
+    /// The template of a snippet
+    pub fn get(&self, name: &str) -> Result<String> {
+        self.load()?
+            .remove(name)
+            .ok_or_else(|| eyre!("No snippet named '{}' (see `qai snippet list`)", name))
+    }
+
+    /// Save a snippet, replacing one of the same name; true when it replaced one
+    pub fn add(&self, name: &str, template: &str) -> Result<bool> {
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(eyre!("Snippet names can't be empty or contain spaces: '{}'", name));
+        }
+        if template.trim().is_empty() {
+            return Err(eyre!("The snippet's query is empty"));
+        }
+        let mut snippets = self.load()?;
+        let replaced = snippets.insert(name.to_string(), template.trim().to_string()).is_some();
+
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).context("Failed to create config directory")?;
+        }
+        let content = serde_yaml::to_string(&snippets).context("Failed to serialize snippets")?;
+        // Write then rename, so a failed write never loses the existing snippets
+        let tmp = self.path.with_extension(format!("yml.{}.tmp", std::process::id()));
+        fs::write(&tmp, content).context("Failed to write snippets file")?;
+        fs::rename(&tmp, &self.path).context("Failed to replace snippets file")?;
+        Ok(replaced)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_placeholders_in_order_once() {
+        assert_eq!(
+            placeholders("compress {dir} into {name}.tar.gz, then list {dir}"),
+            vec!["dir", "name"]
+        );
+        assert!(placeholders("print the first column with awk '{print $1}'").is_empty());
+    }
+
+    #[test]
+    fn test_fill_positional_and_named() {
+        let template = "compress {dir} into {name}.tar.gz";
+        assert_eq!(
+            fill(template, &args(&["src", "backup"])).unwrap(),
+            "compress src into backup.tar.gz"
+        );
+        assert_eq!(
+            fill(template, &args(&["name=backup", "src"])).unwrap(),
+            "compress src into backup.tar.gz"
+        );
+        // Only placeholder names are taken as names
+        assert_eq!(fill("grep for {pattern}", &args(&["a=b"])).unwrap(), "grep for a=b");
+    }
+
+    #[test]
+    fn test_fill_reports_missing_and_extra() {
+        let template = "compress {dir} into {name}.tar.gz";
+        let err = fill(template, &args(&["src"])).unwrap_err();
+        assert!(err.to_string().contains("Missing value for name"));
+        let err = fill(template, &args(&["src", "backup", "now"])).unwrap_err();
+        assert!(err.to_string().contains("Too many arguments: now"));
+    }
+
+    #[test]
+    fn test_store_add_get_and_replace() {
+        let dir = TempDir::new().unwrap();
+        let store = SnippetStore::new(dir.path().join("qai").join("snippets.yml"));
+        assert!(store.load().unwrap().is_empty());
+
+        assert!(!store.add("backup", "compress {dir} into {name}.tar.gz").unwrap());
+        assert!(store.add("backup", "  zip {dir}  ").unwrap());
+        assert!(!store.add("ports", "show what listens on port {port}").unwrap());
+        assert_eq!(store.get("backup").unwrap(), "zip {dir}");
+        assert_eq!(store.load().unwrap().len(), 2);
+        assert!(store.get("missing").is_err());
+        assert!(store.add("two words", "ls").is_err());
+    }
+}
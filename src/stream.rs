@@ -0,0 +1,139 @@
+//! Commands from a streamed answer
+//!
+//! With `query.stream`, `qai query --multi` prints each command as soon as the
+//! model has finished writing it, so fzf fills in while the rest of the answer
+//! is generated. The answer received so far is cleaned and parsed like a whole
+//! one; every command it contains is complete except possibly the last, which
+//! the next line may continue (an open quote, a heredoc, a trailing `\`).
+
+use crate::parse::is_incomplete;
+use crate::sanitize::sanitize_response;
+use crate::tools::DualCommandList;
+
+/// A complete command, and whether it came from the MODERN: section
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamedCommand {
+    pub command: String,
+    pub modern: bool,
+}
+
+/// Collects the pieces of a streamed answer and hands out each command once it is complete
+#[derive(Debug, Default)]
+pub struct CommandStream {
+    text: String,
+    /// Commands already handed out, per section
+    modern: usize,
+    standard: usize,
+}
+
+impl CommandStream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the next piece of the answer, returning the commands it completed
+    pub fn push(&mut self, piece: &str) -> Vec<StreamedCommand> {
+        self.text.push_str(piece);
+        match self.text.rfind('\n') {
+            // Only whole lines: the rest of the last one hasn't arrived yet
+            Some(end) => {
+                let lines = self.text[..=end].to_string();
+                self.take(&lines, false)
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// The answer is complete: return the commands not handed out yet
+    pub fn finish(&mut self) -> Vec<StreamedCommand> {
+        let text = std::mem::take(&mut self.text);
+        let commands = self.take(&text, true);
+        self.text = text;
+        commands
+    }
+
+    fn take(&mut self, text: &str, finished: bool) -> Vec<StreamedCommand> {
+        let dual = DualCommandList::parse(&sanitize_response(text));
+        let mut commands = Vec::new();
+        for (list, taken, modern) in [
+            (&dual.modern, &mut self.modern, true),
+            (&dual.standard, &mut self.standard, false),
+        ] {
+            let mut complete = list.len();
+            if !finished && list.last().is_some_and(|last| is_incomplete(last)) {
+                complete -= 1;
+            }
+            commands.extend(list.iter().take(complete).skip(*taken).map(|command| StreamedCommand {
+                command: command.clone(),
+                modern,
+            }));
+            *taken = (*taken).max(complete);
+        }
+        commands
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commands(streamed: Vec<StreamedCommand>) -> Vec<String> {
+        streamed.into_iter().map(|c| c.command).collect()
+    }
+
+    #[test]
+    fn test_commands_complete_at_end_of_line() {
+        let mut stream = CommandStream::new();
+        assert!(stream.push("MODERN:\nfd -e").is_empty());
+        assert_eq!(
+            stream.push(" rs\nSTAN"),
+            vec![StreamedCommand {
+                command: "fd -e rs".to_string(),
+                modern: true
+            }]
+        );
+        assert!(stream.push("DARD:\n").is_empty());
+        assert_eq!(
+            stream.push("find . -name '*.rs'\nls"),
+            vec![StreamedCommand {
+                command: "find . -name '*.rs'".to_string(),
+                modern: false
+            }]
+        );
+        // The last line has no newline
+        assert_eq!(commands(stream.finish()), vec!["ls"]);
+        assert!(stream.finish().is_empty());
+    }
+
+    #[test]
+    fn test_multi_line_command_waits_until_complete() {
+        let mut stream = CommandStream::new();
+        assert!(stream.push("cat <<EOF > notes.txt\n").is_empty());
+        assert!(stream.push("hello\n").is_empty());
+        assert_eq!(
+            commands(stream.push("EOF\n")),
+            vec!["cat <<EOF > notes.txt\nhello\nEOF"]
+        );
+
+        assert!(stream.push("echo 'open\n").is_empty());
+        assert_eq!(commands(stream.push("quote'\n")), vec!["echo 'open\nquote'"]);
+    }
+
+    #[test]
+    fn test_stream_matches_whole_answer() {
+        let answer = concat!(
+            "Here are some options:\n```\nMODERN:\n1. rg -l TODO\n",
+            "STANDARD:\n2. grep -rl TODO .\n```\nThese search recursively.\n"
+        );
+        let mut stream = CommandStream::new();
+        let mut streamed = Vec::new();
+        for piece in answer.as_bytes().chunks(5) {
+            streamed.extend(commands(stream.push(std::str::from_utf8(piece).unwrap())));
+        }
+        streamed.extend(commands(stream.finish()));
+        assert_eq!(
+            streamed,
+            DualCommandList::parse(&sanitize_response(answer)).all_commands()
+        );
+    }
+}
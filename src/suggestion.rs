@@ -0,0 +1,399 @@
+//! Suggested commands and how `qai query` prints them
+
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+use crate::safety::{Policy, RiskLevel};
+use crate::sanitize::split_trailing_comment;
+use crate::slow;
+
+/// A suggested command with its risk level, and in teach mode a one-line explanation
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Suggestion {
+    pub command: String,
+    pub risk: RiskLevel,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub explanation: Option<String>,
+    /// Why the command may run long, and how to bound it (see [`slow::check`])
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slow: Option<String>,
+}
+
+impl Suggestion {
+    pub fn new(command: String, policy: &Policy) -> Self {
+        let risk = policy.risk_level(&command);
+        let slow = slow::check(&command).map(|slow| slow.to_string());
+        Self {
+            command,
+            risk,
+            explanation: None,
+            slow,
+        }
+    }
+
+    /// Teach mode: the explanation, followed by the hint when the command may run long
+    pub fn taught(self, explanation: Option<String>) -> Self {
+        let explanation = match (explanation, &self.slow) {
+            (Some(explanation), Some(slow)) => Some(format!("{}; {}", explanation, slow)),
+            (explanation, slow) => explanation.or_else(|| slow.clone()),
+        };
+        Self { explanation, ..self }
+    }
+
+    /// The command, followed by a tab and its explanation as a `# comment` when it has one
+    fn line(&self) -> String {
+        match &self.explanation {
+            Some(explanation) => format!("{}\t# {}", self.command, explanation),
+            None => self.command.clone(),
+        }
+    }
+}
+
+/// Split a teach-mode explanation (a trailing `# comment`) off a single-line command
+pub fn split_explanation(command: &str) -> (String, Option<String>) {
+    if command.contains('\n') {
+        return (command.to_string(), None);
+    }
+    let (command, comment) = split_trailing_comment(command);
+    let explanation = comment
+        .map(|comment| comment.trim_start_matches('#').trim().to_string())
+        .filter(|explanation| !explanation.is_empty());
+    (command.to_string(), explanation)
+}
+
+/// Split the explanations off commands in place, keyed by the command they explain
+pub fn split_explanations(commands: &mut [String]) -> HashMap<String, String> {
+    let mut explanations = HashMap::new();
+    for command in commands.iter_mut() {
+        let (bare, explanation) = split_explanation(command);
+        if let Some(explanation) = explanation {
+            explanations.insert(bare.clone(), explanation);
+        }
+        *command = bare;
+    }
+    explanations
+}
+
+/// The form of a command compared to find duplicates among suggestions: runs of unquoted
+/// whitespace become one space, with none around `|`, `&`, and `;`, and a trailing `;` is
+/// dropped. Quoted text is kept as is, and multi-line commands (heredoc bodies) only lose
+/// trailing whitespace
+pub fn dedup_key(command: &str) -> String {
+    let command = command.trim();
+    if command.contains('\n') {
+        return command.lines().map(str::trim_end).collect::<Vec<_>>().join("\n");
+    }
+    let is_operator = |c: char| "|&;".contains(c);
+    let mut key = String::with_capacity(command.len());
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    let mut space = false;
+    for c in command.chars() {
+        if escaped {
+            escaped = false;
+            key.push(c);
+            continue;
+        }
+        if let Some(q) = quote {
+            if c == q {
+                quote = None;
+            } else if c == '\\' && q == '"' {
+                escaped = true;
+            }
+            key.push(c);
+            continue;
+        }
+        if c.is_whitespace() {
+            space = true;
+            continue;
+        }
+        if space && !is_operator(c) && !key.ends_with(is_operator) && !key.is_empty() {
+            key.push(' ');
+        }
+        space = false;
+        match c {
+            '\'' | '"' => quote = Some(c),
+            '\\' => escaped = true,
+            _ => {}
+        }
+        key.push(c);
+    }
+    key.trim_end_matches(';').trim_end().to_string()
+}
+
+/// Drop commands that duplicate an earlier one (by [`dedup_key`]), keeping the first as written
+pub fn dedup(commands: Vec<String>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    commands.into_iter().filter(|cmd| seen.insert(dedup_key(cmd))).collect()
+}
+
+/// Commands sharing at least this share of their words are near-duplicates
+const NEAR_DUPLICATE: f32 = 0.6;
+
+/// How alike two commands are: the share of their distinct words they have in common, with
+/// quotes ignored (1.0 for the same words, 0.0 for none in common)
+pub fn similarity(a: &str, b: &str) -> f32 {
+    let words = |command: &str| -> HashSet<String> {
+        command
+            .split_whitespace()
+            .map(|word| word.trim_matches(|c| c == '\'' || c == '"').to_string())
+            .collect()
+    };
+    let (a, b) = (words(a), words(b));
+    let all = a.union(&b).count();
+    if all == 0 {
+        return 1.0;
+    }
+    a.intersection(&b).count() as f32 / all as f32
+}
+
+/// Keep `count` commands, best first, passing over near-duplicates of commands already kept;
+/// the passed-over ones only fill up when there aren't enough others
+pub fn most_distinct(commands: Vec<String>, count: usize) -> Vec<String> {
+    let mut kept: Vec<String> = Vec::new();
+    let mut similar = Vec::new();
+    for command in commands {
+        if kept.len() >= count {
+            break;
+        }
+        if kept.iter().any(|k| similarity(k, &command) >= NEAR_DUPLICATE) {
+            similar.push(command);
+        } else {
+            kept.push(command);
+        }
+    }
+    let missing = count.saturating_sub(kept.len());
+    kept.extend(similar.into_iter().take(missing));
+    kept
+}
+
+/// Output format of `qai query`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// One command per line
+    #[default]
+    Lines,
+    /// Newline-separated, without a newline after the last command
+    Joined,
+    /// Each command NUL-terminated (multi-line safe, for fzf --read0)
+    Null,
+    /// NUL-terminated, each prefixed with its colored risk marker and a tab (for the picker)
+    Annotated,
+    /// A JSON array of {command, risk} objects
+    Json,
+}
+
+impl OutputFormat {
+    /// Pick the format from the query flags (--json wins over --annotate over --null over
+    /// --no-trailing-newline)
+    pub fn from_flags(null: bool, annotate: bool, json: bool, no_trailing_newline: bool) -> Self {
+        match (null, annotate, json, no_trailing_newline) {
+            (_, _, true, _) => OutputFormat::Json,
+            (_, true, _, _) => OutputFormat::Annotated,
+            (true, _, _, _) => OutputFormat::Null,
+            (_, _, _, true) => OutputFormat::Joined,
+            _ => OutputFormat::Lines,
+        }
+    }
+}
+
+/// Render suggestions for stdout in the given format
+pub fn render(suggestions: &[Suggestion], format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Lines => {
+            let lines: Vec<String> = suggestions.iter().map(Suggestion::line).collect();
+            format!("{}\n", lines.join("\n"))
+        }
+        OutputFormat::Joined => {
+            let lines: Vec<String> = suggestions.iter().map(Suggestion::line).collect();
+            lines.join("\n")
+        }
+        OutputFormat::Null => suggestions.iter().map(|s| format!("{}\0", s.line())).collect(),
+        OutputFormat::Annotated => suggestions
+            .iter()
+            .map(|s| format!("{}\t{}\0", s.risk.marker(), s.line()))
+            .collect(),
+        OutputFormat::Json => format!(
+            "{}\n",
+            serde_json::to_string(suggestions).unwrap_or_else(|_| "[]".to_string())
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn suggestions() -> Vec<Suggestion> {
+        vec![
+            Suggestion {
+                command: "ls -la".to_string(),
+                risk: RiskLevel::Safe,
+                explanation: None,
+                slow: None,
+            },
+            Suggestion {
+                command: "sudo rm -r build".to_string(),
+                risk: RiskLevel::Caution,
+                explanation: None,
+                slow: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_output_format_from_flags() {
+        assert_eq!(
+            OutputFormat::from_flags(false, false, false, false),
+            OutputFormat::Lines
+        );
+        assert_eq!(OutputFormat::from_flags(true, false, false, false), OutputFormat::Null);
+        assert_eq!(
+            OutputFormat::from_flags(true, true, false, false),
+            OutputFormat::Annotated
+        );
+        assert_eq!(OutputFormat::from_flags(false, false, true, false), OutputFormat::Json);
+        assert_eq!(
+            OutputFormat::from_flags(false, false, false, true),
+            OutputFormat::Joined
+        );
+    }
+
+    #[test]
+    fn test_render_lines_and_null() {
+        assert_eq!(
+            render(&suggestions(), OutputFormat::Lines),
+            "ls -la\nsudo rm -r build\n"
+        );
+        assert_eq!(render(&suggestions(), OutputFormat::Null), "ls -la\0sudo rm -r build\0");
+    }
+
+    #[test]
+    fn test_render_joined() {
+        assert_eq!(render(&suggestions(), OutputFormat::Joined), "ls -la\nsudo rm -r build");
+        assert_eq!(render(&suggestions()[..1], OutputFormat::Joined), "ls -la");
+        assert_eq!(render(&[], OutputFormat::Joined), "");
+    }
+
+    #[test]
+    fn test_render_annotated() {
+        let output = render(&suggestions(), OutputFormat::Annotated);
+        let entries: Vec<&str> = output.split_terminator('\0').collect();
+        assert_eq!(entries[0], "  \tls -la");
+        assert_eq!(entries[1], format!("{}\tsudo rm -r build", RiskLevel::Caution.marker()));
+    }
+
+    #[test]
+    fn test_split_explanation() {
+        assert_eq!(
+            split_explanation("du -sh * | sort -h  # sizes here, largest last"),
+            (
+                "du -sh * | sort -h".to_string(),
+                Some("sizes here, largest last".to_string())
+            )
+        );
+        assert_eq!(
+            split_explanation("echo '# quoted'"),
+            ("echo '# quoted'".to_string(), None)
+        );
+        assert_eq!(split_explanation("ls #"), ("ls".to_string(), None));
+        let heredoc = "cat <<EOF > a.txt\nx # y\nEOF";
+        assert_eq!(split_explanation(heredoc), (heredoc.to_string(), None));
+
+        let mut commands = vec!["ls -la # list all".to_string(), "pwd".to_string()];
+        let explanations = split_explanations(&mut commands);
+        assert_eq!(commands, vec!["ls -la", "pwd"]);
+        assert_eq!(explanations["ls -la"], "list all");
+        assert_eq!(explanations.len(), 1);
+    }
+
+    #[test]
+    fn test_dedup_key() {
+        assert_eq!(dedup_key("ls   -la"), "ls -la");
+        assert_eq!(dedup_key("ps aux|grep  node ;"), dedup_key("ps aux | grep node"));
+        assert_eq!(dedup_key("make && make install"), "make&&make install");
+        assert_eq!(dedup_key("sleep 5 &"), "sleep 5&");
+        // Quoted whitespace matters
+        assert_ne!(dedup_key("echo 'a  b'"), dedup_key("echo 'a b'"));
+        assert_eq!(dedup_key(r#"echo "x \" |  y"  |wc"#), r#"echo "x \" |  y"|wc"#);
+        assert_eq!(dedup_key("cat <<EOF\n  a  \nEOF  "), "cat <<EOF\n  a\nEOF");
+    }
+
+    #[test]
+    fn test_dedup_keeps_first_variant() {
+        let commands = vec![
+            "ls  -la".to_string(),
+            "du -sh *".to_string(),
+            "ls -la".to_string(),
+            "ls -la ;".to_string(),
+        ];
+        assert_eq!(dedup(commands), vec!["ls  -la", "du -sh *"]);
+    }
+
+    #[test]
+    fn test_similarity() {
+        assert_eq!(similarity("ls -la", "ls  -la"), 1.0);
+        assert_eq!(similarity("find . -name '*.rs'", r#"find . -name "*.rs""#), 1.0);
+        assert_eq!(similarity("grep -r foo .", "grep -rn foo ."), 0.6);
+        assert_eq!(similarity("ls -la", "du -sh *"), 0.0);
+    }
+
+    #[test]
+    fn test_most_distinct() {
+        let commands = |commands: &[&str]| commands.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        let candidates = commands(&["grep -r foo .", "grep -rn foo .", "rg foo", "ag foo", "git grep foo"]);
+        assert_eq!(
+            most_distinct(candidates.clone(), 3),
+            commands(&["grep -r foo .", "rg foo", "ag foo"])
+        );
+        // Near-duplicates fill up when the distinct ones run out
+        assert_eq!(
+            most_distinct(candidates[..3].to_vec(), 3),
+            commands(&["grep -r foo .", "rg foo", "grep -rn foo ."])
+        );
+        assert!(most_distinct(candidates, 0).is_empty());
+    }
+
+    #[test]
+    fn test_render_explained() {
+        let mut suggestions = suggestions();
+        suggestions[0] = suggestions[0].clone().taught(Some("list everything".to_string()));
+        assert_eq!(
+            render(&suggestions, OutputFormat::Lines),
+            "ls -la\t# list everything\nsudo rm -r build\n"
+        );
+        let annotated = render(&suggestions, OutputFormat::Annotated);
+        assert!(annotated.starts_with("  \tls -la\t# list everything\0"));
+        let parsed: serde_json::Value = serde_json::from_str(&render(&suggestions, OutputFormat::Json)).unwrap();
+        assert_eq!(parsed[0]["explanation"], "list everything");
+        assert!(parsed[1].get("explanation").is_none());
+    }
+
+    #[test]
+    fn test_taught_notes_slow_commands() {
+        let policy = Policy::from_config(&Default::default()).unwrap();
+        let slow = Suggestion::new("find / -name '*.conf'".to_string(), &policy);
+        assert!(slow.slow.as_deref().is_some_and(|hint| hint.contains("-maxdepth")));
+        let taught = slow.clone().taught(Some("find config files".to_string()));
+        assert_eq!(
+            taught.explanation,
+            Some(format!("find config files; {}", slow.slow.as_deref().unwrap()))
+        );
+        assert_eq!(slow.clone().taught(None).explanation, slow.slow);
+        let parsed: serde_json::Value = serde_json::from_str(&render(&[slow], OutputFormat::Json)).unwrap();
+        assert!(parsed[0]["slow"].as_str().unwrap().starts_with("may run long:"));
+
+        let quick = Suggestion::new("ls -la".to_string(), &policy).taught(None);
+        assert_eq!(quick.explanation, None);
+        assert_eq!(quick.slow, None);
+    }
+
+    #[test]
+    fn test_render_json() {
+        let output = render(&suggestions(), OutputFormat::Json);
+        let parsed: serde_json::Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(parsed[0]["command"], "ls -la");
+        assert_eq!(parsed[0]["risk"], "safe");
+        assert_eq!(parsed[1]["risk"], "caution");
+    }
+}
@@ -0,0 +1,109 @@
+//! Syntax validation of suggested commands
+//!
+//! Parses each candidate with the shell's no-exec mode (`zsh -n`, or `bash -n`
+//! without zsh) so malformed quoting never reaches the buffer, and collects
+//! shellcheck errors when shellcheck is installed.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Shell used for syntax checks: zsh (what the integration runs), else bash
+pub fn check_shell() -> Option<&'static str> {
+    ["zsh", "bash"].into_iter().find(|shell| which::which(shell).is_ok())
+}
+
+/// Parse `cmd` with `shell -n`; Err holds the shell's first error line.
+/// Commands are accepted when the shell can't be run at all
+pub fn check_syntax(shell: &str, cmd: &str) -> Result<(), String> {
+    let output = match Command::new(shell)
+        .args(["-n", "-c", cmd])
+        .stdin(Stdio::null())
+        .output()
+    {
+        Ok(output) => output,
+        Err(e) => {
+            log::debug!("Syntax check with {} failed to run: {}", shell, e);
+            return Ok(());
+        }
+    };
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let error = stderr.lines().next().unwrap_or("syntax error").trim();
+    // "zsh:1: unmatched '" / "bash: -c: line 1: unexpected EOF ..." -> the message itself
+    let error = error.rsplit(": ").next().unwrap_or(error);
+    Err(error.to_string())
+}
+
+/// shellcheck errors for `cmd` (checked as bash), or nothing when shellcheck isn't installed
+pub fn shellcheck(cmd: &str) -> Vec<String> {
+    if which::which("shellcheck").is_err() {
+        return Vec::new();
+    }
+    let child = Command::new("shellcheck")
+        .args(["--shell=bash", "--severity=error", "--format=gcc", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn();
+    let Ok(mut child) = child else {
+        return Vec::new();
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(cmd.as_bytes());
+    }
+    match child.wait_with_output() {
+        Ok(output) => parse_shellcheck_output(&String::from_utf8_lossy(&output.stdout)),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Messages from shellcheck's gcc format: `-:1:6: error: Couldn't parse ... [SC1073]`
+fn parse_shellcheck_output(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter_map(|line| {
+            line.split_once(": error: ")
+                .map(|(_, message)| message.trim().to_string())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_syntax_valid() {
+        let shell = check_shell().expect("zsh or bash must be installed to run tests");
+        assert!(check_syntax(shell, "ls -la | wc -l").is_ok());
+        assert!(check_syntax(shell, "cat <<'EOF'\nhello\nEOF").is_ok());
+        // Parsing doesn't run anything
+        assert!(check_syntax(shell, "false").is_ok());
+    }
+
+    #[test]
+    fn test_check_syntax_invalid() {
+        let shell = check_shell().unwrap();
+        assert!(check_syntax(shell, "echo 'unterminated").is_err());
+        assert!(check_syntax(shell, "if true; then echo hi").is_err());
+        assert!(check_syntax(shell, "echo $(date").is_err());
+    }
+
+    #[test]
+    fn test_check_syntax_missing_shell() {
+        assert!(check_syntax("qai-no-such-shell", "echo 'oops").is_ok());
+    }
+
+    #[test]
+    fn test_parse_shellcheck_output() {
+        let output = "-:1:6: error: Couldn't parse this single quoted string. [SC1073]\n\
+                      -:1:1: warning: Quote this to prevent word splitting. [SC2046]\n";
+        assert_eq!(
+            parse_shellcheck_output(output),
+            vec!["Couldn't parse this single quoted string. [SC1073]"]
+        );
+        assert!(parse_shellcheck_output("").is_empty());
+    }
+}
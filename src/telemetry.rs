@@ -0,0 +1,311 @@
+//! Opt-in anonymous usage telemetry
+//!
+//! Off unless turned on with `qai telemetry enable`. While on, each qai command adds to a
+//! local tally of how often it ran and how long it took; queries, commands, paths, and
+//! anything else typed are never recorded. `qai warmup` sends the tally to
+//! `telemetry.endpoint` at most once a day, when one is configured, then starts a new one.
+//! `qai telemetry schema` documents every field sent.
+
+use chrono::{DateTime, Duration, Utc};
+use eyre::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::PathBuf;
+use uuid::Uuid;
+
+use crate::lock;
+
+/// Version of the payload layout, sent along so the receiver can tell layouts apart
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The fields of a payload, as shown by `qai telemetry schema`
+pub const SCHEMA: &str = r#"schema        number   payload layout version (1)
+install-id    string   random UUID made when telemetry was enabled; disabling deletes it
+version       string   qai version
+os            string   operating system family, e.g. "linux" or "macos"
+since         string   start of the tallied period (RFC 3339)
+until         string   end of the tallied period (RFC 3339)
+commands      object   per subcommand name (e.g. "query", "history"):
+  count       number     times it ran to completion
+  mean-ms     number     mean wall-clock time in milliseconds
+
+Nothing else is recorded: no queries, suggestions, commands, paths, or config values."#;
+
+/// Runs and total time of one subcommand
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandStats {
+    pub count: u64,
+    pub total_ms: u64,
+}
+
+/// The local tally, which only exists while telemetry is enabled
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct State {
+    install_id: Uuid,
+    since: DateTime<Utc>,
+    #[serde(default)]
+    commands: BTreeMap<String, CommandStats>,
+}
+
+/// A command's entry in the payload
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CommandSummary {
+    pub count: u64,
+    pub mean_ms: u64,
+}
+
+/// What is sent (see [`SCHEMA`])
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Payload {
+    pub schema: u32,
+    pub install_id: Uuid,
+    pub version: &'static str,
+    pub os: &'static str,
+    pub since: DateTime<Utc>,
+    pub until: DateTime<Utc>,
+    pub commands: BTreeMap<String, CommandSummary>,
+}
+
+/// The telemetry state file: enabled while it exists
+#[derive(Debug)]
+pub struct Telemetry {
+    path: PathBuf,
+}
+
+impl Telemetry {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Get the default state file path
+    pub fn default_path() -> PathBuf {
+        crate::paths::data_dir().join("telemetry.json")
+    }
+
+    /// Lock the state file, so `disable` cannot be undone by a run that loaded the tally before it
+    fn lock(&self) -> Result<File> {
+        lock::acquire(&self.path.with_extension("lock"), "telemetry file")
+    }
+
+    fn load(&self) -> Option<State> {
+        fs::read_to_string(&self.path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+    }
+
+    fn save(&self, state: &State) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).context("Failed to create telemetry directory")?;
+        }
+        let content = serde_json::to_string_pretty(state).context("Failed to serialize telemetry")?;
+        // Write then rename, so concurrent shells never read a partly written file
+        let tmp = self.path.with_extension(format!("json.{}.tmp", std::process::id()));
+        fs::write(&tmp, content).context("Failed to write telemetry file")?;
+        fs::rename(&tmp, &self.path).context("Failed to replace telemetry file")
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.load().is_some()
+    }
+
+    /// Turn telemetry on with a new anonymous id and an empty tally; a no-op when already on
+    pub fn enable(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).context("Failed to create telemetry directory")?;
+        }
+        let _lock = self.lock()?;
+        if self.enabled() {
+            return Ok(());
+        }
+        self.save(&State {
+            install_id: Uuid::new_v4(),
+            since: Utc::now(),
+            commands: BTreeMap::new(),
+        })
+    }
+
+    /// Turn telemetry off, deleting the id and anything tallied but not sent
+    pub fn disable(&self) -> Result<()> {
+        if !self.path.exists() {
+            return Ok(());
+        }
+        let _lock = self.lock()?;
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e).context("Failed to remove telemetry file"),
+            _ => Ok(()),
+        }
+    }
+
+    /// Count a run of a subcommand; nothing happens while telemetry is off
+    pub fn record(&self, command: &str, elapsed: std::time::Duration) -> Result<()> {
+        // Checked before locking so runs with telemetry off never create the lock file
+        if !self.path.exists() {
+            return Ok(());
+        }
+        let _lock = self.lock()?;
+        // Loaded under the lock: a missing file means telemetry was disabled meanwhile
+        let Some(mut state) = self.load() else {
+            return Ok(());
+        };
+        let stats = state.commands.entry(command.to_string()).or_default();
+        stats.count += 1;
+        stats.total_ms += elapsed.as_millis() as u64;
+        self.save(&state)
+    }
+
+    /// The payload for the current tally, when telemetry is on
+    pub fn payload(&self) -> Option<Payload> {
+        let state = self.load()?;
+        let commands = state
+            .commands
+            .into_iter()
+            .map(|(name, stats)| {
+                let summary = CommandSummary {
+                    count: stats.count,
+                    mean_ms: stats.total_ms / stats.count.max(1),
+                };
+                (name, summary)
+            })
+            .collect();
+        Some(Payload {
+            schema: SCHEMA_VERSION,
+            install_id: state.install_id,
+            version: env!("CARGO_PKG_VERSION"),
+            os: std::env::consts::OS,
+            since: state.since,
+            until: Utc::now(),
+            commands,
+        })
+    }
+
+    /// Whether a tally is on, non-empty, and at least a day old, so it is time to send it
+    pub fn due(&self) -> bool {
+        self.load()
+            .is_some_and(|state| !state.commands.is_empty() && Utc::now() - state.since >= Duration::days(1))
+    }
+
+    /// Start a new tally after the current one was sent
+    pub fn reset(&self) -> Result<()> {
+        if !self.path.exists() {
+            return Ok(());
+        }
+        let _lock = self.lock()?;
+        let Some(state) = self.load() else {
+            return Ok(());
+        };
+        self.save(&State {
+            since: Utc::now(),
+            commands: BTreeMap::new(),
+            ..state
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn telemetry(dir: &TempDir) -> Telemetry {
+        Telemetry::new(dir.path().join("telemetry.json"))
+    }
+
+    fn ms(ms: u64) -> std::time::Duration {
+        std::time::Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_telemetry_off_by_default() {
+        let dir = TempDir::new().unwrap();
+        let telemetry = telemetry(&dir);
+        assert!(!telemetry.enabled());
+        telemetry.record("query", ms(100)).unwrap();
+        assert!(telemetry.payload().is_none());
+        assert!(!dir.path().join("telemetry.json").exists());
+    }
+
+    #[test]
+    fn test_telemetry_tallies_counts_and_latency() {
+        let dir = TempDir::new().unwrap();
+        let telemetry = telemetry(&dir);
+        telemetry.enable().unwrap();
+        telemetry.record("query", ms(300)).unwrap();
+        telemetry.record("query", ms(500)).unwrap();
+        telemetry.record("history", ms(20)).unwrap();
+
+        let payload = telemetry.payload().unwrap();
+        assert_eq!(payload.schema, SCHEMA_VERSION);
+        assert_eq!(payload.commands["query"], CommandSummary { count: 2, mean_ms: 400 });
+        assert_eq!(payload.commands["history"].count, 1);
+        // Not a day old yet
+        assert!(!telemetry.due());
+    }
+
+    #[test]
+    fn test_telemetry_payload_fields_match_schema() {
+        let dir = TempDir::new().unwrap();
+        let telemetry = telemetry(&dir);
+        telemetry.enable().unwrap();
+        telemetry.record("query", ms(1)).unwrap();
+        let payload = serde_json::to_value(telemetry.payload().unwrap()).unwrap();
+        for key in payload.as_object().unwrap().keys() {
+            assert!(
+                SCHEMA.lines().any(|line| line.starts_with(key.as_str())),
+                "{} is undocumented",
+                key
+            );
+        }
+        for key in payload["commands"]["query"].as_object().unwrap().keys() {
+            assert!(SCHEMA.contains(&format!("  {} ", key)), "{} is undocumented", key);
+        }
+    }
+
+    #[test]
+    fn test_telemetry_reset_keeps_id_and_disable_deletes() {
+        let dir = TempDir::new().unwrap();
+        let telemetry = telemetry(&dir);
+        telemetry.enable().unwrap();
+        telemetry.record("query", ms(10)).unwrap();
+        let id = telemetry.payload().unwrap().install_id;
+
+        telemetry.reset().unwrap();
+        let payload = telemetry.payload().unwrap();
+        assert_eq!(payload.install_id, id);
+        assert!(payload.commands.is_empty());
+
+        telemetry.disable().unwrap();
+        assert!(!telemetry.enabled());
+        telemetry.disable().unwrap();
+    }
+
+    #[test]
+    fn test_telemetry_record_does_not_reenable_after_disable() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("telemetry.json");
+        let telemetry = Telemetry::new(path.clone());
+        telemetry.enable().unwrap();
+
+        // A run that starts recording while `disable` holds the lock must see the deletion
+        let held = lock::acquire(&path.with_extension("lock"), "telemetry file").unwrap();
+        let recorder = std::thread::spawn(move || Telemetry::new(path).record("query", ms(10)));
+        std::thread::sleep(ms(100));
+        fs::remove_file(dir.path().join("telemetry.json")).unwrap();
+        drop(held);
+
+        recorder.join().unwrap().unwrap();
+        assert!(!telemetry.enabled());
+    }
+
+    #[test]
+    fn test_telemetry_off_creates_no_files() {
+        let dir = TempDir::new().unwrap();
+        let telemetry = telemetry(&dir);
+        telemetry.record("query", ms(10)).unwrap();
+        telemetry.reset().unwrap();
+        telemetry.disable().unwrap();
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,323 @@
+//! Styled terminal output for the informational commands
+//!
+//! `history`, `tools`, `doctor`, and `warmup` print through these helpers:
+//! headings, status lines, and tables aligned by display width, with the widest
+//! columns cut to fit the terminal. Color is off with `--no-color`, when
+//! `NO_COLOR` is set, or when stdout is not a terminal (then nothing is cut).
+
+use colored::{ColoredString, Colorize};
+use std::io::IsTerminal;
+use unicode_width::UnicodeWidthStr;
+
+/// Gap between table columns
+const GAP: &str = "  ";
+
+/// Columns are never cut narrower than this
+const MIN_WIDTH: usize = 12;
+
+/// Turn color off for the rest of the process when `no_color` (the `--no-color` flag) is set;
+/// otherwise `NO_COLOR`, `CLICOLOR`, and whether stdout is a terminal decide
+pub fn init(no_color: bool) {
+    if no_color {
+        colored::control::set_override(false);
+    }
+}
+
+/// Width of the terminal stdout is attached to, or None when it is redirected
+pub fn terminal_width() -> Option<usize> {
+    if !std::io::stdout().is_terminal() {
+        return None;
+    }
+    terminal_size::terminal_size().map(|(width, _)| width.0 as usize)
+}
+
+/// How a piece of text is shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Style {
+    #[default]
+    Plain,
+    Bold,
+    Dim,
+    Ok,
+    Warn,
+    Error,
+}
+
+impl Style {
+    pub fn paint(self, text: &str) -> ColoredString {
+        match self {
+            Style::Plain => text.normal(),
+            Style::Bold => text.bold(),
+            Style::Dim => text.dimmed(),
+            Style::Ok => text.green(),
+            Style::Warn => text.yellow(),
+            Style::Error => text.red(),
+        }
+    }
+}
+
+/// A section heading
+pub fn heading(text: &str) -> ColoredString {
+    text.bold().underline()
+}
+
+/// Secondary text, like file locations
+pub fn dim(text: &str) -> ColoredString {
+    text.dimmed()
+}
+
+/// Outcome of a check in `doctor`, `warmup`, and the `--help` footer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    Warn,
+    Fail,
+    /// Not applicable, or not running
+    Skip,
+}
+
+impl Status {
+    fn mark(self) -> &'static str {
+        match self {
+            Status::Ok => "✅",
+            Status::Warn => "⚠️ ",
+            Status::Fail => "❌",
+            Status::Skip => "➖",
+        }
+    }
+
+    fn style(self) -> Style {
+        match self {
+            Status::Ok | Status::Skip => Style::Plain,
+            Status::Warn => Style::Warn,
+            Status::Fail => Style::Error,
+        }
+    }
+}
+
+/// `LABEL:   ✅ detail`, with labels padded so the marks line up
+pub fn status(label: &str, status: Status, detail: &str) -> String {
+    let label = format!("{}:", label);
+    format!(
+        "{}{} {} {}",
+        Style::Bold.paint(&label),
+        " ".repeat(8usize.saturating_sub(label.width())),
+        status.mark(),
+        status.style().paint(detail)
+    )
+}
+
+/// A table cell: one line of text and its style
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Cell {
+    text: String,
+    style: Style,
+}
+
+impl Cell {
+    pub fn new(text: impl Into<String>, style: Style) -> Self {
+        // Multi-line commands are shown on one line
+        let text = text.into().trim_end().replace('\n', " ↵ ");
+        Self { text, style }
+    }
+}
+
+impl From<&str> for Cell {
+    fn from(text: &str) -> Self {
+        Cell::new(text, Style::Plain)
+    }
+}
+
+impl From<String> for Cell {
+    fn from(text: String) -> Self {
+        Cell::new(text, Style::Plain)
+    }
+}
+
+/// Rows of cells, printed with aligned columns under an optional header
+#[derive(Debug, Clone, Default)]
+pub struct Table {
+    header: Vec<String>,
+    rows: Vec<Vec<Cell>>,
+}
+
+impl Table {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn header(mut self, header: &[&str]) -> Self {
+        self.header = header.iter().map(|title| title.to_string()).collect();
+        self
+    }
+
+    pub fn row(&mut self, cells: Vec<Cell>) {
+        self.rows.push(cells);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// The table as lines; with a `width`, the widest columns are cut until it fits
+    pub fn render(&self, width: Option<usize>) -> String {
+        let columns = self
+            .rows
+            .iter()
+            .map(Vec::len)
+            .chain([self.header.len()])
+            .max()
+            .unwrap_or(0);
+        if columns == 0 {
+            return String::new();
+        }
+        let mut widths = vec![0; columns];
+        for (i, title) in self.header.iter().enumerate() {
+            widths[i] = widths[i].max(title.width());
+        }
+        for row in &self.rows {
+            for (i, cell) in row.iter().enumerate() {
+                widths[i] = widths[i].max(cell.text.width());
+            }
+        }
+        if let Some(width) = width {
+            let mut total = widths.iter().sum::<usize>() + GAP.len() * (columns - 1);
+            while total > width {
+                let Some(widest) = (0..columns)
+                    .max_by_key(|&i| widths[i])
+                    .filter(|&i| widths[i] > MIN_WIDTH)
+                else {
+                    break;
+                };
+                widths[widest] -= 1;
+                total -= 1;
+            }
+        }
+
+        let mut out = String::new();
+        if !self.header.is_empty() {
+            let header: Vec<Cell> = self
+                .header
+                .iter()
+                .map(|title| Cell::new(title.as_str(), Style::Bold))
+                .collect();
+            out.push_str(&render_row(&header, &widths));
+        }
+        for row in &self.rows {
+            out.push_str(&render_row(row, &widths));
+        }
+        out
+    }
+
+    /// Print the table to stdout, fitted to the terminal
+    pub fn print(&self) {
+        print!("{}", self.render(terminal_width()));
+    }
+}
+
+fn render_row(row: &[Cell], widths: &[usize]) -> String {
+    let mut line = String::new();
+    for (i, &width) in widths.iter().enumerate() {
+        let cell = row.get(i).cloned().unwrap_or_default();
+        let text = truncate(&cell.text, width);
+        if !text.is_empty() {
+            line.push_str(&cell.style.paint(&text).to_string());
+        }
+        if i + 1 < widths.len() {
+            line.push_str(&" ".repeat(width - text.width()));
+            line.push_str(GAP);
+        }
+    }
+    format!("{}\n", line.trim_end())
+}
+
+/// `text` cut to `width` display columns, ending in `…` when cut
+fn truncate(text: &str, width: usize) -> String {
+    if text.width() <= width {
+        return text.to_string();
+    }
+    let mut out = String::new();
+    let mut used = 0;
+    for c in text.chars() {
+        let w = unicode_width::UnicodeWidthChar::width(c).unwrap_or(0);
+        if used + w + 1 > width {
+            break;
+        }
+        out.push(c);
+        used += w;
+    }
+    out.push('…');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plain() {
+        colored::control::set_override(false);
+    }
+
+    #[test]
+    fn test_table_aligns_columns() {
+        plain();
+        let mut table = Table::new().header(&["TIME", "QUERY", "COMMAND"]);
+        table.row(vec!["10:00".into(), "list files".into(), "ls -la".into()]);
+        table.row(vec!["10:05".into(), "日本語".into(), "echo ✓".into()]);
+        assert_eq!(
+            table.render(None),
+            "TIME   QUERY       COMMAND\n10:00  list files  ls -la\n10:05  日本語      echo ✓\n"
+        );
+    }
+
+    #[test]
+    fn test_table_fits_width() {
+        plain();
+        let mut table = Table::new();
+        table.row(vec!["a".into(), "find . -name '*.rs' -exec wc -l {} +".into()]);
+        let line = table.render(Some(20));
+        assert_eq!(line, "a  find . -name '*.…\n");
+        assert_eq!(line.trim_end().width(), 20);
+
+        // Never narrower than the minimum, and not cut without a width
+        assert_eq!(table.render(Some(4)), "a  find . -nam…\n");
+        assert!(table.render(None).contains("{} +"));
+    }
+
+    #[test]
+    fn test_table_cuts_widest_column_first() {
+        plain();
+        let mut table = Table::new().header(&["QUERY", "COMMAND"]);
+        table.row(vec![
+            "a very long query about the rust files changed last week".into(),
+            "fd -e rs --changed-within 1week".into(),
+        ]);
+        assert_eq!(
+            table.render(Some(60)),
+            "QUERY                          COMMAND\n\
+             a very long query about the …  fd -e rs --changed-within 1w…\n"
+        );
+    }
+
+    #[test]
+    fn test_cell_flattens_multi_line_text() {
+        let cell = Cell::new("cat <<EOF\nhi\nEOF\n", Style::Plain);
+        assert_eq!(cell.text, "cat <<EOF ↵ hi ↵ EOF");
+    }
+
+    #[test]
+    fn test_status_aligns_marks() {
+        plain();
+        assert_eq!(
+            status("API", Status::Ok, "key configured"),
+            "API:     ✅ key configured"
+        );
+        assert_eq!(status("PRELOAD", Status::Fail, "no prompts"), "PRELOAD: ❌ no prompts");
+    }
+
+    #[test]
+    fn test_empty_table() {
+        assert!(Table::new().is_empty());
+        assert_eq!(Table::new().render(Some(80)), "");
+    }
+}
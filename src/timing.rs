@@ -0,0 +1,89 @@
+//! Per-phase timings for `--profile-startup`
+//!
+//! `mark` records how long the phase that just ended took, measured from the
+//! previous mark. It does nothing unless `enable` was called, so phases can be
+//! marked anywhere on the interactive path at no cost.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+struct Timings {
+    last: Instant,
+    phases: Vec<(String, Duration)>,
+}
+
+static TIMINGS: Mutex<Option<Timings>> = Mutex::new(None);
+
+/// Start recording phases, timed from `started` (e.g. the start of `main`)
+pub fn enable(started: Instant) {
+    if let Ok(mut timings) = TIMINGS.lock() {
+        *timings = Some(Timings {
+            last: started,
+            phases: Vec::new(),
+        });
+    }
+}
+
+/// End the current phase, named `phase`
+pub fn mark(phase: &str) {
+    if let Ok(mut guard) = TIMINGS.lock()
+        && let Some(timings) = guard.as_mut()
+    {
+        let now = Instant::now();
+        timings.phases.push((phase.to_string(), now - timings.last));
+        timings.last = now;
+    }
+}
+
+/// One line per phase and a total, or None when profiling is off
+pub fn report() -> Option<String> {
+    let guard = TIMINGS.lock().ok()?;
+    let timings = guard.as_ref()?;
+    Some(format_report(&timings.phases))
+}
+
+fn format_report(phases: &[(String, Duration)]) -> String {
+    let width = phases
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0)
+        .max("total".len());
+    let millis = |duration: &Duration| duration.as_secs_f64() * 1000.0;
+    let mut lines: Vec<String> = phases
+        .iter()
+        .map(|(name, duration)| format!("{:<width$}  {:>9.2} ms", name, millis(duration)))
+        .collect();
+    let total: Duration = phases.iter().map(|(_, duration)| *duration).sum();
+    lines.push(format!("{:<width$}  {:>9.2} ms", "total", millis(&total)));
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_report() {
+        let phases = vec![
+            ("load config".to_string(), Duration::from_micros(1500)),
+            ("api request".to_string(), Duration::from_millis(250)),
+        ];
+        assert_eq!(
+            format_report(&phases),
+            "load config       1.50 ms\napi request     250.00 ms\ntotal           251.50 ms"
+        );
+    }
+
+    #[test]
+    fn test_mark_and_report() {
+        // Only this test touches the global timings
+        mark("ignored");
+        enable(Instant::now());
+        mark("first");
+        mark("second");
+        let report = report().unwrap();
+        let names: Vec<&str> = report.lines().map(|line| line.split("  ").next().unwrap()).collect();
+        assert_eq!(names, vec!["first", "second", "total"]);
+    }
+}
@@ -5,9 +5,74 @@
 
 use eyre::{Context, Result};
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::fs;
-use std::path::PathBuf;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::parse;
+use crate::paths;
+use crate::secrets;
+
+/// Prompt variables filled in by container and cloud CLI detection
+pub const CLOUD_CONTEXT_KEYS: &[&str] = &[
+    "docker",
+    "kube_context",
+    "kube_namespace",
+    "aws_profile",
+    "gcloud_project",
+    "az_subscription",
+];
+
+/// What a tool is used for, to group prompt hints by task
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ToolCategory {
+    FileListing,
+    Searching,
+    Viewing,
+    Http,
+    Json,
+}
+
+impl ToolCategory {
+    /// All categories, in prompt order
+    pub const ALL: [ToolCategory; 5] = [
+        ToolCategory::FileListing,
+        ToolCategory::Searching,
+        ToolCategory::Viewing,
+        ToolCategory::Http,
+        ToolCategory::Json,
+    ];
+
+    /// Known tools per category, most preferred first
+    pub fn tools(self) -> &'static [&'static str] {
+        match self {
+            ToolCategory::FileListing => &["eza", "exa", "lsd", "broot", "tree", "dust", "duf"],
+            ToolCategory::Searching => &["rg", "fd", "ag", "ack", "fzf", "sd"],
+            ToolCategory::Viewing => &["bat", "delta", "glow", "mdcat", "less"],
+            ToolCategory::Http => &["xh", "http", "curlie", "httpie"],
+            ToolCategory::Json => &["jq", "yq", "jless", "gron", "fx"],
+        }
+    }
+
+    /// Short description used in prompt hints
+    pub fn label(self) -> &'static str {
+        match self {
+            ToolCategory::FileListing => "listing files",
+            ToolCategory::Searching => "searching",
+            ToolCategory::Viewing => "viewing files",
+            ToolCategory::Http => "HTTP",
+            ToolCategory::Json => "JSON/YAML",
+        }
+    }
+
+    /// Category of a tool, if it is a known one
+    pub fn of(tool: &str) -> Option<ToolCategory> {
+        Self::ALL.into_iter().find(|category| category.tools().contains(&tool))
+    }
+}
 
 /// Standard Unix tools that are always available
 const STANDARD_TOOLS: &[&str] = &[
@@ -16,12 +81,24 @@ const STANDARD_TOOLS: &[&str] = &[
     "echo", "printf", "test", "true", "false", "cd", "pwd", "env", "export", "source", "sh", "bash", "zsh",
 ];
 
-/// Words to skip when extracting binary from command
-#[allow(dead_code)]
-const SKIP_WORDS: &[&str] = &["sudo", "env", "time", "nice", "nohup", "strace", "ltrace", "doas"];
+/// Tools that come with Windows (cmd.exe builtins, System32 programs, and the shells themselves)
+#[rustfmt::skip]
+const WINDOWS_STANDARD_TOOLS: &[&str] = &[
+    "dir", "type", "copy", "xcopy", "robocopy", "move", "del", "erase", "ren", "mkdir", "md", "rmdir", "rd", "cd",
+    "echo", "set", "where", "findstr", "find", "sort", "more", "tree", "attrib", "icacls", "tasklist", "taskkill",
+    "ipconfig", "netstat", "ping", "curl", "tar", "cmd", "powershell", "pwsh",
+];
+
+/// The tools that come with this OS
+fn standard_tools() -> &'static [&'static str] {
+    if cfg!(windows) {
+        WINDOWS_STANDARD_TOOLS
+    } else {
+        STANDARD_TOOLS
+    }
+}
 
 /// Parsed dual-list response from AI
-#[allow(dead_code)]
 #[derive(Debug, Default, Clone)]
 pub struct DualCommandList {
     /// Commands using modern tools (may be empty)
@@ -30,18 +107,35 @@ pub struct DualCommandList {
     pub standard: Vec<String>,
 }
 
-#[allow(dead_code)]
 impl DualCommandList {
     /// Parse AI response into dual lists
+    /// Multi-line commands (continuations, open quotes, heredocs) are kept together verbatim
     pub fn parse(response: &str) -> Self {
         let mut result = Self::default();
         let mut current_section: Option<&str> = None;
+        let mut pending: Option<String> = None;
 
-        for line in response.lines() {
-            let line = line.trim();
+        for raw_line in response.lines() {
+            let line = raw_line.trim();
+            let lower = line.to_lowercase();
+            let is_marker = lower.starts_with("modern:") || lower.starts_with("standard:") || line.starts_with("```");
+
+            // Continue a multi-line command until it is complete (or a section ends)
+            if let Some(mut command) = pending.take() {
+                if !is_marker {
+                    command.push('\n');
+                    command.push_str(raw_line.trim_end());
+                    if parse::is_incomplete(&command) {
+                        pending = Some(command);
+                    } else {
+                        result.push(current_section, command);
+                    }
+                    continue;
+                }
+                result.push(current_section, command);
+            }
 
             // Check for section markers (case-insensitive)
-            let lower = line.to_lowercase();
             if lower == "modern:" || lower.starts_with("modern:") {
                 current_section = Some("modern");
                 continue;
@@ -56,39 +150,93 @@ impl DualCommandList {
                 continue;
             }
 
-            // Add to appropriate section
-            match current_section {
-                Some("modern") => result.modern.push(line.to_string()),
-                Some("standard") => result.standard.push(line.to_string()),
-                Some(_) => {
-                    // Unknown section - treat as standard
-                    result.standard.push(line.to_string());
-                }
-                None => {
-                    // No section marker yet - treat as standard (fallback)
-                    result.standard.push(line.to_string());
-                }
+            if parse::is_incomplete(line) {
+                pending = Some(line.to_string());
+            } else {
+                result.push(current_section, line.to_string());
             }
         }
+        if let Some(command) = pending {
+            result.push(current_section, command);
+        }
 
         result
     }
 
+    /// Add a command to its section; unknown or missing sections count as standard
+    fn push(&mut self, section: Option<&str>, command: String) {
+        match section {
+            Some("modern") => self.modern.push(command),
+            _ => self.standard.push(command),
+        }
+    }
+
     /// Get all commands, modern first, then standard
     pub fn all_commands(&self) -> Vec<String> {
         let mut all = self.modern.clone();
         all.extend(self.standard.clone());
         all
     }
+}
+
+/// System package manager, used to suggest installing missing tools
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackageManager {
+    Brew,
+    Apt,
+    Dnf,
+    Pacman,
+    Scoop,
+    Choco,
+}
 
-    /// Check if the response is empty
-    pub fn is_empty(&self) -> bool {
-        self.modern.is_empty() && self.standard.is_empty()
+impl PackageManager {
+    /// Detect the system package manager from the binaries on PATH
+    pub fn detect() -> Option<Self> {
+        let candidates: &[(&str, Self)] = if cfg!(target_os = "macos") {
+            &[("brew", Self::Brew)]
+        } else if cfg!(windows) {
+            &[("scoop", Self::Scoop), ("choco", Self::Choco)]
+        } else {
+            &[
+                ("apt-get", Self::Apt),
+                ("dnf", Self::Dnf),
+                ("pacman", Self::Pacman),
+                ("brew", Self::Brew),
+            ]
+        };
+
+        candidates
+            .iter()
+            .find(|(binary, _)| which::which(binary).is_ok())
+            .map(|(_, manager)| *manager)
+    }
+
+    /// Package name providing `binary`, where it differs from the binary name
+    pub fn package_name(self, binary: &str) -> &str {
+        match (self, binary) {
+            (Self::Scoop | Self::Choco, "delta") => "delta",
+            (_, "rg") => "ripgrep",
+            (_, "btm") => "bottom",
+            (_, "http") => "httpie",
+            (_, "delta") => "git-delta",
+            (Self::Apt | Self::Dnf, "fd") => "fd-find",
+            (Self::Dnf, "dust") => "du-dust",
+            _ => binary,
+        }
     }
 
-    /// Total number of commands
-    pub fn len(&self) -> usize {
-        self.modern.len() + self.standard.len()
+    /// Full install command for the package providing `binary`
+    pub fn install_command(self, binary: &str) -> String {
+        let package = self.package_name(binary);
+        match self {
+            Self::Brew => format!("brew install {}", package),
+            Self::Apt => format!("sudo apt install {}", package),
+            Self::Dnf => format!("sudo dnf install {}", package),
+            Self::Pacman => format!("sudo pacman -S {}", package),
+            Self::Scoop => format!("scoop install {}", package),
+            Self::Choco => format!("choco install {}", package),
+        }
     }
 }
 
@@ -105,6 +253,26 @@ pub struct ToolCache {
     #[serde(default)]
     pub version: u32,
 
+    /// Fingerprint of $PATH (and its directory mtimes) the entries were validated against
+    #[serde(default)]
+    pub path_hash: u64,
+
+    /// Unix time the unavailable entries were last re-checked
+    #[serde(default)]
+    pub unavailable_checked_at: u64,
+
+    /// User shell aliases (name -> expansion), imported by the init script
+    #[serde(default)]
+    pub aliases: BTreeMap<String, String>,
+
+    /// User shell function names, imported by the init script
+    #[serde(default)]
+    pub functions: BTreeSet<String>,
+
+    /// Versions of categorized tools, recorded by `qai tools --refresh`
+    #[serde(default)]
+    pub versions: BTreeMap<String, String>,
+
     /// Whether cache has been modified
     #[serde(skip)]
     dirty: bool,
@@ -113,17 +281,25 @@ pub struct ToolCache {
 impl ToolCache {
     const CACHE_VERSION: u32 = 1;
 
+    /// How long a negative entry is trusted before the tool is looked up again
+    const UNAVAILABLE_TTL_SECS: u64 = 60 * 60;
+
     /// Create a new empty cache
     pub fn new() -> Self {
         Self {
             available: HashSet::new(),
             unavailable: HashSet::new(),
             version: Self::CACHE_VERSION,
+            path_hash: Self::current_path_hash(),
+            unavailable_checked_at: unix_now(),
+            aliases: BTreeMap::new(),
+            functions: BTreeSet::new(),
+            versions: BTreeMap::new(),
             dirty: false,
         }
     }
 
-    /// Load cache from disk
+    /// Load cache from disk, re-validating entries if $PATH changed since they were cached
     pub fn load() -> Self {
         let cache_path = Self::cache_path();
         if let Ok(content) = fs::read_to_string(&cache_path)
@@ -131,6 +307,11 @@ impl ToolCache {
             && cache.version == Self::CACHE_VERSION
         {
             cache.dirty = false;
+            if cache.revalidate_if_path_changed() {
+                log::info!("PATH changed since tool cache was written, re-validated entries");
+            } else if cache.recheck_unavailable_if_expired() {
+                log::info!("Unavailable tool entries expired, re-checked them");
+            }
             return cache;
         }
         Self::new()
@@ -173,10 +354,70 @@ impl ToolCache {
 
     /// Get the default cache path
     pub fn cache_path() -> PathBuf {
-        dirs::cache_dir()
-            .unwrap_or_else(|| PathBuf::from("."))
-            .join("qai")
-            .join("tools.json")
+        paths::cache_dir().join("tools.json")
+    }
+
+    /// Fingerprint the current $PATH
+    pub fn current_path_hash() -> u64 {
+        Self::path_hash_for(&std::env::var("PATH").unwrap_or_default())
+    }
+
+    /// Fingerprint a PATH value: the string itself plus the mtime of each directory,
+    /// so installing or removing a binary in a PATH directory also invalidates the cache
+    pub fn path_hash_for(path_var: &str) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        path_var.hash(&mut hasher);
+        for dir in std::env::split_paths(path_var) {
+            let mtime = fs::metadata(&dir).and_then(|m| m.modified()).ok();
+            mtime.hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    /// Re-validate all cached entries if $PATH changed since they were recorded
+    /// Returns true if the cache was re-validated
+    pub fn revalidate_if_path_changed(&mut self) -> bool {
+        self.revalidate_against(Self::current_path_hash())
+    }
+
+    /// Re-validate all cached entries unless `path_hash` matches the stored fingerprint
+    fn revalidate_against(&mut self, path_hash: u64) -> bool {
+        if self.path_hash == path_hash {
+            return false;
+        }
+
+        let known: Vec<String> = self.available.drain().chain(self.unavailable.drain()).collect();
+        for tool in &known {
+            self.is_available(tool);
+        }
+        self.path_hash = path_hash;
+        self.unavailable_checked_at = unix_now();
+        self.dirty = true;
+
+        true
+    }
+
+    /// Re-check unavailable entries once they are older than the negative TTL,
+    /// so a tool installed after it was cached as missing is picked up
+    /// Returns true if the entries were re-checked
+    pub fn recheck_unavailable_if_expired(&mut self) -> bool {
+        self.recheck_unavailable_at(unix_now())
+    }
+
+    /// Re-check unavailable entries if they were last checked more than the TTL before `now`
+    fn recheck_unavailable_at(&mut self, now: u64) -> bool {
+        if now.saturating_sub(self.unavailable_checked_at) < Self::UNAVAILABLE_TTL_SECS {
+            return false;
+        }
+
+        let missing: Vec<String> = self.unavailable.drain().collect();
+        for tool in &missing {
+            self.is_available(tool);
+        }
+        self.unavailable_checked_at = now;
+        self.dirty = true;
+
+        true
     }
 
     /// Check if a binary is available, using cache
@@ -204,28 +445,35 @@ impl ToolCache {
     }
 
     /// Extract the primary binary from a command string
-    /// Handles: sudo, env VAR=x, time, nice, flags like -n, numeric args, etc.
-    #[allow(dead_code)]
-    pub fn extract_binary(cmd: &str) -> Option<&str> {
-        cmd.split_whitespace().find(|word| {
-            !word.contains('=')
-                && !word.starts_with('-')
-                && !word.chars().next().map(|c| c.is_ascii_digit()).unwrap_or(false)
-                && !SKIP_WORDS.contains(word)
+    /// Handles: sudo, env VAR=x, time, nice, flags like -n, numeric args, quoting, etc.
+    pub fn extract_binary(cmd: &str) -> Option<String> {
+        parse::extract_binary(cmd)
+    }
+
+    /// Extract every binary in a command's pipelines, lists, and substitutions
+    pub fn extract_binaries(cmd: &str) -> Vec<String> {
+        parse::extract_binaries(cmd)
+    }
+
+    /// Check that every binary a command runs is a builtin, user alias/function, or available on PATH
+    pub fn command_available(&mut self, cmd: &str) -> bool {
+        Self::extract_binaries(cmd).iter().all(|bin| {
+            parse::is_shell_builtin(bin)
+                || self.aliases.contains_key(bin)
+                || self.functions.contains(bin)
+                || self.is_available(bin)
         })
     }
 
     /// Filter commands to only those with available binaries
     /// Returns (available_commands, unavailable_commands)
-    #[allow(dead_code)]
     pub fn filter_commands(&mut self, commands: &[String]) -> (Vec<String>, Vec<String>) {
         let mut available = Vec::new();
         let mut unavailable = Vec::new();
 
         for cmd in commands {
-            let is_available = Self::extract_binary(cmd)
-                .map(|bin| self.is_available(bin))
-                .unwrap_or(true); // If can't parse, assume available
+            // Commands with no parseable binary are assumed available
+            let is_available = self.command_available(cmd);
 
             if is_available {
                 available.push(cmd.clone());
@@ -237,18 +485,60 @@ impl ToolCache {
         (available, unavailable)
     }
 
-    /// Process dual-list response: validate and merge
-    #[allow(dead_code)]
-    pub fn process_response(&mut self, response: &DualCommandList) -> Vec<String> {
-        // Filter modern commands to only available ones
-        let (available_modern, _) = self.filter_commands(&response.modern);
+    /// Rewrite a command whose binary is unavailable to use its standard equivalent
+    /// Returns None if the binary is available, has no substitution, or the substitute is missing too
+    pub fn substitute(&mut self, cmd: &str, substitutions: &BTreeMap<String, String>) -> Option<String> {
+        let binary = Self::extract_binary(cmd)?;
+        let standard = substitutions.get(&binary)?;
+        if self.is_available(&binary) || !self.is_available(standard) {
+            return None;
+        }
+
+        parse::replace_word(cmd, &binary, standard)
+    }
+
+    /// Build "install with: ..." hints for the missing binaries of dropped commands
+    /// Returns one hint per distinct binary, or nothing if no package manager is detected
+    pub fn install_hints(&mut self, dropped: &[String], manager: Option<PackageManager>) -> Vec<String> {
+        let Some(manager) = manager else {
+            return Vec::new();
+        };
+
+        let mut seen = HashSet::new();
+        let mut hints = Vec::new();
+        for binary in dropped.iter().flat_map(|cmd| Self::extract_binaries(cmd)) {
+            if parse::is_shell_builtin(&binary) || self.is_available(&binary) || !seen.insert(binary.clone()) {
+                continue;
+            }
+            hints.push(format!("install with: {}", manager.install_command(&binary)));
+        }
+        hints
+    }
+
+    /// Process dual-list response: validate, substitute, and merge
+    pub fn process_response(
+        &mut self,
+        response: &DualCommandList,
+        substitutions: &BTreeMap<String, String>,
+    ) -> Vec<String> {
+        // Filter modern commands to only available ones, rewriting the rest where possible
+        let (mut available_modern, unavailable_modern) = self.filter_commands(&response.modern);
+        available_modern.extend(
+            unavailable_modern
+                .iter()
+                .filter_map(|cmd| self.substitute(cmd, substitutions)),
+        );
 
         // Standard commands should always be available, but validate anyway
         let (available_standard, _) = self.filter_commands(&response.standard);
 
         // Combine: available modern first, then standard
         let mut result = available_modern;
-        result.extend(available_standard);
+        for cmd in available_standard {
+            if !result.contains(&cmd) {
+                result.push(cmd);
+            }
+        }
 
         // Ensure we have at least something
         if result.is_empty() {
@@ -259,49 +549,231 @@ impl ToolCache {
         result
     }
 
-    /// Get list of available modern tools for prompt enhancement
-    pub fn available_tools_for_prompt(&self) -> String {
-        if self.available.is_empty() {
+    /// Get "prefer X over Y" hints for installed modern tools that have a standard equivalent
+    pub fn substitution_hints_for_prompt(&mut self, substitutions: &BTreeMap<String, String>) -> String {
+        let preferences: Vec<String> = substitutions
+            .iter()
+            .filter(|(modern, _)| self.is_available(modern))
+            .map(|(modern, standard)| format!("{} over {}", modern, standard))
+            .collect();
+
+        if preferences.is_empty() {
             return String::new();
         }
 
-        // Filter to "interesting" modern tools (not standard Unix)
-        let standard_set: HashSet<&str> = STANDARD_TOOLS.iter().copied().collect();
+        format!("Prefer {}.\n", preferences.join(", "))
+    }
+
+    /// Describe installed modern tools for the prompt, grouped by category
+    /// e.g. "For JSON/YAML use jq 1.7.1; yq also available."
+    pub fn available_tools_for_prompt(&self) -> String {
+        let mut hint = String::new();
+
+        for category in ToolCategory::ALL {
+            let installed: Vec<&str> = category
+                .tools()
+                .iter()
+                .copied()
+                .filter(|tool| self.available.contains(*tool))
+                .collect();
+            let Some((preferred, others)) = installed.split_first() else {
+                continue;
+            };
+
+            let preferred = match self.versions.get(*preferred) {
+                Some(version) => format!("{} {}", preferred, version),
+                None => preferred.to_string(),
+            };
+            hint.push_str(&format!("For {} use {}", category.label(), preferred));
+            if !others.is_empty() {
+                hint.push_str(&format!("; {} also available", others.join(", ")));
+            }
+            hint.push_str(".\n");
+        }
 
-        let mut modern_tools: Vec<&String> = self
+        // Modern tools outside the known categories, sorted for consistent output
+        let mut other: Vec<&str> = self
             .available
             .iter()
-            .filter(|t| !standard_set.contains(t.as_str()))
+            .map(String::as_str)
+            .filter(|tool| !is_standard_tool(tool) && ToolCategory::of(tool).is_none())
             .collect();
+        other.sort();
+        if !other.is_empty() {
+            hint.push_str(&format!("Also installed: {}.\n", other.join(", ")));
+        }
 
-        if modern_tools.is_empty() {
-            return String::new();
+        hint
+    }
+
+    /// Record the versions of installed categorized tools (runs `<tool> --version`)
+    pub fn probe_versions(&mut self) {
+        let installed: Vec<String> = self
+            .available
+            .iter()
+            .filter(|tool| ToolCategory::of(tool).is_some())
+            .cloned()
+            .collect();
+
+        for tool in installed {
+            // The full path, so npm-style .cmd shims run on Windows too
+            let program = which::which(&tool).unwrap_or_else(|_| PathBuf::from(&tool));
+            let output = Command::new(program).arg("--version").output();
+            if let Ok(output) = output
+                && output.status.success()
+                && let Some(version) = parse_version(&String::from_utf8_lossy(&output.stdout))
+            {
+                self.versions.insert(tool, version);
+                self.dirty = true;
+            }
         }
+    }
 
-        // Sort for consistent output
-        modern_tools.sort();
+    /// Installed tools named as a word in the query, sorted
+    pub fn tools_mentioned(&self, query: &str) -> Vec<&str> {
+        let words: HashSet<&str> = query
+            .split(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_'))
+            .collect();
 
-        format!(
-            "User has these modern tools installed: {}\nPrefer these when appropriate.\n",
-            modern_tools.iter().map(|s| s.as_str()).collect::<Vec<_>>().join(", ")
-        )
+        let mut mentioned: Vec<&str> = self
+            .available
+            .iter()
+            .map(|t| t.as_str())
+            .filter(|t| words.contains(t))
+            .collect();
+        mentioned.sort();
+        mentioned
+    }
+
+    /// Build prompt context from the tldr pages of installed tools the query names
+    /// Each page is truncated to `max_chars`
+    pub fn tldr_context(&self, query: &str, max_chars: usize) -> String {
+        let roots = tldr_roots();
+        self.tools_mentioned(query)
+            .into_iter()
+            .filter_map(|tool| {
+                let page = find_tldr_page_in(&roots, tool)?;
+                let content = fs::read_to_string(&page).ok()?;
+                log::debug!("Including tldr page: {}", page.display());
+                Some(format!(
+                    "Reference for {}:\n{}\n",
+                    tool,
+                    condense_page(&content, max_chars)
+                ))
+            })
+            .collect()
+    }
+
+    /// Parse `alias` output (zsh `name=value` or bash `alias name='value'`) and
+    /// bare function names, one per line, into (aliases, functions); values are kept
+    /// quoted as the shell printed them
+    pub fn parse_alias_listing(listing: &str) -> (BTreeMap<String, String>, BTreeSet<String>) {
+        let mut aliases = BTreeMap::new();
+        let mut functions = BTreeSet::new();
+
+        for line in listing.lines() {
+            let line = line.trim();
+            let line = line.strip_prefix("alias ").unwrap_or(line);
+            if line.is_empty() {
+                continue;
+            }
+
+            match line.split_once('=') {
+                Some((name, value)) => {
+                    aliases.insert(name.to_string(), value.to_string());
+                }
+                None if !line.contains(char::is_whitespace) => {
+                    functions.insert(line.to_string());
+                }
+                None => {}
+            }
+        }
+
+        (aliases, functions)
+    }
+
+    /// Replace the imported aliases and functions
+    pub fn import_aliases(&mut self, aliases: BTreeMap<String, String>, functions: BTreeSet<String>) {
+        self.aliases = aliases;
+        self.functions = functions;
+        self.dirty = true;
+    }
+
+    /// Describe the user's aliases and functions for the prompt, with secrets in alias values
+    /// redacted
+    pub fn aliases_for_prompt(&self) -> String {
+        const MAX_ENTRIES: usize = 50;
+        let mut hint = String::new();
+
+        if !self.aliases.is_empty() {
+            let aliases: Vec<String> = self
+                .aliases
+                .iter()
+                .take(MAX_ENTRIES)
+                .map(|(name, value)| format!("{}={}", name, secrets::redact_secrets(value).0))
+                .collect();
+            hint.push_str(&format!("User has these shell aliases: {}\n", aliases.join(", ")));
+        }
+        if !self.functions.is_empty() {
+            let functions: Vec<&str> = self.functions.iter().take(MAX_ENTRIES).map(|f| f.as_str()).collect();
+            hint.push_str(&format!("User has these shell functions: {}\n", functions.join(", ")));
+        }
+        if !hint.is_empty() {
+            hint.push_str("Use these where they fit, and don't assume their names refer to other programs.\n");
+        }
+
+        hint
+    }
+
+    /// Detect the current contexts of installed container and cloud CLIs
+    /// Only tools on PATH are probed; kubectl only when `kubernetes` is enabled.
+    /// Keys are from CLOUD_CONTEXT_KEYS
+    pub fn cloud_contexts(&mut self, kubernetes: bool) -> BTreeMap<String, String> {
+        let mut contexts = BTreeMap::new();
+
+        if self.is_available("docker") {
+            let docker = std::env::var("DOCKER_CONTEXT").unwrap_or_else(|_| "available".to_string());
+            contexts.insert("docker".to_string(), docker);
+        }
+        if kubernetes
+            && self.is_available("kubectl")
+            && let Some((context, namespace)) = kube_current_context()
+        {
+            contexts.insert("kube_context".to_string(), context);
+            contexts.insert("kube_namespace".to_string(), namespace);
+        }
+        if self.is_available("aws")
+            && let Some(profile) = aws_profile()
+        {
+            contexts.insert("aws_profile".to_string(), profile);
+        }
+        if self.is_available("gcloud")
+            && let Some(project) = gcloud_project()
+        {
+            contexts.insert("gcloud_project".to_string(), project);
+        }
+        if self.is_available("az")
+            && let Some(subscription) = az_subscription()
+        {
+            contexts.insert("az_subscription".to_string(), subscription);
+        }
+
+        contexts
     }
 
     /// Clear the cache
     pub fn clear(&mut self) {
         self.available.clear();
         self.unavailable.clear();
+        self.aliases.clear();
+        self.functions.clear();
+        self.versions.clear();
         self.dirty = true;
     }
 
     /// Get statistics about the cache
     pub fn stats(&self) -> ToolStats {
-        let standard_set: HashSet<&str> = STANDARD_TOOLS.iter().copied().collect();
-        let modern_count = self
-            .available
-            .iter()
-            .filter(|t| !standard_set.contains(t.as_str()))
-            .count();
+        let modern_count = self.available.iter().filter(|t| !is_standard_tool(t)).count();
 
         ToolStats {
             available_count: self.available.len(),
@@ -310,6 +782,36 @@ impl ToolCache {
         }
     }
 
+    /// Build a machine-readable report of the full cache
+    pub fn report(&self) -> ToolReport {
+        let mut available: Vec<ToolEntry> = self
+            .available
+            .iter()
+            .map(|name| ToolEntry {
+                name: name.clone(),
+                modern: !is_standard_tool(name),
+            })
+            .collect();
+        available.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut unavailable: Vec<String> = self.unavailable.iter().cloned().collect();
+        unavailable.sort();
+
+        ToolReport {
+            cache_version: self.version,
+            cache_path: Self::cache_path(),
+            path_hash: self.path_hash,
+            unavailable_checked_at: self.unavailable_checked_at,
+            generated_at: unix_now(),
+            stats: self.stats(),
+            available,
+            unavailable,
+            aliases: self.aliases.clone(),
+            functions: self.functions.clone(),
+            versions: self.versions.clone(),
+        }
+    }
+
     /// Check if the cache is dirty (has unsaved changes)
     #[allow(dead_code)]
     pub fn is_dirty(&self) -> bool {
@@ -323,14 +825,227 @@ impl ToolCache {
     }
 }
 
+/// Directories where tldr clients and cheat keep their pages
+pub fn tldr_roots() -> Vec<PathBuf> {
+    let platform = if cfg!(target_os = "macos") {
+        "osx"
+    } else if cfg!(windows) {
+        "windows"
+    } else {
+        "linux"
+    };
+    let mut page_dirs = Vec::new();
+
+    if let Some(cache_dir) = dirs::cache_dir() {
+        // tealdeer and tldr-python
+        page_dirs.push(cache_dir.join("tealdeer").join("tldr-pages").join("pages"));
+        page_dirs.push(cache_dir.join("tldr").join("pages"));
+    }
+    if let Some(home_dir) = dirs::home_dir() {
+        // tldr-node
+        page_dirs.push(home_dir.join(".tldr").join("cache").join("pages"));
+    }
+
+    let mut roots: Vec<PathBuf> = page_dirs
+        .into_iter()
+        .flat_map(|dir| [dir.join("common"), dir.join(platform)])
+        .collect();
+
+    if let Some(config_dir) = dirs::config_dir() {
+        roots.push(config_dir.join("cheat").join("cheatsheets").join("community"));
+        roots.push(config_dir.join("cheat").join("cheatsheets").join("personal"));
+    }
+
+    roots
+}
+
+/// Find the page for `tool` in the first root that has one (tldr `<tool>.md` or cheat `<tool>`)
+pub fn find_tldr_page_in(roots: &[PathBuf], tool: &str) -> Option<PathBuf> {
+    roots
+        .iter()
+        .flat_map(|root| [root.join(format!("{}.md", tool)), root.join(tool)])
+        .find(|page| page.is_file())
+}
+
+/// Drop blank lines and truncate a page to `max_chars` (on a char boundary)
+fn condense_page(content: &str, max_chars: usize) -> String {
+    let condensed = content
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    match condensed.char_indices().nth(max_chars) {
+        Some((end, _)) => format!("{}...", &condensed[..end]),
+        None => condensed,
+    }
+}
+
+/// Check if a tool is one of the standard Unix tools (as opposed to a modern alternative)
+fn is_standard_tool(name: &str) -> bool {
+    standard_tools().contains(&name)
+}
+
+/// Extract the version number from the first line of `--version` output
+/// e.g. "jq-1.7.1" -> "1.7.1", "ripgrep 14.1.0 (rev ...)" -> "14.1.0", "xh v0.22.0" -> "0.22.0"
+pub fn parse_version(output: &str) -> Option<String> {
+    output.lines().next()?.split_whitespace().find_map(|word| {
+        let start = word.find(|c: char| c.is_ascii_digit())?;
+        let version = word[start..].trim_end_matches(|c: char| !c.is_ascii_digit());
+        (version.contains('.') && version.chars().all(|c| c.is_ascii_digit() || c == '.')).then(|| version.to_string())
+    })
+}
+
+/// Seconds since the Unix epoch
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Describe detected container and cloud contexts for the prompt
+pub fn cloud_contexts_for_prompt(contexts: &BTreeMap<String, String>) -> String {
+    if contexts.is_empty() {
+        return String::new();
+    }
+
+    let current: Vec<String> = contexts
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect();
+    format!(
+        "Current container/cloud contexts: {}\nTarget these when a command needs a context, profile, project, or subscription.\n",
+        current.join(", ")
+    )
+}
+
+/// Current kubectl context and namespace, read from `kubectl config view --minify`
+fn kube_current_context() -> Option<(String, String)> {
+    let output = Command::new("kubectl")
+        .args([
+            "config",
+            "view",
+            "--minify",
+            "-o",
+            "jsonpath={.current-context}|{.contexts[0].context.namespace}",
+        ])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_kube_context(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Parse `<context>|<namespace>` output; an unset namespace means `default`
+pub fn parse_kube_context(output: &str) -> Option<(String, String)> {
+    let (context, namespace) = output.trim().split_once('|')?;
+    if context.is_empty() {
+        return None;
+    }
+    let namespace = if namespace.is_empty() { "default" } else { namespace };
+    Some((context.to_string(), namespace.to_string()))
+}
+
+/// Active AWS profile from the environment, or `default` when AWS is configured
+fn aws_profile() -> Option<String> {
+    if let Ok(profile) = std::env::var("AWS_PROFILE").or_else(|_| std::env::var("AWS_DEFAULT_PROFILE")) {
+        return Some(profile);
+    }
+    let aws_dir = dirs::home_dir()?.join(".aws");
+    (aws_dir.join("config").is_file() || aws_dir.join("credentials").is_file()).then(|| "default".to_string())
+}
+
+/// Active gcloud project from the environment or gcloud's config directory
+fn gcloud_project() -> Option<String> {
+    if let Ok(project) = std::env::var("CLOUDSDK_CORE_PROJECT") {
+        return Some(project);
+    }
+    let config_dir = std::env::var("CLOUDSDK_CONFIG")
+        .map(PathBuf::from)
+        .ok()
+        .or_else(|| dirs::home_dir().map(|home| home.join(".config").join("gcloud")))?;
+    gcloud_project_in(&config_dir)
+}
+
+/// Read the `project` of the active configuration in a gcloud config directory
+pub fn gcloud_project_in(config_dir: &Path) -> Option<String> {
+    let active = fs::read_to_string(config_dir.join("active_config"))
+        .map(|name| name.trim().to_string())
+        .unwrap_or_else(|_| "default".to_string());
+    let config = fs::read_to_string(config_dir.join("configurations").join(format!("config_{}", active))).ok()?;
+
+    let mut in_core = false;
+    for line in config.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_core = line == "[core]";
+        } else if in_core
+            && let Some((key, value)) = line.split_once('=')
+            && key.trim() == "project"
+        {
+            return Some(value.trim().to_string());
+        }
+    }
+    None
+}
+
+/// Default Azure subscription from the az CLI profile
+fn az_subscription() -> Option<String> {
+    let config_dir = std::env::var("AZURE_CONFIG_DIR")
+        .map(PathBuf::from)
+        .ok()
+        .or_else(|| dirs::home_dir().map(|home| home.join(".azure")))?;
+    az_subscription_in(&config_dir.join("azureProfile.json"))
+}
+
+/// Read the name of the default subscription from an `azureProfile.json`
+pub fn az_subscription_in(profile: &Path) -> Option<String> {
+    let content = fs::read_to_string(profile).ok()?;
+    // az writes the profile with a UTF-8 BOM
+    let profile: serde_json::Value = serde_json::from_str(content.trim_start_matches('\u{feff}')).ok()?;
+
+    profile["subscriptions"]
+        .as_array()?
+        .iter()
+        .find(|sub| sub["isDefault"].as_bool() == Some(true))
+        .and_then(|sub| sub["name"].as_str())
+        .map(str::to_string)
+}
+
 /// Statistics about the tool cache
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct ToolStats {
     pub available_count: usize,
     pub unavailable_count: usize,
     pub modern_tools_count: usize,
 }
 
+/// An available tool and whether it is a modern alternative to a standard tool
+#[derive(Debug, Serialize)]
+pub struct ToolEntry {
+    pub name: String,
+    pub modern: bool,
+}
+
+/// Machine-readable dump of the tool cache (`qai tools --json`)
+/// Timestamps are seconds since the Unix epoch
+#[derive(Debug, Serialize)]
+pub struct ToolReport {
+    pub cache_version: u32,
+    pub cache_path: PathBuf,
+    pub path_hash: u64,
+    pub unavailable_checked_at: u64,
+    pub generated_at: u64,
+    pub stats: ToolStats,
+    pub available: Vec<ToolEntry>,
+    pub unavailable: Vec<String>,
+    pub aliases: BTreeMap<String, String>,
+    pub functions: BTreeSet<String>,
+    pub versions: BTreeMap<String, String>,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -396,6 +1111,28 @@ find . -name "*.rs"
         assert_eq!(parsed.standard.len(), 2);
     }
 
+    #[test]
+    fn test_dual_command_list_parse_keeps_multi_line_commands() {
+        let response = "MODERN:\nfd -e rs \\\n  -x wc -l\nSTANDARD:\ncat <<'EOF' > notes.txt\n# not a comment\n  \"quoted\" line\nEOF\nfind . -name '*.rs'\n";
+        let parsed = DualCommandList::parse(response);
+        assert_eq!(parsed.modern, vec!["fd -e rs \\\n  -x wc -l"]);
+        assert_eq!(
+            parsed.standard,
+            vec![
+                "cat <<'EOF' > notes.txt\n# not a comment\n  \"quoted\" line\nEOF",
+                "find . -name '*.rs'"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dual_command_list_parse_section_ends_incomplete_command() {
+        let response = "MODERN:\nrg foo |\nSTANDARD:\ngrep -r foo .\n";
+        let parsed = DualCommandList::parse(response);
+        assert_eq!(parsed.modern, vec!["rg foo |"]);
+        assert_eq!(parsed.standard, vec!["grep -r foo ."]);
+    }
+
     #[test]
     fn test_dual_command_list_parse_skips_empty_and_comments() {
         let response = r#"
@@ -443,27 +1180,6 @@ find .
         assert_eq!(all[2], "ls");
     }
 
-    #[test]
-    fn test_dual_command_list_is_empty() {
-        let empty = DualCommandList::default();
-        assert!(empty.is_empty());
-
-        let mut not_empty = DualCommandList::default();
-        not_empty.standard.push("ls".to_string());
-        assert!(!not_empty.is_empty());
-    }
-
-    #[test]
-    fn test_dual_command_list_len() {
-        let mut list = DualCommandList::default();
-        assert_eq!(list.len(), 0);
-
-        list.modern.push("fd".to_string());
-        list.standard.push("find".to_string());
-        list.standard.push("ls".to_string());
-        assert_eq!(list.len(), 3);
-    }
-
     // ToolCache tests
 
     #[test]
@@ -477,39 +1193,87 @@ find .
 
     #[test]
     fn test_tool_cache_extract_binary_simple() {
-        assert_eq!(ToolCache::extract_binary("ls -la"), Some("ls"));
-        assert_eq!(ToolCache::extract_binary("find . -name '*.rs'"), Some("find"));
-        assert_eq!(ToolCache::extract_binary("cat file.txt"), Some("cat"));
+        assert_eq!(ToolCache::extract_binary("ls -la").as_deref(), Some("ls"));
+        assert_eq!(
+            ToolCache::extract_binary("find . -name '*.rs'").as_deref(),
+            Some("find")
+        );
+        assert_eq!(ToolCache::extract_binary("cat file.txt").as_deref(), Some("cat"));
     }
 
     #[test]
     fn test_tool_cache_extract_binary_with_sudo() {
-        assert_eq!(ToolCache::extract_binary("sudo ls -la"), Some("ls"));
-        assert_eq!(ToolCache::extract_binary("sudo apt install pkg"), Some("apt"));
+        assert_eq!(ToolCache::extract_binary("sudo ls -la").as_deref(), Some("ls"));
+        assert_eq!(
+            ToolCache::extract_binary("sudo apt install pkg").as_deref(),
+            Some("apt")
+        );
     }
 
     #[test]
     fn test_tool_cache_extract_binary_with_env() {
-        assert_eq!(ToolCache::extract_binary("env VAR=value cmd arg"), Some("cmd"));
-        assert_eq!(ToolCache::extract_binary("FOO=bar baz"), Some("baz"));
+        assert_eq!(
+            ToolCache::extract_binary("env VAR=value cmd arg").as_deref(),
+            Some("cmd")
+        );
+        assert_eq!(ToolCache::extract_binary("FOO=bar baz").as_deref(), Some("baz"));
     }
 
     #[test]
     fn test_tool_cache_extract_binary_with_time() {
-        assert_eq!(ToolCache::extract_binary("time ls -la"), Some("ls"));
-        assert_eq!(ToolCache::extract_binary("nice -n 10 make"), Some("make"));
+        assert_eq!(ToolCache::extract_binary("time ls -la").as_deref(), Some("ls"));
+        assert_eq!(ToolCache::extract_binary("nice -n 10 make").as_deref(), Some("make"));
     }
 
     #[test]
     fn test_tool_cache_extract_binary_empty() {
-        assert_eq!(ToolCache::extract_binary(""), None);
-        assert_eq!(ToolCache::extract_binary("   "), None);
+        assert_eq!(ToolCache::extract_binary("").as_deref(), None);
+        assert_eq!(ToolCache::extract_binary("   ").as_deref(), None);
     }
 
     #[test]
     fn test_tool_cache_extract_binary_only_env_vars() {
         // Edge case: only env vars, no actual command
-        assert_eq!(ToolCache::extract_binary("FOO=bar BAZ=qux"), None);
+        assert_eq!(ToolCache::extract_binary("FOO=bar BAZ=qux").as_deref(), None);
+    }
+
+    #[test]
+    fn test_extract_binaries_pipeline() {
+        assert_eq!(ToolCache::extract_binaries("cat log | jqq '.foo'"), vec!["cat", "jqq"]);
+        assert_eq!(
+            ToolCache::extract_binaries("find . -name '*.rs' | xargs -n 1 wc -l"),
+            vec!["find", "wc"]
+        );
+        assert_eq!(ToolCache::extract_binaries("ls 2>&1 | less"), vec!["ls", "less"]);
+    }
+
+    #[test]
+    fn test_extract_binaries_skips_variables() {
+        assert!(ToolCache::extract_binaries("$EDITOR file.txt").is_empty());
+    }
+
+    #[test]
+    fn test_command_available_checks_whole_pipeline() {
+        let mut cache = ToolCache::new();
+        assert!(cache.command_available("cat /etc/hosts | grep local"));
+        assert!(!cache.command_available("cat /etc/hosts | nonexistent_cmd_xyz123 '.foo'"));
+        assert!(!cache.command_available("ls && echo $(nonexistent_cmd_xyz123)"));
+    }
+
+    #[test]
+    fn test_command_available_allows_builtins() {
+        let mut cache = ToolCache::new();
+        assert!(cache.command_available("cd /tmp && ls"));
+        assert!(cache.command_available("for f in *; do echo $f; done"));
+    }
+
+    #[test]
+    fn test_filter_commands_pipeline_typo() {
+        let mut cache = ToolCache::new();
+        let commands = vec!["cat log | nonexistent_cmd_xyz123 '.foo'".to_string()];
+        let (available, unavailable) = cache.filter_commands(&commands);
+        assert!(available.is_empty());
+        assert_eq!(unavailable.len(), 1);
     }
 
     #[test]
@@ -567,7 +1331,7 @@ find .
         response.standard.push("ls -la".to_string());
         response.standard.push("find .".to_string());
 
-        let result = cache.process_response(&response);
+        let result = cache.process_response(&response, &BTreeMap::new());
 
         // Should only contain available commands
         assert!(result.contains(&"ls -la".to_string()));
@@ -587,7 +1351,7 @@ find .
         response.standard.push("ls -la".to_string());
         response.standard.push("find .".to_string());
 
-        let result = cache.process_response(&response);
+        let result = cache.process_response(&response, &BTreeMap::new());
 
         // Should fallback to unfiltered standard when all filtered out
         assert_eq!(result.len(), 2);
@@ -733,6 +1497,130 @@ find .
         assert!(cache.is_dirty());
     }
 
+    #[test]
+    fn test_path_hash_differs_for_different_paths() {
+        assert_ne!(
+            ToolCache::path_hash_for("/usr/bin"),
+            ToolCache::path_hash_for("/usr/local/bin")
+        );
+        assert_eq!(
+            ToolCache::path_hash_for("/usr/bin"),
+            ToolCache::path_hash_for("/usr/bin")
+        );
+    }
+
+    #[test]
+    fn test_path_hash_includes_directory_mtime() {
+        let temp_dir = TempDir::new().unwrap();
+        let path_var = temp_dir.path().display().to_string();
+        let before = ToolCache::path_hash_for(&path_var);
+
+        // Removing the directory changes its metadata, so the fingerprint changes
+        drop(temp_dir);
+        assert_ne!(before, ToolCache::path_hash_for(&path_var));
+    }
+
+    #[test]
+    fn test_revalidate_unchanged_path_is_noop() {
+        let mut cache = ToolCache::new();
+        cache
+            .available
+            .insert("this_binary_definitely_does_not_exist_12345".to_string());
+        let hash = cache.path_hash;
+
+        assert!(!cache.revalidate_against(hash));
+        assert!(cache.available.contains("this_binary_definitely_does_not_exist_12345"));
+        assert!(!cache.is_dirty());
+    }
+
+    #[test]
+    fn test_revalidate_changed_path_rechecks_entries() {
+        let mut cache = ToolCache::new();
+        cache.path_hash = 1;
+        // Stale entries, both wrong for this system
+        cache
+            .available
+            .insert("this_binary_definitely_does_not_exist_12345".to_string());
+        cache.unavailable.insert("ls".to_string());
+
+        assert!(cache.revalidate_against(2));
+        assert!(cache.available.contains("ls"));
+        assert!(
+            cache
+                .unavailable
+                .contains("this_binary_definitely_does_not_exist_12345")
+        );
+        assert_eq!(cache.path_hash, 2);
+        assert!(cache.is_dirty());
+    }
+
+    #[test]
+    fn test_tool_cache_report() {
+        let mut cache = ToolCache::new();
+        cache.available.insert("rg".to_string());
+        cache.available.insert("ls".to_string());
+        cache.unavailable.insert("zzz".to_string());
+        cache.unavailable.insert("aaa".to_string());
+
+        let report = cache.report();
+        assert_eq!(report.cache_version, ToolCache::CACHE_VERSION);
+        let available: Vec<(&str, bool)> = report.available.iter().map(|t| (t.name.as_str(), t.modern)).collect();
+        assert_eq!(available, vec![("ls", false), ("rg", true)]);
+        assert_eq!(report.unavailable, vec!["aaa", "zzz"]);
+        assert_eq!(report.stats.modern_tools_count, 1);
+
+        let json: serde_json::Value = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["available"][1]["name"], "rg");
+        assert_eq!(json["available"][1]["modern"], true);
+        assert!(json["unavailable_checked_at"].is_u64());
+    }
+
+    #[test]
+    fn test_recheck_unavailable_after_ttl() {
+        let mut cache = ToolCache::new();
+        cache.unavailable.insert("ls".to_string());
+        cache
+            .unavailable
+            .insert("this_binary_definitely_does_not_exist_12345".to_string());
+        cache.unavailable_checked_at = 1_000;
+
+        assert!(cache.recheck_unavailable_at(1_000 + ToolCache::UNAVAILABLE_TTL_SECS));
+        assert!(cache.available.contains("ls"));
+        assert!(!cache.unavailable.contains("ls"));
+        assert!(
+            cache
+                .unavailable
+                .contains("this_binary_definitely_does_not_exist_12345")
+        );
+        assert_eq!(cache.unavailable_checked_at, 1_000 + ToolCache::UNAVAILABLE_TTL_SECS);
+        assert!(cache.is_dirty());
+    }
+
+    #[test]
+    fn test_recheck_unavailable_within_ttl_is_noop() {
+        let mut cache = ToolCache::new();
+        cache.unavailable.insert("ls".to_string());
+        cache.unavailable_checked_at = 1_000;
+
+        assert!(!cache.recheck_unavailable_at(1_000 + ToolCache::UNAVAILABLE_TTL_SECS - 1));
+        assert!(cache.unavailable.contains("ls"));
+        assert!(!cache.is_dirty());
+    }
+
+    #[test]
+    fn test_tool_cache_load_without_path_hash() {
+        // Caches written before path_hash existed still load
+        let temp_dir = TempDir::new().unwrap();
+        let cache_path = temp_dir.path().join("tools.json");
+        fs::write(&cache_path, r#"{"available":["ls"],"unavailable":[],"version":1}"#).unwrap();
+
+        let cache = ToolCache::load_from(&cache_path);
+        assert!(cache.available.contains("ls"));
+        assert_eq!(cache.path_hash, 0);
+        // Unknown check time, so negative entries are re-checked on the next load()
+        assert_eq!(cache.unavailable_checked_at, 0);
+    }
+
     #[test]
     fn test_tool_cache_cache_path() {
         let path = ToolCache::cache_path();
@@ -760,23 +1648,432 @@ find .
     fn test_process_response_empty() {
         let mut cache = ToolCache::new();
         let response = DualCommandList::default();
-        let result = cache.process_response(&response);
+        let result = cache.process_response(&response, &BTreeMap::new());
         assert!(result.is_empty());
     }
 
+    fn substitutions() -> BTreeMap<String, String> {
+        [
+            ("nonexistent_modern_tool_xyz", "ls"),
+            ("other_missing_tool_xyz", "also_missing_xyz"),
+        ]
+        .into_iter()
+        .map(|(m, s)| (m.to_string(), s.to_string()))
+        .collect()
+    }
+
+    #[test]
+    fn test_substitute_unavailable_modern_tool() {
+        let mut cache = ToolCache::new();
+        let result = cache.substitute("nonexistent_modern_tool_xyz -la /tmp", &substitutions());
+        assert_eq!(result, Some("ls -la /tmp".to_string()));
+    }
+
+    #[test]
+    fn test_substitute_preserves_prefix() {
+        let mut cache = ToolCache::new();
+        let result = cache.substitute("sudo nonexistent_modern_tool_xyz -la", &substitutions());
+        assert_eq!(result, Some("sudo ls -la".to_string()));
+    }
+
+    #[test]
+    fn test_substitute_available_tool_unchanged() {
+        let mut cache = ToolCache::new();
+        let mut subs = BTreeMap::new();
+        subs.insert("ls".to_string(), "cat".to_string());
+        assert_eq!(cache.substitute("ls -la", &subs), None);
+    }
+
+    #[test]
+    fn test_substitute_missing_standard_tool() {
+        let mut cache = ToolCache::new();
+        assert_eq!(cache.substitute("other_missing_tool_xyz arg", &substitutions()), None);
+    }
+
+    #[test]
+    fn test_process_response_rewrites_instead_of_dropping() {
+        let mut cache = ToolCache::new();
+
+        let mut response = DualCommandList::default();
+        response.modern.push("nonexistent_modern_tool_xyz -la".to_string());
+        response.standard.push("find .".to_string());
+
+        let result = cache.process_response(&response, &substitutions());
+        assert_eq!(result, vec!["ls -la".to_string(), "find .".to_string()]);
+    }
+
+    #[test]
+    fn test_process_response_dedups_rewritten_commands() {
+        let mut cache = ToolCache::new();
+
+        let mut response = DualCommandList::default();
+        response.modern.push("nonexistent_modern_tool_xyz -la".to_string());
+        response.standard.push("ls -la".to_string());
+
+        let result = cache.process_response(&response, &substitutions());
+        assert_eq!(result, vec!["ls -la".to_string()]);
+    }
+
+    #[test]
+    fn test_substitution_hints_for_prompt() {
+        let mut cache = ToolCache::new();
+        cache.available.insert("rg".to_string());
+        cache.unavailable.insert("fd".to_string());
+        let subs: BTreeMap<String, String> = [("rg", "grep"), ("fd", "find")]
+            .into_iter()
+            .map(|(m, s)| (m.to_string(), s.to_string()))
+            .collect();
+
+        let hints = cache.substitution_hints_for_prompt(&subs);
+        assert_eq!(hints, "Prefer rg over grep.\n");
+    }
+
+    #[test]
+    fn test_substitution_hints_for_prompt_empty() {
+        let mut cache = ToolCache::new();
+        cache.unavailable.insert("rg".to_string());
+        let mut subs = BTreeMap::new();
+        subs.insert("rg".to_string(), "grep".to_string());
+
+        assert!(cache.substitution_hints_for_prompt(&subs).is_empty());
+    }
+
+    #[test]
+    fn test_package_manager_install_command() {
+        assert_eq!(PackageManager::Brew.install_command("fd"), "brew install fd");
+        assert_eq!(PackageManager::Apt.install_command("fd"), "sudo apt install fd-find");
+        assert_eq!(PackageManager::Dnf.install_command("jq"), "sudo dnf install jq");
+        assert_eq!(PackageManager::Pacman.install_command("rg"), "sudo pacman -S ripgrep");
+        assert_eq!(PackageManager::Scoop.install_command("rg"), "scoop install ripgrep");
+        assert_eq!(PackageManager::Choco.install_command("jq"), "choco install jq");
+    }
+
+    #[test]
+    fn test_package_manager_package_name() {
+        assert_eq!(PackageManager::Brew.package_name("rg"), "ripgrep");
+        assert_eq!(PackageManager::Brew.package_name("fd"), "fd");
+        assert_eq!(PackageManager::Apt.package_name("delta"), "git-delta");
+        assert_eq!(PackageManager::Scoop.package_name("delta"), "delta");
+        assert_eq!(PackageManager::Pacman.package_name("eza"), "eza");
+    }
+
+    #[test]
+    fn test_install_hints_one_per_binary() {
+        let mut cache = ToolCache::new();
+        cache.unavailable.insert("fd".to_string());
+        cache.unavailable.insert("rg".to_string());
+        let dropped = vec!["fd -e rs".to_string(), "fd -t d".to_string(), "ls | rg foo".to_string()];
+        let hints = cache.install_hints(&dropped, Some(PackageManager::Brew));
+        assert_eq!(
+            hints,
+            vec![
+                "install with: brew install fd".to_string(),
+                "install with: brew install ripgrep".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_install_hints_no_package_manager() {
+        let mut cache = ToolCache::new();
+        let dropped = vec!["fd -e rs".to_string()];
+        assert!(cache.install_hints(&dropped, None).is_empty());
+    }
+
+    #[test]
+    fn test_package_manager_detect_does_not_panic() {
+        let _ = PackageManager::detect();
+    }
+
+    #[test]
+    fn test_tools_mentioned() {
+        let mut cache = ToolCache::new();
+        cache.available.insert("fd".to_string());
+        cache.available.insert("jq".to_string());
+        cache.available.insert("rg".to_string());
+
+        assert_eq!(
+            cache.tools_mentioned("use jq to pretty-print, then fd"),
+            vec!["fd", "jq"]
+        );
+        // Substrings of other words don't count
+        assert!(cache.tools_mentioned("find offd files").is_empty());
+    }
+
+    #[test]
+    fn test_find_tldr_page_in_prefers_first_root() {
+        let temp_dir = TempDir::new().unwrap();
+        let common = temp_dir.path().join("common");
+        let linux = temp_dir.path().join("linux");
+        fs::create_dir_all(&common).unwrap();
+        fs::create_dir_all(&linux).unwrap();
+        fs::write(common.join("fd.md"), "# fd").unwrap();
+        fs::write(linux.join("fd.md"), "# fd linux").unwrap();
+
+        let page = find_tldr_page_in(&[common.clone(), linux], "fd").unwrap();
+        assert_eq!(page, common.join("fd.md"));
+    }
+
+    #[test]
+    fn test_find_tldr_page_in_cheat_layout() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("jq"), "# jq cheatsheet").unwrap();
+
+        let roots = vec![temp_dir.path().to_path_buf()];
+        assert!(find_tldr_page_in(&roots, "jq").is_some());
+        assert!(find_tldr_page_in(&roots, "fd").is_none());
+    }
+
+    #[test]
+    fn test_condense_page_drops_blank_lines() {
+        let page = "# fd\n\n> Find entries\n\n- Search:\n\n`fd pattern`\n";
+        assert_eq!(
+            condense_page(page, 1000),
+            "# fd\n> Find entries\n- Search:\n`fd pattern`"
+        );
+    }
+
+    #[test]
+    fn test_condense_page_truncates() {
+        assert_eq!(condense_page("abcdefgh", 4), "abcd...");
+        assert_eq!(condense_page("héllo", 2), "hé...");
+    }
+
+    #[test]
+    fn test_tldr_context_no_mentions() {
+        let cache = ToolCache::new();
+        assert!(cache.tldr_context("list files", 1500).is_empty());
+    }
+
+    #[test]
+    fn test_parse_alias_listing_zsh() {
+        let listing = "gco='git checkout'\nll='ls -la'\ng=git\nmkcd\n";
+        let (aliases, functions) = ToolCache::parse_alias_listing(listing);
+        assert_eq!(aliases.get("gco"), Some(&"'git checkout'".to_string()));
+        assert_eq!(aliases.get("ll"), Some(&"'ls -la'".to_string()));
+        assert_eq!(aliases.get("g"), Some(&"git".to_string()));
+        assert!(functions.contains("mkcd"));
+        assert_eq!(functions.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_alias_listing_bash() {
+        let listing = "alias gst='git status'\nalias la='ls -A'\n";
+        let (aliases, functions) = ToolCache::parse_alias_listing(listing);
+        assert_eq!(aliases.get("gst"), Some(&"'git status'".to_string()));
+        assert_eq!(aliases.get("la"), Some(&"'ls -A'".to_string()));
+        assert!(functions.is_empty());
+    }
+
+    #[test]
+    fn test_parse_alias_listing_ignores_noise() {
+        let (aliases, functions) = ToolCache::parse_alias_listing("\n  \nsome random text\n");
+        assert!(aliases.is_empty());
+        assert!(functions.is_empty());
+    }
+
+    #[test]
+    fn test_import_aliases_replaces_previous() {
+        let mut cache = ToolCache::new();
+        let (aliases, functions) = ToolCache::parse_alias_listing("old='echo old'\nold_fn");
+        cache.import_aliases(aliases, functions);
+        let (aliases, functions) = ToolCache::parse_alias_listing("gco='git checkout'");
+        cache.import_aliases(aliases, functions);
+
+        assert!(cache.is_dirty());
+        assert!(cache.aliases.contains_key("gco"));
+        assert!(!cache.aliases.contains_key("old"));
+        assert!(cache.functions.is_empty());
+    }
+
+    #[test]
+    fn test_aliases_for_prompt() {
+        let mut cache = ToolCache::new();
+        assert!(cache.aliases_for_prompt().is_empty());
+
+        let (aliases, functions) = ToolCache::parse_alias_listing("gco='git checkout'\nmkcd");
+        cache.import_aliases(aliases, functions);
+
+        let hint = cache.aliases_for_prompt();
+        assert!(hint.contains("gco='git checkout'"));
+        assert!(hint.contains("shell functions: mkcd"));
+    }
+
+    #[test]
+    fn test_aliases_for_prompt_keeps_quoting_and_redacts_secrets() {
+        let mut cache = ToolCache::new();
+        let listing = "today='date \"+%Y-%m-%d\"'\nprs='gh pr list --token sk-abcdefghijklmnopqrstuvwxyz'";
+        let (aliases, functions) = ToolCache::parse_alias_listing(listing);
+        cache.import_aliases(aliases, functions);
+
+        let hint = cache.aliases_for_prompt();
+        assert!(hint.contains(r#"today='date "+%Y-%m-%d"'"#));
+        assert!(hint.contains("prs='gh pr list --token ***"));
+        assert!(!hint.contains("sk-abcdefghijklmnopqrstuvwxyz"));
+    }
+
+    #[test]
+    fn test_command_available_accepts_aliases_and_functions() {
+        let mut cache = ToolCache::new();
+        let (aliases, functions) = ToolCache::parse_alias_listing("gco_xyz='git checkout'\nmkcd_xyz");
+        cache.import_aliases(aliases, functions);
+
+        assert!(cache.command_available("gco_xyz main"));
+        assert!(cache.command_available("mkcd_xyz build && ls"));
+    }
+
+    #[test]
+    fn test_tool_cache_persists_aliases() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache_path = temp_dir.path().join("tools.json");
+
+        let mut cache = ToolCache::new();
+        let (aliases, functions) = ToolCache::parse_alias_listing("gco='git checkout'\nmkcd");
+        cache.import_aliases(aliases, functions);
+        cache.save_to(&cache_path).unwrap();
+
+        let loaded = ToolCache::load_from(&cache_path);
+        assert_eq!(loaded.aliases, cache.aliases);
+        assert_eq!(loaded.functions, cache.functions);
+    }
+
     #[test]
     fn test_available_tools_sorted() {
         let mut cache = ToolCache::new();
         cache.available.insert("zoxide".to_string());
-        cache.available.insert("eza".to_string());
-        cache.available.insert("bat".to_string());
+        cache.available.insert("atuin".to_string());
+        cache.available.insert("mise".to_string());
 
         let prompt = cache.available_tools_for_prompt();
-        // Should be sorted alphabetically
-        let bat_pos = prompt.find("bat").unwrap();
-        let eza_pos = prompt.find("eza").unwrap();
+        // Uncategorized tools should be sorted alphabetically
+        let atuin_pos = prompt.find("atuin").unwrap();
+        let mise_pos = prompt.find("mise").unwrap();
         let zoxide_pos = prompt.find("zoxide").unwrap();
-        assert!(bat_pos < eza_pos);
-        assert!(eza_pos < zoxide_pos);
+        assert!(atuin_pos < mise_pos);
+        assert!(mise_pos < zoxide_pos);
+    }
+
+    #[test]
+    fn test_available_tools_grouped_by_category() {
+        let mut cache = ToolCache::new();
+        for tool in ["yq", "jq", "rg", "fd", "zoxide", "grep"] {
+            cache.available.insert(tool.to_string());
+        }
+        cache.versions.insert("jq".to_string(), "1.7.1".to_string());
+
+        let prompt = cache.available_tools_for_prompt();
+        assert_eq!(
+            prompt,
+            "For searching use rg; fd also available.\n\
+             For JSON/YAML use jq 1.7.1; yq also available.\n\
+             Also installed: zoxide.\n"
+        );
+    }
+
+    #[test]
+    fn test_tool_category_of() {
+        assert_eq!(ToolCategory::of("eza"), Some(ToolCategory::FileListing));
+        assert_eq!(ToolCategory::of("rg"), Some(ToolCategory::Searching));
+        assert_eq!(ToolCategory::of("bat"), Some(ToolCategory::Viewing));
+        assert_eq!(ToolCategory::of("xh"), Some(ToolCategory::Http));
+        assert_eq!(ToolCategory::of("jq"), Some(ToolCategory::Json));
+        assert_eq!(ToolCategory::of("zoxide"), None);
+    }
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(parse_version("jq-1.7.1\n").as_deref(), Some("1.7.1"));
+        assert_eq!(
+            parse_version("ripgrep 14.1.0 (rev e50df40a19)\n").as_deref(),
+            Some("14.1.0")
+        );
+        assert_eq!(parse_version("xh v0.22.0").as_deref(), Some("0.22.0"));
+        assert_eq!(
+            parse_version("yq (https://github.com/mikefarah/yq/) version v4.40.5").as_deref(),
+            Some("4.40.5")
+        );
+        assert_eq!(parse_version("no version here"), None);
+        assert_eq!(parse_version(""), None);
+    }
+
+    #[test]
+    fn test_gcloud_project_in_active_config() {
+        let temp_dir = TempDir::new().unwrap();
+        let configs = temp_dir.path().join("configurations");
+        fs::create_dir_all(&configs).unwrap();
+        fs::write(temp_dir.path().join("active_config"), "work\n").unwrap();
+        fs::write(
+            configs.join("config_work"),
+            "[compute]\nproject = wrong\n\n[core]\naccount = me@example.com\nproject = my-project\n",
+        )
+        .unwrap();
+
+        assert_eq!(gcloud_project_in(temp_dir.path()).as_deref(), Some("my-project"));
+    }
+
+    #[test]
+    fn test_gcloud_project_in_missing_config() {
+        let temp_dir = TempDir::new().unwrap();
+        assert_eq!(gcloud_project_in(temp_dir.path()), None);
+    }
+
+    #[test]
+    fn test_az_subscription_in_default_with_bom() {
+        let temp_dir = TempDir::new().unwrap();
+        let profile = temp_dir.path().join("azureProfile.json");
+        fs::write(
+            &profile,
+            "\u{feff}{\"subscriptions\": [{\"name\": \"dev\", \"isDefault\": false}, {\"name\": \"prod\", \"isDefault\": true}]}",
+        )
+        .unwrap();
+
+        assert_eq!(az_subscription_in(&profile).as_deref(), Some("prod"));
+    }
+
+    #[test]
+    fn test_cloud_contexts_skip_unavailable_tools() {
+        let mut cache = ToolCache::new();
+        for tool in ["docker", "kubectl", "aws", "gcloud", "az"] {
+            cache.unavailable.insert(tool.to_string());
+        }
+
+        assert!(cache.cloud_contexts(true).is_empty());
+    }
+
+    #[test]
+    fn test_cloud_contexts_kubernetes_opt_in() {
+        let mut cache = ToolCache::new();
+        // Even with kubectl installed, it isn't probed unless enabled
+        cache.available.insert("kubectl".to_string());
+        for tool in ["docker", "aws", "gcloud", "az"] {
+            cache.unavailable.insert(tool.to_string());
+        }
+
+        assert!(cache.cloud_contexts(false).is_empty());
+    }
+
+    #[test]
+    fn test_parse_kube_context() {
+        assert_eq!(
+            parse_kube_context("prod|payments\n"),
+            Some(("prod".to_string(), "payments".to_string()))
+        );
+        assert_eq!(
+            parse_kube_context("kind-dev|"),
+            Some(("kind-dev".to_string(), "default".to_string()))
+        );
+        assert_eq!(parse_kube_context("|"), None);
+        assert_eq!(parse_kube_context(""), None);
+    }
+
+    #[test]
+    fn test_cloud_contexts_for_prompt() {
+        assert_eq!(cloud_contexts_for_prompt(&BTreeMap::new()), "");
+
+        let mut contexts = BTreeMap::new();
+        contexts.insert("kube_context".to_string(), "prod".to_string());
+        contexts.insert("aws_profile".to_string(), "dev".to_string());
+        let hint = cloud_contexts_for_prompt(&contexts);
+        assert!(hint.starts_with("Current container/cloud contexts: aws_profile=dev, kube_context=prod\n"));
     }
 }
@@ -0,0 +1,112 @@
+//! Steps of `qai tutorial`, the guided first run
+//!
+//! Each step explains one part of qai, then checks that it works in this environment; the
+//! checks themselves run in the binary, since some of them send a query.
+
+use crate::config::BindingsConfig;
+use crate::history::QueryRecord;
+
+/// One step of the tutorial, in order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// Send a query from the command line
+    FirstQuery,
+    /// AI mode in zsh, through the trigger key
+    Trigger,
+    /// Picking one of several suggestions with fzf
+    Picker,
+    /// Queries and picks are remembered
+    History,
+    /// Exit statuses of run suggestions are reported back
+    Feedback,
+}
+
+impl Step {
+    pub const ALL: [Step; 5] = [
+        Step::FirstQuery,
+        Step::Trigger,
+        Step::Picker,
+        Step::History,
+        Step::Feedback,
+    ];
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Step::FirstQuery => "Your first query",
+            Step::Trigger => "AI mode in your shell",
+            Step::Picker => "Choosing between suggestions",
+            Step::History => "History",
+            Step::Feedback => "Feedback",
+        }
+    }
+
+    /// What the step teaches, with the user's key bindings
+    pub fn lesson(self, bindings: &BindingsConfig) -> String {
+        match self {
+            Step::FirstQuery => "`qai query` turns a description into a shell command and prints it. Try one now; \
+                 it is sent to your configured model."
+                .to_string(),
+            Step::Trigger => format!(
+                "In zsh, type `ai` and press {} to enter AI mode, type your query, and press {} to send it. \
+                 The suggestion is inserted at the prompt for review; {} leaves AI mode. You can also type \
+                 `ai: list big files` and press {}.",
+                bindings.trigger, bindings.submit, bindings.cancel, bindings.trigger
+            ),
+            Step::Picker => format!(
+                "With fzf installed, AI mode shows several suggestions to choose from: enter inserts one, {} \
+                 opens it in $EDITOR first, {} runs it, and {} asks for a fresh batch.",
+                bindings.edit, bindings.execute, bindings.regenerate
+            ),
+            Step::History => "Every query is recorded, and commands you pick rank first the next time you ask \
+                 the same thing. `qai history` shows recent queries, `qai history top` your most used commands."
+                .to_string(),
+            Step::Feedback => "When you run a suggestion from AI mode, its exit status is reported back: \
+                 commands that work are preferred, and patterns that keep failing go to the end of the list."
+                .to_string(),
+        }
+    }
+}
+
+/// Whether the zsh integration is loaded in this shell (it exports `QAI_SESSION`)
+pub fn integration_loaded(session: Option<&str>) -> bool {
+    session.is_some_and(|session| !session.is_empty())
+}
+
+/// Whether the shell integration ever reported running a suggestion
+pub fn feedback_recorded(records: &[QueryRecord]) -> bool {
+    records
+        .iter()
+        .any(|record| record.executed && record.exit_code.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lessons_use_configured_keys() {
+        let bindings = BindingsConfig {
+            trigger: "ctrl-space".to_string(),
+            execute: "alt-enter".to_string(),
+            ..Default::default()
+        };
+        assert!(Step::Trigger.lesson(&bindings).contains("press ctrl-space"));
+        assert!(Step::Picker.lesson(&bindings).contains("alt-enter runs it"));
+    }
+
+    #[test]
+    fn test_integration_loaded() {
+        assert!(integration_loaded(Some("1234-1700000000")));
+        assert!(!integration_loaded(Some("")));
+        assert!(!integration_loaded(None));
+    }
+
+    #[test]
+    fn test_feedback_recorded() {
+        let mut record = QueryRecord::new("list files".to_string(), vec!["ls".to_string()], "model".to_string());
+        assert!(!feedback_recorded(std::slice::from_ref(&record)));
+        record.execute();
+        record.exit_code = Some(0);
+        assert!(feedback_recorded(&[record]));
+    }
+}
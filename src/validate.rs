@@ -0,0 +1,352 @@
+//! Strict config validation for `qai config validate` and `qai doctor`
+//!
+//! Loading a config is lenient: unknown keys are ignored, and a bad key name only
+//! fails at shell-init. Validation reports every problem at once, with its line:
+//! syntax and type errors, unknown keys, key names the zsh widget can't bind,
+//! malformed API URLs, and invalid safety regexes.
+
+use eyre::{Context, Result};
+use regex::Regex;
+use serde_json::Value;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::bindings::key_name_to_sequence;
+use crate::config::{Config, ProjectConfig};
+
+/// One problem in a config file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    /// 1-based line, when it can be located
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Validate a user config file (qai.yml or qai.toml)
+pub fn validate_file(path: &Path) -> Result<Vec<Problem>> {
+    let content = fs::read_to_string(path).context(format!("Failed to read {}", path.display()))?;
+    Ok(validate_str(&content, is_toml(path)))
+}
+
+/// Validate a project config file (.qai.yml or .qai.toml), which only accepts a few keys
+pub fn validate_project_file(path: &Path) -> Result<Vec<Problem>> {
+    let content = fs::read_to_string(path).context(format!("Failed to read {}", path.display()))?;
+    let toml = is_toml(path);
+    Ok(match parse::<ProjectConfig>(&content, toml) {
+        Ok(project) => regex_problems(&content, toml, "safety", &project.safety.deny, &project.safety.confirm),
+        Err(problem) => vec![problem],
+    })
+}
+
+/// Validate config file content
+pub fn validate_str(content: &str, toml: bool) -> Vec<Problem> {
+    let config = match parse::<Config>(content, toml) {
+        Ok(config) => config,
+        Err(problem) => return vec![problem],
+    };
+    let Ok(value) = parse::<Value>(content, toml) else {
+        return Vec::new();
+    };
+
+    let mut problems = unknown_keys(value, content, toml);
+
+    let bindings = &config.bindings;
+    let keys = [
+        ("trigger", Some(&bindings.trigger)),
+        ("submit", Some(&bindings.submit)),
+        ("cancel", Some(&bindings.cancel)),
+        ("quick-accept", bindings.quick_accept.as_ref()),
+    ];
+    for (key, name) in keys {
+        if let Some(Err(e)) = name.map(|name| key_name_to_sequence(name)) {
+            problems.push(Problem {
+                line: key_line(content, toml, &["bindings", key]),
+                message: format!("bindings.{}: {}", key, e),
+            });
+        }
+    }
+
+    if let Some(problem) = url_problem(&config.api_base, content, toml, &["api-base"]) {
+        problems.push(problem);
+    }
+    for (name, profile) in &config.profiles {
+        if let Some(problem) = profile
+            .api_base
+            .as_deref()
+            .and_then(|url| url_problem(url, content, toml, &["profiles", name, "api-base"]))
+        {
+            problems.push(problem);
+        }
+    }
+
+    problems.extend(regex_problems(
+        content,
+        toml,
+        "safety",
+        &config.safety.deny,
+        &config.safety.confirm,
+    ));
+    problems.sort_by_key(|problem| problem.line);
+    problems
+}
+
+fn is_toml(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "toml")
+}
+
+/// Deserialize, turning the error into a located problem
+fn parse<T: serde::de::DeserializeOwned>(content: &str, toml: bool) -> Result<T, Problem> {
+    if toml {
+        toml::from_str(content).map_err(|e| Problem {
+            line: e.span().map(|span| line_of_offset(content, span.start)),
+            message: e.message().trim().to_string(),
+        })
+    } else {
+        serde_yaml::from_str(content).map_err(|e| {
+            let message = e.to_string();
+            // The location is reported separately
+            let message = match message.rsplit_once(" at line ") {
+                Some((message, _)) => message.to_string(),
+                None => message,
+            };
+            Problem {
+                line: e.location().map(|location| location.line()),
+                message,
+            }
+        })
+    }
+}
+
+fn line_of_offset(content: &str, offset: usize) -> usize {
+    content[..offset.min(content.len())].matches('\n').count() + 1
+}
+
+/// Report keys of `value` that deserializing a Config ignores, as serde sees them: a snake_case
+/// spelling only counts as known where the field declares it as an alias
+fn unknown_keys(value: Value, content: &str, toml: bool) -> Vec<Problem> {
+    let mut ignored = Vec::new();
+    let _: Result<Config, _> = serde_ignored::deserialize(value, |path| ignored.push(path_keys(&path)));
+    ignored
+        .into_iter()
+        .map(|path| {
+            let keys: Vec<&str> = path.iter().map(String::as_str).collect();
+            Problem {
+                line: key_line(content, toml, &keys),
+                message: format!("unknown key '{}'", path.join(".")),
+            }
+        })
+        .collect()
+}
+
+/// The map keys leading to an ignored value
+fn path_keys(path: &serde_ignored::Path) -> Vec<String> {
+    use serde_ignored::Path;
+    match path {
+        Path::Root => Vec::new(),
+        Path::Map { parent, key } => {
+            let mut keys = path_keys(parent);
+            keys.push(key.clone());
+            keys
+        }
+        Path::Seq { parent, index } => {
+            let mut keys = path_keys(parent);
+            keys.push(index.to_string());
+            keys
+        }
+        Path::Some { parent } | Path::NewtypeStruct { parent } | Path::NewtypeVariant { parent } => path_keys(parent),
+    }
+}
+
+fn url_problem(url: &str, content: &str, toml: bool, path: &[&str]) -> Option<Problem> {
+    let error = match reqwest::Url::parse(url) {
+        Ok(parsed) if !matches!(parsed.scheme(), "http" | "https") => {
+            format!("unsupported scheme '{}' (expected http or https)", parsed.scheme())
+        }
+        Ok(parsed) if parsed.host_str().is_none_or(str::is_empty) => "missing host".to_string(),
+        Ok(_) => return None,
+        Err(e) => e.to_string(),
+    };
+    Some(Problem {
+        line: key_line(content, toml, path),
+        message: format!("{}: invalid URL '{}': {}", path.join("."), url, error),
+    })
+}
+
+fn regex_problems(content: &str, toml: bool, section: &str, deny: &[String], confirm: &[String]) -> Vec<Problem> {
+    [("deny", deny), ("confirm", confirm)]
+        .into_iter()
+        .flat_map(|(list, patterns)| {
+            patterns.iter().filter_map(move |pattern| {
+                Regex::new(pattern).err().map(|e| Problem {
+                    line: key_line(content, toml, &[section, list]),
+                    message: format!(
+                        "{}.{}: invalid pattern '{}': {}",
+                        section,
+                        list,
+                        pattern,
+                        e.to_string().lines().last().unwrap_or_default().trim()
+                    ),
+                })
+            })
+        })
+        .collect()
+}
+
+/// Line of the key at `path` (e.g. ["bindings", "trigger"]), found from indentation in YAML
+/// and from `[table]` headers in TOML; keys match in kebab- or snake_case
+pub fn key_line(content: &str, toml: bool, path: &[&str]) -> Option<usize> {
+    let normalize = |key: &str| key.trim().trim_matches(['"', '\'']).replace('_', "-");
+    let path: Vec<String> = path.iter().map(|key| normalize(key)).collect();
+
+    if toml {
+        let mut table: Vec<String> = Vec::new();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if let Some(header) = line.strip_prefix('[') {
+                table = header.trim_matches(['[', ']']).split('.').map(normalize).collect();
+                if table == path {
+                    return Some(i + 1);
+                }
+            } else if let Some((key, _)) = line.split_once('=')
+                && !line.starts_with('#')
+            {
+                let mut full = table.clone();
+                full.extend(key.split('.').map(normalize));
+                if full == path {
+                    return Some(i + 1);
+                }
+            }
+        }
+        return None;
+    }
+
+    let mut stack: Vec<(usize, String)> = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with('-') {
+            continue;
+        }
+        let Some((key, _)) = trimmed.split_once(':') else {
+            continue;
+        };
+        let indent = line.len() - trimmed.len();
+        while stack.last().is_some_and(|(level, _)| *level >= indent) {
+            stack.pop();
+        }
+        stack.push((indent, normalize(key)));
+        if stack.len() == path.len() && stack.iter().map(|(_, key)| key).eq(path.iter()) {
+            return Some(i + 1);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_valid_config() {
+        let content =
+            "model: gpt-4o\napi_key: sk-test\nbindings:\n  trigger: ctrl-space\nprompt-vars:\n  team: infra\n";
+        assert!(validate_str(content, false).is_empty());
+        assert!(validate_str("", false).is_empty());
+    }
+
+    #[test]
+    fn test_validate_unknown_keys() {
+        let content =
+            "modle: gpt-4o\nbindings:\n  trigger: tab\n  submitt: enter\nprofiles:\n  work:\n    api-bsae: http://x\n";
+        let problems = validate_str(content, false);
+        assert_eq!(
+            problems,
+            vec![
+                Problem {
+                    line: Some(1),
+                    message: "unknown key 'modle'".to_string()
+                },
+                Problem {
+                    line: Some(4),
+                    message: "unknown key 'bindings.submitt'".to_string()
+                },
+                Problem {
+                    line: Some(7),
+                    message: "unknown key 'profiles.work.api-bsae'".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_snake_case_keys_without_alias() {
+        // max_tokens is a declared alias; shell_history and ai_prompt aren't, so loading ignores them
+        let content = "max_tokens: 200\ncontext:\n  shell_history: true\nui:\n  ai_prompt: '> '\n";
+        assert_eq!(
+            validate_str(content, false),
+            vec![
+                Problem {
+                    line: Some(3),
+                    message: "unknown key 'context.shell_history'".to_string()
+                },
+                Problem {
+                    line: Some(5),
+                    message: "unknown key 'ui.ai_prompt'".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_bindings_urls_and_regexes() {
+        let content =
+            "api-base: localhost:8000/v1\nbindings:\n  trigger: hyper-q\nsafety:\n  deny:\n    - '(unclosed'\n";
+        let problems = validate_str(content, false);
+        assert_eq!(problems.len(), 3);
+        assert_eq!(problems[0].line, Some(1));
+        assert!(problems[0].message.contains("invalid URL 'localhost:8000/v1'"));
+        assert_eq!(problems[1].line, Some(3));
+        assert!(problems[1].message.starts_with("bindings.trigger:"));
+        assert_eq!(problems[2].line, Some(5));
+        assert!(problems[2].message.contains("invalid pattern '(unclosed'"));
+    }
+
+    #[test]
+    fn test_validate_type_error_has_line() {
+        let problems = validate_str("model: gpt-4o\nmax-tokens: lots\n", false);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].line, Some(2));
+        assert!(problems[0].message.contains("invalid type"));
+    }
+
+    #[test]
+    fn test_validate_toml() {
+        let content = "model = \"gpt-4o\"\n\n[bindings]\ntrigger = \"tab\"\ncancle = \"esc\"\n";
+        assert_eq!(
+            validate_str(content, true),
+            vec![Problem {
+                line: Some(5),
+                message: "unknown key 'bindings.cancle'".to_string()
+            }]
+        );
+        let problems = validate_str("model = gpt-4o\n", true);
+        assert_eq!(problems[0].line, Some(1));
+    }
+
+    #[test]
+    fn test_key_line() {
+        let content = "# comment\nsafety:\n  deny:\n    - x\ncontext:\n  env_vars:\n    - HOME\n";
+        assert_eq!(key_line(content, false, &["safety", "deny"]), Some(3));
+        assert_eq!(key_line(content, false, &["context", "env-vars"]), Some(6));
+        assert_eq!(key_line(content, false, &["deny"]), None);
+    }
+}